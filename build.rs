@@ -1,5 +1,7 @@
-const USE_COMPUTED_GOTO: bool = true;
+#[cfg(not(feature = "c-vm"))]
+fn main() {}
 
+#[cfg(feature = "c-vm")]
 fn main() {
     println!("cargo:rerun-if-changed=src/vm.c");
     let profile = std::env::var("PROFILE").unwrap();
@@ -8,17 +10,7 @@ fn main() {
             .file("src/vm.c")
             .define("DEBUG", "1")
             .compile("vm"),
-        "release" => {
-            if USE_COMPUTED_GOTO {
-                cc::Build::new()
-                    .file("src/vm.c")
-                    .opt_level(3)
-                    .define("USE_COMPUTED_GOTO", "1")
-                    .compile("vm");
-            } else {
-                cc::Build::new().file("src/vm.c").opt_level(3).compile("vm");
-            }
-        }
+        "release" => cc::Build::new().file("src/vm.c").opt_level(3).compile("vm"),
         _ => unreachable!(),
     }
 }
@@ -0,0 +1,157 @@
+use std::cmp;
+use std::collections::{HashMap, HashSet};
+
+use crate::compile::Signature;
+use crate::parse::{Name, StartArgs, State, ToState};
+
+pub fn for_each_call<'a>(state: &State<'a>, mut f: impl FnMut(&Name<'a>, Signature<'a>)) {
+    for arm in &state.arms {
+        visit(&arm.to_state, &state.state_params, &mut f);
+    }
+}
+
+// `state_params` are the names that refer to a parameter instead of a state
+fn visit<'a>(
+    to_state: &ToState<'a>,
+    state_params: &[Name],
+    f: &mut impl FnMut(&Name<'a>, Signature<'a>),
+) {
+    if let ToState::State {
+        name,
+        state_args,
+        symbol_args,
+    } = to_state
+    {
+        for state_arg in state_args {
+            visit(state_arg, state_params, f);
+        }
+
+        // a bare name that matches a state parameter refers to the parameter
+        let is_param = state_args.is_empty()
+            && symbol_args.is_empty()
+            && state_params.iter().any(|param| param.name == name.name);
+        if !is_param {
+            let signature = Signature {
+                name: name.name,
+                states: state_args.len() as u16,
+                symbols: symbol_args.len() as u16,
+            };
+            f(name, signature);
+        }
+    }
+}
+
+/// The states a run can get to: `start`, the states passed to it and every
+/// state they can go to
+pub fn reachable<'a>(unit: &[State<'a>], start_args: &StartArgs<'a>) -> HashSet<Signature<'a>> {
+    let states: HashMap<_, _> = unit
+        .iter()
+        .map(|state| (Signature::of(state), state))
+        .collect();
+
+    let mut reachable = HashSet::new();
+    let mut stack = vec![Signature::start(start_args)];
+    for state_arg in &start_args.state_args {
+        visit(state_arg, &[], &mut |_, callee| stack.push(callee));
+    }
+    while let Some(signature) = stack.pop() {
+        if !reachable.insert(signature) {
+            continue;
+        }
+        if let Some(state) = states.get(&signature) {
+            for_each_call(state, |_, callee| stack.push(callee));
+        }
+    }
+    reachable
+}
+
+// each state with the states it calls, in source order
+pub fn call_graph<'a>(unit: &[State<'a>]) -> Vec<(Signature<'a>, Vec<Signature<'a>>)> {
+    unit.iter()
+        .map(|state| {
+            let mut callees = Vec::new();
+            for_each_call(state, |_, callee| {
+                if !callees.contains(&callee) {
+                    callees.push(callee);
+                }
+            });
+            (Signature::of(state), callees)
+        })
+        .collect()
+}
+
+// tarjan's algorithm without recursion, since generated machines can have very
+// long call chains. components come out callees first
+pub fn strongly_connected<'a>(
+    graph: &[(Signature<'a>, Vec<Signature<'a>>)],
+) -> Vec<Vec<Signature<'a>>> {
+    let indices: HashMap<_, _> = graph
+        .iter()
+        .enumerate()
+        .map(|(i, (signature, _))| (*signature, i))
+        .collect();
+    let edges: Vec<Vec<usize>> = graph
+        .iter()
+        .map(|(_, callees)| {
+            callees
+                .iter()
+                .filter_map(|callee| indices.get(callee).copied())
+                .collect()
+        })
+        .collect();
+
+    let mut index = vec![usize::MAX; graph.len()];
+    let mut low = vec![0; graph.len()];
+    let mut on_stack = vec![false; graph.len()];
+    let mut stack = Vec::new();
+    let mut next_index = 0;
+    let mut components = Vec::new();
+
+    for root in 0..graph.len() {
+        if index[root] != usize::MAX {
+            continue;
+        }
+
+        let mut work = vec![(root, 0)];
+        index[root] = next_index;
+        low[root] = next_index;
+        next_index += 1;
+        stack.push(root);
+        on_stack[root] = true;
+
+        while let Some((node, edge)) = work.last_mut() {
+            let node = *node;
+            if let Some(&callee) = edges[node].get(*edge) {
+                *edge += 1;
+                if index[callee] == usize::MAX {
+                    index[callee] = next_index;
+                    low[callee] = next_index;
+                    next_index += 1;
+                    stack.push(callee);
+                    on_stack[callee] = true;
+                    work.push((callee, 0));
+                } else if on_stack[callee] {
+                    low[node] = cmp::min(low[node], index[callee]);
+                }
+                continue;
+            }
+
+            work.pop();
+            if let Some(&(caller, _)) = work.last() {
+                low[caller] = cmp::min(low[caller], low[node]);
+            }
+            if low[node] == index[node] {
+                let mut component = Vec::new();
+                while let Some(member) = stack.pop() {
+                    on_stack[member] = false;
+                    component.push(graph[member].0);
+                    if member == node {
+                        break;
+                    }
+                }
+                components.push(component);
+            }
+        }
+    }
+    components
+}
@@ -0,0 +1,177 @@
+use std::io::{self, Stdout, Write};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use termion::{clear, color, cursor, style};
+use unicode_segmentation::UnicodeSegmentation;
+
+use crate::backend::{self, HaltReason, Observer, Simulated};
+use crate::compile::Compiled;
+use crate::cycle::Analyzer;
+use crate::output;
+use crate::tape;
+use crate::vm::{Tape, Vm};
+
+/// Plays the run back in the terminal: every `every` moves, it redraws the
+/// state and the cells around the head over the last frame, at no more than
+/// `fps` frames a second
+pub struct Animation<'a> {
+    compiled: &'a Compiled,
+    every: usize,
+    frame: Duration,
+    width: usize,
+    two_sided: bool,
+    out: Stdout,
+    // when the next frame can be drawn
+    next: Instant,
+    // how many lines the last frame took up, to go back over
+    lines: usize,
+}
+
+impl<'a> Animation<'a> {
+    pub fn new(
+        compiled: &'a Compiled,
+        every: usize,
+        fps: u32,
+        width: usize,
+        two_sided: bool,
+    ) -> Self {
+        Animation {
+            compiled,
+            every,
+            frame: Duration::from_secs(1) / fps,
+            width,
+            two_sided,
+            out: io::stdout(),
+            next: Instant::now(),
+            lines: 0,
+        }
+    }
+
+    /// Draws the configuration the run stopped in, with why it stopped, and
+    /// leaves it on the screen
+    pub fn finish(mut self, simulated: &Simulated) {
+        let origin = simulated.origin as i64;
+        let cell = |position: i64| {
+            usize::try_from(position + origin)
+                .ok()
+                .and_then(|index| simulated.tape.get(index))
+                .copied()
+                .unwrap_or_default()
+        };
+        self.draw(
+            simulated.moves,
+            simulated.final_address,
+            simulated.head_position as i64 - origin,
+            Some(simulated.halt_reason),
+            cell,
+        );
+        write!(self.out, "{}", cursor::Show).ok();
+        self.out.flush().ok();
+    }
+
+    // `cell` is the symbol on a cell, counting from the initial tape's first one
+    fn draw(
+        &mut self,
+        moves: usize,
+        address: u32,
+        head: i64,
+        stopped: Option<HaltReason>,
+        cell: impl Fn(i64) -> u32,
+    ) {
+        let mut frame = String::new();
+        if self.lines > 0 {
+            frame += &format!("{}{}", cursor::Up(self.lines as u16), clear::AfterCursor);
+        } else {
+            frame += cursor::Hide.as_ref();
+        }
+
+        // like the debugger's `where`
+        frame += &format!("move {moves}:");
+        if let Some(reason) = stopped {
+            frame += &format!(" {reason}");
+        }
+        if let Some(info) = self.compiled.states.get(&address) {
+            if !output::color() {
+                frame += &format!(" in `{}`", info.name);
+            } else {
+                frame += &format!(
+                    " in {}{}{}{}{}",
+                    style::Bold,
+                    color::Fg(color::Green),
+                    info.name,
+                    style::Reset,
+                    color::Fg(color::Reset)
+                );
+            }
+        }
+        let reading = self.compiled.symbols[cell(head) as usize].as_str();
+        frame += &format!(", head at {head} reading {}\n", tape::literal(reading));
+
+        let (first, window) = self.window(head, &cell);
+        frame += &tape::render(&window, self.width, Some((head - first) as usize));
+
+        self.lines = frame.matches('\n').count();
+        self.out.write_all(frame.as_bytes()).ok();
+        self.out.flush().ok();
+    }
+
+    // the cells around the head that fit on one line, and where the first one is
+    fn window(&self, head: i64, cell: &impl Fn(i64) -> u32) -> (i64, Vec<&'a str>) {
+        let symbol = |position| self.compiled.symbols[cell(position) as usize].as_str();
+        let width = |symbol: &str| symbol.graphemes(true).count() + 3;
+        let mut first = head;
+        let mut last = head;
+        let mut len = 1 + width(symbol(head));
+        loop {
+            let mut grew = false;
+            let right = width(symbol(last + 1));
+            if len + right <= self.width {
+                last += 1;
+                len += right;
+                grew = true;
+            }
+            // a one-sided tape has nothing left of cell 0
+            if self.two_sided || first > 0 {
+                let left = width(symbol(first - 1));
+                if len + left <= self.width {
+                    first -= 1;
+                    len += left;
+                    grew = true;
+                }
+            }
+            if !grew {
+                break;
+            }
+        }
+        (first, (first..=last).map(symbol).collect())
+    }
+}
+
+impl Analyzer for Animation<'_> {
+    fn check<O: Observer>(&mut self, vm: &Vm<O>) -> Option<HaltReason> {
+        let configuration = vm.configuration();
+        if !configuration.moves.is_multiple_of(self.every) {
+            return None;
+        }
+        // the VM only checks for ^C every few thousand moves, which could take
+        // a while at this speed
+        if backend::interrupted() {
+            return Some(HaltReason::Interrupted);
+        }
+        let tape = vm.tape();
+        self.draw(
+            configuration.moves,
+            configuration.address,
+            configuration.head,
+            None,
+            |position| tape.cell(position),
+        );
+        let now = Instant::now();
+        if now < self.next {
+            thread::sleep(self.next - now);
+        }
+        self.next = self.next.max(now) + self.frame;
+        None
+    }
+}
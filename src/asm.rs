@@ -0,0 +1,739 @@
+use std::collections::{BTreeSet, HashMap};
+use std::fmt::Write;
+use std::fs;
+use std::path::Path;
+
+use crate::bytecode::{self as bc, Operand};
+use crate::compile::{Compiled, SignatureBuf, StateInfo};
+use crate::error::Error;
+use crate::lex::Span;
+use crate::tape;
+
+// the text format, one item per line and `;` to the end of a line is a comment:
+//   .version 2            use version 2 bytecode (optional, and only as the first item)
+//   .symbols '' 'a' ...   the symbol table, starting with the blank symbol
+//   .tape 'a' ...         the initial tape (optional)
+//   /// text              a doc comment for the next state
+//   state LABEL...        a state, with one label for each signature it has
+//   arm                   an arm of the current state, starting with its pattern
+//   entry                 the code that makes the `start` state, after every state
+//   OPCODE OPERAND...     an instruction
+// a label is `name` for a state without parameters, `name/STATES/SYMBOLS` otherwise
+// and `!` for the halt state. skips aren't written: a pattern that doesn't match
+// always jumps to the next arm
+pub const EXTENSION: &str = "tasm";
+
+pub fn is_asm(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|extension| extension == EXTENSION)
+}
+
+pub fn disassemble(compiled: &Compiled) -> String {
+    let bytes = &compiled.bytes;
+    let version = bc::Version::of(bytes);
+    let entry = u32::from_le_bytes(bytes[2..6].try_into().unwrap()) as usize;
+    let mut labels: HashMap<u32, Vec<String>> = HashMap::new();
+    for (signature, &address) in &compiled.signatures {
+        labels.entry(address).or_default().push(label(signature));
+    }
+    for labels in labels.values_mut() {
+        labels.sort();
+    }
+    let starts: BTreeSet<u32> = compiled
+        .states
+        .keys()
+        .chain(compiled.signatures.values())
+        .copied()
+        .collect();
+    let name = |address: u32| match labels.get(&address) {
+        Some(labels) => labels[0].clone(),
+        None if address == bc::HALT_ADDRESS => "!".to_string(),
+        None => format!("@{address:#010x}"),
+    };
+
+    let mut out = String::new();
+    if version != bc::Version::V1 {
+        writeln!(out, ".version {}", version.number()).unwrap();
+    }
+    out.push_str(".symbols");
+    for symbol in &compiled.symbols {
+        write!(out, " {}", tape::literal(symbol)).unwrap();
+    }
+    out.push('\n');
+    if !compiled.tape.is_empty() {
+        out.push_str(".tape");
+        for &symbol in &compiled.tape {
+            write!(
+                out,
+                " {}",
+                tape::literal(&compiled.symbols[symbol as usize])
+            )
+            .unwrap();
+        }
+        out.push('\n');
+    }
+
+    let mut ip = version.code_start() as usize;
+    let mut after_peek = false;
+    while ip < bytes.len() {
+        let address = ip as u32;
+        if ip == entry {
+            out.push_str("\nentry\n");
+        } else if starts.contains(&address) {
+            out.push('\n');
+            if let Some(info) = compiled.states.get(&address) {
+                for line in &info.doc {
+                    writeln!(out, "///{line}").unwrap();
+                }
+            }
+            match labels.get(&address) {
+                Some(labels) => writeln!(out, "state {}", labels.join(" ")).unwrap(),
+                None => writeln!(out, "state {}", name(address)).unwrap(),
+            }
+        }
+
+        let opcode = bytes[ip];
+        let pattern = is_pattern(opcode);
+        if pattern && !after_peek && ip < entry {
+            out.push_str("    arm");
+            if let Some(range) = compiled.source_at(address) {
+                let span = &range.span;
+                write!(
+                    out,
+                    "  ; {}:{}: {}",
+                    span.path.display(),
+                    span.line + 1,
+                    span.as_span().source_line()
+                )
+                .unwrap();
+            }
+            out.push('\n');
+        }
+        after_peek = matches!(bc::narrow(opcode), bc::PEEK_ARG | bc::PEEK_VAL);
+
+        let indent = if ip < entry { 8 } else { 4 };
+        write!(
+            out,
+            "{:indent$}{}",
+            "",
+            bc::name(opcode).expect("invalid bytecode")
+        )
+        .unwrap();
+        ip += 1;
+        for &operand in bc::operands(opcode).unwrap() {
+            let size = operand.size(version);
+            let value = bytes[ip..ip + size]
+                .iter()
+                .rev()
+                .fold(0, |value, &byte| value << 8 | byte as u32);
+            match operand {
+                Operand::U8 | Operand::U16 | Operand::Arg => write!(out, " {value}").unwrap(),
+                Operand::Symbol => {
+                    write!(out, " {}", tape::literal(&compiled.symbols[value as usize])).unwrap()
+                }
+                Operand::Skip | Operand::Skip32 => {}
+                Operand::Address => write!(out, " {}", name(value)).unwrap(),
+            }
+            ip += size;
+        }
+        out.push('\n');
+    }
+    out
+}
+
+fn label(signature: &SignatureBuf) -> String {
+    if signature.states == 0 && signature.symbols == 0 {
+        signature.name.to_string()
+    } else {
+        format!(
+            "{}/{}/{}",
+            signature.name, signature.states, signature.symbols
+        )
+    }
+}
+
+fn is_pattern(opcode: u8) -> bool {
+    matches!(
+        bc::narrow(opcode),
+        bc::COMPARE_ARG | bc::COMPARE_VAL | bc::OTHER | bc::HALT | bc::PEEK_ARG | bc::PEEK_VAL
+    )
+}
+
+fn is_final(opcode: u8) -> bool {
+    matches!(opcode, bc::FINAL_STATE | bc::FINAL_ARG | bc::HALT)
+}
+
+pub fn read(path: &Path) -> Result<Compiled, Error> {
+    let Ok(text) = fs::read_to_string(path) else {
+        return Err(Error::new(
+            format!("couldn't read file {}", path.display()),
+            None,
+        ));
+    };
+    assemble(&text, path)
+}
+
+pub fn assemble(text: &str, path: &Path) -> Result<Compiled, Error> {
+    let mut assembler = Assembler {
+        version: bc::Version::V1,
+        bytes: bc::header(bc::Version::V1, 0),
+        symbols: None,
+        tape: None,
+        states: HashMap::new(),
+        signatures: HashMap::new(),
+        labels: HashMap::new(),
+        fixups: Vec::new(),
+        doc: Vec::new(),
+        section: Section::None,
+        arm: None,
+        last: None,
+        count: 0,
+        entry: None,
+    };
+
+    for (number, line) in text.lines().enumerate() {
+        let line = Line {
+            text: line,
+            number,
+            path,
+        };
+        if let Some(doc) = line.text.trim_start().strip_prefix("///") {
+            assembler.doc.push(doc.to_string());
+            continue;
+        }
+        let tokens = line.tokens()?;
+        if let Some((&first, operands)) = tokens.split_first() {
+            assembler.line(first, operands)?;
+        }
+    }
+    assembler.finish(path)
+}
+
+enum Section<'a> {
+    None,
+    // a state and its address
+    State(Span<'a>, u32),
+    Entry(Span<'a>),
+}
+
+struct Assembler<'a> {
+    version: bc::Version,
+    bytes: Vec<u8>,
+    symbols: Option<Vec<String>>,
+    tape: Option<Vec<u32>>,
+    states: HashMap<u32, StateInfo>,
+    signatures: HashMap<SignatureBuf, u32>,
+    labels: HashMap<&'a str, u32>,
+    // where each address operand goes, and the label it refers to
+    fixups: Vec<(usize, Span<'a>)>,
+    doc: Vec<String>,
+    section: Section<'a>,
+    // the current arm, and the offset of each of its skips with its instruction
+    arm: Option<(Span<'a>, Vec<(usize, Span<'a>)>)>,
+    // the last instruction in the current arm or entry
+    last: Option<(u8, Span<'a>)>,
+    count: usize,
+    entry: Option<u32>,
+}
+
+impl<'a> Assembler<'a> {
+    fn line(&mut self, first: Span<'a>, operands: &[Span<'a>]) -> Result<(), Error> {
+        match first.text {
+            ".version" => {
+                let started = self.symbols.is_some()
+                    || self.tape.is_some()
+                    || !matches!(self.section, Section::None);
+                if started || self.version != bc::Version::V1 {
+                    return Err(Error::new(
+                        "`.version` has to come first".to_string(),
+                        Some(first),
+                    ));
+                }
+                expect_operands(first, operands, 1)?;
+                self.version = match operands[0].text {
+                    "1" => bc::Version::V1,
+                    "2" => bc::Version::V2,
+                    _ => {
+                        return Err(Error::new(
+                            format!("expected version 1 or 2, found `{}`", operands[0].text),
+                            Some(operands[0]),
+                        ))
+                    }
+                };
+                self.bytes = bc::header(self.version, 0);
+            }
+            ".symbols" => {
+                if self.symbols.is_some() {
+                    return Err(Error::new(
+                        "the symbol table is already set".to_string(),
+                        Some(first),
+                    ));
+                }
+                let symbols = operands
+                    .iter()
+                    .map(|&operand| literal(operand))
+                    .collect::<Result<Vec<_>, _>>()?;
+                self.symbols = Some(validate(symbols, first, self.version)?);
+            }
+            ".tape" => {
+                if self.tape.is_some() {
+                    return Err(Error::new(
+                        "the tape is already set".to_string(),
+                        Some(first),
+                    ));
+                }
+                let tape = operands
+                    .iter()
+                    .map(|&operand| self.symbol(operand))
+                    .collect::<Result<_, _>>()?;
+                self.tape = Some(tape);
+            }
+            "state" => {
+                if let Section::Entry(span) = self.section {
+                    return Err(Error::new(
+                        "every state has to come before the entry".to_string(),
+                        Some(first),
+                    )
+                    .with_note(span, "the entry starts here".to_string()));
+                }
+                self.end_section()?;
+                if operands.is_empty() {
+                    return Err(Error::new("a state needs a label".to_string(), Some(first)));
+                }
+                let address = self.address()?;
+                let doc = std::mem::take(&mut self.doc);
+                for &operand in operands {
+                    self.define(operand, address)?;
+                }
+                if !operands[0].text.starts_with('@') {
+                    let (name, _) = parse_label(operands[0].text);
+                    let name = name.to_string();
+                    self.states.insert(address, StateInfo { name, doc });
+                }
+                self.count += 1;
+                self.section = Section::State(first, address);
+            }
+            "arm" => {
+                if !matches!(self.section, Section::State(..)) {
+                    return Err(Error::new(
+                        "an arm has to be inside a state".to_string(),
+                        Some(first),
+                    ));
+                }
+                self.end_arm()?;
+                expect_operands(first, operands, 0)?;
+                self.arm = Some((first, Vec::new()));
+            }
+            "entry" => {
+                if let Section::Entry(span) = self.section {
+                    return Err(
+                        Error::new("there's already an entry".to_string(), Some(first))
+                            .with_note(span, "the first entry starts here".to_string()),
+                    );
+                }
+                self.end_section()?;
+                expect_operands(first, operands, 0)?;
+                self.entry = Some(self.address()?);
+                self.section = Section::Entry(first);
+            }
+            _ => self.instruction(first, operands)?,
+        }
+        Ok(())
+    }
+
+    fn instruction(&mut self, name: Span<'a>, operands: &[Span<'a>]) -> Result<(), Error> {
+        let Some(opcode) = bc::opcode(name.text) else {
+            return Err(Error::new(
+                format!("unknown instruction `{}`", name.text),
+                Some(name),
+            ));
+        };
+        match self.section {
+            Section::None => {
+                return Err(Error::new(
+                    "an instruction has to be inside a state or the entry".to_string(),
+                    Some(name),
+                ))
+            }
+            Section::State(..) if self.arm.is_none() => {
+                return Err(Error::new(
+                    "an instruction in a state has to be inside an arm".to_string(),
+                    Some(name),
+                )
+                .with_help(Some("add `arm` before it".to_string())))
+            }
+            Section::Entry(_) if is_pattern(opcode) => {
+                return Err(Error::new(
+                    format!("`{}` can only be used in an arm", name.text),
+                    Some(name),
+                ))
+            }
+            _ => {}
+        }
+        if let Some((last, span)) = self.last {
+            if is_final(last) {
+                return Err(
+                    Error::new("this instruction is never run".to_string(), Some(name))
+                        .with_note(span, format!("`{}` always leaves the state", span.text)),
+                );
+            }
+        }
+
+        let expected = bc::operands(opcode).unwrap();
+        let written = expected
+            .iter()
+            .filter(|&&operand| !matches!(operand, Operand::Skip | Operand::Skip32));
+        expect_operands(name, operands, written.count())?;
+        self.bytes.push(opcode);
+        let mut operands = operands.iter();
+        for &operand in expected {
+            match operand {
+                Operand::Arg if self.version == bc::Version::V2 => {
+                    let span = *operands.next().unwrap();
+                    let Ok(value) = span.text.parse::<u16>() else {
+                        return Err(Error::new(
+                            format!("expected a number from 0 to 65535, found `{}`", span.text),
+                            Some(span),
+                        ));
+                    };
+                    self.bytes.extend(value.to_le_bytes());
+                }
+                Operand::U8 | Operand::Arg => {
+                    let span = *operands.next().unwrap();
+                    let Ok(value) = span.text.parse::<u8>() else {
+                        let help = (operand == Operand::Arg && span.text.parse::<u16>().is_ok())
+                            .then(|| "add `.version 2` to the top of the file".to_string());
+                        return Err(Error::new(
+                            format!("expected a number from 0 to 255, found `{}`", span.text),
+                            Some(span),
+                        )
+                        .with_help(help));
+                    };
+                    self.bytes.push(value);
+                }
+                Operand::U16 => {
+                    let span = *operands.next().unwrap();
+                    let Ok(value) = span.text.parse::<u16>() else {
+                        return Err(Error::new(
+                            format!("expected a number from 0 to 65535, found `{}`", span.text),
+                            Some(span),
+                        ));
+                    };
+                    self.bytes.extend(value.to_le_bytes());
+                }
+                Operand::Symbol => {
+                    let symbol = self.symbol(*operands.next().unwrap())?;
+                    match self.version {
+                        bc::Version::V1 => self.bytes.extend((symbol as u16).to_le_bytes()),
+                        bc::Version::V2 => self.bytes.extend(symbol.to_le_bytes()),
+                    }
+                }
+                Operand::Skip | Operand::Skip32 => {
+                    let skip = (self.bytes.len(), name);
+                    self.arm.as_mut().unwrap().1.push(skip);
+                    self.bytes.extend(vec![0; operand.size(self.version)]);
+                }
+                Operand::Address => {
+                    self.fixups
+                        .push((self.bytes.len(), *operands.next().unwrap()));
+                    self.bytes.extend([0; 4]);
+                }
+            }
+        }
+        self.last = Some((opcode, name));
+        Ok(())
+    }
+
+    fn symbol(&self, span: Span) -> Result<u32, Error> {
+        let Some(symbols) = &self.symbols else {
+            return Err(Error::new(
+                "a symbol can't be used before the symbol table".to_string(),
+                Some(span),
+            )
+            .with_help(Some("add `.symbols ''` to the top of the file".to_string())));
+        };
+        let symbol = literal(span)?;
+        match symbols.iter().position(|other| *other == symbol) {
+            Some(id) => Ok(id as u32),
+            None => Err(Error::new(
+                format!("symbol `{}` is not in the symbol table", span.text),
+                Some(span),
+            )),
+        }
+    }
+
+    fn address(&self) -> Result<u32, Error> {
+        u32::try_from(self.bytes.len())
+            .map_err(|_| Error::new("the bytecode is too long".to_string(), None))
+    }
+
+    fn define(&mut self, span: Span<'a>, address: u32) -> Result<(), Error> {
+        if span.text == "!" {
+            return Err(Error::new(
+                "`!` is the halt state's label".to_string(),
+                Some(span),
+            ));
+        }
+        if self.labels.insert(span.text, address).is_some() {
+            return Err(Error::new(
+                format!("label `{}` is defined more than once", span.text),
+                Some(span),
+            ));
+        }
+        if !span.text.starts_with('@') {
+            let (name, (states, symbols)) = parse_label(span.text);
+            let signature = SignatureBuf {
+                name: name.to_string(),
+                states,
+                symbols,
+            };
+            self.signatures.insert(signature, address);
+        }
+        Ok(())
+    }
+
+    fn end_section(&mut self) -> Result<(), Error> {
+        self.end_arm()?;
+        match self.section {
+            Section::State(span, address) if self.bytes.len() == address as usize => {
+                Err(Error::new("this state has no arms".to_string(), Some(span))
+                    .with_help(Some("give it an arm with just `HALT`".to_string())))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    // patches the skips of the current arm to jump past it
+    fn end_arm(&mut self) -> Result<(), Error> {
+        self.check_final()?;
+        if let Some((span, skips)) = self.arm.take() {
+            if self.last.is_none() {
+                return Err(Error::new("this arm is empty".to_string(), Some(span)));
+            }
+            for (location, name) in skips {
+                // only the patterns with 16-bit skips have a wide version
+                let narrow = bc::opcode(name.text).and_then(bc::wide).is_some();
+                let size = if narrow { 2 } else { 4 };
+                let skip = (self.bytes.len() - location - size) as u32;
+                if narrow {
+                    let Ok(skip) = u16::try_from(skip) else {
+                        return Err(Error::new("this arm is too long".to_string(), Some(span))
+                            .with_note(name, "its skip only has 16 bits".to_string())
+                            .with_help(Some(format!("use `{}32` instead", name.text))));
+                    };
+                    self.bytes[location..location + 2].copy_from_slice(&skip.to_le_bytes());
+                } else {
+                    self.bytes[location..location + 4].copy_from_slice(&skip.to_le_bytes());
+                }
+            }
+        }
+        self.last = None;
+        Ok(())
+    }
+
+    fn check_final(&self) -> Result<(), Error> {
+        let span = match (&self.section, self.arm.as_ref(), self.last) {
+            (_, _, Some((opcode, _))) if is_final(opcode) => return Ok(()),
+            (_, _, Some((_, span))) => span,
+            (_, Some(&(span, _)), None) => span,
+            (Section::Entry(span), _, None) => *span,
+            _ => return Ok(()),
+        };
+        Err(Error::new(
+            "the VM would run past the end of this code".to_string(),
+            Some(span),
+        )
+        .with_help(Some(
+            "end it with `FINAL_STATE`, `FINAL_ARG` or `HALT`".to_string(),
+        )))
+    }
+
+    fn finish(mut self, path: &Path) -> Result<Compiled, Error> {
+        self.end_section()?;
+        let Some(entry) = self.entry else {
+            return Err(Error::new(format!("{} has no entry", path.display()), None)
+                .with_help(Some(
+                "add `entry` after the last state, followed by the code that starts the machine"
+                    .to_string(),
+            )));
+        };
+        let max = self.version.max_states();
+        if self.count > max {
+            let help = (self.version == bc::Version::V1)
+                .then(|| "add `.version 2` to the top of the file".to_string());
+            return Err(Error::new(
+                format!("{} has more than {max} states", path.display()),
+                None,
+            )
+            .with_help(help));
+        }
+        bc::set_state_count(&mut self.bytes, self.count as u32);
+        self.bytes[2..6].copy_from_slice(&entry.to_le_bytes());
+
+        for (location, span) in self.fixups {
+            let address = match self.labels.get(span.text) {
+                Some(&address) => address,
+                None if span.text == "!" => bc::HALT_ADDRESS,
+                None => {
+                    return Err(Error::new(
+                        format!("label `{}` is not defined", span.text),
+                        Some(span),
+                    ))
+                }
+            };
+            self.bytes[location..location + 4].copy_from_slice(&address.to_le_bytes());
+        }
+
+        Ok(Compiled {
+            bytes: self.bytes,
+            symbols: self.symbols.unwrap_or_else(|| vec![String::new()]),
+            states: self.states,
+            signatures: self.signatures,
+            arms: Vec::new(),
+            source_map: Vec::new(),
+            tape: self.tape.unwrap_or_default(),
+        })
+    }
+}
+
+fn validate(symbols: Vec<String>, span: Span, version: bc::Version) -> Result<Vec<String>, Error> {
+    if symbols.first().is_none_or(|symbol| !symbol.is_empty()) {
+        return Err(Error::new(
+            "the symbol table has to start with the blank symbol `''`".to_string(),
+            Some(span),
+        ));
+    }
+    let max = version.max_symbols();
+    if symbols.len() > max {
+        let help = (version == bc::Version::V1)
+            .then(|| "add `.version 2` to the top of the file".to_string());
+        return Err(Error::new(
+            format!("the symbol table has more than {max} symbols"),
+            Some(span),
+        )
+        .with_help(help));
+    }
+    for (i, symbol) in symbols.iter().enumerate() {
+        if symbols[..i].contains(symbol) {
+            return Err(Error::new(
+                format!(
+                    "symbol `{}` is in the symbol table more than once",
+                    tape::literal(symbol)
+                ),
+                Some(span),
+            ));
+        }
+    }
+    Ok(symbols)
+}
+
+fn expect_operands(name: Span, operands: &[Span], expected: usize) -> Result<(), Error> {
+    if operands.len() == expected {
+        return Ok(());
+    }
+    let plural = if expected == 1 { "" } else { "s" };
+    let were = if operands.len() == 1 { "was" } else { "were" };
+    Err(Error::new(
+        format!(
+            "`{}` takes {expected} operand{plural}, but {} {were} given",
+            name.text,
+            operands.len()
+        ),
+        Some(operands.get(expected).copied().unwrap_or(name)),
+    ))
+}
+
+// `name` or `name/STATES/SYMBOLS`
+fn parse_label(label: &str) -> (&str, (u16, u16)) {
+    let mut parts = label.rsplitn(3, '/');
+    if let (Some(symbols), Some(states), Some(name)) = (parts.next(), parts.next(), parts.next()) {
+        if let (Ok(states), Ok(symbols)) = (states.parse(), symbols.parse()) {
+            return (name, (states, symbols));
+        }
+    }
+    (label, (0, 0))
+}
+
+fn literal(span: Span) -> Result<String, Error> {
+    let invalid = || {
+        Error::new(
+            format!("expected a symbol like `'a'`, found `{}`", span.text),
+            Some(span),
+        )
+    };
+    let inner = span
+        .text
+        .strip_prefix('\'')
+        .and_then(|text| text.strip_suffix('\''))
+        .ok_or_else(invalid)?;
+    let mut symbol = String::new();
+    let mut chars = inner.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => symbol.push(chars.next().ok_or_else(invalid)?),
+            '\'' => return Err(invalid()),
+            c => symbol.push(c),
+        }
+    }
+    Ok(symbol)
+}
+
+struct Line<'a> {
+    text: &'a str,
+    number: usize,
+    path: &'a Path,
+}
+
+impl<'a> Line<'a> {
+    // splits the line on whitespace, keeping quoted symbols together and
+    // dropping comments
+    fn tokens(&self) -> Result<Vec<Span<'a>>, Error> {
+        let mut tokens = Vec::new();
+        let mut chars = self.text.char_indices().peekable();
+        while let Some(&(start, c)) = chars.peek() {
+            if c.is_whitespace() {
+                chars.next();
+                continue;
+            }
+            if c == ';' {
+                break;
+            }
+            let mut quoted = false;
+            let mut end = self.text.len();
+            while let Some(&(i, c)) = chars.peek() {
+                if !quoted && (c.is_whitespace() || c == ';') {
+                    end = i;
+                    break;
+                }
+                chars.next();
+                match c {
+                    '\'' => quoted = !quoted,
+                    '\\' if quoted => {
+                        chars.next();
+                    }
+                    _ => {}
+                }
+            }
+            let span = self.span(start, end);
+            if quoted {
+                return Err(Error::new(
+                    "this symbol is missing its closing `'`".to_string(),
+                    Some(span),
+                ));
+            }
+            tokens.push(span);
+        }
+        Ok(tokens)
+    }
+
+    fn span(&self, start: usize, end: usize) -> Span<'a> {
+        Span {
+            text: &self.text[start..end],
+            prefix: &self.text[..start],
+            suffix: &self.text[end..],
+            line: self.number,
+            column: self.text[..start].chars().count(),
+            path: self.path,
+        }
+    }
+}
@@ -0,0 +1,750 @@
+use std::cmp;
+use std::ops::ControlFlow;
+use std::path::PathBuf;
+use std::process;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use clap::ValueEnum;
+
+use crate::error::Error;
+#[cfg(feature = "c-vm")]
+use crate::ffi;
+use crate::mmap::MmapTape;
+use crate::rle::RleTape;
+use crate::table::Table;
+use crate::transposition::CacheSize;
+use crate::vm::{self, DenseTape};
+
+pub struct Simulated {
+    pub tape: Vec<u32>,
+    // `head_position` and `rightmost_head_position` are indices into `tape`
+    pub head_position: usize,
+    pub rightmost_head_position: usize,
+    // the index in `tape` of the initial tape's first cell, which is only ever
+    // more than 0 on a two-sided tape
+    pub origin: usize,
+    // where the head would have gone without the left edge (so it can be
+    // negative), counting from `origin`
+    pub leftmost_index: i64,
+    // the number of left moves that ended at (or were stopped by) the left edge
+    pub left_edge_contacts: usize,
+    pub final_address: u32,
+    // the final state and the states and symbols it was passed, written the way
+    // `Vm::write_state` writes them
+    pub final_state: Vec<u32>,
+    pub moves: usize,
+    pub halt_reason: HaltReason,
+    // the length of the tape's buffer before trimming, which it never shrinks from
+    pub peak_tape_len: usize,
+    // the length of every buffer the tape has had, added up (so it depends on
+    // how the backend grows the tape)
+    pub cells_allocated: usize,
+}
+
+/// Why a run stopped
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HaltReason {
+    /// The machine halted: no arm matched, or it went to `!`
+    Halted,
+    /// The run hit `--max-moves` before the machine halted
+    MaxMoves,
+    /// The head moved left off cell 0 of a one-way tape
+    LeftEdge,
+    /// The head moved past `--max-tape-cells` cells
+    TapeLimit,
+    /// The user pressed Ctrl-C (see `catch_interrupts`)
+    Interrupted,
+    /// `cycle::observe` found the configuration after `repeat` moves to be the
+    /// same as the one after `first` moves, moved `shift` cells to the right (or
+    /// left if it's negative), so the machine never halts
+    Cycle {
+        first: usize,
+        repeat: usize,
+        shift: i64,
+    },
+}
+
+impl HaltReason {
+    /// The process exit code for a run that stopped this way (1, 2 and 5 are
+    /// for errors)
+    pub fn exit_code(self) -> u8 {
+        match self {
+            HaltReason::Halted => 0,
+            HaltReason::MaxMoves => 3,
+            HaltReason::LeftEdge => 4,
+            HaltReason::TapeLimit => 6,
+            HaltReason::Cycle { .. } => 7,
+            // what a shell reports for a process killed by SIGINT
+            HaltReason::Interrupted => 130,
+        }
+    }
+}
+
+impl std::fmt::Display for HaltReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            HaltReason::Halted => f.write_str("halted"),
+            HaltReason::MaxMoves => f.write_str("reached the move limit"),
+            HaltReason::LeftEdge => f.write_str("fell off the left edge"),
+            HaltReason::TapeLimit => f.write_str("reached the tape cell limit"),
+            HaltReason::Interrupted => f.write_str("interrupted"),
+            HaltReason::Cycle {
+                first,
+                repeat,
+                shift: 0,
+            } => write!(
+                f,
+                "provably non-halting: configuration repeated at move {repeat} \
+                 (first seen at move {first})"
+            ),
+            HaltReason::Cycle {
+                first,
+                repeat,
+                shift,
+            } => write!(
+                f,
+                "provably non-halting: configuration repeated {} cell{} to the {} at move \
+                 {repeat} (first seen at move {first})",
+                shift.unsigned_abs(),
+                if shift.unsigned_abs() == 1 { "" } else { "s" },
+                if *shift > 0 { "right" } else { "left" },
+            ),
+        }
+    }
+}
+
+impl Simulated {
+    /// Where the head ended up, counting from the initial tape's first cell (so
+    /// it's negative if it ended up left of it on a two-sided tape)
+    pub fn head(&self) -> i64 {
+        self.head_position as i64 - self.origin as i64
+    }
+}
+
+pub struct Options {
+    pub max_moves: usize,
+    pub trim: Trim,
+    // run machines without parameters on a transition table (see `table::Table`)
+    pub table: bool,
+    // grow the tape to the left instead of stopping when the head moves off cell 0
+    pub two_sided: bool,
+    // stop once the head has spanned more cells than this (see `vm::DenseTape::span`)
+    pub max_tape_cells: usize,
+    // run the transition table this many cells at a time, if there is one (see
+    // `Table::run_blocks`)
+    pub block_size: Option<usize>,
+    // how the Rust VM and the transition table keep the tape (see `vm::Tape`)
+    pub tape_repr: TapeRepr,
+    // where a dense tape keeps its cells
+    pub tape_backing: TapeBacking,
+    // give the Rust VM a transposition cache this big, if there's no tape cell
+    // limit (see `transposition::Transpositions`)
+    pub transpositions: Option<CacheSize>,
+    // how the C VM dispatches right hand sides, or `None` for its default (see
+    // `set_dispatch` in vm.c)
+    pub dispatch: Option<Dispatch>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum Trim {
+    // remove blanks after the last non-blank symbol
+    TrailingBlanks,
+    // keep every cell that was on the initial tape or visited by the head
+    None,
+    // remove blanks after the last non-blank symbol or the head, whichever is further right
+    Window,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum TapeRepr {
+    // every cell in a `Vec` (see `vm::DenseTape`)
+    #[default]
+    Dense,
+    // runs of the same symbol (see `rle::RleTape`)
+    Rle,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum Dispatch {
+    // a computed goto on each opcode, the default if the C compiler has them
+    Goto,
+    // a switch on each opcode
+    Switch,
+    // a computed goto on each instruction's label, looked up before it runs
+    Threaded,
+}
+
+/// Where a dense tape keeps its cells
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub enum TapeBacking {
+    /// In a `Vec` (see `vm::DenseTape`)
+    #[default]
+    Memory,
+    /// In a file mapped into memory (see `mmap::MmapTape`)
+    Mmap(PathBuf),
+}
+
+impl FromStr for TapeBacking {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once(':') {
+            _ if s == "memory" => Ok(TapeBacking::Memory),
+            Some(("mmap", path)) if !path.is_empty() => Ok(TapeBacking::Mmap(path.into())),
+            _ => Err("expected `memory` or `mmap:PATH`".to_string()),
+        }
+    }
+}
+
+/// Where a run has got to, for a `Hook`
+#[derive(Clone, Copy, Debug)]
+pub struct Checkpoint {
+    pub moves: usize,
+    /// Counts from the initial tape's first cell, like `Simulated::head`
+    pub head: i64,
+    /// The address of the state the machine is in
+    pub address: u32,
+}
+
+/// Gets called every `every` moves (which can't be 0) of a run, before the next
+/// move, on either VM. An `Observer` sees every move, but only on the Rust VM.
+/// Returning `ControlFlow::Break` stops the run with `HaltReason::Interrupted`
+pub struct Hook<'a> {
+    pub every: usize,
+    pub callback: &'a mut dyn FnMut(Checkpoint) -> ControlFlow<()>,
+}
+
+pub trait Backend {
+    /// Runs the bytecode and returns the untouched final configuration. Only
+    /// `max_moves`, `two_sided` and `max_tape_cells` matter here, and
+    /// `tape_repr`, `tape_backing` and `transpositions` on the Rust VM
+    fn execute(&self, bytes: &[u8], tape: &[u32], options: &Options) -> Result<Simulated, Error>;
+
+    /// Like `execute`, but calls `hook` as it goes
+    fn execute_hooked(
+        &self,
+        bytes: &[u8],
+        tape: &[u32],
+        options: &Options,
+        hook: Hook,
+    ) -> Result<Simulated, Error>;
+
+    /// Like `run`, but never on the transition table, which doesn't keep track
+    /// of the states' addresses
+    fn run_hooked(
+        &self,
+        bytes: &[u8],
+        tape: &[u32],
+        options: &Options,
+        hook: Hook,
+    ) -> Result<Simulated, Error> {
+        let mut simulated = self.execute_hooked(bytes, tape, options, hook)?;
+        trim(&mut simulated, tape.len(), options.trim);
+        Ok(simulated)
+    }
+
+    fn run(&self, bytes: &[u8], tape: &[u32], options: &Options) -> Result<Simulated, Error> {
+        let table = options.table.then(|| Table::build(bytes, tape)).flatten();
+        let mut simulated = match (table, options.block_size, options.tape_repr) {
+            (Some(table), Some(block), _) => table.run_blocks(tape, options, block),
+            (Some(table), None, TapeRepr::Dense) => match &options.tape_backing {
+                TapeBacking::Memory => {
+                    let mut tape = DenseTape::new(tape.to_vec(), options.two_sided);
+                    tape.max_cells = options.max_tape_cells;
+                    table.run(tape, options)
+                }
+                TapeBacking::Mmap(path) => table.run(
+                    MmapTape::new(path, tape, options.two_sided, options.max_tape_cells)?,
+                    options,
+                ),
+            },
+            (Some(table), None, TapeRepr::Rle) => table.run(
+                RleTape::new(tape, options.two_sided, options.max_tape_cells),
+                options,
+            ),
+            (None, ..) => self.execute(bytes, tape, options)?,
+        };
+        trim(&mut simulated, tape.len(), options.trim);
+        Ok(simulated)
+    }
+}
+
+/// Watches a simulation one move at a time (only the Rust VM supports this)
+pub trait Observer {
+    // positions count from the initial tape's first cell, like `Simulated::head`
+    fn write(&mut self, _position: i64, _symbol: u32) {}
+    fn state(&mut self, _address: u32) {}
+    fn moved(&mut self, _moves: usize, _head: i64) {}
+    // `offset` is where the taken arm's right hand side starts (see `compile::ArmInfo`)
+    fn arm(&mut self, _offset: u32) {}
+    // called before each instruction runs
+    fn instruction(&mut self, _opcode: u8) {}
+    // called when the VM makes a state to pass to another one, with how many of
+    // them are alive and whether it reused one that was freed
+    fn allocated(&mut self, _live: usize, _reused: bool) {}
+    // called when the VM looks a move up in its transposition cache (see
+    // `transposition::Transpositions`), with whether it was there
+    fn cached(&mut self, _hit: bool) {}
+}
+
+impl Observer for () {}
+
+impl<O: Observer> Observer for &mut O {
+    fn write(&mut self, position: i64, symbol: u32) {
+        (**self).write(position, symbol);
+    }
+
+    fn state(&mut self, address: u32) {
+        (**self).state(address);
+    }
+
+    fn moved(&mut self, moves: usize, head: i64) {
+        (**self).moved(moves, head);
+    }
+
+    fn arm(&mut self, offset: u32) {
+        (**self).arm(offset);
+    }
+
+    fn instruction(&mut self, opcode: u8) {
+        (**self).instruction(opcode);
+    }
+
+    fn allocated(&mut self, live: usize, reused: bool) {
+        (**self).allocated(live, reused);
+    }
+
+    fn cached(&mut self, hit: bool) {
+        (**self).cached(hit);
+    }
+}
+
+// lets a run be watched by more than one observer at once
+impl<A: Observer, B: Observer> Observer for (A, B) {
+    fn write(&mut self, position: i64, symbol: u32) {
+        self.0.write(position, symbol);
+        self.1.write(position, symbol);
+    }
+
+    fn state(&mut self, address: u32) {
+        self.0.state(address);
+        self.1.state(address);
+    }
+
+    fn moved(&mut self, moves: usize, head: i64) {
+        self.0.moved(moves, head);
+        self.1.moved(moves, head);
+    }
+
+    fn arm(&mut self, offset: u32) {
+        self.0.arm(offset);
+        self.1.arm(offset);
+    }
+
+    fn instruction(&mut self, opcode: u8) {
+        self.0.instruction(opcode);
+        self.1.instruction(opcode);
+    }
+
+    fn allocated(&mut self, live: usize, reused: bool) {
+        self.0.allocated(live, reused);
+        self.1.allocated(live, reused);
+    }
+
+    fn cached(&mut self, hit: bool) {
+        self.0.cached(hit);
+        self.1.cached(hit);
+    }
+}
+
+impl<O: Observer> Observer for Option<O> {
+    fn write(&mut self, position: i64, symbol: u32) {
+        if let Some(observer) = self {
+            observer.write(position, symbol);
+        }
+    }
+
+    fn state(&mut self, address: u32) {
+        if let Some(observer) = self {
+            observer.state(address);
+        }
+    }
+
+    fn moved(&mut self, moves: usize, head: i64) {
+        if let Some(observer) = self {
+            observer.moved(moves, head);
+        }
+    }
+
+    fn arm(&mut self, offset: u32) {
+        if let Some(observer) = self {
+            observer.arm(offset);
+        }
+    }
+
+    fn instruction(&mut self, opcode: u8) {
+        if let Some(observer) = self {
+            observer.instruction(opcode);
+        }
+    }
+
+    fn allocated(&mut self, live: usize, reused: bool) {
+        if let Some(observer) = self {
+            observer.allocated(live, reused);
+        }
+    }
+
+    fn cached(&mut self, hit: bool) {
+        if let Some(observer) = self {
+            observer.cached(hit);
+        }
+    }
+}
+
+// backends grow the tape however they like, so cut it down (or pad it) to a
+// length that only depends on what the machine did
+pub fn trim(simulated: &mut Simulated, initial_len: usize, trim: Trim) {
+    // cells left of the origin are trimmed the same way as the ones on the right
+    let origin = simulated.origin;
+    let first_non_blank = simulated
+        .tape
+        .iter()
+        .position(|&symbol| symbol != 0)
+        .unwrap_or(origin);
+    let start = match trim {
+        Trim::TrailingBlanks => cmp::min(first_non_blank, origin),
+        Trim::None => cmp::min(
+            origin,
+            (origin as i64 + simulated.leftmost_index).max(0) as usize,
+        ),
+        Trim::Window => cmp::min(first_non_blank, origin),
+    };
+    // positions are indices into the tape, so the head's cell always stays
+    let start = cmp::min(start, simulated.head_position);
+    simulated.tape.drain(..start);
+    simulated.origin -= start;
+    simulated.head_position -= start;
+    simulated.rightmost_head_position -= start;
+
+    let non_blank = simulated
+        .tape
+        .iter()
+        .rposition(|&symbol| symbol != 0)
+        .map_or(0, |i| i + 1);
+    let len = match trim {
+        Trim::TrailingBlanks => non_blank,
+        Trim::None => cmp::max(
+            cmp::max(non_blank, simulated.origin + initial_len),
+            simulated.rightmost_head_position + 1,
+        ),
+        Trim::Window => cmp::max(non_blank, simulated.head_position + 1),
+    };
+    simulated.tape.resize(len, 0);
+}
+
+pub fn observe(
+    bytes: &[u8],
+    tape: &[u32],
+    options: &Options,
+    observer: impl Observer,
+) -> Result<Simulated, Error> {
+    let mut simulated = vm::RustVm.observe(bytes, tape, options, observer)?;
+    trim(&mut simulated, tape.len(), options.trim);
+    Ok(simulated)
+}
+
+/// The Rust VM if `rust_vm` is set, or the C VM otherwise
+#[cfg(feature = "c-vm")]
+pub fn select(rust_vm: bool) -> &'static dyn Backend {
+    if rust_vm {
+        &vm::RustVm
+    } else {
+        &ffi::CVm
+    }
+}
+
+/// The Rust VM, since tml was built without the C VM
+#[cfg(not(feature = "c-vm"))]
+pub fn select(_rust_vm: bool) -> &'static dyn Backend {
+    &vm::RustVm
+}
+
+// moves between checks for an interrupt, so the VMs don't pay for an atomic load
+// on every move. the C VM has its own copy
+pub(crate) const INTERRUPT_CHECK_INTERVAL: usize = 1 << 16;
+
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+static CATCHING: AtomicBool = AtomicBool::new(false);
+
+/// Makes Ctrl-C stop the run in progress with `HaltReason::Interrupted`, so it
+/// still returns the tape it got to. Pressing it again exits right away. Calling
+/// it again forgets an earlier Ctrl-C, so the next run isn't stopped before it
+/// starts
+pub fn catch_interrupts() -> Result<(), Error> {
+    INTERRUPTED.store(false, Ordering::Relaxed);
+    #[cfg(feature = "c-vm")]
+    ffi::clear_interrupt();
+    if CATCHING.swap(true, Ordering::Relaxed) {
+        return Ok(());
+    }
+    ctrlc::set_handler(|| {
+        if INTERRUPTED.swap(true, Ordering::Relaxed) {
+            process::exit(HaltReason::Interrupted.exit_code().into());
+        }
+        #[cfg(feature = "c-vm")]
+        ffi::interrupt();
+    })
+    .map_err(|error| Error::new(format!("couldn't handle Ctrl-C: {error}"), None))
+}
+
+/// Whether Ctrl-C has been pressed since `catch_interrupts`
+pub fn interrupted() -> bool {
+    INTERRUPTED.load(Ordering::Relaxed)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::Path;
+
+    use super::*;
+    use crate::compile::{self, Compiled};
+    use crate::lex::{self, Edition, Tokens};
+    use crate::parse::{self, StartArgs};
+
+    const LEX_OPTIONS: lex::Options = lex::Options {
+        allow_tabs: false,
+        bare_digits: true,
+        edition: Edition::E2023,
+    };
+
+    fn compile(code: &str, tape: &str) -> Compiled {
+        let path = Path::new("test.tml");
+        let unit = parse::parse(Tokens::new(code, path, LEX_OPTIONS).unwrap()).unwrap();
+        let tape = match tape {
+            "" => Vec::new(),
+            tape => parse::parse_tape(Tokens::new(tape, path, LEX_OPTIONS).unwrap()).unwrap(),
+        };
+        compile::compile(unit, tape, StartArgs::default()).unwrap()
+    }
+
+    fn options(max_moves: usize) -> Options {
+        Options {
+            max_moves,
+            trim: Trim::TrailingBlanks,
+            table: false,
+            two_sided: false,
+            max_tape_cells: usize::MAX,
+            block_size: None,
+            tape_repr: TapeRepr::Dense,
+            tape_backing: TapeBacking::Memory,
+            transpositions: None,
+            dispatch: None,
+        }
+    }
+
+    // the parts of a run every backend has to agree on
+    fn outcome(simulated: Simulated) -> (Vec<u32>, i64, usize, HaltReason) {
+        let head = simulated.head();
+        (simulated.tape, head, simulated.moves, simulated.halt_reason)
+    }
+
+    // runs `compiled` on every backend and checks they all end up the same way
+    // as the Rust VM, on a one-sided tape and a two-sided one. returns how the
+    // Rust VM ends up on a one-sided tape
+    fn run_everywhere(compiled: &Compiled, max_moves: usize) -> (Vec<u32>, i64, usize, HaltReason) {
+        let run = |backend: &dyn Backend, options: Options| {
+            outcome(
+                backend
+                    .run(&compiled.bytes, &compiled.tape, &options)
+                    .unwrap(),
+            )
+        };
+
+        let mut one_sided = None;
+        for two_sided in [false, true] {
+            let base = || Options {
+                two_sided,
+                ..options(max_moves)
+            };
+            let expected = run(&vm::RustVm, base());
+
+            let variants = [
+                (
+                    "table",
+                    Options {
+                        table: true,
+                        ..base()
+                    },
+                ),
+                (
+                    "rle",
+                    Options {
+                        tape_repr: TapeRepr::Rle,
+                        ..base()
+                    },
+                ),
+                (
+                    "table, rle",
+                    Options {
+                        table: true,
+                        tape_repr: TapeRepr::Rle,
+                        ..base()
+                    },
+                ),
+                (
+                    "table, blocks",
+                    Options {
+                        table: true,
+                        block_size: Some(3),
+                        ..base()
+                    },
+                ),
+                (
+                    "transpositions",
+                    Options {
+                        transpositions: Some(CacheSize {
+                            window: 2,
+                            capacity: 64,
+                        }),
+                        ..base()
+                    },
+                ),
+            ];
+            for (name, options) in variants {
+                assert_eq!(
+                    run(&vm::RustVm, options),
+                    expected,
+                    "rust vm, {name}, two-sided: {two_sided}"
+                );
+            }
+
+            #[cfg(feature = "c-vm")]
+            for dispatch in [
+                None,
+                Some(Dispatch::Switch),
+                Some(Dispatch::Goto),
+                Some(Dispatch::Threaded),
+            ] {
+                let options = Options { dispatch, ..base() };
+                assert_eq!(
+                    run(&ffi::CVm, options),
+                    expected,
+                    "c vm, {dispatch:?}, two-sided: {two_sided}"
+                );
+            }
+
+            one_sided.get_or_insert(expected);
+        }
+        one_sided.unwrap()
+    }
+
+    #[test]
+    fn examples_agree() {
+        let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("examples");
+        let mut paths: Vec<_> = fs::read_dir(dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .filter(|path| path.extension().is_some_and(|ext| ext == "tml"))
+            .collect();
+        paths.sort();
+        assert!(!paths.is_empty());
+
+        for path in paths {
+            let code = fs::read_to_string(&path).unwrap();
+            let compiled = compile(&code, "");
+            for max_moves in [0, 1, 7, 1000, 20_000] {
+                run_everywhere(&compiled, max_moves);
+            }
+        }
+    }
+
+    // the final tape as symbols, with the head, moves and halt reason
+    fn golden(code: &str, tape: &str) -> (Vec<String>, i64, usize, HaltReason) {
+        let compiled = compile(code, tape);
+        let (tape, head, moves, halt_reason) = run_everywhere(&compiled, 1000);
+        let tape = tape
+            .iter()
+            .map(|&i| compiled.symbols[i as usize].clone())
+            .collect();
+        (tape, head, moves, halt_reason)
+    }
+
+    fn symbols(symbols: &[&str]) -> Vec<String> {
+        symbols.iter().map(|symbol| symbol.to_string()).collect()
+    }
+
+    #[test]
+    fn halts() {
+        let code = "start { '' | '1' > '0' > '1' | !, }";
+        assert_eq!(
+            golden(code, ""),
+            (symbols(&["1", "0", "1"]), 2, 1, HaltReason::Halted)
+        );
+    }
+
+    #[test]
+    fn falls_off_the_left_edge() {
+        let code = "start { _ | > | back, }\nback { _ | < | back, }";
+        assert_eq!(golden(code, ""), (vec![], 0, 2, HaltReason::LeftEdge));
+    }
+
+    #[test]
+    fn passes_states_and_symbols() {
+        let code = "\
+start { _ | | copy(!; '1'), }
+copy(k; x) {
+    '0' | x > | copy(k; x),
+    '1' | '0' > | copy(copy(k; '0'); x),
+    '' | < | k,
+}";
+        assert_eq!(
+            golden(code, "0 1 0 0 1 0"),
+            (
+                symbols(&["1", "0", "1", "1", "0", "0"]),
+                5,
+                14,
+                HaltReason::Halted
+            )
+        );
+    }
+
+    // the arguments waiting to be passed at each level add up to more states
+    // than the C VM's stack used to have room for
+    #[test]
+    fn nests_state_arguments_deeply() {
+        let params: Vec<_> = (0..255).map(|i| format!("A{i}")).collect();
+        let args = vec!["!"; 254].join(", ");
+        let mut expr = "!".to_string();
+        for _ in 0..270 {
+            expr = format!("g({args}, {expr})");
+        }
+        let code = format!(
+            "start {{ _ | | {expr}, }}\ng({}) {{ _ | '1' > | A254, }}",
+            params.join(", ")
+        );
+
+        // the parser recurses once per level, which is too deep for a test
+        // thread's stack in a debug build
+        let (tape, head, moves, halt_reason) = std::thread::Builder::new()
+            .stack_size(16 << 20)
+            .spawn(move || golden(&code, ""))
+            .unwrap()
+            .join()
+            .unwrap();
+        assert_eq!(tape.len(), 270);
+        assert_eq!((head, moves, halt_reason), (270, 271, HaltReason::Halted));
+    }
+
+    #[test]
+    fn stops_at_the_move_limit() {
+        let code = "start { _ | '1' > | start, }";
+        let (tape, head, moves, halt_reason) = golden(code, "");
+        assert_eq!(tape.len(), 1000);
+        assert_eq!(
+            (head, moves, halt_reason),
+            (1000, 1000, HaltReason::MaxMoves)
+        );
+    }
+}
@@ -0,0 +1,196 @@
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use clap::ValueEnum;
+use rayon::prelude::*;
+use serde_json::json;
+use termion::{color, style};
+
+use crate::backend::{self, Simulated};
+use crate::compile::Compiled;
+use crate::error::Error;
+use crate::lex::{self, Tokens};
+use crate::output;
+use crate::parse;
+use crate::tape;
+use crate::tmb;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum Format {
+    // one aligned row per tape
+    #[default]
+    Table,
+    // one JSON object per tape
+    Jsonl,
+}
+
+/// How to read the final tape as a decimal (see `tape::parse_decimal`)
+pub struct DecimalOptions {
+    pub radix: usize,
+    pub digits: Option<usize>,
+    pub start: usize,
+    pub stride: usize,
+}
+
+pub struct Options {
+    /// Threads to run tapes on, or `None` for one per core
+    pub jobs: Option<usize>,
+    pub format: Format,
+    pub rust_vm: bool,
+    /// `None` leaves the decimal out
+    pub decimal: Option<DecimalOptions>,
+    pub backend: backend::Options,
+}
+
+// the widest halt reason that isn't a cycle, so the decimals line up
+const HALT_REASON_WIDTH: usize = 27;
+const MOVES_WIDTH: usize = 12;
+
+/// Runs a compiled machine on every tape, a tape per thread at a time, printing
+/// each result as soon as its run finishes (so not in the order of `tapes`).
+/// The machine's tape is ignored. Tapes that can't be read, or that have
+/// symbols the machine never uses, get an error instead of a result, and the
+/// batch fails once every other tape has run
+pub fn run(
+    compiled: &Compiled,
+    tapes: &[PathBuf],
+    lex_options: lex::Options,
+    options: &Options,
+) -> Result<(), Error> {
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(options.jobs.unwrap_or(0))
+        .build()
+        .map_err(|err| Error::new(format!("couldn't start the thread pool: {err}"), None))?;
+
+    let width = tapes
+        .iter()
+        .map(|path| path.display().to_string().len())
+        .max()
+        .unwrap_or(0)
+        .max("tape".len());
+    if options.format == Format::Table {
+        let header = format!(
+            "{:width$}  {:>MOVES_WIDTH$}  {:HALT_REASON_WIDTH$}{}",
+            "tape",
+            "moves",
+            "halt reason",
+            if options.decimal.is_some() {
+                "  decimal"
+            } else {
+                ""
+            },
+        );
+        if !output::color() {
+            println!("{}", header.trim_end());
+        } else {
+            println!("{}{}{}", style::Bold, header.trim_end(), style::Reset);
+        }
+    }
+
+    let failed = AtomicUsize::new(0);
+    pool.install(|| {
+        tapes.par_iter().for_each(|path| {
+            // after Ctrl-C, the runs that already started stop as interrupted
+            // and the rest don't start
+            if backend::interrupted() {
+                return;
+            }
+            let result = simulate(compiled, path, lex_options, options);
+            if result.is_err() {
+                failed.fetch_add(1, Ordering::Relaxed);
+            }
+
+            // hold stdout so a row and its error don't interleave with others
+            let _stdout = io::stdout().lock();
+            match (options.format, result) {
+                (Format::Table, Ok(simulated)) => {
+                    let decimal = options
+                        .decimal
+                        .as_ref()
+                        .map(|decimal| format!("  {}", read_decimal(compiled, &simulated, decimal)))
+                        .unwrap_or_default();
+                    let row = format!(
+                        "{:width$}  {:>MOVES_WIDTH$}  {:HALT_REASON_WIDTH$}{decimal}",
+                        path.display(),
+                        simulated.moves,
+                        simulated.halt_reason.to_string(),
+                    );
+                    println!("{}", row.trim_end());
+                }
+                (Format::Table, Err(error)) => {
+                    let row = format!("{:width$}  {:>MOVES_WIDTH$}  ", path.display(), "-");
+                    if !output::color() {
+                        println!("{row}error");
+                    } else {
+                        println!(
+                            "{row}{}{}error{}{}",
+                            style::Bold,
+                            color::Fg(color::Red),
+                            style::Reset,
+                            color::Fg(color::Reset)
+                        );
+                    }
+                    error.print();
+                }
+                (Format::Jsonl, Ok(simulated)) => {
+                    let decimal = options
+                        .decimal
+                        .as_ref()
+                        .map(|decimal| read_decimal(compiled, &simulated, decimal));
+                    println!(
+                        "{}",
+                        json!({
+                            "tape": path.display().to_string(),
+                            "ok": true,
+                            "halt_reason": simulated.halt_reason.to_string(),
+                            "moves": simulated.moves,
+                            "head": simulated.head(),
+                            "decimal": decimal,
+                        })
+                    );
+                }
+                (Format::Jsonl, Err(error)) => println!(
+                    "{}",
+                    json!({
+                        "tape": path.display().to_string(),
+                        "ok": false,
+                        "diagnostics": error.to_json(),
+                    })
+                ),
+            }
+        });
+    });
+
+    match failed.into_inner() {
+        0 => Ok(()),
+        1 => Err(Error::new("1 tape failed".to_string(), None)),
+        failed => Err(Error::new(format!("{failed} tapes failed"), None)),
+    }
+}
+
+fn simulate(
+    compiled: &Compiled,
+    path: &Path,
+    lex_options: lex::Options,
+    options: &Options,
+) -> Result<Simulated, Error> {
+    let tokens = Tokens::from_path_buf(path.to_path_buf(), lex_options)?;
+    let tape = tmb::map_tape(compiled, &parse::parse_tape(tokens)?)?;
+    backend::select(options.rust_vm).run(&compiled.bytes, &tape, &options.backend)
+}
+
+pub(crate) fn read_decimal(compiled: &Compiled, simulated: &Simulated, decimal: &DecimalOptions) -> String {
+    let symbols: Vec<_> = simulated.tape[simulated.origin..]
+        .iter()
+        .map(|&symbol| compiled.symbols[symbol as usize].as_str())
+        .collect();
+    tape::parse_decimal(
+        &symbols,
+        decimal.radix,
+        decimal.digits,
+        decimal.start,
+        decimal.stride,
+    )
+    .to_string()
+}
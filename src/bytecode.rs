@@ -1,4 +1,11 @@
+use std::collections::HashMap;
+
 use termion::{color, style};
+use unicode_segmentation::UnicodeSegmentation;
+
+use crate::compile::{Compiled, SourceRange, StateInfo};
+use crate::output;
+use crate::tape;
 
 pub const LEFT: u8 = 0;
 pub const RIGHT: u8 = 1;
@@ -22,28 +29,392 @@ pub const COMPARE_ARG: u8 = 16;
 pub const COMPARE_VAL: u8 = 17;
 pub const OTHER: u8 = 18;
 pub const HALT: u8 = 19;
+pub const PEEK_ARG: u8 = 20;
+pub const PEEK_VAL: u8 = 21;
+
+// wider versions for moves over 255 cells and arms over 65535 bytes
+pub const LEFT_N16: u8 = 22;
+pub const RIGHT_N16: u8 = 23;
+pub const COMPARE_ARG32: u8 = 24;
+pub const COMPARE_VAL32: u8 = 25;
+pub const PEEK_ARG32: u8 = 26;
+pub const PEEK_VAL32: u8 = 27;
+
+// a write followed by a move of one cell
+pub const WRITE_ARG_LEFT: u8 = 28;
+pub const WRITE_ARG_RIGHT: u8 = 29;
+pub const WRITE_VAL_LEFT: u8 = 30;
+pub const WRITE_VAL_RIGHT: u8 = 31;
+pub const WRITE_BOUND_LEFT: u8 = 32;
+pub const WRITE_BOUND_RIGHT: u8 = 33;
 
 pub const HALT_ADDRESS: u32 = 6;
 
-pub fn dump(bytes: &mut dyn Iterator<Item = u8>, no_color: bool) {
+// written where a version 1 header has its state count
+pub const V2_FLAG: u16 = u16::MAX;
+
+/// Version 1 bytecode has a 16-bit state count, 16-bit symbol ids and 8-bit argument
+/// indices. Version 2 is for machines that don't fit: its state count comes after
+/// the halt (with `V2_FLAG` where version 1 has the count), symbol ids have 32 bits
+/// and argument indices have 16
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Version {
+    V1,
+    V2,
+}
+
+impl Version {
+    pub fn of(bytes: &[u8]) -> Version {
+        if u16::from_le_bytes([bytes[0], bytes[1]]) == V2_FLAG {
+            Version::V2
+        } else {
+            Version::V1
+        }
+    }
+
+    pub fn number(self) -> u32 {
+        match self {
+            Version::V1 => 1,
+            Version::V2 => 2,
+        }
+    }
+
+    /// Where the first state starts
+    pub fn code_start(self) -> u32 {
+        match self {
+            Version::V1 => HALT_ADDRESS + 1,
+            Version::V2 => HALT_ADDRESS + 5,
+        }
+    }
+
+    pub fn max_states(self) -> usize {
+        match self {
+            Version::V1 => V2_FLAG as usize - 1,
+            Version::V2 => u32::MAX as usize,
+        }
+    }
+
+    pub fn max_symbols(self) -> usize {
+        match self {
+            Version::V1 => 1 << 16,
+            Version::V2 => u32::MAX as usize,
+        }
+    }
+
+    /// The most state or symbol parameters a state can have
+    pub fn max_args(self) -> usize {
+        match self {
+            Version::V1 => u8::MAX as usize,
+            Version::V2 => u16::MAX as usize,
+        }
+    }
+}
+
+/// A header with a placeholder entry address
+pub fn header(version: Version, state_count: u32) -> Vec<u8> {
+    let mut bytes = match version {
+        Version::V1 => (state_count as u16).to_le_bytes().to_vec(),
+        Version::V2 => V2_FLAG.to_le_bytes().to_vec(),
+    };
+    bytes.extend(u32::MAX.to_le_bytes());
+    bytes.push(HALT);
+    if version == Version::V2 {
+        bytes.extend(state_count.to_le_bytes());
+    }
+    bytes
+}
+
+pub fn state_count(bytes: &[u8]) -> u32 {
+    match Version::of(bytes) {
+        Version::V1 => u16::from_le_bytes([bytes[0], bytes[1]]) as u32,
+        Version::V2 => u32::from_le_bytes(bytes[7..11].try_into().unwrap()),
+    }
+}
+
+pub fn set_state_count(bytes: &mut [u8], count: u32) {
+    match Version::of(bytes) {
+        Version::V1 => bytes[0..2].copy_from_slice(&(count as u16).to_le_bytes()),
+        Version::V2 => bytes[7..11].copy_from_slice(&count.to_le_bytes()),
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Operand {
+    // a count
+    U8,
+    // a count over 255
+    U16,
+    // an argument index or count (8 bits in version 1 and 16 in version 2)
+    Arg,
+    // a symbol id (16 bits in version 1 and 32 in version 2)
+    Symbol,
+    // how far to jump if the pattern doesn't match
+    Skip,
+    // the same, for arms over 65535 bytes
+    Skip32,
+    // a state's address
+    Address,
+}
+
+const INSTRUCTIONS: [(&str, &[Operand]); 34] = {
+    use Operand::*;
+    [
+        ("LEFT", &[]),
+        ("RIGHT", &[]),
+        ("LEFT_N", &[U8]),
+        ("RIGHT_N", &[U8]),
+        ("WRITE_ARG", &[Arg]),
+        ("WRITE_VAL", &[Symbol]),
+        ("WRITE_BOUND", &[]),
+        ("SYMBOL_ARG", &[Arg]),
+        ("SYMBOL_VAL", &[Symbol]),
+        ("SYMBOL_BOUND", &[]),
+        ("TAKE_ARG", &[Arg]),
+        ("CLONE_ARG", &[Arg]),
+        ("FREE_ARG", &[Arg]),
+        ("MAKE_STATE", &[Arg, Address]),
+        ("FINAL_STATE", &[Address]),
+        ("FINAL_ARG", &[Arg]),
+        ("COMPARE_ARG", &[Arg, Skip]),
+        ("COMPARE_VAL", &[Symbol, Skip]),
+        ("OTHER", &[]),
+        ("HALT", &[]),
+        ("PEEK_ARG", &[Arg, Skip]),
+        ("PEEK_VAL", &[Symbol, Skip]),
+        ("LEFT_N16", &[U16]),
+        ("RIGHT_N16", &[U16]),
+        ("COMPARE_ARG32", &[Arg, Skip32]),
+        ("COMPARE_VAL32", &[Symbol, Skip32]),
+        ("PEEK_ARG32", &[Arg, Skip32]),
+        ("PEEK_VAL32", &[Symbol, Skip32]),
+        ("WRITE_ARG_LEFT", &[Arg]),
+        ("WRITE_ARG_RIGHT", &[Arg]),
+        ("WRITE_VAL_LEFT", &[Symbol]),
+        ("WRITE_VAL_RIGHT", &[Symbol]),
+        ("WRITE_BOUND_LEFT", &[]),
+        ("WRITE_BOUND_RIGHT", &[]),
+    ]
+};
+
+pub fn name(opcode: u8) -> Option<&'static str> {
+    INSTRUCTIONS.get(opcode as usize).map(|&(name, _)| name)
+}
+
+pub fn opcode(name: &str) -> Option<u8> {
+    INSTRUCTIONS
+        .iter()
+        .position(|&(other, _)| other == name)
+        .map(|opcode| opcode as u8)
+}
+
+pub fn operands(opcode: u8) -> Option<&'static [Operand]> {
+    INSTRUCTIONS
+        .get(opcode as usize)
+        .map(|&(_, operands)| operands)
+}
+
+/// The version of a pattern with a 32-bit skip, for arms too long for a 16-bit one
+pub fn wide(opcode: u8) -> Option<u8> {
+    match opcode {
+        COMPARE_ARG => Some(COMPARE_ARG32),
+        COMPARE_VAL => Some(COMPARE_VAL32),
+        PEEK_ARG => Some(PEEK_ARG32),
+        PEEK_VAL => Some(PEEK_VAL32),
+        _ => None,
+    }
+}
+
+/// The version of a pattern with a 16-bit skip (any other opcode is returned as is)
+pub fn narrow(opcode: u8) -> u8 {
+    match opcode {
+        COMPARE_ARG32 => COMPARE_ARG,
+        COMPARE_VAL32 => COMPARE_VAL,
+        PEEK_ARG32 => PEEK_ARG,
+        PEEK_VAL32 => PEEK_VAL,
+        _ => opcode,
+    }
+}
+
+/// The instruction that does `write` and then `step` (`LEFT` or `RIGHT`), if there is one
+pub fn fused(write: u8, step: u8) -> Option<u8> {
+    match (write, step) {
+        (WRITE_ARG, LEFT) => Some(WRITE_ARG_LEFT),
+        (WRITE_ARG, RIGHT) => Some(WRITE_ARG_RIGHT),
+        (WRITE_VAL, LEFT) => Some(WRITE_VAL_LEFT),
+        (WRITE_VAL, RIGHT) => Some(WRITE_VAL_RIGHT),
+        (WRITE_BOUND, LEFT) => Some(WRITE_BOUND_LEFT),
+        (WRITE_BOUND, RIGHT) => Some(WRITE_BOUND_RIGHT),
+        _ => None,
+    }
+}
+
+impl Operand {
+    pub fn size(self, version: Version) -> usize {
+        match (self, version) {
+            (Operand::U8, _) | (Operand::Arg, Version::V1) => 1,
+            (Operand::U16 | Operand::Skip, _)
+            | (Operand::Arg, Version::V2)
+            | (Operand::Symbol, Version::V1) => 2,
+            (Operand::Skip32 | Operand::Address, _) | (Operand::Symbol, Version::V2) => 4,
+        }
+    }
+}
+
+pub fn dump(compiled: &Compiled) {
+    // a state's signatures, so jumps can say where they go
+    let mut names: HashMap<u32, Vec<String>> = HashMap::new();
+    for (signature, &address) in &compiled.signatures {
+        names
+            .entry(address)
+            .or_default()
+            .push(signature.to_string());
+    }
+    let names = names
+        .into_iter()
+        .map(|(address, mut names)| {
+            names.sort();
+            (address, names.join(", "))
+        })
+        .collect();
+
     let mut dumper = Dumper {
-        bytes,
-        no_color,
+        bytes: &mut compiled.bytes.iter().copied(),
+        states: &compiled.states,
+        symbols: &compiled.symbols,
+        names,
+        source_map: &compiled.source_map,
         address: 0,
+        version: Version::V1,
     };
 
     dumper.dump();
 }
 
+/// Prints every symbol with its id and how many instructions use it
+pub fn dump_symbols(compiled: &Compiled) {
+    let uses = symbol_uses(&compiled.bytes);
+    let rows: Vec<_> = compiled
+        .symbols
+        .iter()
+        .enumerate()
+        .map(|(id, symbol)| {
+            let escaped: String = symbol.escape_default().collect();
+            [
+                id.to_string(),
+                tape::literal(symbol),
+                format!("'{escaped}'"),
+                uses.get(&(id as u32)).copied().unwrap_or(0).to_string(),
+            ]
+        })
+        .collect();
+    table(["id", "symbol", "escaped", "uses"], rows);
+}
+
+/// Prints every state with its address and how many bytes of bytecode it takes up
+pub fn dump_states(compiled: &Compiled) {
+    let mut states: Vec<_> = compiled
+        .signatures
+        .iter()
+        .map(|(signature, &address)| (address, signature))
+        .collect();
+    states.sort_by_key(|&(address, _)| address);
+
+    // the entry code comes right after the last state
+    let entry = u32::from_le_bytes(compiled.bytes[2..6].try_into().unwrap());
+    let ends = states
+        .iter()
+        .skip(1)
+        .map(|&(address, _)| address)
+        .chain([entry]);
+    let rows: Vec<_> = states
+        .iter()
+        .zip(ends)
+        .map(|(&(address, signature), end)| {
+            [
+                format!("{address:#010x}"),
+                (end - address).to_string(),
+                signature.states.to_string(),
+                signature.symbols.to_string(),
+                signature.to_string(),
+            ]
+        })
+        .collect();
+    table(["address", "bytes", "states", "symbols", "signature"], rows);
+}
+
+// how many instructions have each symbol as an operand
+fn symbol_uses(bytes: &[u8]) -> HashMap<u32, usize> {
+    let mut uses = HashMap::new();
+    let version = Version::of(bytes);
+    let mut ip = version.code_start() as usize;
+    while ip < bytes.len() {
+        let operands = operands(bytes[ip]).expect("invalid bytecode");
+        ip += 1;
+        for operand in operands {
+            let size = operand.size(version);
+            if *operand == Operand::Symbol {
+                let value = bytes[ip..ip + size]
+                    .iter()
+                    .rev()
+                    .fold(0, |value, &byte| value << 8 | byte as u32);
+                *uses.entry(value).or_default() += 1;
+            }
+            ip += size;
+        }
+    }
+    uses
+}
+
+fn table<const N: usize>(header: [&str; N], rows: Vec<[String; N]>) {
+    let width = |text: &str| text.graphemes(true).count();
+    let mut widths = header.map(width);
+    for row in &rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.graphemes(true).count());
+        }
+    }
+    let line = |cells: Vec<&str>| {
+        let padded: Vec<_> = cells
+            .iter()
+            .zip(widths)
+            .map(|(cell, width)| {
+                format!("{cell}{}", " ".repeat(width - cell.graphemes(true).count()))
+            })
+            .collect();
+        padded.join("  ").trim_end().to_string()
+    };
+
+    let header = line(header.to_vec());
+    if !output::color() {
+        println!("{header}");
+    } else {
+        println!(
+            "{}{}{header}{}{}",
+            style::Bold,
+            color::Fg(color::Blue),
+            style::Reset,
+            color::Fg(color::Reset)
+        );
+    }
+    for row in &rows {
+        println!("{}", line(row.iter().map(String::as_str).collect()));
+    }
+    println!();
+}
+
 struct Dumper<'a> {
     bytes: &'a mut dyn Iterator<Item = u8>,
+    states: &'a HashMap<u32, StateInfo>,
+    symbols: &'a [String],
+    names: HashMap<u32, String>,
+    source_map: &'a [SourceRange],
     address: u32,
-    no_color: bool,
+    version: Version,
 }
 
 macro_rules! text {
-    ($self:expr, $text:expr, $color:ident) => {
-        if $self.no_color {
+    ($text:expr, $color:ident) => {
+        if !output::color() {
             print!("{}", $text);
         } else {
             print!(
@@ -59,17 +430,25 @@ macro_rules! text {
 }
 
 macro_rules! textln {
-    ($self: expr, $text:expr, $color:ident) => {{
-        text!($self, $text, $color);
+    ($text:expr, $color:ident) => {{
+        text!($text, $color);
         println!();
     }};
 }
 
 impl Dumper<'_> {
     fn dump(&mut self) {
-        let count = self.next_u16();
+        let flag = self.next_u16();
+        let entry = self.next_u32();
+        assert_eq!(self.next_u8(), HALT, "invalid bytecode");
+        let count = if flag == V2_FLAG {
+            self.version = Version::V2;
+            self.next_u32()
+        } else {
+            flag as u32
+        };
 
-        if self.no_color {
+        if !output::color() {
             println!("number of states: {count}");
         } else {
             println!(
@@ -81,30 +460,44 @@ impl Dumper<'_> {
             );
         }
 
-        if self.no_color {
-            println!("start address: {:#010x}\n", self.next_u32());
+        if !output::color() {
+            println!("entry address: {entry:#010x}");
         } else {
             println!(
-                "{}{}start address:{}{} {:#010x}\n",
+                "{}{}entry address:{}{} {entry:#010x}",
                 style::Bold,
                 color::Fg(color::Blue),
                 style::Reset,
                 color::Fg(color::Reset),
-                self.next_u32()
             );
         }
 
-        assert_eq!(self.next_u8(), HALT, "invalid bytecode");
+        if self.version == Version::V2 {
+            if !output::color() {
+                println!("bytecode version: 2");
+            } else {
+                println!(
+                    "{}{}bytecode version:{}{} 2",
+                    style::Bold,
+                    color::Fg(color::Blue),
+                    style::Reset,
+                    color::Fg(color::Reset),
+                );
+            }
+        }
+        println!();
 
         for i in 0..count {
-            if self.no_color {
+            let StateInfo { name, doc } = &self.states[&self.address];
+            let name = self.names.get(&self.address).unwrap_or(name);
+            if !output::color() {
                 println!(
-                    "========== state {i: <5} ({:#010x}) ==========",
+                    "========== state {i: <5} ({:#010x}) {name} ==========",
                     self.address
                 );
             } else {
                 println!(
-                    "{}{}========== state {i: <5} ({:#010x}) =========={}{}",
+                    "{}{}========== state {i: <5} ({:#010x}) {name} =========={}{}",
                     style::Bold,
                     color::Fg(color::Green),
                     self.address,
@@ -112,9 +505,27 @@ impl Dumper<'_> {
                     color::Fg(color::Reset)
                 );
             }
+            for line in doc {
+                println!("/// {line}");
+            }
             self.state();
         }
 
+        if !output::color() {
+            println!("========== entry ({:#010x}) ==========", self.address);
+        } else {
+            println!(
+                "{}{}========== entry ({:#010x}) =========={}{}",
+                style::Bold,
+                color::Fg(color::Green),
+                self.address,
+                style::Reset,
+                color::Fg(color::Reset)
+            );
+        }
+        self.instructions();
+        println!();
+
         assert!(self.bytes.next().is_none(), "invalid bytecode");
     }
 
@@ -127,7 +538,7 @@ impl Dumper<'_> {
     }
 
     fn arm(&mut self, i: u32) -> bool {
-        if self.no_color {
+        if !output::color() {
             println!("arm {i}:");
         } else {
             println!(
@@ -138,13 +549,31 @@ impl Dumper<'_> {
                 color::Fg(color::Reset)
             );
         }
+        let source = self
+            .source_map
+            .iter()
+            .find(|range| range.start == self.address && range.arm.is_some());
+        if let Some(range) = source {
+            let span = &range.span;
+            println!(
+                "// {}:{}: {}",
+                span.path.display(),
+                span.line + 1,
+                span.as_span().source_line()
+            );
+        }
 
         let arm_kind = self.pattern();
         if arm_kind == ArmKind::Halt {
             return false;
         }
 
-        textln!(self, "instructions:", Blue);
+        self.instructions();
+        arm_kind != ArmKind::Other
+    }
+
+    fn instructions(&mut self) {
+        textln!("instructions:", Blue);
 
         let mut seen_state = false;
         macro_rules! state_instr {
@@ -153,7 +582,7 @@ impl Dumper<'_> {
                 {
                     if !seen_state {
                         seen_state = true;
-                        if self.no_color {
+                        if !output::color() {
                             println!("--");
                         } else {
                             println!(
@@ -171,75 +600,97 @@ impl Dumper<'_> {
 
         loop {
             match self.next_u8() {
-                LEFT => textln!(self, "    LEFT", Green),
-                RIGHT => textln!(self, "    RIGHT", Green),
+                LEFT => textln!("    LEFT", Green),
+                RIGHT => textln!("    RIGHT", Green),
                 LEFT_N => {
-                    text!(self, "    LEFT_N", Green);
+                    text!("    LEFT_N", Green);
                     println!(" (n: {})", self.next_u8());
                 }
                 RIGHT_N => {
-                    text!(self, "    RIGHT_N", Green);
+                    text!("    RIGHT_N", Green);
                     println!(" (n: {})", self.next_u8());
                 }
+                LEFT_N16 => {
+                    text!("    LEFT_N16", Green);
+                    println!(" (n: {})", self.next_u16());
+                }
+                RIGHT_N16 => {
+                    text!("    RIGHT_N16", Green);
+                    println!(" (n: {})", self.next_u16());
+                }
                 WRITE_ARG => {
-                    text!(self, "    WRITE_ARG", Green);
-                    println!(" (arg: {})", self.next_u8());
+                    text!("    WRITE_ARG", Green);
+                    println!(" (arg: {})", self.next_arg());
                 }
                 WRITE_VAL => {
-                    text!(self, "    WRITE_VAL", Green);
-                    println!(" (value: {})", self.next_u16());
+                    text!("    WRITE_VAL", Green);
+                    println!(" (value: {})", self.value());
+                }
+                WRITE_BOUND => textln!("    WRITE_BOUND", Green),
+                WRITE_ARG_LEFT => {
+                    text!("    WRITE_ARG_LEFT", Green);
+                    println!(" (arg: {})", self.next_arg());
+                }
+                WRITE_ARG_RIGHT => {
+                    text!("    WRITE_ARG_RIGHT", Green);
+                    println!(" (arg: {})", self.next_arg());
+                }
+                WRITE_VAL_LEFT => {
+                    text!("    WRITE_VAL_LEFT", Green);
+                    println!(" (value: {})", self.value());
+                }
+                WRITE_VAL_RIGHT => {
+                    text!("    WRITE_VAL_RIGHT", Green);
+                    println!(" (value: {})", self.value());
                 }
-                WRITE_BOUND => textln!(self, "    WRITE_BOUND", Green),
+                WRITE_BOUND_LEFT => textln!("    WRITE_BOUND_LEFT", Green),
+                WRITE_BOUND_RIGHT => textln!("    WRITE_BOUND_RIGHT", Green),
 
                 SYMBOL_ARG => {
                     state_instr!();
-                    text!(self, "    SYMBOL_ARG", Green);
-                    println!(" (arg: {})", self.next_u8());
+                    text!("    SYMBOL_ARG", Green);
+                    println!(" (arg: {})", self.next_arg());
                 }
                 SYMBOL_VAL => {
                     state_instr!();
-                    text!(self, "    SYMBOL_VAL", Green);
-                    println!(" (value: {})", self.next_u16());
+                    text!("    SYMBOL_VAL", Green);
+                    println!(" (value: {})", self.value());
                 }
                 SYMBOL_BOUND => {
                     state_instr!();
-                    textln!(self, "    SYMBOL_BOUND", Green);
+                    textln!("    SYMBOL_BOUND", Green);
                 }
                 TAKE_ARG => {
                     state_instr!();
-                    text!(self, "    TAKE_ARG", Green);
-                    println!(" (arg: {})", self.next_u8());
+                    text!("    TAKE_ARG", Green);
+                    println!(" (arg: {})", self.next_arg());
                 }
                 CLONE_ARG => {
                     state_instr!();
-                    text!(self, "    CLONE_ARG", Green);
-                    println!(" (arg: {})", self.next_u8());
+                    text!("    CLONE_ARG", Green);
+                    println!(" (arg: {})", self.next_arg());
                 }
                 FREE_ARG => {
                     state_instr!();
-                    text!(self, "    FREE_ARG", Green);
-                    println!(" (arg: {})", self.next_u8());
+                    text!("    FREE_ARG", Green);
+                    println!(" (arg: {})", self.next_arg());
                 }
                 MAKE_STATE => {
                     state_instr!();
-                    text!(self, "    MAKE_STATE", Green);
-                    println!(
-                        " (args: {}) (addr: {:#010x})",
-                        self.next_u8(),
-                        self.next_u32()
-                    );
+                    text!("    MAKE_STATE", Green);
+                    println!(" (args: {}) (addr: {})", self.next_arg(), self.target());
                 }
                 FINAL_STATE => {
                     state_instr!();
-                    text!(self, "    FINAL_STATE", Green);
-                    println!(" (addr: {:#010x})", self.next_u32());
-                    return arm_kind != ArmKind::Other;
+                    text!("    FINAL_STATE", Green);
+                    println!(" (addr: {})", self.target());
+                    return;
                 }
                 FINAL_ARG => {
                     state_instr!();
-                    text!(self, "    FINAL_ARG", Green);
-                    println!(" (arg: {})", self.next_u8());
-                    return arm_kind != ArmKind::Other;
+                    text!("    FINAL_ARG", Green);
+                    println!(" (arg: {})", self.next_arg());
+                    return;
                 }
 
                 _ => panic!("invalid bytecode"),
@@ -249,28 +700,86 @@ impl Dumper<'_> {
 
     fn pattern(&mut self) -> ArmKind {
         match self.next_u8() {
+            PEEK_ARG => {
+                text!("    PEEK_ARG", Green);
+                println!(" (arg: {}) (skip: {})", self.next_arg(), self.next_u16());
+                self.pattern();
+                ArmKind::Continue
+            }
+            PEEK_VAL => {
+                text!("    PEEK_VAL", Green);
+                println!(" (value: {}) (skip: {})", self.value(), self.next_u16());
+                self.pattern();
+                ArmKind::Continue
+            }
+            PEEK_ARG32 => {
+                text!("    PEEK_ARG32", Green);
+                println!(" (arg: {}) (skip: {})", self.next_arg(), self.next_u32());
+                self.pattern();
+                ArmKind::Continue
+            }
+            PEEK_VAL32 => {
+                text!("    PEEK_VAL32", Green);
+                println!(" (value: {}) (skip: {})", self.value(), self.next_u32());
+                self.pattern();
+                ArmKind::Continue
+            }
             COMPARE_ARG => {
-                text!(self, "    COMPARE_ARG", Green);
-                println!(" (arg: {}) (skip: {})", self.next_u8(), self.next_u16());
+                text!("    COMPARE_ARG", Green);
+                println!(" (arg: {}) (skip: {})", self.next_arg(), self.next_u16());
                 ArmKind::Continue
             }
             COMPARE_VAL => {
-                text!(self, "    COMPARE_VAL", Green);
-                println!(" (value: {}) (skip: {})", self.next_u16(), self.next_u16());
+                text!("    COMPARE_VAL", Green);
+                println!(" (value: {}) (skip: {})", self.value(), self.next_u16());
+                ArmKind::Continue
+            }
+            COMPARE_ARG32 => {
+                text!("    COMPARE_ARG32", Green);
+                println!(" (arg: {}) (skip: {})", self.next_arg(), self.next_u32());
+                ArmKind::Continue
+            }
+            COMPARE_VAL32 => {
+                text!("    COMPARE_VAL32", Green);
+                println!(" (value: {}) (skip: {})", self.value(), self.next_u32());
                 ArmKind::Continue
             }
             OTHER => {
-                textln!(self, "    OTHER", Green);
+                textln!("    OTHER", Green);
                 ArmKind::Other
             }
             HALT => {
-                textln!(self, "    HALT", Green);
+                textln!("    HALT", Green);
                 ArmKind::Halt
             }
             _ => panic!("invalid bytecode"),
         }
     }
 
+    // a symbol operand, followed by the symbol
+    fn value(&mut self) -> String {
+        let id = self.next_symbol();
+        match self.symbols.get(id as usize) {
+            Some(symbol) => format!("{id} {}", tape::literal(symbol)),
+            None => id.to_string(),
+        }
+    }
+
+    // a state's address, followed by its name
+    fn target(&mut self) -> String {
+        let address = self.next_u32();
+        if address == HALT_ADDRESS {
+            return format!("{address:#010x} !");
+        }
+        match self.names.get(&address) {
+            Some(name) => format!("{address:#010x} {name}"),
+            None => match self.states.get(&address) {
+                Some(info) => format!("{address:#010x} {}", info.name),
+                None => format!("{address:#010x}"),
+            },
+        }
+    }
+
     fn next_u8(&mut self) -> u8 {
         self.address += 1;
         self.bytes.next().expect("invalid bytecode")
@@ -288,6 +797,20 @@ impl Dumper<'_> {
             self.next_u8(),
         ])
     }
+
+    fn next_arg(&mut self) -> u16 {
+        match self.version {
+            Version::V1 => self.next_u8() as u16,
+            Version::V2 => self.next_u16(),
+        }
+    }
+
+    fn next_symbol(&mut self) -> u32 {
+        match self.version {
+            Version::V1 => self.next_u16() as u32,
+            Version::V2 => self.next_u32(),
+        }
+    }
 }
 
 #[derive(PartialEq)]
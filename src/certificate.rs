@@ -0,0 +1,307 @@
+use std::fs;
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::backend::{self, Observer, Simulated, Trim};
+use crate::bytecode as bc;
+use crate::compile::Compiled;
+use crate::error::Error;
+
+// version 1 hashed the initial tape's symbol ids, which change with the symbols
+// the tape adds to the symbol table. version 2 hashes the symbols themselves
+const VERSION: u32 = 2;
+
+/// A compact record of a halting run that can be checked by replaying it
+#[derive(Serialize, Deserialize)]
+pub struct Certificate {
+    pub version: u32,
+    // hash of the bytecode and the symbol table
+    pub machine: String,
+    // hash of the initial tape's symbols
+    pub tape: String,
+    pub interval: usize,
+    // configuration hashes after `interval`, `2 * interval`, ... moves
+    pub checkpoints: Vec<String>,
+    pub moves: usize,
+    #[serde(rename = "final")]
+    pub final_configuration: Configuration,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Eq)]
+pub struct Configuration {
+    pub state: String,
+    pub head: usize,
+    // hash of the state, head and tape
+    pub hash: String,
+}
+
+/// Hashes the configuration every `interval` moves
+pub struct Recorder {
+    tape: Vec<u32>,
+    version: bc::Version,
+    state: u32,
+    interval: usize,
+    checkpoints: Vec<String>,
+}
+
+impl Recorder {
+    pub fn new(compiled: &Compiled, interval: usize) -> Self {
+        Recorder {
+            tape: compiled.tape.clone(),
+            version: bc::Version::of(&compiled.bytes),
+            state: 0,
+            interval,
+            checkpoints: Vec::new(),
+        }
+    }
+
+    pub fn finish(self, compiled: &Compiled, simulated: &Simulated) -> Certificate {
+        Certificate {
+            version: VERSION,
+            machine: machine_hash(compiled),
+            tape: tape_hash(compiled, VERSION),
+            interval: self.interval,
+            checkpoints: self.checkpoints,
+            moves: simulated.moves,
+            final_configuration: Configuration {
+                state: state_name(compiled, simulated.final_address),
+                head: simulated.head_position,
+                hash: configuration_hash(
+                    simulated.final_address,
+                    simulated.head_position,
+                    &simulated.tape,
+                    self.version,
+                ),
+            },
+        }
+    }
+}
+
+impl Observer for Recorder {
+    // certificates are only made for one-sided tapes, so positions are indices
+    fn write(&mut self, position: i64, symbol: u32) {
+        let index = position as usize;
+        if index >= self.tape.len() {
+            self.tape.resize(index + 1, 0);
+        }
+        self.tape[index] = symbol;
+    }
+
+    fn state(&mut self, address: u32) {
+        self.state = address;
+    }
+
+    fn moved(&mut self, moves: usize, head: i64) {
+        if moves.is_multiple_of(self.interval) {
+            let hash = configuration_hash(self.state, head as usize, &self.tape, self.version);
+            self.checkpoints.push(hash);
+        }
+    }
+}
+
+pub fn write(certificate: &Certificate, path: &Path) -> Result<(), Error> {
+    let json = serde_json::to_string_pretty(certificate).unwrap();
+    fs::write(path, json + "\n")
+        .map_err(|_| Error::new(format!("couldn't write file {}", path.display()), None))
+}
+
+pub fn read(path: &Path) -> Result<Certificate, Error> {
+    let Ok(json) = fs::read_to_string(path) else {
+        return Err(Error::new(
+            format!("couldn't read file {}", path.display()),
+            None,
+        ));
+    };
+    let certificate: Certificate = serde_json::from_str(&json).map_err(|error| {
+        Error::new(
+            format!("{} is not a valid certificate ({error})", path.display()),
+            None,
+        )
+    })?;
+    if certificate.version == 0 || certificate.version > VERSION {
+        return Err(Error::new(
+            format!(
+                "{} is a version {} certificate (expected version {VERSION} or older)",
+                path.display(),
+                certificate.version
+            ),
+            None,
+        ));
+    }
+    if certificate.interval == 0 {
+        return Err(Error::new(
+            format!("{} has a checkpoint interval of 0", path.display()),
+            None,
+        ));
+    }
+    Ok(certificate)
+}
+
+/// Replays the run in the certificate and checks every checkpoint along the way
+pub fn verify(certificate: &Certificate, compiled: &Compiled) -> Result<(), Error> {
+    check_inputs(certificate, compiled)?;
+
+    // one extra move tells us whether the machine really halts where the certificate says
+    let options = backend::Options {
+        max_moves: certificate.moves.saturating_add(1),
+        trim: Trim::TrailingBlanks,
+        table: false,
+        two_sided: false,
+        max_tape_cells: usize::MAX,
+        block_size: None,
+        tape_repr: backend::TapeRepr::Dense,
+        tape_backing: backend::TapeBacking::Memory,
+        transpositions: None,
+        dispatch: None,
+    };
+    let mut recorder = Recorder::new(compiled, certificate.interval);
+    let simulated = backend::observe(&compiled.bytes, &compiled.tape, &options, &mut recorder)?;
+    check_replay(certificate, &recorder.finish(compiled, &simulated))
+}
+
+// the tape goes first, since symbols that are only on the tape are in the machine's
+// symbol table too
+fn check_inputs(certificate: &Certificate, compiled: &Compiled) -> Result<(), Error> {
+    if certificate.tape != tape_hash(compiled, certificate.version) {
+        Err(Error::new(
+            "the certificate is for a different initial tape".to_string(),
+            None,
+        ))
+    } else if certificate.machine != machine_hash(compiled) {
+        Err(Error::new(
+            "the certificate is for a different machine".to_string(),
+            None,
+        )
+        .with_help(Some(
+            "check the machine file and `--start-args`".to_string(),
+        )))
+    } else {
+        Ok(())
+    }
+}
+
+fn check_replay(certificate: &Certificate, replay: &Certificate) -> Result<(), Error> {
+    if replay.moves > certificate.moves {
+        return Err(Error::new(
+            format!("the machine doesn't halt after {} moves", certificate.moves),
+            None,
+        ));
+    } else if replay.moves < certificate.moves {
+        return Err(Error::new(
+            format!(
+                "the machine halts after {} moves, not {}",
+                replay.moves, certificate.moves
+            ),
+            None,
+        ));
+    }
+
+    for (i, (expected, actual)) in certificate
+        .checkpoints
+        .iter()
+        .zip(&replay.checkpoints)
+        .enumerate()
+    {
+        if expected != actual {
+            return Err(Error::new(
+                format!(
+                    "the configuration after {} moves doesn't match",
+                    (i + 1) * certificate.interval
+                ),
+                None,
+            ));
+        }
+    }
+    if certificate.checkpoints.len() != replay.checkpoints.len() {
+        return Err(Error::new(
+            format!(
+                "the certificate has {} checkpoints, but the run has {}",
+                certificate.checkpoints.len(),
+                replay.checkpoints.len()
+            ),
+            None,
+        ));
+    }
+
+    if certificate.final_configuration != replay.final_configuration {
+        return Err(Error::new(
+            "the final configuration doesn't match".to_string(),
+            None,
+        ));
+    }
+    Ok(())
+}
+
+fn state_name(compiled: &Compiled, address: u32) -> String {
+    match compiled.states.get(&address) {
+        Some(info) => info.name.clone(),
+        None if address == bc::HALT_ADDRESS => "!".to_string(),
+        None => format!("{address:#010x}"),
+    }
+}
+
+fn machine_hash(compiled: &Compiled) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update((compiled.bytes.len() as u64).to_le_bytes());
+    hasher.update(&compiled.bytes);
+    for symbol in &compiled.symbols {
+        hasher.update((symbol.len() as u64).to_le_bytes());
+        hasher.update(symbol.as_bytes());
+    }
+    hex(hasher.finalize().as_slice())
+}
+
+fn tape_hash(compiled: &Compiled, version: u32) -> String {
+    let mut hasher = Sha256::new();
+    if version == 1 {
+        update_tape(
+            &mut hasher,
+            &compiled.tape,
+            bc::Version::of(&compiled.bytes),
+        );
+    } else {
+        let len = compiled
+            .tape
+            .iter()
+            .rposition(|&symbol| symbol != 0)
+            .map_or(0, |i| i + 1);
+        hasher.update((len as u64).to_le_bytes());
+        for &symbol in &compiled.tape[..len] {
+            let symbol = &compiled.symbols[symbol as usize];
+            hasher.update((symbol.len() as u64).to_le_bytes());
+            hasher.update(symbol.as_bytes());
+        }
+    }
+    hex(hasher.finalize().as_slice())
+}
+
+fn configuration_hash(state: u32, head: usize, tape: &[u32], version: bc::Version) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(state.to_le_bytes());
+    hasher.update((head as u64).to_le_bytes());
+    update_tape(&mut hasher, tape, version);
+    hex(hasher.finalize().as_slice())
+}
+
+// trailing blanks don't change the configuration, and backends leave different
+// amounts of them. symbols are hashed at the width the bytecode gives them, so
+// certificates for version 1 machines stay valid
+fn update_tape(hasher: &mut Sha256, tape: &[u32], version: bc::Version) {
+    let len = tape
+        .iter()
+        .rposition(|&symbol| symbol != 0)
+        .map_or(0, |i| i + 1);
+    hasher.update((len as u64).to_le_bytes());
+    for &symbol in &tape[..len] {
+        match version {
+            bc::Version::V1 => hasher.update((symbol as u16).to_le_bytes()),
+            bc::Version::V2 => hasher.update(symbol.to_le_bytes()),
+        }
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
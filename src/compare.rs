@@ -0,0 +1,162 @@
+use termion::{color, style};
+
+use crate::backend::{self, Backend, HaltReason, Simulated};
+use crate::compile::Compiled;
+use crate::error::Error;
+use crate::ffi::CVm;
+use crate::output;
+use crate::tape;
+use crate::vm::RustVm;
+
+/// Runs a machine's bytecode on the Rust VM and on the C VM (never on the
+/// transition table) and checks that they stop with the same tape, head
+/// position, move count, final address and halt reason, printing what differs
+/// if they don't. With `every`, it also checks them after every `every` moves,
+/// which means running both from the start again each time, since the C VM
+/// can't pause. Returns the Rust VM's run
+pub fn run(
+    compiled: &Compiled,
+    options: &backend::Options,
+    every: Option<usize>,
+) -> Result<Simulated, Error> {
+    // the last checkpoint the VMs agreed at
+    let mut agreed = None;
+    if let Some(every) = every {
+        let mut limit = every;
+        while limit < options.max_moves {
+            let (rust, c) = run_both(compiled, options, limit)?;
+            if rust.halt_reason == HaltReason::Interrupted
+                || c.halt_reason == HaltReason::Interrupted
+            {
+                return Ok(rust);
+            }
+            if !agree(compiled, &rust, &c) {
+                return Err(disagree(format!("with a limit of {limit} moves"), agreed));
+            }
+            agreed = Some(limit);
+            // a run that stopped early would stop the same way every time
+            if rust.halt_reason != HaltReason::MaxMoves {
+                return Ok(rust);
+            }
+            limit = limit.saturating_add(every);
+        }
+    }
+
+    let (rust, c) = run_both(compiled, options, options.max_moves)?;
+    let interrupted =
+        rust.halt_reason == HaltReason::Interrupted || c.halt_reason == HaltReason::Interrupted;
+    if !interrupted && !agree(compiled, &rust, &c) {
+        return Err(disagree("at the end of the run".to_string(), agreed));
+    }
+    Ok(rust)
+}
+
+fn run_both(
+    compiled: &Compiled,
+    options: &backend::Options,
+    max_moves: usize,
+) -> Result<(Simulated, Simulated), Error> {
+    let options = backend::Options {
+        max_moves,
+        trim: options.trim,
+        table: false,
+        two_sided: options.two_sided,
+        max_tape_cells: options.max_tape_cells,
+        block_size: None,
+        tape_repr: backend::TapeRepr::Dense,
+        tape_backing: backend::TapeBacking::Memory,
+        transpositions: None,
+        dispatch: options.dispatch,
+    };
+    Ok((
+        RustVm.run(&compiled.bytes, &compiled.tape, &options)?,
+        CVm.run(&compiled.bytes, &compiled.tape, &options)?,
+    ))
+}
+
+// prints what differs between the runs, if anything
+fn agree(compiled: &Compiled, rust: &Simulated, c: &Simulated) -> bool {
+    let mut differences = Vec::new();
+    let mut compare = |name: &str, rust: String, c: String| {
+        if rust != c {
+            differences.push((
+                name.to_string(),
+                format!("{rust} on the Rust VM, {c} on the C VM"),
+            ));
+        }
+    };
+    compare(
+        "number of moves",
+        rust.moves.to_string(),
+        c.moves.to_string(),
+    );
+    compare(
+        "final head position",
+        rust.head().to_string(),
+        c.head().to_string(),
+    );
+    compare(
+        "final address",
+        rust.final_address.to_string(),
+        c.final_address.to_string(),
+    );
+    compare(
+        "final state",
+        compiled.describe_state(&rust.final_state),
+        compiled.describe_state(&c.final_state),
+    );
+    compare(
+        "halt reason",
+        rust.halt_reason.to_string(),
+        c.halt_reason.to_string(),
+    );
+
+    // both tapes were trimmed the same way, so their cells line up once they're
+    // shifted to the same origin
+    let low = -(rust.origin.max(c.origin) as i64);
+    let high =
+        (rust.tape.len() as i64 - rust.origin as i64).max(c.tape.len() as i64 - c.origin as i64);
+    let cell = |simulated: &Simulated, position: i64| {
+        let index = position + simulated.origin as i64;
+        usize::try_from(index)
+            .ok()
+            .and_then(|index| simulated.tape.get(index))
+            .map_or(0, |&symbol| symbol)
+    };
+    let literal = |symbol: u32| tape::literal(&compiled.symbols[symbol as usize]);
+    if let Some(position) = (low..high).find(|&position| cell(rust, position) != cell(c, position))
+    {
+        differences.push((
+            "tape".to_string(),
+            format!(
+                "cell {position} is `{}` on the Rust VM, `{}` on the C VM",
+                literal(cell(rust, position)),
+                literal(cell(c, position))
+            ),
+        ));
+    }
+
+    if differences.is_empty() {
+        return true;
+    }
+    for (name, difference) in differences {
+        if !output::color() {
+            println!("{name}: {difference}");
+        } else {
+            println!(
+                "{}{}{name}:{}{} {difference}",
+                style::Bold,
+                color::Fg(color::Red),
+                style::Reset,
+                color::Fg(color::Reset)
+            );
+        }
+    }
+    println!();
+    false
+}
+
+fn disagree(when: String, agreed: Option<usize>) -> Error {
+    Error::new(format!("the Rust VM and the C VM disagree {when}"), None)
+        .with_help(agreed.map(|agreed| format!("they agreed with a limit of {agreed} moves")))
+}
@@ -1,36 +1,187 @@
-use std::collections::{hash_map::Entry, HashMap, VecDeque};
+use std::collections::{hash_map::Entry, HashMap, HashSet, VecDeque};
 
 use crate::bytecode as bc;
 use crate::error::Error;
-use crate::lex::Span;
-use crate::parse::{Arm, Name, Op, Pattern, State, Symbol, ToState};
+use crate::lex::{Span, SpanBuf};
+use crate::parse::{Alphabet, Arm, Name, Op, Pattern, StartArgs, State, Symbol, ToState, Unit};
+use crate::suggest;
+use crate::tape;
 
 pub struct Compiled {
     pub bytes: Vec<u8>,
     pub symbols: Vec<String>,
-    pub states: HashMap<u32, String>,
-    pub tape: Vec<u16>,
+    pub states: HashMap<u32, StateInfo>,
+    pub signatures: HashMap<SignatureBuf, u32>,
+    pub arms: Vec<ArmInfo>,
+    pub source_map: Vec<SourceRange>,
+    pub tape: Vec<u32>,
 }
 
-pub fn compile(unit: Vec<State>, symbols: Vec<Symbol>) -> Result<Compiled, Error> {
+impl Compiled {
+    /// The part of the machine that compiled to the bytecode at `address`, if any
+    /// (the header and the entry code don't have one)
+    pub fn source_at(&self, address: u32) -> Option<&SourceRange> {
+        let i = self
+            .source_map
+            .partition_point(|range| range.end <= address);
+        self.source_map
+            .get(i)
+            .filter(|range| range.start <= address)
+    }
+
+    /// Makes the entry code move the head to cell `head` (counting from the
+    /// initial tape's first cell) before the start state runs. It doesn't count
+    /// as a move, but a one-way tape still stops a head that goes left of cell 0
+    pub fn set_head_start(&mut self, head: i64) {
+        let entry = u32::from_le_bytes(self.bytes[2..6].try_into().unwrap()) as usize;
+        let mut moves = Vec::new();
+        let n = head.unsigned_abs() as usize;
+        if head < 0 {
+            push_move(&mut moves, n, bc::LEFT, bc::LEFT_N, bc::LEFT_N16);
+        } else {
+            push_move(&mut moves, n, bc::RIGHT, bc::RIGHT_N, bc::RIGHT_N16);
+        }
+        // the entry code is last, so nothing jumps past it
+        self.bytes.splice(entry..entry, moves);
+    }
+
+    /// A state (written the way `Vm::write_state` writes it) as it would be
+    /// passed in the source, like `carry(f(!); '0')`. A state without a name
+    /// shows its address
+    pub fn describe_state(&self, state: &[u32]) -> String {
+        let mut words = state.iter().copied();
+        let mut next = || words.next().unwrap_or_default();
+        let mut out = String::new();
+        // the open parentheses, with how many states each still has to show and
+        // the symbols that come after them
+        let mut open: Vec<(u32, String)> = Vec::new();
+        loop {
+            let address = next();
+            // a copy the optimizer made for constant symbols is named like
+            // `f(_; 'a')`, and its states go where the `_`s are
+            let mut symbols = Vec::new();
+            match self.states.get(&address) {
+                Some(info) => match info.name.split_once('(') {
+                    Some((name, params)) => {
+                        out.push_str(name);
+                        let consts = params.split_once("; ").map_or("", |(_, consts)| consts);
+                        let consts = consts.strip_suffix(')').unwrap_or(consts);
+                        if !consts.is_empty() {
+                            symbols.push(consts.to_string());
+                        }
+                    }
+                    None => out.push_str(&info.name),
+                },
+                None if address == bc::HALT_ADDRESS => out.push('!'),
+                None => out.push_str(&format!("{address:#010x}")),
+            }
+            symbols.extend(
+                (0..next()).map(|_| match self.symbols.get(next() as usize) {
+                    Some(symbol) => tape::literal(symbol),
+                    None => "?".to_string(),
+                }),
+            );
+            let symbols = symbols.join(", ");
+            let states = next();
+            if states > 0 {
+                out.push('(');
+                open.push((states, symbols));
+                continue;
+            } else if !symbols.is_empty() {
+                out.push_str(&format!("(; {symbols})"));
+            }
+            // that finished a state, which might finish the one it was passed to
+            loop {
+                let Some((remaining, symbols)) = open.last_mut() else {
+                    return out;
+                };
+                *remaining -= 1;
+                if *remaining > 0 {
+                    out.push_str(", ");
+                    break;
+                }
+                if !symbols.is_empty() {
+                    out.push_str("; ");
+                    out.push_str(symbols);
+                }
+                out.push(')');
+                open.pop();
+            }
+        }
+    }
+}
+
+pub struct StateInfo {
+    pub name: String,
+    pub doc: Vec<String>,
+}
+
+// where an arm's right hand side starts, so the VM can tell which arm it took
+pub struct ArmInfo {
+    pub offset: u32,
+    // address of the state the arm belongs to
+    pub state: u32,
+    // the arm's pattern
+    pub span: SpanBuf,
+}
+
+// the bytecode in `start..end` came from one arm, or from the halt at the end of
+// a state that doesn't have a catchall
+pub struct SourceRange {
+    pub start: u32,
+    pub end: u32,
+    // index of the state in source order
+    pub state: usize,
+    // index of the arm in its state (`None` for the halt)
+    pub arm: Option<usize>,
+    // the arm's pattern or the state's name
+    pub span: SpanBuf,
+}
+
+pub fn compile(unit: Unit, symbols: Vec<Symbol>, start_args: StartArgs) -> Result<Compiled, Error> {
+    compile_with_symbols(unit, symbols, start_args, None)
+}
+
+/// Like `compile`, but symbols get their ids from `table` (the symbol with id `i` is
+/// `table[i]`, so `table[0]` has to be the blank) instead of in the order they show up.
+/// Symbols that aren't in the table are an error.
+pub fn compile_with_symbols(
+    unit: Unit,
+    symbols: Vec<Symbol>,
+    start_args: StartArgs,
+    table: Option<&[String]>,
+) -> Result<Compiled, Error> {
+    let version = pick_version(&unit, &symbols, &start_args, table);
     let mut compiler = Compiler {
-        bytes: vec![0, 0, 0xff, 0xff, 0xff, 0xff, bc::HALT],
+        version,
+        bytes: bc::header(version, 0),
         forward_refs: HashMap::new(),
         addresses: HashMap::new(),
-        symbols: Symbols::new(),
-        states: unit.into(),
+        definitions: HashMap::new(),
+        overloads: HashMap::new(),
+        symbols: match table {
+            Some(table) => Symbols::fixed(table, version)?,
+            None => Symbols::new(version),
+        },
+        alphabet: None,
+        states: unit.states.into(),
         state_names: HashMap::new(),
+        arms: Vec::new(),
+        source_map: Vec::new(),
     };
 
-    compiler.compile()?;
+    if let Some(alphabet) = unit.alphabet {
+        compiler.declare_alphabet(alphabet)?;
+    }
+    compiler.compile(start_args)?;
 
     let mut tape = Vec::with_capacity(symbols.len());
     for symbol in symbols {
-        tape.push(compiler.symbols.insert(symbol)?);
+        tape.push(compiler.insert_symbol(symbol)?);
     }
 
-    let mut symbols = vec![String::new(); compiler.symbols.0.len()];
-    for (symbol, index) in compiler.symbols.0 {
+    let mut symbols = vec![String::new(); compiler.symbols.ids.len()];
+    for (symbol, index) in compiler.symbols.ids {
         symbols[index as usize] = symbol;
     }
 
@@ -38,44 +189,241 @@ pub fn compile(unit: Vec<State>, symbols: Vec<Symbol>) -> Result<Compiled, Error
         bytes: compiler.bytes,
         symbols,
         states: compiler.state_names,
+        signatures: compiler
+            .addresses
+            .into_iter()
+            .map(|(signature, address)| (signature.to_buf(), address))
+            .collect(),
+        arms: compiler.arms,
+        source_map: compiler.source_map,
         tape,
     })
 }
 
-struct Compiler {
+struct Compiler<'a> {
+    version: bc::Version,
     bytes: Vec<u8>,
-    forward_refs: HashMap<Signature, Vec<ForwardRef>>,
-    addresses: HashMap<Signature, u32>,
+    forward_refs: HashMap<Signature<'a>, Vec<ForwardRef<'a>>>,
+    addresses: HashMap<Signature<'a>, u32>,
+    definitions: HashMap<Signature<'a>, Span<'a>>,
+    // every signature defined for each name
+    overloads: HashMap<&'a str, Vec<Signature<'a>>>,
     symbols: Symbols,
-    states: VecDeque<State>,
-    state_names: HashMap<u32, String>,
+    // the declared alphabet, blank included
+    alphabet: Option<DeclaredAlphabet<'a>>,
+    states: VecDeque<State<'a>>,
+    state_names: HashMap<u32, StateInfo>,
+    arms: Vec<ArmInfo>,
+    source_map: Vec<SourceRange>,
+}
+
+struct DeclaredAlphabet<'a> {
+    span: Span<'a>,
+    symbols: Vec<String>,
 }
 
-impl Compiler {
-    fn compile(&mut self) -> Result<(), Error> {
+impl<'a> Compiler<'a> {
+    fn declare_alphabet(&mut self, alphabet: Alphabet<'a>) -> Result<(), Error> {
+        let mut symbols = vec![String::new()];
+        let mut spans: HashMap<String, Span> = HashMap::new();
+        for symbol in alphabet.symbols {
+            if let Some(&first) = spans.get(&symbol.symbol) {
+                return Err(Error::new(
+                    format!(
+                        "symbol `{}` is in the alphabet twice",
+                        tape::literal(&symbol.symbol)
+                    ),
+                    Some(symbol.span),
+                )
+                .with_note(first, "first declared here".to_string()));
+            }
+            spans.insert(symbol.symbol.clone(), symbol.span);
+            if !symbol.symbol.is_empty() {
+                symbols.push(symbol.symbol.clone());
+            }
+            // give the alphabet the first indices, in the order it was declared
+            self.symbols.insert(symbol)?;
+        }
+
+        self.alphabet = Some(DeclaredAlphabet {
+            span: alphabet.span,
+            symbols,
+        });
+        Ok(())
+    }
+
+    fn insert_symbol(&mut self, symbol: Symbol<'a>) -> Result<u32, Error> {
+        if let Some(alphabet) = &self.alphabet {
+            if !alphabet.symbols.contains(&symbol.symbol) {
+                let suggestions = suggest::closest(
+                    &symbol.symbol,
+                    alphabet
+                        .symbols
+                        .iter()
+                        .map(|candidate| (candidate.as_str(), tape::literal(candidate))),
+                );
+                return Err(Error::new(
+                    format!(
+                        "symbol `{}` is not in the alphabet",
+                        tape::literal(&symbol.symbol)
+                    ),
+                    Some(symbol.span),
+                )
+                .with_note(alphabet.span, "alphabet declared here".to_string())
+                .with_help(suggest::did_you_mean(&suggestions)));
+            }
+        }
+        self.symbols.insert(symbol)
+    }
+
+    // with a declared alphabet, every state has to say what it does with every
+    // symbol (states without arms are explicit halts)
+    fn check_exhaustive(
+        &self,
+        signature: Signature<'a>,
+        name: &Name<'a>,
+        arms: &[Arm<'a>],
+        symbol_map: &HashMap<&'a str, u16>,
+    ) -> Result<(), Error> {
+        let Some(alphabet) = &self.alphabet else {
+            return Ok(());
+        };
+        if arms.is_empty() {
+            return Ok(());
+        }
+
+        let mut handled = Vec::new();
+        let mut param_arm = None;
+        for arm in arms {
+            match (&arm.pattern, &arm.lookahead) {
+                (Pattern::Symbol(symbol), None) => handled.push(symbol.symbol.as_str()),
+                (Pattern::Name(name), _) if symbol_map.contains_key(name.name) => {
+                    param_arm.get_or_insert(name.span);
+                }
+                (Pattern::Name(_), None) => return Ok(()),
+                (_, Some(_)) => {}
+            }
+        }
+
+        let missing: Vec<_> = alphabet
+            .symbols
+            .iter()
+            .filter(|symbol| !handled.contains(&symbol.as_str()))
+            .map(|symbol| format!("`{}`", tape::literal(symbol)))
+            .collect();
+        if missing.is_empty() {
+            return Ok(());
+        }
+
+        let label = match missing.split_last() {
+            Some((last, [])) => format!("{last} isn't handled"),
+            Some((last, rest)) => format!("{} and {last} aren't handled", rest.join(", ")),
+            None => unreachable!(),
+        };
+        let mut error = Error::new(
+            format!("state `{signature}` doesn't handle every symbol in the alphabet"),
+            Some(name.span),
+        )
+        .with_label(label);
+        if let Some(span) = param_arm {
+            error = error.with_note(
+                span,
+                "arms that match a symbol parameter don't count".to_string(),
+            );
+        }
+        Err(error.with_help(Some(
+            "add an arm for each of them or a catchall arm like `_ | | !`".to_string(),
+        )))
+    }
+
+    fn compile(&mut self, start_args: StartArgs<'a>) -> Result<(), Error> {
+        let mut index = 0;
         while let Some(state) = self.states.pop_front() {
-            self.compile_state(state)?;
+            self.compile_state(state, index)?;
+            index += 1;
         }
 
-        if let Some((signature, refs)) = self.forward_refs.iter().next() {
-            let span = refs[0].span;
+        self.check_resolved()?;
+        self.compile_entry(start_args)?;
+        // the start arguments can name states too
+        self.check_resolved()
+    }
+
+    // reports every missing signature at its first use, in source order
+    fn check_resolved(&self) -> Result<(), Error> {
+        let mut unresolved: Vec<_> = self
+            .forward_refs
+            .iter()
+            .map(|(signature, refs)| {
+                let first = refs
+                    .iter()
+                    .min_by_key(|forward_ref| position(forward_ref.span))
+                    .unwrap();
+                (signature, first)
+            })
+            .collect();
+        unresolved.sort_by_key(|(signature, first)| (position(first.span), signature.name));
+        if !unresolved.is_empty() {
+            let errors = unresolved
+                .into_iter()
+                .map(|(signature, first)| self.unresolved(signature, first))
+                .collect();
+            return Err(Error::combine(errors));
+        }
+        Ok(())
+    }
+
+    fn compile_entry(
+        &mut self,
+        StartArgs {
+            state_args,
+            symbol_args,
+        }: StartArgs<'a>,
+    ) -> Result<(), Error> {
+        let max = self.version.max_args();
+        if state_args.len() > max || symbol_args.len() > max {
             return Err(Error::new(
-                format!("no function with signature `{signature}`"),
-                Some(span),
+                format!("too many start arguments (max is {max})"),
+                None,
             ));
         }
 
         let start_signature = Signature {
             name: "start",
-            states: 0,
-            symbols: 0,
+            states: state_args.len() as u16,
+            symbols: symbol_args.len() as u16,
         };
+        let (states, symbols) = (start_signature.states, start_signature.symbols);
 
-        if let Some(&start_address) = self.addresses.get(&start_signature) {
-            self.bytes[2..6].copy_from_slice(&start_address.to_le_bytes());
-        } else {
-            return Err(Error::new("no `start` function".to_string(), None));
+        let Some(&start_address) = self.addresses.get(&start_signature) else {
+            let msg = if states == 0 && symbols == 0 {
+                "no `start` function".to_string()
+            } else {
+                format!("no `start` function with signature `{start_signature}`")
+            };
+            return Err(self.with_overloads(Error::new(msg, None), "start"));
+        };
+
+        let entry_address = self.bytes.len() as u32;
+        self.bytes[2..6].copy_from_slice(&entry_address.to_le_bytes());
+
+        let empty_map = HashMap::new();
+        for state_arg in state_args {
+            self.compile_to_state(
+                state_arg,
+                &empty_map,
+                &empty_map,
+                &mut HashMap::new(),
+                "",
+                false,
+            )?;
         }
+        for symbol_arg in symbol_args {
+            self.compile_symbol_arg(symbol_arg, &empty_map, "")?;
+        }
+
+        self.bytes.push(bc::FINAL_STATE);
+        self.bytes.extend(start_address.to_le_bytes());
 
         Ok(())
     }
@@ -83,24 +431,34 @@ impl Compiler {
     fn compile_state(
         &mut self,
         State {
+            doc,
             name,
             state_params,
             symbol_params,
             arms,
-        }: State,
+            ..
+        }: State<'a>,
+        index: usize,
     ) -> Result<(), Error> {
         self.increment_count(name.span)?;
 
-        let state_map = make_map(&state_params, "state")?;
-        let symbol_map = make_map(&symbol_params, "symbol")?;
+        let state_map = make_map(&state_params, "state", self.version)?;
+        let symbol_map = make_map(&symbol_params, "symbol", self.version)?;
 
         let address = self.bytes.len() as u32;
-        self.state_names.insert(address, name.name.to_string());
+        self.state_names.insert(
+            address,
+            StateInfo {
+                name: name.name.to_string(),
+                doc: doc.into_iter().map(str::to_string).collect(),
+            },
+        );
         let signature = Signature {
             name: name.name,
-            states: state_map.len() as u8,
-            symbols: symbol_map.len() as u8,
+            states: state_map.len() as u16,
+            symbols: symbol_map.len() as u16,
         };
+        self.check_exhaustive(signature, &name, &arms, &symbol_map)?;
 
         if let Some((_, refs)) = self.forward_refs.remove_entry(&signature) {
             let bytes = address.to_le_bytes();
@@ -112,18 +470,41 @@ impl Compiler {
             return Err(Error::new(
                 format!("a function with signature `{signature}` already exists"),
                 Some(name.span),
+            )
+            .with_label("redefined here".to_string())
+            .with_note(
+                self.definitions[&signature],
+                "first defined here".to_string(),
             ));
         }
+        self.definitions.insert(signature, name.span);
+        self.overloads
+            .entry(signature.name)
+            .or_default()
+            .push(signature);
 
         if arms.is_empty() {
-            self.bytes.push(bc::HALT);
+            self.push_halt(index, name.span);
         } else {
             let arm_count = arms.len();
             for (i, arm) in arms.into_iter().enumerate() {
                 let is_last_arm = i == arm_count - 1;
-                let is_catchall = self.compile_arm(arm, &state_map, &symbol_map, is_last_arm)?;
+                let start = self.bytes.len() as u32;
+                let span = match &arm.pattern {
+                    Pattern::Symbol(symbol) => symbol.span,
+                    Pattern::Name(name) => name.span,
+                };
+                let is_catchall =
+                    self.compile_arm(arm, address, &state_map, &symbol_map, is_last_arm)?;
+                self.source_map.push(SourceRange {
+                    start,
+                    end: self.bytes.len() as u32,
+                    state: index,
+                    arm: Some(i),
+                    span: span.to_buf(),
+                });
                 if is_last_arm && !is_catchall {
-                    self.bytes.push(bc::HALT);
+                    self.push_halt(index, name.span);
                 }
             }
         }
@@ -135,11 +516,13 @@ impl Compiler {
         &mut self,
         Arm {
             pattern,
+            lookahead,
             ops,
             to_state,
-        }: Arm,
-        state_map: &HashMap<&'static str, u8>,
-        symbol_map: &HashMap<&'static str, u8>,
+        }: Arm<'a>,
+        state_address: u32,
+        state_map: &HashMap<&'a str, u16>,
+        symbol_map: &HashMap<&'a str, u16>,
         is_last_arm: bool,
     ) -> Result<bool, Error> {
         let pattern_span = match &pattern {
@@ -147,12 +530,30 @@ impl Compiler {
             Pattern::Name(name) => name.span,
         };
 
-        let bound = self.compile_pattern(pattern, symbol_map, is_last_arm)?;
+        let lookahead_start = if let Some(lookahead) = lookahead {
+            let start = self.bytes.len();
+            self.compile_lookahead(lookahead, symbol_map)?;
+            self.bytes.extend(u16::MAX.to_le_bytes());
+            Some(start)
+        } else {
+            None
+        };
+
+        let pattern_start = self.bytes.len();
+        let bound = self.compile_pattern(
+            pattern,
+            symbol_map,
+            is_last_arm || lookahead_start.is_some(),
+        )?;
 
-        let location = self.bytes.len();
         if bound.is_empty() {
             self.bytes.extend(u16::MAX.to_le_bytes());
         }
+        self.arms.push(ArmInfo {
+            offset: self.bytes.len() as u32,
+            state: state_address,
+            span: pattern_span.to_buf(),
+        });
 
         self.compile_ops(OpIter(ops.into()), symbol_map, bound)?;
 
@@ -160,42 +561,104 @@ impl Compiler {
         count_state_args(&to_state, &mut counts)?;
         self.compile_to_state(to_state, state_map, symbol_map, &mut counts, bound, true)?;
 
+        // the pattern comes after the lookahead, so it's patched first in case it
+        // has to be widened
         if bound.is_empty() {
-            let jump_size = self.bytes.len() - location - 2;
-            match TryInto::<u16>::try_into(jump_size) {
-                Ok(jump_size) => {
-                    let bytes = jump_size.to_le_bytes();
-                    self.bytes[location..location + 2].copy_from_slice(&bytes);
-                }
-                Err(_) => {
-                    return Err(Error::new(
-                        "this arm is too complicated".to_string(),
-                        Some(pattern_span),
-                    ))
-                }
+            self.patch_jump(pattern_start);
+        }
+        if let Some(start) = lookahead_start {
+            self.patch_jump(start);
+        }
+
+        Ok(!bound.is_empty() && lookahead_start.is_none())
+    }
+
+    fn push_halt(&mut self, index: usize, span: Span<'a>) {
+        let start = self.bytes.len() as u32;
+        self.bytes.push(bc::HALT);
+        self.source_map.push(SourceRange {
+            start,
+            end: start + 1,
+            state: index,
+            arm: None,
+            span: span.to_buf(),
+        });
+    }
+
+    // patches the skip of the pattern at `start` to jump to the end of the arm. if
+    // the arm is too long for a 16-bit skip, the pattern is swapped for its 32-bit
+    // version and everything after it moves along
+    fn patch_jump(&mut self, start: usize) {
+        let opcode = self.bytes[start];
+        let location = start + 1 + bc::operands(opcode).unwrap()[0].size(self.version);
+        let jump_size = self.bytes.len() - location - 2;
+        if let Ok(jump_size) = u16::try_from(jump_size) {
+            self.bytes[location..location + 2].copy_from_slice(&jump_size.to_le_bytes());
+            return;
+        }
+
+        self.bytes[start] = bc::wide(opcode).unwrap();
+        self.bytes
+            .splice(location..location + 2, (jump_size as u32).to_le_bytes());
+        for f_ref in self.forward_refs.values_mut().flatten() {
+            if f_ref.location > location {
+                f_ref.location += 2;
             }
         }
+        let arm = self.arms.last_mut().unwrap();
+        if arm.offset as usize > location {
+            arm.offset += 2;
+        }
+    }
 
-        Ok(!bound.is_empty())
+    fn compile_lookahead(
+        &mut self,
+        lookahead: Pattern<'a>,
+        symbol_map: &HashMap<&'a str, u16>,
+    ) -> Result<(), Error> {
+        match lookahead {
+            Pattern::Symbol(symbol) => {
+                let value = self.insert_symbol(symbol)?;
+                self.bytes.push(bc::PEEK_VAL);
+                self.push_symbol(value);
+                Ok(())
+            }
+            Pattern::Name(name) => {
+                if let Some(&arg_index) = symbol_map.get(name.name) {
+                    self.bytes.push(bc::PEEK_ARG);
+                    self.push_arg(arg_index);
+                    Ok(())
+                } else {
+                    Err(Error::new(
+                        format!(
+                            "no symbol parameter with name `{}` (a lookahead must be a symbol or symbol parameter)",
+                            name.name
+                        ),
+                        Some(name.span),
+                    )
+                    .with_help(suggest_names(name.name, symbol_map.keys().copied())))
+                }
+            }
+        }
     }
 
     fn compile_pattern(
         &mut self,
-        pattern: Pattern,
-        symbol_map: &HashMap<&'static str, u8>,
+        pattern: Pattern<'a>,
+        symbol_map: &HashMap<&'a str, u16>,
         is_last_arm: bool,
-    ) -> Result<&'static str, Error> {
+    ) -> Result<&'a str, Error> {
         match pattern {
             Pattern::Symbol(symbol) => {
-                let value = self.symbols.insert(symbol)?;
+                let value = self.insert_symbol(symbol)?;
                 self.bytes.push(bc::COMPARE_VAL);
-                self.bytes.extend(value.to_le_bytes());
+                self.push_symbol(value);
                 Ok("")
             }
             Pattern::Name(name) => {
                 if let Some(&arg_index) = symbol_map.get(name.name) {
                     self.bytes.push(bc::COMPARE_ARG);
-                    self.bytes.push(arg_index);
+                    self.push_arg(arg_index);
                     Ok("")
                 } else if is_last_arm {
                     self.bytes.push(bc::OTHER);
@@ -210,59 +673,35 @@ impl Compiler {
         }
     }
 
+    // moves the head `n` cells with as few instructions as it can
+    fn compile_move(&mut self, n: usize, one: u8, short: u8, long: u8) {
+        push_move(&mut self.bytes, n, one, short, long);
+    }
+
     fn compile_ops(
         &mut self,
-        ops: OpIter,
-        symbol_map: &HashMap<&'static str, u8>,
+        ops: OpIter<'a>,
+        symbol_map: &HashMap<&'a str, u16>,
         bound: &str,
     ) -> Result<(), Error> {
         for op in ops {
             match op {
-                MultiOp::Left(mut n) => {
-                    while n >= 255 {
-                        self.bytes.push(bc::LEFT_N);
-                        self.bytes.push(255);
-                        n -= 255;
-                    }
-
-                    if n == 1 {
-                        self.bytes.push(bc::LEFT);
-                    } else if n > 0 {
-                        self.bytes.push(bc::LEFT_N);
-                        self.bytes.push(n as u8);
-                    }
-                }
-                MultiOp::Right(mut n) => {
-                    while n >= 255 {
-                        self.bytes.push(bc::RIGHT_N);
-                        self.bytes.push(255);
-                        n -= 255;
-                    }
-
-                    if n == 1 {
-                        self.bytes.push(bc::RIGHT);
-                    } else if n > 0 {
-                        self.bytes.push(bc::RIGHT_N);
-                        self.bytes.push(n as u8);
-                    }
-                }
+                MultiOp::Left(n) => self.compile_move(n, bc::LEFT, bc::LEFT_N, bc::LEFT_N16),
+                MultiOp::Right(n) => self.compile_move(n, bc::RIGHT, bc::RIGHT_N, bc::RIGHT_N16),
                 MultiOp::Name(name) => {
                     if let Some(&arg_index) = symbol_map.get(name.name) {
                         self.bytes.push(bc::WRITE_ARG);
-                        self.bytes.push(arg_index);
+                        self.push_arg(arg_index);
                     } else if name.name == bound {
                         self.bytes.push(bc::WRITE_BOUND);
                     } else {
-                        return Err(Error::new(
-                            format!("no value with name `{}`", name.name),
-                            Some(name.span),
-                        ));
+                        return Err(unknown_value(&name, symbol_map, bound));
                     }
                 }
                 MultiOp::Symbol(symbol) => {
-                    let value = self.symbols.insert(symbol)?;
+                    let value = self.insert_symbol(symbol)?;
                     self.bytes.push(bc::WRITE_VAL);
-                    self.bytes.extend(value.to_le_bytes());
+                    self.push_symbol(value);
                 }
             }
         }
@@ -271,10 +710,10 @@ impl Compiler {
 
     fn compile_to_state(
         &mut self,
-        to_state: ToState,
-        state_map: &HashMap<&'static str, u8>,
-        symbol_map: &HashMap<&'static str, u8>,
-        arg_counts: &mut HashMap<&'static str, usize>,
+        to_state: ToState<'a>,
+        state_map: &HashMap<&'a str, u16>,
+        symbol_map: &HashMap<&'a str, u16>,
+        arg_counts: &mut HashMap<&'a str, usize>,
         bound: &str,
         is_outer: bool,
     ) -> Result<(), Error> {
@@ -289,37 +728,38 @@ impl Compiler {
                         for (arg, count) in arg_counts {
                             if *count == 0 {
                                 self.bytes.push(bc::FREE_ARG);
-                                self.bytes.push(state_map[arg]);
+                                self.push_arg(state_map[arg]);
                             }
                         }
                         self.bytes.push(bc::FINAL_ARG);
-                        self.bytes.push(state_map[name.name]);
+                        self.push_arg(state_map[name.name]);
                         Ok(())
                     } else {
                         self.bytes.push(bc::TAKE_ARG);
-                        self.bytes.push(state_map[name.name]);
+                        self.push_arg(state_map[name.name]);
                         Ok(())
                     }
                 }
                 Some(&count) => {
                     self.bytes.push(bc::CLONE_ARG);
-                    self.bytes.push(state_map[name.name]);
+                    self.push_arg(state_map[name.name]);
                     arg_counts.insert(name.name, count - 1);
                     Ok(())
                 }
                 None => {
-                    if state_args.len() > 255 {
+                    let max = self.version.max_args();
+                    if state_args.len() > max {
                         return Err(Error::new(
                             format!(
-                                "`{}` is called with too many state arguments (max is 255)",
+                                "`{}` is called with too many state arguments (max is {max})",
                                 name.name
                             ),
                             Some(name.span),
                         ));
-                    } else if symbol_args.len() > 255 {
+                    } else if symbol_args.len() > max {
                         return Err(Error::new(
                             format!(
-                                "`{}` is called with too many symbol arguments (max is 255)",
+                                "`{}` is called with too many symbol arguments (max is {max})",
                                 name.name
                             ),
                             Some(name.span),
@@ -328,8 +768,14 @@ impl Compiler {
 
                     let signature = Signature {
                         name: name.name,
-                        states: state_args.len() as u8,
-                        symbols: symbol_args.len() as u8,
+                        states: state_args.len() as u16,
+                        symbols: symbol_args.len() as u16,
+                    };
+
+                    let scope = if state_args.is_empty() && symbol_args.is_empty() {
+                        state_map.keys().copied().collect()
+                    } else {
+                        Vec::new()
                     };
 
                     for state_arg in state_args {
@@ -339,39 +785,20 @@ impl Compiler {
                     }
 
                     for symbol_arg in symbol_args {
-                        match symbol_arg {
-                            Pattern::Symbol(symbol) => {
-                                let value = self.symbols.insert(symbol)?;
-                                self.bytes.push(bc::SYMBOL_VAL);
-                                self.bytes.extend(&value.to_le_bytes());
-                            }
-                            Pattern::Name(name) => {
-                                if let Some(&arg_index) = symbol_map.get(name.name) {
-                                    self.bytes.push(bc::SYMBOL_ARG);
-                                    self.bytes.push(arg_index);
-                                } else if name.name == bound {
-                                    self.bytes.push(bc::SYMBOL_BOUND);
-                                } else {
-                                    return Err(Error::new(
-                                        format!("no value with name `{}`", name.name),
-                                        Some(name.span),
-                                    ));
-                                }
-                            }
-                        }
+                        self.compile_symbol_arg(symbol_arg, symbol_map, bound)?;
                     }
 
                     match (self.addresses.get(&signature), is_outer) {
                         (Some(&address), false) => {
                             self.bytes.push(bc::MAKE_STATE);
-                            self.bytes.push(signature.states);
+                            self.push_arg(signature.states);
                             self.bytes.extend(&address.to_le_bytes());
                         }
                         (Some(&address), true) => {
                             for (arg, count) in arg_counts {
                                 if *count == 0 {
                                     self.bytes.push(bc::FREE_ARG);
-                                    self.bytes.push(state_map[arg]);
+                                    self.push_arg(state_map[arg]);
                                 }
                             }
 
@@ -380,11 +807,12 @@ impl Compiler {
                         }
                         (None, false) => {
                             self.bytes.push(bc::MAKE_STATE);
-                            self.bytes.push(signature.states);
+                            self.push_arg(signature.states);
 
                             let forward_ref = ForwardRef {
                                 location: self.bytes.len(),
                                 span: name.span,
+                                scope,
                             };
                             self.bytes.extend(&u32::MAX.to_le_bytes());
 
@@ -399,7 +827,7 @@ impl Compiler {
                             for (arg, count) in arg_counts {
                                 if *count == 0 {
                                     self.bytes.push(bc::FREE_ARG);
-                                    self.bytes.push(state_map[arg]);
+                                    self.push_arg(state_map[arg]);
                                 }
                             }
 
@@ -408,6 +836,7 @@ impl Compiler {
                             let forward_ref = ForwardRef {
                                 location: self.bytes.len(),
                                 span: name.span,
+                                scope,
                             };
 
                             self.bytes.extend(&u32::MAX.to_le_bytes());
@@ -431,7 +860,7 @@ impl Compiler {
                     Ok(())
                 } else {
                     self.bytes.push(bc::MAKE_STATE);
-                    self.bytes.push(0);
+                    self.push_arg(0);
                     self.bytes.extend(bc::HALT_ADDRESS.to_le_bytes());
                     Ok(())
                 }
@@ -439,46 +868,283 @@ impl Compiler {
         }
     }
 
-    fn increment_count(&mut self, span: Span) -> Result<(), Error> {
-        let bytes = [self.bytes[0], self.bytes[1]];
-        let count = u16::from_le_bytes(bytes);
-        if let Some(new_count) = count.checked_add(1) {
-            let bytes = new_count.to_le_bytes();
-            self.bytes[..2].copy_from_slice(&bytes);
+    fn compile_symbol_arg(
+        &mut self,
+        symbol_arg: Pattern<'a>,
+        symbol_map: &HashMap<&'a str, u16>,
+        bound: &str,
+    ) -> Result<(), Error> {
+        match symbol_arg {
+            Pattern::Symbol(symbol) => {
+                let value = self.insert_symbol(symbol)?;
+                self.bytes.push(bc::SYMBOL_VAL);
+                self.push_symbol(value);
+            }
+            Pattern::Name(name) => {
+                if let Some(&arg_index) = symbol_map.get(name.name) {
+                    self.bytes.push(bc::SYMBOL_ARG);
+                    self.push_arg(arg_index);
+                } else if name.name == bound {
+                    self.bytes.push(bc::SYMBOL_BOUND);
+                } else {
+                    return Err(unknown_value(&name, symbol_map, bound));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn unresolved(&self, signature: &Signature, forward_ref: &ForwardRef) -> Error {
+        let span = forward_ref.span;
+        if self.overloads.contains_key(signature.name) {
+            let error = Error::new(
+                format!("wrong number of arguments for `{}`", signature.name),
+                Some(span),
+            )
+            .with_label(format!("called with {}", arguments(signature)));
+            self.with_overloads(error, signature.name)
+        } else {
+            Error::new(
+                format!("no function with signature `{signature}`"),
+                Some(span),
+            )
+            .with_help(self.suggest_signatures(signature, &forward_ref.scope))
+        }
+    }
+
+    fn with_overloads(&self, mut error: Error, name: &str) -> Error {
+        let mut overloads = self.overloads.get(name).cloned().unwrap_or_default();
+        overloads.sort_by_key(|overload| (overload.states, overload.symbols));
+        for overload in overloads {
+            error = error.with_note(
+                self.definitions[&overload],
+                format!("`{overload}` takes {}", arguments(&overload)),
+            );
+        }
+        error
+    }
+
+    fn suggest_signatures(&self, signature: &Signature, scope: &[&'a str]) -> Option<String> {
+        let mut candidates: Vec<_> = self
+            .addresses
+            .keys()
+            .filter(|candidate| *candidate != signature)
+            .copied()
+            .chain(scope.iter().map(|&name| Signature {
+                name,
+                states: 0,
+                symbols: 0,
+            }))
+            .collect();
+        candidates.sort_by_key(|candidate| {
+            let same_arity =
+                candidate.states == signature.states && candidate.symbols == signature.symbols;
+            (
+                !same_arity,
+                candidate.name,
+                candidate.states,
+                candidate.symbols,
+            )
+        });
+        candidates.dedup();
+
+        let suggestions: Vec<_> = suggest::closest(
+            signature.name,
+            candidates
+                .into_iter()
+                .map(|candidate| (candidate.name, candidate.to_string())),
+        );
+        suggest::did_you_mean(&suggestions)
+    }
+
+    fn increment_count(&mut self, span: Span<'a>) -> Result<(), Error> {
+        let count = bc::state_count(&self.bytes) as usize;
+        let max = self.version.max_states();
+        if count < max {
+            bc::set_state_count(&mut self.bytes, count as u32 + 1);
             Ok(())
         } else {
             Err(Error::new(
-                "too many states in program (max is 65536)".to_string(),
+                format!("too many states in program (max is {max})"),
                 Some(span),
             ))
         }
     }
+
+    fn push_arg(&mut self, index: u16) {
+        match self.version {
+            bc::Version::V1 => self.bytes.push(index as u8),
+            bc::Version::V2 => self.bytes.extend(index.to_le_bytes()),
+        }
+    }
+
+    fn push_symbol(&mut self, id: u32) {
+        match self.version {
+            bc::Version::V1 => self.bytes.extend((id as u16).to_le_bytes()),
+            bc::Version::V2 => self.bytes.extend(id.to_le_bytes()),
+        }
+    }
+}
+
+// the oldest bytecode version with room for every state, symbol and argument
+fn pick_version(
+    unit: &Unit,
+    tape: &[Symbol],
+    start_args: &StartArgs,
+    table: Option<&[String]>,
+) -> bc::Version {
+    let mut scan = VersionScan {
+        symbols: HashSet::from([""]),
+        args: 0,
+    };
+    scan.symbols
+        .extend(tape.iter().map(|symbol| symbol.symbol.as_str()));
+    if let Some(alphabet) = &unit.alphabet {
+        scan.symbols
+            .extend(alphabet.symbols.iter().map(|symbol| symbol.symbol.as_str()));
+    }
+    scan.call(&start_args.state_args, &start_args.symbol_args);
+    for state in &unit.states {
+        scan.args = scan
+            .args
+            .max(state.state_params.len())
+            .max(state.symbol_params.len());
+        for arm in &state.arms {
+            scan.pattern(&arm.pattern);
+            if let Some(lookahead) = &arm.lookahead {
+                scan.pattern(lookahead);
+            }
+            for op in &arm.ops {
+                if let Op::Symbol(symbol) = op {
+                    scan.symbols.insert(&symbol.symbol);
+                }
+            }
+            scan.target(&arm.to_state);
+        }
+    }
+
+    let symbols = table.map_or(scan.symbols.len(), <[String]>::len);
+    let v1 = bc::Version::V1;
+    if unit.states.len() > v1.max_states()
+        || symbols > v1.max_symbols()
+        || scan.args > v1.max_args()
+    {
+        bc::Version::V2
+    } else {
+        v1
+    }
+}
+
+struct VersionScan<'a> {
+    symbols: HashSet<&'a str>,
+    // the most arguments of one kind in any signature
+    args: usize,
+}
+
+impl<'a> VersionScan<'a> {
+    fn pattern(&mut self, pattern: &'a Pattern) {
+        if let Pattern::Symbol(symbol) = pattern {
+            self.symbols.insert(&symbol.symbol);
+        }
+    }
+
+    fn call(&mut self, state_args: &'a [ToState], symbol_args: &'a [Pattern]) {
+        self.args = self.args.max(state_args.len()).max(symbol_args.len());
+        for state_arg in state_args {
+            self.target(state_arg);
+        }
+        for symbol_arg in symbol_args {
+            self.pattern(symbol_arg);
+        }
+    }
+
+    fn target(&mut self, to_state: &'a ToState) {
+        if let ToState::State {
+            state_args,
+            symbol_args,
+            ..
+        } = to_state
+        {
+            self.call(state_args, symbol_args);
+        }
+    }
 }
 
-fn make_map(params: &[Name], kind: &str) -> Result<HashMap<&'static str, u8>, Error> {
+fn make_map<'a>(
+    params: &[Name<'a>],
+    kind: &str,
+    version: bc::Version,
+) -> Result<HashMap<&'a str, u16>, Error> {
     let mut map = HashMap::new();
 
-    for name in params {
+    for (i, name) in params.iter().enumerate() {
         if map.contains_key(name.name) {
+            let first = params[..i]
+                .iter()
+                .find(|param| param.name == name.name)
+                .unwrap();
             return Err(Error::new(
                 format!("duplicate {kind} parameter `{}`", name.name),
                 Some(name.span),
-            ));
-        } else if map.len() == 256 {
+            )
+            .with_note(first.span, "first declared here".to_string()));
+        } else if map.len() == version.max_args() {
             return Err(Error::new(
-                format!("too many {kind} parameters (max is 255)"),
+                format!("too many {kind} parameters (max is {})", version.max_args()),
                 Some(name.span),
             ));
         }
-        map.insert(name.name, map.len() as u8);
+        map.insert(name.name, map.len() as u16);
     }
 
     Ok(map)
 }
 
-fn count_state_args(
-    state: &ToState,
-    counts: &mut HashMap<&'static str, usize>,
+// the shortest way to move `n` cells with `one`, `short` (an 8-bit count) and
+// `long` (a 16-bit count)
+fn push_move(bytes: &mut Vec<u8>, mut n: usize, one: u8, short: u8, long: u8) {
+    while n > u8::MAX as usize {
+        let step = n.min(u16::MAX as usize);
+        bytes.push(long);
+        bytes.extend((step as u16).to_le_bytes());
+        n -= step;
+    }
+
+    if n == 1 {
+        bytes.push(one);
+    } else if n > 0 {
+        bytes.push(short);
+        bytes.push(n as u8);
+    }
+}
+
+fn unknown_value(name: &Name, symbol_map: &HashMap<&str, u16>, bound: &str) -> Error {
+    let bound = Some(bound).filter(|bound| !bound.is_empty());
+    Error::new(
+        format!("no value with name `{}`", name.name),
+        Some(name.span),
+    )
+    .with_help(suggest_names(
+        name.name,
+        symbol_map.keys().copied().chain(bound),
+    ))
+}
+
+fn suggest_names<'a>(name: &str, candidates: impl Iterator<Item = &'a str>) -> Option<String> {
+    let mut candidates: Vec<_> = candidates.collect();
+    candidates.sort();
+    let suggestions = suggest::closest(
+        name,
+        candidates
+            .into_iter()
+            .map(|candidate| (candidate, candidate.to_string())),
+    );
+    suggest::did_you_mean(&suggestions)
+}
+
+fn count_state_args<'a>(
+    state: &ToState<'a>,
+    counts: &mut HashMap<&'a str, usize>,
 ) -> Result<(), Error> {
     match state {
         ToState::State {
@@ -511,13 +1177,64 @@ fn count_state_args(
 }
 
 #[derive(PartialEq, Eq, Hash, Clone, Copy)]
-struct Signature {
-    name: &'static str,
-    states: u8,
-    symbols: u8,
+pub struct Signature<'a> {
+    pub name: &'a str,
+    pub states: u16,
+    pub symbols: u16,
+}
+
+/// A `Signature` that owns its name, for compiled machines that outlive their
+/// source
+#[derive(PartialEq, Eq, Hash, Clone, Debug)]
+pub struct SignatureBuf {
+    pub name: String,
+    pub states: u16,
+    pub symbols: u16,
+}
+
+impl SignatureBuf {
+    pub fn as_signature(&self) -> Signature<'_> {
+        Signature {
+            name: &self.name,
+            states: self.states,
+            symbols: self.symbols,
+        }
+    }
 }
 
-impl std::fmt::Display for Signature {
+impl std::fmt::Display for SignatureBuf {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.as_signature().fmt(f)
+    }
+}
+
+impl<'a> Signature<'a> {
+    pub fn to_buf(self) -> SignatureBuf {
+        SignatureBuf {
+            name: self.name.to_string(),
+            states: self.states,
+            symbols: self.symbols,
+        }
+    }
+
+    pub fn of(state: &State<'a>) -> Self {
+        Signature {
+            name: state.name.name,
+            states: state.state_params.len() as u16,
+            symbols: state.symbol_params.len() as u16,
+        }
+    }
+
+    pub fn start(start_args: &StartArgs) -> Self {
+        Signature {
+            name: "start",
+            states: start_args.state_args.len() as u16,
+            symbols: start_args.symbol_args.len() as u16,
+        }
+    }
+}
+
+impl std::fmt::Display for Signature<'_> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(f, "{}", self.name)?;
         match (self.states, self.symbols) {
@@ -553,42 +1270,127 @@ impl std::fmt::Display for Signature {
     }
 }
 
-struct ForwardRef {
+fn position(span: Span) -> (usize, usize) {
+    (span.line, span.prefix.len())
+}
+
+fn arguments(signature: &Signature) -> String {
+    if signature.states == 0 && signature.symbols == 0 {
+        return "no arguments".to_string();
+    }
+    let count = |n, kind| match n {
+        0 => format!("no {kind} arguments"),
+        1 => format!("1 {kind} argument"),
+        _ => format!("{n} {kind} arguments"),
+    };
+    format!(
+        "{} and {}",
+        count(signature.states, "state"),
+        count(signature.symbols, "symbol")
+    )
+}
+
+struct ForwardRef<'a> {
     location: usize,
-    span: Span,
+    span: Span<'a>,
+    scope: Vec<&'a str>,
 }
 
-struct Symbols(HashMap<String, u16>);
+struct Symbols {
+    ids: HashMap<String, u32>,
+    // a table from a symbols file can't grow
+    fixed: bool,
+    max: usize,
+}
 
 impl Symbols {
-    fn new() -> Self {
-        Symbols(HashMap::from([(String::new(), 0)]))
+    fn new(version: bc::Version) -> Self {
+        Symbols {
+            ids: HashMap::from([(String::new(), 0)]),
+            fixed: false,
+            max: version.max_symbols(),
+        }
     }
 
-    fn insert(&mut self, symbol: Symbol) -> Result<u16, Error> {
+    fn fixed(table: &[String], version: bc::Version) -> Result<Self, Error> {
+        let max = version.max_symbols();
+        if table.first().is_none_or(|blank| !blank.is_empty()) {
+            return Err(Error::new(
+                "the symbol table has to start with the blank symbol `''`".to_string(),
+                None,
+            ));
+        }
+        if table.len() > max {
+            return Err(Error::new(
+                format!("too many symbols in the symbol table (max is {max})"),
+                None,
+            ));
+        }
+
+        let mut ids = HashMap::new();
+        for (id, symbol) in table.iter().enumerate() {
+            if ids.insert(symbol.clone(), id as u32).is_some() {
+                return Err(Error::new(
+                    format!(
+                        "symbol `{}` is in the symbol table twice",
+                        tape::literal(symbol)
+                    ),
+                    None,
+                ));
+            }
+        }
+        Ok(Symbols {
+            ids,
+            fixed: true,
+            max,
+        })
+    }
+
+    fn insert(&mut self, symbol: Symbol) -> Result<u32, Error> {
         let Symbol { symbol, span } = symbol;
-        let len = self.0.len();
-        if let Some(&value) = self.0.get(&symbol) {
+        let len = self.ids.len();
+        if let Some(&value) = self.ids.get(&symbol) {
             Ok(value)
+        } else if self.fixed {
+            let mut table: Vec<_> = self.ids.iter().collect();
+            table.sort_by_key(|&(_, id)| id);
+            let suggestions = suggest::closest(
+                &symbol,
+                table
+                    .into_iter()
+                    .map(|(candidate, _)| (candidate.as_str(), tape::literal(candidate))),
+            );
+            Err(Error::new(
+                format!(
+                    "symbol `{}` is not in the symbol table",
+                    tape::literal(&symbol)
+                ),
+                Some(span),
+            )
+            .with_help(suggest::did_you_mean(&suggestions).or_else(|| {
+                Some(
+                    "add it to the end of the symbols file so existing ids don't change"
+                        .to_string(),
+                )
+            })))
         } else {
-            match len.try_into() {
-                Ok(value) => {
-                    self.0.insert(symbol, value);
-                    Ok(value)
-                }
-                Err(_) => Err(Error::new(
-                    "too many unique symbols in program (max is 65536)".to_string(),
+            if len < self.max {
+                self.ids.insert(symbol, len as u32);
+                Ok(len as u32)
+            } else {
+                Err(Error::new(
+                    format!("too many unique symbols in program (max is {})", self.max),
                     Some(span),
-                )),
+                ))
             }
         }
     }
 }
 
-struct OpIter(VecDeque<Op>);
+struct OpIter<'a>(VecDeque<Op<'a>>);
 
-impl OpIter {
-    fn count_moves(&mut self, mut offset: isize) -> Option<MultiOp> {
+impl<'a> OpIter<'a> {
+    fn count_moves(&mut self, mut offset: isize) -> Option<MultiOp<'a>> {
         while matches!(self.0.front(), Some(Op::Left(_) | Op::Right(_))) {
             match self.0.pop_front() {
                 Some(Op::Left(_)) => offset -= 1,
@@ -607,8 +1409,8 @@ impl OpIter {
     }
 }
 
-impl Iterator for OpIter {
-    type Item = MultiOp;
+impl<'a> Iterator for OpIter<'a> {
+    type Item = MultiOp<'a>;
     fn next(&mut self) -> Option<Self::Item> {
         match self.0.pop_front()? {
             Op::Left(_) => self.count_moves(-1),
@@ -619,9 +1421,9 @@ impl Iterator for OpIter {
     }
 }
 
-enum MultiOp {
+enum MultiOp<'a> {
     Left(usize),
     Right(usize),
-    Name(Name),
-    Symbol(Symbol),
+    Name(Name<'a>),
+    Symbol(Symbol<'a>),
 }
@@ -0,0 +1,173 @@
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use serde::Deserialize;
+
+use crate::error::Error;
+
+/// The name of a project's config file, which applies in its directory and
+/// every directory under it
+pub const PROJECT_FILE: &str = "tml.toml";
+
+/// Defaults for command line options, from `~/.config/tml/config.toml`, then
+/// the nearest `tml.toml` and then `TML_*` environment variables, each
+/// overriding what came before. Options given on the command line override all
+/// of them
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    pub max_moves: Option<usize>,
+    pub max_tape_cells: Option<u64>,
+    pub two_sided: Option<bool>,
+    pub decimal_radix: Option<u32>,
+    pub decimal_digits: Option<u32>,
+    pub decimal_start: Option<u32>,
+    pub decimal_stride: Option<u32>,
+    /// `false` turns color off like `--no-color`, and `true` turns it on even
+    /// when stdout isn't a terminal
+    pub color: Option<bool>,
+    pub vm: Option<Vm>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Vm {
+    C,
+    Rust,
+}
+
+impl FromStr for Vm {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "c" => Ok(Vm::C),
+            "rust" => Ok(Vm::Rust),
+            _ => Err("expected `c` or `rust`".to_string()),
+        }
+    }
+}
+
+impl Config {
+    /// Reads every layer there is. A missing file is fine, but one that can't be
+    /// read or parsed is an error
+    pub fn load() -> Result<Config, Error> {
+        let mut config = Config::default();
+        if let Some(path) = user_file().filter(|path| path.is_file()) {
+            config = read(&path)?.over(config);
+        }
+        if let Some(path) = project_file() {
+            config = read(&path)?.over(config);
+        }
+        Ok(from_env()?.over(config))
+    }
+
+    // `self`'s settings, and `base`'s where `self` doesn't have one
+    fn over(self, base: Config) -> Config {
+        Config {
+            max_moves: self.max_moves.or(base.max_moves),
+            max_tape_cells: self.max_tape_cells.or(base.max_tape_cells),
+            two_sided: self.two_sided.or(base.two_sided),
+            decimal_radix: self.decimal_radix.or(base.decimal_radix),
+            decimal_digits: self.decimal_digits.or(base.decimal_digits),
+            decimal_start: self.decimal_start.or(base.decimal_start),
+            decimal_stride: self.decimal_stride.or(base.decimal_stride),
+            color: self.color.or(base.color),
+            vm: self.vm.or(base.vm),
+        }
+    }
+
+    // the same limits the flags have. `setting` says where a key was set
+    fn check(self, setting: impl Fn(&str) -> String) -> Result<Config, Error> {
+        let out_of_range = |key: &str, range: &str| {
+            Err(Error::new(
+                format!("{} must be {range}", setting(key)),
+                None,
+            ))
+        };
+        if self.max_tape_cells == Some(0) {
+            return out_of_range("max_tape_cells", "at least 1");
+        }
+        if self
+            .decimal_radix
+            .is_some_and(|radix| !(1..=36).contains(&radix))
+        {
+            return out_of_range("decimal_radix", "from 1 to 36");
+        }
+        if self.decimal_digits.is_some_and(|digits| digits < 3) {
+            return out_of_range("decimal_digits", "at least 3");
+        }
+        if self.decimal_stride == Some(0) {
+            return out_of_range("decimal_stride", "at least 1");
+        }
+        Ok(self)
+    }
+}
+
+// `$XDG_CONFIG_HOME/tml/config.toml`, or `~/.config/tml/config.toml`
+fn user_file() -> Option<PathBuf> {
+    let dir = match env::var_os("XDG_CONFIG_HOME") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => PathBuf::from(env::var_os("HOME")?).join(".config"),
+    };
+    Some(dir.join("tml").join("config.toml"))
+}
+
+// the `tml.toml` in the current directory or the closest of its parents
+fn project_file() -> Option<PathBuf> {
+    let dir = env::current_dir().ok()?;
+    dir.ancestors()
+        .map(|dir| dir.join(PROJECT_FILE))
+        .find(|path| path.is_file())
+}
+
+fn read(path: &Path) -> Result<Config, Error> {
+    let Ok(text) = fs::read_to_string(path) else {
+        return Err(Error::new(
+            format!("couldn't read file {}", path.display()),
+            None,
+        ));
+    };
+    let config = toml::from_str::<Config>(&text).map_err(|error| {
+        let line = error
+            .span()
+            .map_or(1, |span| text[..span.start].lines().count().max(1));
+        Error::new(
+            format!(
+                "{} is not a valid config file (line {line}: {})",
+                path.display(),
+                error.message()
+            ),
+            None,
+        )
+    })?;
+    config.check(|key| format!("`{key}` in {}", path.display()))
+}
+
+fn from_env() -> Result<Config, Error> {
+    Config {
+        max_moves: var("TML_MAX_MOVES")?,
+        max_tape_cells: var("TML_MAX_TAPE_CELLS")?,
+        two_sided: var("TML_TWO_SIDED")?,
+        decimal_radix: var("TML_DECIMAL_RADIX")?,
+        decimal_digits: var("TML_DECIMAL_DIGITS")?,
+        decimal_start: var("TML_DECIMAL_START")?,
+        decimal_stride: var("TML_DECIMAL_STRIDE")?,
+        color: var("TML_COLOR")?,
+        vm: var("TML_VM")?,
+    }
+    .check(|key| format!("`TML_{}`", key.to_uppercase()))
+}
+
+// an unset or empty variable is no setting
+fn var<T: FromStr>(name: &str) -> Result<Option<T>, Error> {
+    match env::var(name) {
+        Ok(value) if !value.is_empty() => value
+            .parse()
+            .map(Some)
+            .map_err(|_| Error::new(format!("`{value}` isn't a valid value for `{name}`"), None)),
+        _ => Ok(None),
+    }
+}
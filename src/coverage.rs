@@ -0,0 +1,83 @@
+use std::collections::{BTreeMap, HashMap};
+
+use crate::backend::Observer;
+use crate::compile::{ArmInfo, Compiled};
+use crate::metrics::metric;
+
+/// Counts how many times each arm is taken
+pub struct Coverage {
+    // indexed by the offset of the arm's right hand side
+    hits: Vec<usize>,
+}
+
+impl Coverage {
+    pub fn new(compiled: &Compiled) -> Self {
+        Coverage {
+            hits: vec![0; compiled.bytes.len()],
+        }
+    }
+
+    pub fn hits(&self, arm: &ArmInfo) -> usize {
+        self.hits[arm.offset as usize]
+    }
+
+    pub fn print(&self, compiled: &Compiled) {
+        let signatures: HashMap<_, _> = compiled
+            .signatures
+            .iter()
+            .map(|(signature, &address)| (address, signature))
+            .collect();
+        let mut states: BTreeMap<u32, Vec<&ArmInfo>> = BTreeMap::new();
+        for arm in &compiled.arms {
+            states.entry(arm.state).or_default().push(arm);
+        }
+
+        let total = compiled.arms.len();
+        let taken = compiled
+            .arms
+            .iter()
+            .filter(|arm| self.hits(arm) > 0)
+            .count();
+        metric(
+            "arm coverage",
+            format!("{taken}/{total} ({})", percent(taken, total)),
+        );
+        for (address, arms) in &states {
+            let taken = arms.iter().filter(|arm| self.hits(arm) > 0).count();
+            let name = match (signatures.get(address), compiled.states.get(address)) {
+                (Some(signature), _) => signature.to_string(),
+                (None, Some(info)) => info.name.clone(),
+                (None, None) => format!("{address:#010x}"),
+            };
+            println!(
+                "    {name}: {taken}/{} ({})",
+                arms.len(),
+                percent(taken, arms.len())
+            );
+            for arm in arms.iter().filter(|arm| self.hits(arm) == 0) {
+                let span = &arm.span;
+                println!(
+                    "        {}:{}: {}",
+                    span.path.display(),
+                    span.line + 1,
+                    span.as_span().source_line()
+                );
+            }
+        }
+        println!();
+    }
+}
+
+impl Observer for Coverage {
+    fn arm(&mut self, offset: u32) {
+        self.hits[offset as usize] += 1;
+    }
+}
+
+fn percent(part: usize, whole: usize) -> String {
+    if whole == 0 {
+        "100%".to_string()
+    } else {
+        format!("{:.1}%", 100.0 * part as f64 / whole as f64)
+    }
+}
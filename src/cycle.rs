@@ -0,0 +1,298 @@
+use crate::backend::{self, HaltReason, Observer, Options, Simulated};
+use crate::error::Error;
+use crate::vm::{DenseTape, StepOutcome, Tape, Vm};
+
+/// Watches a run on the Rust VM for proof that the machine never halts (or just
+/// looks at each configuration, like `spacetime::SpaceTime`)
+pub trait Analyzer {
+    /// Looks at the machine before each move, and returns a `HaltReason::Cycle`
+    /// once it can tell the machine never halts
+    fn check<O: Observer>(&mut self, vm: &Vm<O>) -> Option<HaltReason>;
+}
+
+impl Analyzer for () {
+    fn check<O: Observer>(&mut self, _vm: &Vm<O>) -> Option<HaltReason> {
+        None
+    }
+}
+
+impl<A: Analyzer> Analyzer for &mut A {
+    fn check<O: Observer>(&mut self, vm: &Vm<O>) -> Option<HaltReason> {
+        (**self).check(vm)
+    }
+}
+
+impl<A: Analyzer, B: Analyzer> Analyzer for (A, B) {
+    fn check<O: Observer>(&mut self, vm: &Vm<O>) -> Option<HaltReason> {
+        self.0.check(vm).or_else(|| self.1.check(vm))
+    }
+}
+
+impl<A: Analyzer> Analyzer for Option<A> {
+    fn check<O: Observer>(&mut self, vm: &Vm<O>) -> Option<HaltReason> {
+        self.as_mut()?.check(vm)
+    }
+}
+
+/// Runs the machine on the Rust VM like `backend::observe`, but stops as soon
+/// as `analyzer` proves it never halts
+pub fn observe(
+    bytes: &[u8],
+    tape: &[u32],
+    options: &Options,
+    mut analyzer: impl Analyzer,
+    observer: impl Observer,
+) -> Result<Simulated, Error> {
+    let mut initial = DenseTape::new(tape.to_vec(), options.two_sided);
+    initial.max_cells = options.max_tape_cells;
+    let mut vm = Vm::observed(bytes, initial, observer);
+    // runs the entry code
+    let mut outcome = vm.run_for(0)?;
+    let mut cycle = None;
+    while outcome == StepOutcome::Paused && vm.moves() < options.max_moves {
+        cycle = analyzer.check(&vm);
+        if cycle.is_some() {
+            break;
+        }
+        outcome = vm.run_for(1)?;
+    }
+
+    let mut simulated = vm.finish()?;
+    if let Some(cycle) = cycle {
+        simulated.halt_reason = cycle;
+    }
+    backend::trim(&mut simulated, tape.len(), options.trim);
+    Ok(simulated)
+}
+
+/// Finds the machine getting back to a configuration it was in before: the
+/// same state (with the same arguments), head position and tape. From there it
+/// would make the same moves forever.
+///
+/// It checks every `interval` moves, and only keeps one earlier configuration,
+/// using Brent's algorithm: it's replaced after 1, 2, 4, 8, ... checks, so a
+/// cycle is found within a few times its length of entering it
+pub struct Cycles {
+    interval: usize,
+    // the configuration being looked for
+    saved: Saved,
+    // checks since it was saved, and how many there'll be before it's replaced
+    checks: usize,
+    power: usize,
+    // reused by every check, so checks don't allocate
+    state: Vec<u32>,
+}
+
+#[derive(Default)]
+struct Saved {
+    moves: usize,
+    address: u32,
+    head: i64,
+    state: Vec<u32>,
+    // the non-blank cells and where the first one is
+    start: i64,
+    cells: Vec<u32>,
+}
+
+impl Cycles {
+    pub fn new(interval: usize) -> Self {
+        Cycles {
+            interval,
+            saved: Saved::default(),
+            checks: 0,
+            power: 0,
+            state: Vec::new(),
+        }
+    }
+}
+
+impl Analyzer for Cycles {
+    fn check<O: Observer>(&mut self, vm: &Vm<O>) -> Option<HaltReason> {
+        let configuration = vm.configuration();
+        if !configuration.moves.is_multiple_of(self.interval) {
+            return None;
+        }
+        // cheapest first: most checks don't get past the address and head
+        if self.power > 0
+            && configuration.address == self.saved.address
+            && configuration.head == self.saved.head
+            && vm.tape().non_blank() == (self.saved.start, self.saved.cells.as_slice())
+        {
+            self.state.clear();
+            vm.write_state(&mut self.state);
+            if self.state == self.saved.state {
+                return Some(HaltReason::Cycle {
+                    first: self.saved.moves,
+                    repeat: configuration.moves,
+                    shift: 0,
+                });
+            }
+        }
+
+        self.checks += 1;
+        if self.checks >= self.power {
+            let (start, cells) = vm.tape().non_blank();
+            self.saved.moves = configuration.moves;
+            self.saved.address = configuration.address;
+            self.saved.head = configuration.head;
+            self.saved.state.clear();
+            vm.write_state(&mut self.saved.state);
+            self.saved.start = start;
+            self.saved.cells.clear();
+            self.saved.cells.extend(cells);
+            self.checks = 0;
+            self.power = (self.power * 2).max(1);
+        }
+        None
+    }
+}
+
+/// Finds the machine repeating itself while it drifts along the tape (a Lin
+/// recurrence). It looks at the moves where the head is further right than it's
+/// ever been, with nothing but blanks past it. If two of them are in the same
+/// state, and the cells from the head back to the furthest one it went to in
+/// between are the same, just shifted, then it'll do the same thing again from
+/// the second one, shifted again, forever. The same goes for drifting left on a
+/// two-sided tape (on a one-sided tape, that ends at the left edge).
+///
+/// Like `Cycles`, it only keeps one earlier move for each direction
+pub struct Translations {
+    right: Drift,
+    left: Option<Drift>,
+    // the first and last non-blank cells when it started. the cells past the
+    // furthest ones the head has been on haven't changed since
+    bounds: Option<(i64, i64)>,
+}
+
+struct Drift {
+    // 1 for right, -1 for left
+    direction: i64,
+    saved: Option<Record>,
+    checks: usize,
+    power: usize,
+    // the furthest the head has been in the other direction since `saved`
+    reach: i64,
+    state: Vec<u32>,
+}
+
+struct Record {
+    moves: usize,
+    address: u32,
+    head: i64,
+    state: Vec<u32>,
+    // the whole tape, with cells counted from `origin` like `Tape`
+    cells: Vec<u32>,
+    origin: usize,
+}
+
+impl Record {
+    fn cell(&self, position: i64) -> u32 {
+        usize::try_from(position + self.origin as i64)
+            .ok()
+            .and_then(|index| self.cells.get(index))
+            .copied()
+            .unwrap_or_default()
+    }
+}
+
+impl Translations {
+    pub fn new(two_sided: bool) -> Self {
+        Translations {
+            right: Drift::new(1),
+            left: two_sided.then(|| Drift::new(-1)),
+            bounds: None,
+        }
+    }
+}
+
+impl Analyzer for Translations {
+    fn check<O: Observer>(&mut self, vm: &Vm<O>) -> Option<HaltReason> {
+        let (first, last) = *self.bounds.get_or_insert_with(|| {
+            let (start, cells) = vm.tape().non_blank();
+            match cells.len() {
+                0 => (i64::MAX, i64::MIN),
+                len => (start, start + len as i64 - 1),
+            }
+        });
+        let tape = vm.tape();
+        let head = vm.configuration().head;
+        let rightmost = tape.rightmost as i64 - tape.origin as i64;
+        let cycle = self.right.check(vm, head == rightmost && head >= last);
+        match &mut self.left {
+            Some(left) => cycle.or_else(|| left.check(vm, head == tape.leftmost && head <= first)),
+            None => cycle,
+        }
+    }
+}
+
+impl Drift {
+    fn new(direction: i64) -> Self {
+        Drift {
+            direction,
+            saved: None,
+            checks: 0,
+            power: 1,
+            reach: 0,
+            state: Vec::new(),
+        }
+    }
+
+    // `record` is whether the head is the furthest it's been in `direction`, with
+    // only blanks past it
+    fn check<O: Observer>(&mut self, vm: &Vm<O>, record: bool) -> Option<HaltReason> {
+        let configuration = vm.configuration();
+        let head = configuration.head;
+        self.reach = match self.direction {
+            1 => self.reach.min(head),
+            _ => self.reach.max(head),
+        };
+        if !record {
+            return None;
+        }
+
+        if let Some(saved) = &self.saved {
+            if configuration.address == saved.address && head != saved.head {
+                let tape = vm.tape();
+                let shift = head - saved.head;
+                // the cells it could have read since `saved`, including the one
+                // right of the head that lookahead reads. past the head they're
+                // all blank anyway
+                let (low, high) = match self.direction {
+                    1 => (self.reach, saved.head),
+                    _ => (saved.head, self.reach + 1),
+                };
+                if (low..=high).all(|position| saved.cell(position) == tape.cell(position + shift))
+                {
+                    self.state.clear();
+                    vm.write_state(&mut self.state);
+                    if self.state == saved.state {
+                        return Some(HaltReason::Cycle {
+                            first: saved.moves,
+                            repeat: configuration.moves,
+                            shift,
+                        });
+                    }
+                }
+            }
+        }
+
+        self.checks += 1;
+        if self.checks >= self.power {
+            let tape = vm.tape();
+            let mut state = Vec::new();
+            vm.write_state(&mut state);
+            self.saved = Some(Record {
+                moves: configuration.moves,
+                address: configuration.address,
+                head,
+                state,
+                cells: tape.tape.clone(),
+                origin: tape.origin,
+            });
+            self.reach = head;
+            self.checks = 0;
+            self.power *= 2;
+        }
+        None
+    }
+}
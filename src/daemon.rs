@@ -0,0 +1,365 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use clap::ValueEnum;
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+use crate::backend::{self, Trim};
+use crate::compile::Compiled;
+use crate::error::Error;
+use crate::events::Address;
+use crate::lex::{self, Edition, Tokens};
+use crate::parse::{self, StartArgs};
+use crate::pass::PassManager;
+use crate::stats;
+use crate::symbols;
+use crate::warning;
+
+#[derive(Deserialize)]
+#[serde(tag = "method", rename_all = "kebab-case")]
+enum Request {
+    Check {
+        file: PathBuf,
+        #[serde(flatten)]
+        source: Source,
+    },
+    Run {
+        file: PathBuf,
+        tape: Option<PathBuf>,
+        #[serde(flatten)]
+        source: Source,
+        max_moves: Option<usize>,
+        trim: Option<String>,
+        #[serde(default)]
+        rust_vm: bool,
+    },
+    Shutdown,
+}
+
+// the same knobs as `SourceArguments` on the command line
+#[derive(Clone, Default, Deserialize, PartialEq, Eq, Hash)]
+#[serde(default)]
+struct Source {
+    start_args: Option<String>,
+    allow_tabs: bool,
+    no_bare_digits: bool,
+    edition: Edition,
+    symbols_file: Option<PathBuf>,
+}
+
+#[derive(PartialEq, Eq, Hash)]
+struct Key {
+    file: PathBuf,
+    tape: Option<PathBuf>,
+    source: Source,
+}
+
+// a compiled machine along with the text it was compiled from, so unchanged
+// files aren't leaked again
+struct Entry {
+    code: String,
+    tape: Option<String>,
+    symbols: Option<String>,
+    outcome: Arc<Outcome>,
+}
+
+struct Outcome {
+    // `Err` holds the diagnostics, warnings included
+    compiled: Result<Compiled, Value>,
+    states: usize,
+    warnings: Value,
+}
+
+struct Daemon {
+    address: Address,
+    cache: Mutex<HashMap<Key, Entry>>,
+    shutdown: AtomicBool,
+}
+
+/// Serves compile and run requests on `address` until a client asks it to shut down
+pub fn run(address: Address) -> Result<(), Error> {
+    let error = |what: &str, error: std::io::Error| {
+        Error::new(format!("couldn't {what} {address} ({error})"), None)
+    };
+    let listener = match &address {
+        Address::Unix(path) => {
+            Listener::Unix(UnixListener::bind(path).map_err(|e| error("listen on", e))?)
+        }
+        Address::Tcp(host) => {
+            Listener::Tcp(TcpListener::bind(host).map_err(|e| error("listen on", e))?)
+        }
+    };
+    println!("listening on {address}");
+
+    let daemon = Arc::new(Daemon {
+        address: address.clone(),
+        cache: Mutex::new(HashMap::new()),
+        shutdown: AtomicBool::new(false),
+    });
+    let result = loop {
+        let accepted = listener.accept();
+        if daemon.shutdown.load(Ordering::SeqCst) {
+            break Ok(());
+        }
+        let (reader, writer) = match accepted {
+            Ok(stream) => stream,
+            Err(e) => break Err(error("accept a connection on", e)),
+        };
+        let daemon = Arc::clone(&daemon);
+        thread::spawn(move || daemon.serve(reader, writer));
+    };
+
+    if let Address::Unix(path) = &address {
+        let _ = fs::remove_file(path);
+    }
+    result
+}
+
+enum Listener {
+    Unix(UnixListener),
+    Tcp(TcpListener),
+}
+
+type Stream = (Box<dyn Read + Send>, Box<dyn Write + Send>);
+
+impl Listener {
+    fn accept(&self) -> std::io::Result<Stream> {
+        match self {
+            Listener::Unix(listener) => {
+                let (stream, _) = listener.accept()?;
+                Ok((Box::new(stream.try_clone()?), Box::new(stream)))
+            }
+            Listener::Tcp(listener) => {
+                let (stream, _) = listener.accept()?;
+                Ok((Box::new(stream.try_clone()?), Box::new(stream)))
+            }
+        }
+    }
+}
+
+impl Daemon {
+    fn serve(&self, reader: Box<dyn Read + Send>, mut writer: Box<dyn Write + Send>) {
+        for line in BufReader::new(reader).lines() {
+            let Ok(line) = line else {
+                return;
+            };
+            if line.trim().is_empty() {
+                continue;
+            }
+
+            let (id, response) = match serde_json::from_str::<Value>(&line) {
+                Ok(request) => {
+                    let id = request.get("id").cloned().unwrap_or(Value::Null);
+                    match Request::deserialize(request) {
+                        Ok(Request::Shutdown) => {
+                            let _ = writeln!(writer, "{}", json!({ "id": id, "ok": true }));
+                            self.stop();
+                            return;
+                        }
+                        Ok(request) => (id, self.handle(request)),
+                        Err(e) => (id, failure(format!("invalid request ({e})"))),
+                    }
+                }
+                Err(e) => (Value::Null, failure(format!("invalid request ({e})"))),
+            };
+
+            let mut response = response;
+            response["id"] = id;
+            if writeln!(writer, "{response}").is_err() {
+                return;
+            }
+        }
+    }
+
+    fn handle(&self, request: Request) -> Value {
+        match request {
+            Request::Check { file, source } => {
+                let key = Key {
+                    file,
+                    tape: None,
+                    source,
+                };
+                let (outcome, cached) = match self.load(key) {
+                    Ok(loaded) => loaded,
+                    Err(response) => return response,
+                };
+                match &outcome.compiled {
+                    Ok(compiled) => json!({
+                        "ok": true,
+                        "cached": cached,
+                        "states": outcome.states,
+                        "bytes": compiled.bytes.len(),
+                        "diagnostics": outcome.warnings,
+                    }),
+                    Err(diagnostics) => json!({ "ok": false, "diagnostics": diagnostics }),
+                }
+            }
+            Request::Run {
+                file,
+                tape,
+                source,
+                max_moves,
+                trim,
+                rust_vm,
+            } => {
+                let trim = match trim {
+                    Some(trim) => match Trim::from_str(&trim, false) {
+                        Ok(trim) => trim,
+                        Err(_) => return failure(format!("unknown trim policy `{trim}`")),
+                    },
+                    None => Trim::TrailingBlanks,
+                };
+                let (outcome, cached) = match self.load(Key { file, tape, source }) {
+                    Ok(loaded) => loaded,
+                    Err(response) => return response,
+                };
+                let compiled = match &outcome.compiled {
+                    Ok(compiled) => compiled,
+                    Err(diagnostics) => return json!({ "ok": false, "diagnostics": diagnostics }),
+                };
+
+                let options = backend::Options {
+                    max_moves: max_moves.unwrap_or(usize::MAX),
+                    trim,
+                    table: true,
+                    two_sided: false,
+                    max_tape_cells: usize::MAX,
+                    block_size: None,
+                    tape_repr: backend::TapeRepr::Dense,
+                    tape_backing: backend::TapeBacking::Memory,
+                    transpositions: None,
+                    dispatch: None,
+                };
+                let simulated =
+                    match backend::select(rust_vm).run(&compiled.bytes, &compiled.tape, &options) {
+                        Ok(simulated) => simulated,
+                        Err(error) => return json!({ "ok": false, "diagnostics": error.to_json() }),
+                    };
+
+                let tape: Vec<_> = simulated
+                    .tape
+                    .iter()
+                    .map(|&i| compiled.symbols[i as usize].as_str())
+                    .collect();
+                json!({
+                    "ok": true,
+                    "cached": cached,
+                    "diagnostics": outcome.warnings,
+                    "tape": tape,
+                    "moves": simulated.moves,
+                    "head": simulated.head_position,
+                    "segments": stats::segments(&simulated.tape)
+                        .into_iter()
+                        .map(|segment| [segment.start, segment.end])
+                        .collect::<Vec<_>>(),
+                })
+            }
+            Request::Shutdown => unreachable!(),
+        }
+    }
+
+    // compiles the machine unless the same text was already compiled with the same options
+    fn load(&self, key: Key) -> Result<(Arc<Outcome>, bool), Value> {
+        let read = |path: &Path| {
+            fs::read_to_string(path)
+                .map_err(|_| failure(format!("couldn't read file {}", path.display())))
+        };
+        let code = read(&key.file)?;
+        let tape = key.tape.as_deref().map(read).transpose()?;
+        let symbols = key.source.symbols_file.as_deref().map(read).transpose()?;
+
+        if let Some(entry) = self.cache.lock().unwrap().get(&key) {
+            if entry.code == code && entry.tape == tape && entry.symbols == symbols {
+                return Ok((Arc::clone(&entry.outcome), true));
+            }
+        }
+
+        let outcome = Arc::new(compile(&key, &code, tape.as_deref(), symbols.as_deref()));
+        let entry = Entry {
+            code,
+            tape,
+            symbols,
+            outcome: Arc::clone(&outcome),
+        };
+        self.cache.lock().unwrap().insert(key, entry);
+        Ok((outcome, false))
+    }
+
+    fn stop(&self) {
+        self.shutdown.store(true, Ordering::SeqCst);
+        // wake up the accept loop so it sees the flag
+        match &self.address {
+            Address::Unix(path) => drop(UnixStream::connect(path)),
+            Address::Tcp(host) => drop(TcpStream::connect(host)),
+        }
+    }
+}
+
+fn compile(key: &Key, code: &str, tape: Option<&str>, symbols: Option<&str>) -> Outcome {
+    let options = lex::Options {
+        allow_tabs: key.source.allow_tabs,
+        bare_digits: !key.source.no_bare_digits,
+        edition: key.source.edition,
+    };
+    let mut states = 0;
+    let mut warnings = Vec::new();
+    let compiled = (|| {
+        // the compiled machine owns everything it keeps from the source, so the
+        // source only has to outlive this closure
+        let unit = parse::parse(Tokens::new(code, &key.file, options)?)?;
+        let tape = match (tape, &key.tape) {
+            (Some(tape), Some(path)) => parse::parse_tape(Tokens::new(tape, path, options)?)?,
+            _ => Vec::new(),
+        };
+        let start_args = match &key.source.start_args {
+            Some(start_args) if !start_args.trim().is_empty() => {
+                let path = Path::new("<start-args>");
+                parse::parse_start_args(Tokens::new(start_args, path, options)?)?
+            }
+            _ => StartArgs::default(),
+        };
+
+        for warning in warning::check(&unit, &start_args, &tape) {
+            if warning.lint.enabled_by_default() {
+                warnings.push(warning.diagnostic);
+            }
+        }
+        states = unit.states.len();
+        let mut passes = PassManager::new();
+        if let (Some(json), Some(path)) = (symbols, &key.source.symbols_file) {
+            passes.symbol_table(symbols::parse(json, path)?);
+        }
+        passes.compile(unit, tape, start_args)
+    })();
+
+    match compiled {
+        Ok(compiled) => Outcome {
+            compiled: Ok(compiled),
+            states,
+            warnings: Error::combine(warnings).to_json(),
+        },
+        Err(error) => {
+            warnings.push(error);
+            Outcome {
+                compiled: Err(Error::combine(warnings).to_json()),
+                states,
+                warnings: json!([]),
+            }
+        }
+    }
+}
+
+fn failure(message: String) -> Value {
+    json!({
+        "ok": false,
+        "diagnostics": [{ "level": "error", "message": message }],
+    })
+}
@@ -0,0 +1,495 @@
+use std::cmp;
+use std::collections::HashSet;
+use std::io::{self, BufRead, Write};
+use std::mem;
+
+use crate::backend::{self, HaltReason, Observer, Options, Simulated};
+use crate::compile::Compiled;
+use crate::error::Error;
+use crate::suggest;
+use crate::tape;
+use crate::vm::{DenseTape, Tape, Vm};
+
+// cells shown on each side of the head by `print tape`
+const DEFAULT_WINDOW: usize = 10;
+// moves between the saved copies of the machine that `reverse` and `goto` replay
+// from, to start with
+const CHECKPOINT_INTERVAL: usize = 1024;
+// past this many copies, every other one is dropped and the interval doubles, so
+// a long run doesn't keep an unbounded number of tapes around. it's even so the
+// newest one is kept
+const MAX_CHECKPOINTS: usize = 256;
+
+const HELP: &str = "\
+commands:
+    step [N]         make N moves (1 by default)
+    continue         run until a breakpoint or the machine stops
+    reverse [N]      go back N moves (1 by default)
+    goto MOVE        go forwards or back to move MOVE
+    break [STATE]    pause whenever the machine enters STATE, or list the breakpoints
+    break 'SYMBOL'   pause whenever the machine writes SYMBOL
+    delete BREAK     remove a breakpoint (a state or a quoted symbol)
+    print tape [N]   print N cells on each side of the head (10 by default)
+    where            print the move count, state and head position
+    help             print this message
+    quit             leave the debugger
+an empty line repeats the last command, and each command can be shortened to
+its first letter";
+
+/// The breakpoints `run` starts with
+#[derive(Debug, Default)]
+pub struct Breakpoints {
+    /// State names
+    pub states: Vec<String>,
+    /// Symbols, as they're written in the machine (quoted or not)
+    pub symbols: Vec<String>,
+}
+
+/// Runs `compiled` on the Rust VM under the commands typed on stdin, until
+/// `quit` or the end of the input
+pub fn run(compiled: &Compiled, two_sided: bool) -> Result<(), Error> {
+    let tape = DenseTape::new(compiled.tape.clone(), two_sided);
+    let mut debugger = Debugger::new(compiled, tape, usize::MAX, false)?;
+    debugger.where_();
+    debugger.prompt();
+    Ok(())
+}
+
+/// Runs `compiled` on the Rust VM like `backend::run`, but opens the debugger
+/// whenever the machine enters one of the states in `breakpoints` or writes
+/// one of the symbols, until it's told to `continue`. Quitting the debugger
+/// stops the run as if it were interrupted
+pub fn run_with_breakpoints(
+    compiled: &Compiled,
+    options: &Options,
+    breakpoints: &Breakpoints,
+) -> Result<Simulated, Error> {
+    let mut tape = DenseTape::new(compiled.tape.clone(), options.two_sided);
+    tape.max_cells = options.max_tape_cells;
+    let mut debugger = Debugger::new(compiled, tape, options.max_moves, true)?;
+    for name in &breakpoints.states {
+        debugger.add_breakpoint(Breakpoint::State(name.clone()))?;
+    }
+    for symbol in &breakpoints.symbols {
+        let symbol = debugger.symbol_id(symbol)?;
+        debugger.add_breakpoint(Breakpoint::Symbol(symbol))?;
+    }
+
+    let mut quit = false;
+    while !quit && !debugger.done() {
+        if debugger.run_to_breakpoint()? {
+            quit = !debugger.prompt();
+        }
+    }
+
+    let stopped = debugger.vm.stopped().is_some();
+    let mut simulated = debugger.vm.finish()?;
+    if quit && !stopped {
+        simulated.halt_reason = HaltReason::Interrupted;
+    }
+    backend::trim(&mut simulated, compiled.tape.len(), options.trim);
+    Ok(simulated)
+}
+
+#[derive(Clone, PartialEq, Eq)]
+enum Breakpoint {
+    State(String),
+    Symbol(u32),
+}
+
+// notices writes of the symbols with a breakpoint
+#[derive(Clone, Default)]
+struct Writes {
+    symbols: HashSet<u32>,
+    // the last one written since this was cleared
+    written: Option<u32>,
+}
+
+impl Observer for Writes {
+    fn write(&mut self, _position: i64, symbol: u32) {
+        if self.symbols.contains(&symbol) {
+            self.written = Some(symbol);
+        }
+    }
+}
+
+struct Debugger<'a> {
+    compiled: &'a Compiled,
+    vm: Vm<'a, Writes>,
+    max_moves: usize,
+    // whether `continue` goes back to `run_with_breakpoints` instead of running
+    // the machine itself
+    in_run: bool,
+    breakpoints: Vec<Breakpoint>,
+    // the addresses of the states with a breakpoint (there's one for each
+    // combination of arguments a state is passed)
+    break_addresses: HashSet<u32>,
+    // copies of the machine every `interval` moves, from move 0 to as far as it
+    // got. going back replays from the last one before the move it goes to
+    checkpoints: Vec<Vm<'a, Writes>>,
+    interval: usize,
+}
+
+impl<'a> Debugger<'a> {
+    fn new(
+        compiled: &'a Compiled,
+        tape: DenseTape,
+        max_moves: usize,
+        in_run: bool,
+    ) -> Result<Self, Error> {
+        let mut vm = Vm::observed(&compiled.bytes, tape, Writes::default());
+        // runs the entry code, so the machine starts in `start`
+        vm.run_for(0)?;
+        Ok(Debugger {
+            compiled,
+            vm,
+            max_moves,
+            in_run,
+            breakpoints: Vec::new(),
+            break_addresses: HashSet::new(),
+            checkpoints: Vec::new(),
+            interval: CHECKPOINT_INTERVAL,
+        })
+    }
+
+    // reads and runs commands until `quit` or the end of the input (returning
+    // false), or `continue` inside `run_with_breakpoints` (returning true)
+    fn prompt(&mut self) -> bool {
+        let mut last = String::new();
+        let mut stdin = io::stdin().lock();
+        loop {
+            print!("(tml) ");
+            io::stdout().flush().ok();
+            let mut line = String::new();
+            match stdin.read_line(&mut line) {
+                Ok(0) | Err(_) => {
+                    println!();
+                    return false;
+                }
+                Ok(_) => {}
+            }
+            if !line.trim().is_empty() {
+                last = line.trim().to_string();
+            }
+            match self.command(&last) {
+                Ok(Some(resume)) => return resume,
+                Ok(None) => {}
+                Err(error) => error.print(),
+            }
+            // there's nothing left to debug once a run stops
+            if self.in_run && self.done() {
+                return true;
+            }
+        }
+    }
+
+    // returns whether to leave the prompt, and if so whether to carry on
+    fn command(&mut self, line: &str) -> Result<Option<bool>, Error> {
+        let words: Vec<_> = line.split_whitespace().collect();
+        match words[..] {
+            [] => {}
+            ["s" | "step"] => self.step(1)?,
+            ["s" | "step", moves] => self.step(count(moves)?)?,
+            ["c" | "continue"] if self.in_run => return Ok(Some(true)),
+            ["c" | "continue"] => {
+                if !self.stopped() && !self.run_to_breakpoint()? {
+                    self.where_();
+                }
+            }
+            ["r" | "reverse"] => self.reverse(1)?,
+            ["r" | "reverse", moves] => self.reverse(count(moves)?)?,
+            ["g" | "goto", moves] => {
+                self.goto(count(moves)?)?;
+                self.where_();
+            }
+            ["b" | "break"] => self.list_breakpoints(),
+            ["b" | "break", word] => {
+                let breakpoint = self.parse_breakpoint(word)?;
+                self.add_breakpoint(breakpoint)?;
+            }
+            ["d" | "delete", word] => {
+                let breakpoint = self.parse_breakpoint(word)?;
+                self.delete_breakpoint(breakpoint)?;
+            }
+            ["p" | "print", "tape"] => self.print_tape(DEFAULT_WINDOW),
+            ["p" | "print", "tape", cells] => self.print_tape(count(cells)?),
+            ["w" | "where"] => self.where_(),
+            ["h" | "help"] => println!("{HELP}"),
+            ["q" | "quit"] => return Ok(Some(false)),
+            _ => {
+                return Err(Error::new(format!("unknown command `{line}`"), None)
+                    .with_help(Some("type `help` for a list of commands".to_string())))
+            }
+        }
+        Ok(None)
+    }
+
+    fn step(&mut self, moves: usize) -> Result<(), Error> {
+        if self.stopped() {
+            return Ok(());
+        }
+        self.advance(moves)?;
+        self.where_();
+        Ok(())
+    }
+
+    fn reverse(&mut self, moves: usize) -> Result<(), Error> {
+        if self.vm.moves() == 0 {
+            println!("the machine is at its first move");
+            return Ok(());
+        }
+        self.goto(self.vm.moves().saturating_sub(moves))?;
+        self.where_();
+        Ok(())
+    }
+
+    // goes back by replaying from the last checkpoint before `moves`. the
+    // machine is deterministic, so it makes the same moves it did the first time
+    fn goto(&mut self, moves: usize) -> Result<(), Error> {
+        if moves < self.vm.moves() {
+            let checkpoint =
+                &self.checkpoints[(moves / self.interval).min(self.checkpoints.len() - 1)];
+            // the breakpoints may have changed since the checkpoint
+            let writes = mem::take(self.vm.observer_mut());
+            self.vm = checkpoint.clone();
+            *self.vm.observer_mut() = writes;
+        }
+        self.advance(moves - self.vm.moves())
+    }
+
+    // makes up to `moves` moves (stopping at the move limit), saving a checkpoint
+    // at each multiple of the interval it gets to for the first time
+    fn advance(&mut self, moves: usize) -> Result<(), Error> {
+        let target = self.vm.moves().saturating_add(moves).min(self.max_moves);
+        while self.vm.moves() < target && self.vm.stopped().is_none() {
+            let next = self.checkpoints.len() * self.interval;
+            if self.vm.moves() == next {
+                self.checkpoint();
+                continue;
+            }
+            self.vm.run_for(target.min(next) - self.vm.moves())?;
+        }
+        Ok(())
+    }
+
+    fn checkpoint(&mut self) {
+        self.checkpoints.push(self.vm.clone());
+        if self.checkpoints.len() > MAX_CHECKPOINTS {
+            let mut index = 0;
+            self.checkpoints.retain(|_| {
+                index += 1;
+                index % 2 == 1
+            });
+            self.interval *= 2;
+        }
+    }
+
+    // runs until the machine enters a state with a breakpoint (from a different
+    // state) or writes a symbol with one, and returns whether it did. if it did,
+    // it prints where the machine is and the tape around the head
+    fn run_to_breakpoint(&mut self) -> Result<bool, Error> {
+        while !self.done() {
+            let address = self.vm.address();
+            self.vm.observer_mut().written = None;
+            self.advance(1)?;
+            if self.vm.stopped().is_some() {
+                break;
+            }
+            let entered = self.vm.address();
+            let hit = if let Some(symbol) = self.vm.observer_mut().written {
+                format!("wrote {}", tape::literal(self.symbol(symbol)))
+            } else if entered != address && self.break_addresses.contains(&entered) {
+                format!("entered `{}`", self.compiled.states[&entered].name)
+            } else {
+                continue;
+            };
+            println!("breakpoint: {hit}");
+            self.where_();
+            self.print_tape(DEFAULT_WINDOW);
+            return Ok(true);
+        }
+        Ok(false)
+    }
+
+    // whether the machine stopped or reached the move limit
+    fn done(&self) -> bool {
+        self.vm.stopped().is_some() || self.vm.moves() >= self.max_moves
+    }
+
+    // prints why the machine stopped if it has, since it can't move any more
+    fn stopped(&self) -> bool {
+        match self.vm.stopped() {
+            Some(reason) => {
+                println!("the machine has stopped ({reason})");
+                true
+            }
+            None if self.vm.moves() >= self.max_moves => {
+                println!("the machine has stopped ({})", HaltReason::MaxMoves);
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn list_breakpoints(&self) {
+        if self.breakpoints.is_empty() {
+            println!("no breakpoints");
+        }
+        for breakpoint in &self.breakpoints {
+            println!("break {}", self.describe(breakpoint));
+        }
+    }
+
+    // a quoted symbol or a state name
+    fn parse_breakpoint(&self, word: &str) -> Result<Breakpoint, Error> {
+        if word.starts_with('\'') {
+            Ok(Breakpoint::Symbol(self.symbol_id(word)?))
+        } else {
+            Ok(Breakpoint::State(word.to_string()))
+        }
+    }
+
+    fn add_breakpoint(&mut self, breakpoint: Breakpoint) -> Result<(), Error> {
+        if self.breakpoints.contains(&breakpoint) {
+            return Ok(());
+        }
+        match &breakpoint {
+            Breakpoint::State(name) => {
+                let addresses = self.addresses(name)?;
+                self.break_addresses.extend(addresses);
+            }
+            Breakpoint::Symbol(symbol) => {
+                self.vm.observer_mut().symbols.insert(*symbol);
+            }
+        }
+        self.breakpoints.push(breakpoint);
+        Ok(())
+    }
+
+    fn delete_breakpoint(&mut self, breakpoint: Breakpoint) -> Result<(), Error> {
+        let Some(i) = self.breakpoints.iter().position(|b| *b == breakpoint) else {
+            return Err(Error::new(
+                format!("there's no breakpoint on {}", self.describe(&breakpoint)),
+                None,
+            ));
+        };
+        self.breakpoints.remove(i);
+        match &breakpoint {
+            Breakpoint::State(name) => {
+                for address in self.addresses(name)? {
+                    self.break_addresses.remove(&address);
+                }
+            }
+            Breakpoint::Symbol(symbol) => {
+                self.vm.observer_mut().symbols.remove(symbol);
+            }
+        }
+        Ok(())
+    }
+
+    fn describe(&self, breakpoint: &Breakpoint) -> String {
+        match breakpoint {
+            Breakpoint::State(name) => name.clone(),
+            Breakpoint::Symbol(symbol) => tape::literal(self.symbol(*symbol)),
+        }
+    }
+
+    // every address a state with this name was compiled to
+    fn addresses(&self, name: &str) -> Result<Vec<u32>, Error> {
+        let addresses: Vec<_> = self
+            .compiled
+            .states
+            .iter()
+            .filter(|(_, info)| info.name == name)
+            .map(|(&address, _)| address)
+            .collect();
+        if addresses.is_empty() {
+            let mut names: Vec<_> = self
+                .compiled
+                .states
+                .values()
+                .map(|info| info.name.as_str())
+                .collect();
+            names.sort();
+            names.dedup();
+            let suggestions =
+                suggest::closest(name, names.into_iter().map(|name| (name, name.to_string())));
+            return Err(Error::new(format!("there's no state named `{name}`"), None)
+                .with_help(suggest::did_you_mean(&suggestions)));
+        }
+        Ok(addresses)
+    }
+
+    // a symbol as it's written in the machine, with or without quotes
+    fn symbol_id(&self, symbol: &str) -> Result<u32, Error> {
+        let unquoted = match symbol.strip_prefix('\'').and_then(|s| s.strip_suffix('\'')) {
+            Some(quoted) => quoted.replace("\\'", "'").replace("\\\\", "\\"),
+            None => symbol.to_string(),
+        };
+        match self.compiled.symbols.iter().position(|s| *s == unquoted) {
+            Some(id) => Ok(id as u32),
+            None => Err(Error::new(
+                format!(
+                    "the machine never uses the symbol {}",
+                    tape::literal(&unquoted)
+                ),
+                None,
+            )),
+        }
+    }
+
+    fn print_tape(&self, cells: usize) {
+        let tape = self.vm.tape();
+        let head = self.vm.configuration().head;
+        // a one-sided tape has nothing left of cell 0
+        let first = match tape.two_sided {
+            true => head - cells as i64,
+            false => cmp::max(head - cells as i64, 0),
+        };
+        let last = head + cells as i64;
+        let window: Vec<_> = (first..=last)
+            .map(|position| self.symbol(tape.cell(position)))
+            .collect();
+        let width = match termion::terminal_size() {
+            Ok((width, _)) => width as usize,
+            Err(_) => 80,
+        };
+        println!("cells {first} to {last}, with a double border on the head's:");
+        tape::dump(&window, width, Some((head - first) as usize));
+    }
+
+    fn where_(&self) {
+        let configuration = self.vm.configuration();
+        let reading = tape::literal(self.symbol(configuration.symbol));
+        // a machine that jumped to the halt state isn't in any state
+        let state = match self.compiled.states.get(&configuration.address) {
+            Some(info) => match self.compiled.source_at(configuration.address) {
+                Some(range) => format!(
+                    " in `{}` ({}:{})",
+                    info.name,
+                    range.span.path.display(),
+                    range.span.line + 1
+                ),
+                None => format!(" in `{}`", info.name),
+            },
+            None => String::new(),
+        };
+        let stopped = match self.vm.stopped() {
+            Some(reason) => format!(" {reason}"),
+            None => String::new(),
+        };
+        println!(
+            "move {}:{stopped}{state}, head at {} reading {reading}",
+            configuration.moves, configuration.head
+        );
+    }
+
+    fn symbol(&self, id: u32) -> &'a str {
+        &self.compiled.symbols[id as usize]
+    }
+}
+
+fn count(word: &str) -> Result<usize, Error> {
+    word.parse()
+        .map_err(|_| Error::new(format!("expected a number, found `{word}`"), None))
+}
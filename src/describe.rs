@@ -0,0 +1,149 @@
+use termion::{color, style};
+
+use crate::compile::{Compiled, Signature};
+use crate::error::Error;
+use crate::fmt;
+use crate::output;
+use crate::parse::{Name, State, ToState};
+use crate::suggest;
+
+pub fn print(unit: &[State], compiled: &Compiled, name: &str) -> Result<(), Error> {
+    let states: Vec<_> = unit
+        .iter()
+        .filter(|state| state.name.name == name)
+        .collect();
+    if states.is_empty() {
+        let mut names: Vec<_> = unit.iter().map(|state| state.name.name).collect();
+        names.sort();
+        names.dedup();
+        let suggestions =
+            suggest::closest(name, names.into_iter().map(|name| (name, name.to_string())));
+        return Err(Error::new(format!("no state with name `{name}`"), None)
+            .with_help(suggest::did_you_mean(&suggestions)));
+    }
+
+    for (i, state) in states.into_iter().enumerate() {
+        if i > 0 {
+            println!();
+        }
+        describe(state, compiled);
+    }
+    Ok(())
+}
+
+fn describe(state: &State, compiled: &Compiled) {
+    let signature = Signature::of(state);
+    let span = state.name.span;
+
+    for line in &state.doc {
+        println!("/// {line}");
+    }
+    heading("signature", &signature.to_string());
+    heading(
+        "defined at",
+        &format!(
+            "{}:{}:{}",
+            span.path.display(),
+            span.line + 1,
+            span.column + 1
+        ),
+    );
+    heading(
+        "address",
+        &format!("{:#010x}", compiled.signatures[&signature.to_buf()]),
+    );
+
+    heading("state parameters", &names(&state.state_params));
+    heading("symbol parameters", &names(&state.symbol_params));
+
+    heading("arms", "");
+    for arm in &state.arms {
+        println!(
+            "    {} -> {}",
+            fmt::format_pattern(arm),
+            fmt::format_to_state(&arm.to_state)
+        );
+    }
+
+    let mut targets = Vec::new();
+    for arm in &state.arms {
+        collect_targets(&arm.to_state, state, compiled, &mut targets);
+    }
+    heading("transitions", "");
+    for target in targets {
+        println!("    {target}");
+    }
+}
+
+fn collect_targets(
+    to_state: &ToState,
+    state: &State,
+    compiled: &Compiled,
+    targets: &mut Vec<String>,
+) {
+    let target = match to_state {
+        ToState::State {
+            name,
+            state_args,
+            symbol_args,
+        } => {
+            for state_arg in state_args {
+                collect_targets(state_arg, state, compiled, targets);
+            }
+
+            let is_param = state_args.is_empty()
+                && symbol_args.is_empty()
+                && state
+                    .state_params
+                    .iter()
+                    .any(|param| param.name == name.name);
+            if is_param {
+                format!("{} (state parameter)", name.name)
+            } else {
+                let signature = Signature {
+                    name: name.name,
+                    states: state_args.len() as u16,
+                    symbols: symbol_args.len() as u16,
+                };
+                match compiled.signatures.get(&signature.to_buf()) {
+                    Some(address) => format!("{signature} ({address:#010x})"),
+                    None => format!("{signature} (undefined)"),
+                }
+            }
+        }
+        ToState::Halt { .. } => "! (halt)".to_string(),
+    };
+
+    if !targets.contains(&target) {
+        targets.push(target);
+    }
+}
+
+fn names(params: &[Name]) -> String {
+    if params.is_empty() {
+        "none".to_string()
+    } else {
+        let names: Vec<_> = params.iter().map(|param| param.name).collect();
+        names.join(", ")
+    }
+}
+
+fn heading(label: &str, value: &str) {
+    let value = if value.is_empty() {
+        String::new()
+    } else {
+        format!(" {value}")
+    };
+
+    if !output::color() {
+        println!("{label}:{value}");
+    } else {
+        println!(
+            "{}{}{label}:{}{}{value}",
+            style::Bold,
+            color::Fg(color::Green),
+            style::Reset,
+            color::Fg(color::Reset)
+        );
+    }
+}
@@ -0,0 +1,184 @@
+use std::fmt::Write;
+
+use crate::bytecode as bc;
+use crate::compile::Compiled;
+use crate::error::Error;
+use crate::tape;
+
+const VM_SOURCE: &str = include_str!("vm.c");
+const WAT_TEMPLATE: &str = include_str!("vm.wat");
+
+const FREE_LISTS: usize = 1024;
+const FREE_LISTS_SIZE: usize = 4 * (3 + 255 + 255 + 1);
+const STATE_STACK: usize = 4096;
+const SYMBOL_STACK: usize = 8192;
+const BYTES: usize = 12288;
+const INITIAL_TAPE_CAPACITY: usize = 256;
+
+const C_MAIN: &str = r#"
+int main(int argc, char **argv) {
+  size_t max_moves = SIZE_MAX;
+  if (argc > 1) {
+    max_moves = strtoull(argv[1], NULL, 10);
+  }
+
+  Vm *vm = new_vm(NULL);
+  if (!vm) {
+    fprintf(stderr, "error: ran out of memory\n");
+    return 1;
+  }
+  if (!init_tape(vm, INITIAL_TAPE, INITIAL_TAPE_LEN, false, SIZE_MAX)) {
+    fprintf(stderr, "error: ran out of memory\n");
+    free_vm(vm);
+    return 1;
+  }
+  uint8_t status = run(vm, BYTECODE, sizeof(BYTECODE), max_moves);
+  if (status == INVALID_BYTECODE) {
+    fprintf(stderr, "error: invalid bytecode at address %u after %zu moves: %s\n",
+            get_error_address(vm), get_move_count(vm), get_error_message(vm));
+    free_vm(vm);
+    return 1;
+  }
+  if (status == OUT_OF_MEMORY) {
+    fprintf(stderr, "error: ran out of memory after %zu moves\n", get_move_count(vm));
+    free_vm(vm);
+    return 1;
+  }
+
+  uint32_t *final_tape = get_tape(vm);
+  size_t len = get_tape_len(vm);
+  while (len && final_tape[len - 1] == 0) {
+    len--;
+  }
+
+  printf("final tape:\n");
+  for (size_t i = 0; i < len; i++) {
+    printf(i ? " %s" : "%s", SYMBOLS[final_tape[i]]);
+  }
+  printf("\n\nnumber of moves: %zu\n", get_move_count(vm));
+  printf("final head position: %zu\n", get_tape_head_position(vm));
+
+  free_vm(vm);
+  return 0;
+}
+"#;
+
+pub fn c(compiled: &Compiled) -> String {
+    let mut out = String::new();
+
+    out.push_str("// Generated by tml. Usage: ./machine [MAX_MOVES]\n\n");
+    out.push_str(VM_SOURCE);
+
+    out.push_str("\nuint8_t BYTECODE[] = {");
+    write_array(&mut out, compiled.bytes.iter().map(|b| format!("{b:#04x}")));
+    out.push_str("};\n");
+
+    writeln!(out, "\nsize_t INITIAL_TAPE_LEN = {};", compiled.tape.len()).unwrap();
+    out.push_str("uint32_t INITIAL_TAPE[] = {");
+    if compiled.tape.is_empty() {
+        out.push('0');
+    } else {
+        write_array(&mut out, compiled.tape.iter().map(u32::to_string));
+    }
+    out.push_str("};\n");
+
+    out.push_str("\nconst char *SYMBOLS[] = {");
+    write_array(
+        &mut out,
+        compiled
+            .symbols
+            .iter()
+            .map(|symbol| c_string(&tape::literal(symbol))),
+    );
+    out.push_str("};\n");
+
+    out.push_str(C_MAIN);
+    out
+}
+
+/// The WebAssembly VM only runs version 1 bytecode (its tape has 16-bit cells)
+pub fn wat(compiled: &Compiled) -> Result<String, Error> {
+    if bc::Version::of(&compiled.bytes) != bc::Version::V1 {
+        return Err(Error::new(
+            "the machine needs version 2 bytecode, which the WebAssembly VM can't run".to_string(),
+            None,
+        )
+        .with_help(Some("emit C instead with `--emit c`".to_string())));
+    }
+
+    let initial_tape = BYTES + compiled.bytes.len();
+    let symbols = initial_tape + 2 * compiled.tape.len();
+
+    let mut symbol_data = Vec::new();
+    for symbol in &compiled.symbols {
+        symbol_data.extend(symbol.as_bytes());
+        symbol_data.push(0);
+    }
+
+    let heap = (symbols + symbol_data.len() + 7) & !7;
+    let tape_capacity = compiled.tape.len().max(INITIAL_TAPE_CAPACITY);
+    let pages = (heap + 2 * tape_capacity) / 65536 + 1;
+
+    let tape_data: Vec<_> = compiled
+        .tape
+        .iter()
+        .flat_map(|&s| (s as u16).to_le_bytes())
+        .collect();
+
+    Ok(WAT_TEMPLATE
+        .replace("{{PAGES}}", &pages.to_string())
+        .replace("{{FREE_LISTS}}", &FREE_LISTS.to_string())
+        .replace("{{FREE_LISTS_SIZE}}", &FREE_LISTS_SIZE.to_string())
+        .replace("{{FREE_LISTS_LEN}}", &(FREE_LISTS_SIZE / 4).to_string())
+        .replace("{{STATE_STACK}}", &STATE_STACK.to_string())
+        .replace("{{SYMBOL_STACK}}", &SYMBOL_STACK.to_string())
+        .replace("{{BYTES}}", &BYTES.to_string())
+        .replace("{{INITIAL_TAPE}}", &initial_tape.to_string())
+        .replace("{{INITIAL_TAPE_BYTES}}", &tape_data.len().to_string())
+        .replace("{{SYMBOLS}}", &symbols.to_string())
+        .replace("{{SYMBOLS_LEN}}", &symbol_data.len().to_string())
+        .replace("{{HEAP}}", &heap.to_string())
+        .replace("{{TAPE_CAPACITY}}", &tape_capacity.to_string())
+        .replace("{{TAPE_CAPACITY_BYTES}}", &(2 * tape_capacity).to_string())
+        .replace("{{BYTES_DATA}}", &wat_string(&compiled.bytes))
+        .replace("{{INITIAL_TAPE_DATA}}", &wat_string(&tape_data))
+        .replace("{{SYMBOLS_DATA}}", &wat_string(&symbol_data)))
+}
+
+pub fn wasm(compiled: &Compiled) -> Result<Vec<u8>, Error> {
+    wat::parse_str(wat(compiled)?)
+        .map_err(|error| Error::new(format!("couldn't assemble wasm module: {error}"), None))
+}
+
+fn wat_string(bytes: &[u8]) -> String {
+    let mut string = String::with_capacity(3 * bytes.len());
+    for byte in bytes {
+        write!(string, "\\{byte:02x}").unwrap();
+    }
+    string
+}
+
+fn write_array(out: &mut String, items: impl Iterator<Item = String>) {
+    for (i, item) in items.enumerate() {
+        if i % 12 == 0 {
+            out.push_str("\n   ");
+        }
+        write!(out, " {item},").unwrap();
+    }
+    out.push('\n');
+}
+
+// hex escapes in C don't stop after two digits, so use octal escapes instead
+fn c_string(s: &str) -> String {
+    let mut literal = String::from('"');
+    for &byte in s.as_bytes() {
+        match byte {
+            b'"' => literal.push_str("\\\""),
+            b'\\' => literal.push_str("\\\\"),
+            b' '..=b'~' => literal.push(byte as char),
+            _ => write!(literal, "\\{byte:03o}").unwrap(),
+        }
+    }
+    literal.push('"');
+    literal
+}
@@ -1,34 +1,229 @@
+use clap::ValueEnum;
+use serde_json::{json, Value};
 use termion::{color, style};
 
-use crate::lex::Span;
+use crate::lex::{Span, SpanBuf};
+use crate::output;
 
 #[derive(Debug)]
 pub struct Error {
-    msg: String,
-    span: Option<Span>,
+    diagnostics: Vec<Diagnostic>,
+}
+
+#[derive(Debug)]
+pub struct Diagnostic {
+    pub level: Level,
+    pub msg: String,
+    pub span: Option<SpanBuf>,
+    pub label: Option<String>,
+    pub notes: Vec<(SpanBuf, String)>,
+    pub help: Option<String>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum Level {
+    Error,
+    Warning,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum ErrorFormat {
+    // rendered with source snippets
+    Human,
+    // one JSON object per diagnostic per line
+    Json,
 }
 
 impl Error {
     pub fn new(msg: String, span: Option<Span>) -> Self {
-        Error { msg, span }
+        Error::with_level(Level::Error, msg, span)
+    }
+
+    pub fn warning(msg: String, span: Option<Span>) -> Self {
+        Error::with_level(Level::Warning, msg, span)
+    }
+
+    fn with_level(level: Level, msg: String, span: Option<Span>) -> Self {
+        Error {
+            diagnostics: vec![Diagnostic {
+                level,
+                msg,
+                span: span.map(Span::to_buf),
+                label: None,
+                notes: Vec::new(),
+                help: None,
+            }],
+        }
+    }
+
+    pub fn with_label(mut self, label: String) -> Self {
+        if let Some(diagnostic) = self.diagnostics.last_mut() {
+            diagnostic.label = Some(label);
+        }
+        self
+    }
+
+    pub fn with_note(mut self, span: Span, label: String) -> Self {
+        if let Some(diagnostic) = self.diagnostics.last_mut() {
+            diagnostic.notes.push((span.to_buf(), label));
+        }
+        self
+    }
+
+    pub fn with_help(mut self, help: Option<String>) -> Self {
+        if let Some(diagnostic) = self.diagnostics.last_mut() {
+            diagnostic.help = help;
+        }
+        self
+    }
+
+    /// Turns every diagnostic into an error
+    pub fn deny(mut self) -> Self {
+        for diagnostic in &mut self.diagnostics {
+            diagnostic.level = Level::Error;
+        }
+        self
+    }
+
+    pub fn diagnostics(&self) -> &[Diagnostic] {
+        &self.diagnostics
+    }
+
+    pub fn combine(errors: Vec<Error>) -> Self {
+        Error {
+            diagnostics: errors
+                .into_iter()
+                .flat_map(|error| error.diagnostics)
+                .collect(),
+        }
+    }
+
+    /// Every diagnostic as a JSON object (see `Diagnostic::to_json`)
+    pub fn to_json(&self) -> Value {
+        self.diagnostics.iter().map(Diagnostic::to_json).collect()
+    }
+
+    pub fn print(&self) {
+        for (i, diagnostic) in self.diagnostics.iter().enumerate() {
+            if i > 0 {
+                println!();
+            }
+            diagnostic.print();
+        }
+
+        let count = |level| {
+            self.diagnostics
+                .iter()
+                .filter(|diagnostic| diagnostic.level == level)
+                .count()
+        };
+        let (errors, warnings) = (count(Level::Error), count(Level::Warning));
+        if errors > 1 {
+            println!();
+            Level::Error.print(&format!("aborting due to {errors} errors"));
+        } else if errors == 0 && warnings > 0 {
+            println!();
+            let plural = if warnings == 1 { "" } else { "s" };
+            Level::Warning.print(&format!("{warnings} warning{plural} emitted"));
+        }
     }
+}
+
+impl Level {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Level::Error => "error",
+            Level::Warning => "warning",
+        }
+    }
+
+    fn print(self, msg: &str) {
+        let label = self.as_str();
 
-    pub fn print(&self, no_color: bool) {
-        if no_color {
-            println!("error: {}", self.msg);
+        if !output::color() {
+            println!("{label}: {msg}");
         } else {
+            let label_color: &dyn color::Color = match self {
+                Level::Error => &color::Red,
+                Level::Warning => &color::Yellow,
+            };
             println!(
-                "{}{}error:{}{} {}",
+                "{}{}{label}:{}{} {msg}",
                 style::Bold,
-                color::Fg(color::Red),
+                color::Fg(label_color),
                 style::Reset,
                 color::Fg(color::Reset),
-                self.msg
             );
         }
+    }
+}
+
+impl Diagnostic {
+    /// The diagnostic's level, message and location, with lines and columns counted from 1
+    pub fn to_json(&self) -> Value {
+        let location = |span: &SpanBuf| {
+            json!({
+                "file": span.path.display().to_string(),
+                "line": span.line + 1,
+                "column": span.column + 1,
+            })
+        };
+        let mut value = json!({
+            "level": self.level.as_str(),
+            "message": self.msg,
+        });
+        if let Some(span) = &self.span {
+            value["span"] = location(span);
+        }
+        if let Some(label) = &self.label {
+            value["label"] = json!(label);
+        }
+        if !self.notes.is_empty() {
+            value["notes"] = self
+                .notes
+                .iter()
+                .map(|(span, note)| json!({ "span": location(span), "message": note }))
+                .collect();
+        }
+        if let Some(help) = &self.help {
+            value["help"] = json!(help);
+        }
+        value
+    }
+
+    fn print(&self) {
+        self.level.print(&self.msg);
+
+        let gutter_width = self
+            .span
+            .iter()
+            .chain(self.notes.iter().map(|(span, _)| span))
+            .map(|span| span.as_span().gutter_width())
+            .max()
+            .unwrap_or(0);
+
+        if let Some(span) = &self.span {
+            span.as_span().print(self.label.as_deref(), gutter_width);
+        }
+        for (i, (span, label)) in self.notes.iter().enumerate() {
+            if i > 0 || self.span.is_some() {
+                Span::print_gap(gutter_width);
+            }
+            span.as_span().print_note(label, gutter_width);
+        }
 
-        if let Some(span) = self.span {
-            span.print(no_color);
+        if let Some(help) = &self.help {
+            if !output::color() {
+                println!("help: {help}");
+            } else {
+                println!(
+                    "{}{}help:{}{} {help}",
+                    style::Bold,
+                    color::Fg(color::Blue),
+                    style::Reset,
+                    color::Fg(color::Reset),
+                );
+            }
         }
     }
 }
@@ -0,0 +1,175 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::io::{BufWriter, Write};
+use std::net::TcpListener;
+use std::os::unix::net::UnixListener;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+use serde_json::{json, Value};
+
+use crate::backend::{Observer, Simulated};
+use crate::compile::Compiled;
+use crate::error::Error;
+use crate::stats;
+
+#[derive(Clone, Debug)]
+pub enum Address {
+    Unix(PathBuf),
+    Tcp(String),
+}
+
+impl FromStr for Address {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Some(path) = s.strip_prefix("unix:") {
+            Ok(Address::Unix(PathBuf::from(path)))
+        } else if let Some(address) = s.strip_prefix("tcp:") {
+            Ok(Address::Tcp(address.to_string()))
+        } else {
+            Err("expected `unix:PATH` or `tcp:HOST:PORT`".to_string())
+        }
+    }
+}
+
+impl fmt::Display for Address {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Address::Unix(path) => write!(f, "unix:{}", path.display()),
+            Address::Tcp(address) => write!(f, "tcp:{address}"),
+        }
+    }
+}
+
+/// Waits for a visualizer to connect to `address`
+pub fn listen(address: &Address) -> Result<Box<dyn Write>, Error> {
+    let error = |what: &str, error: std::io::Error| {
+        Error::new(format!("couldn't {what} {address} ({error})"), None)
+    };
+
+    println!("waiting for a visualizer to connect to {address}");
+    match address {
+        Address::Unix(path) => {
+            let listener = UnixListener::bind(path).map_err(|e| error("listen on", e))?;
+            let accepted = listener.accept();
+            let _ = fs::remove_file(path);
+            let (stream, _) = accepted.map_err(|e| error("accept a connection on", e))?;
+            Ok(Box::new(stream))
+        }
+        Address::Tcp(host) => {
+            let listener = TcpListener::bind(host).map_err(|e| error("listen on", e))?;
+            let (stream, _) = listener
+                .accept()
+                .map_err(|e| error("accept a connection on", e))?;
+            Ok(Box::new(stream))
+        }
+    }
+}
+
+/// Streams newline-delimited JSON events while the Rust VM runs
+pub struct Events<'a> {
+    // `None` once the visualizer goes away, so the simulation keeps going without it
+    writer: Option<BufWriter<Box<dyn Write>>>,
+    compiled: &'a Compiled,
+    batch: usize,
+    writes: BTreeMap<i64, u32>,
+    state: u32,
+    head: i64,
+    moves: usize,
+}
+
+impl<'a> Events<'a> {
+    pub fn new(writer: Box<dyn Write>, compiled: &'a Compiled, batch: usize) -> Self {
+        let mut events = Events {
+            writer: Some(BufWriter::new(writer)),
+            compiled,
+            batch,
+            writes: BTreeMap::new(),
+            state: 0,
+            head: 0,
+            moves: 0,
+        };
+        let tape: Vec<_> = compiled.tape.iter().map(|&i| events.symbol(i)).collect();
+        events.send(json!({
+            "event": "start",
+            "tape": tape,
+            "head": 0,
+        }));
+        events
+    }
+
+    pub fn finish(mut self, simulated: &Simulated) {
+        // the last move may have ended a batch already
+        if !self.writes.is_empty() || !self.moves.is_multiple_of(self.batch) {
+            self.flush_batch();
+        }
+        let tape: Vec<_> = simulated.tape.iter().map(|&i| self.symbol(i)).collect();
+        self.send(json!({
+            "event": "result",
+            "moves": simulated.moves,
+            "head": simulated.head_position,
+            "state": self.state_name(simulated.final_address),
+            "tape": tape,
+            "segments": stats::segments(&simulated.tape)
+                .into_iter()
+                .map(|segment| [segment.start, segment.end])
+                .collect::<Vec<_>>(),
+        }));
+        if let Some(writer) = &mut self.writer {
+            let _ = writer.flush();
+        }
+    }
+
+    fn flush_batch(&mut self) {
+        let writes: Vec<_> = std::mem::take(&mut self.writes)
+            .into_iter()
+            .map(|(index, symbol)| json!([index, self.symbol(symbol)]))
+            .collect();
+        self.send(json!({
+            "event": "batch",
+            "moves": self.moves,
+            "head": self.head,
+            "state": self.state_name(self.state),
+            "writes": writes,
+        }));
+    }
+
+    fn send(&mut self, event: Value) {
+        if let Some(writer) = &mut self.writer {
+            if writeln!(writer, "{event}").is_err() {
+                self.writer = None;
+            }
+        }
+    }
+
+    fn symbol(&self, index: u32) -> &'a str {
+        &self.compiled.symbols[index as usize]
+    }
+
+    fn state_name(&self, address: u32) -> String {
+        match self.compiled.states.get(&address) {
+            Some(info) => info.name.clone(),
+            None => format!("{address:#010x}"),
+        }
+    }
+}
+
+impl Observer for Events<'_> {
+    fn write(&mut self, position: i64, symbol: u32) {
+        self.writes.insert(position, symbol);
+    }
+
+    fn state(&mut self, address: u32) {
+        self.state = address;
+    }
+
+    fn moved(&mut self, moves: usize, head: i64) {
+        self.moves = moves;
+        self.head = head;
+        if moves.is_multiple_of(self.batch) {
+            self.flush_batch();
+        }
+    }
+}
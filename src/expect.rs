@@ -0,0 +1,110 @@
+use std::ops::Range;
+use std::path::Path;
+
+use termion::color;
+
+use crate::output;
+use crate::tape;
+
+/// Compares the final tape (as `--output tape` prints it) with `expected` cell
+/// by cell, and prints a unified diff of the cells that differ, numbered like
+/// head positions. Blanks past the end of either tape don't count. Returns
+/// whether they matched
+pub fn tape(path: &Path, expected: &[&str], found: &[&str], origin: usize) -> bool {
+    let differs = |i: usize| cell(expected, i) != cell(found, i);
+
+    let len = expected.len().max(found.len());
+    let mut hunks: Vec<Range<usize>> = Vec::new();
+    for i in (0..len).filter(|&i| differs(i)) {
+        match hunks.last_mut() {
+            Some(hunk) if hunk.end == i => hunk.end += 1,
+            _ => hunks.push(i..i + 1),
+        }
+    }
+    if hunks.is_empty() {
+        return true;
+    }
+
+    println!("--- {}", path.display());
+    println!("+++ final tape");
+    for hunk in hunks {
+        let first = hunk.start as i64 - origin as i64;
+        let last = hunk.end as i64 - 1 - origin as i64;
+        if first == last {
+            println!("@@ cell {first} @@");
+        } else {
+            println!("@@ cells {first} to {last} @@");
+        }
+        for i in hunk.clone() {
+            removed(&tape::literal(cell(expected, i)));
+        }
+        for i in hunk {
+            added(&tape::literal(cell(found, i)));
+        }
+    }
+    false
+}
+
+/// Compares the final decimal with `expected`, ignoring zeros at the end, and
+/// prints both if they differ. Returns whether they matched
+pub fn decimal(expected: &str, found: &str) -> bool {
+    if digits(expected) == digits(found) {
+        return true;
+    }
+    println!("--- expected decimal");
+    println!("+++ final decimal");
+    removed(expected);
+    added(found);
+    false
+}
+
+/// Checks that `--expect-decimal` was given a decimal the way they're printed,
+/// like `0.75`
+pub fn parse_decimal(s: &str) -> Result<String, String> {
+    let valid = match s.split_once('.') {
+        Some(("0" | "", fraction)) => {
+            !fraction.is_empty() && fraction.chars().all(|c| c.is_ascii_digit())
+        }
+        Some(_) => false,
+        None => s == "0",
+    };
+    if !valid {
+        return Err("expected a decimal from 0 to 1, like `0.75`".to_string());
+    }
+    Ok(s.to_string())
+}
+
+// a cell past the end of a tape is blank
+fn cell<'a>(tape: &[&'a str], i: usize) -> &'a str {
+    tape.get(i).copied().unwrap_or_default()
+}
+
+// the digits after the point, without trailing zeros
+fn digits(decimal: &str) -> &str {
+    let fraction = decimal.split_once('.').map_or("", |(_, fraction)| fraction);
+    fraction.trim_end_matches('0')
+}
+
+fn removed(line: &str) {
+    if !output::color() {
+        println!("-{line}");
+    } else {
+        println!(
+            "{}-{line}{}",
+            color::Fg(color::Red),
+            color::Fg(color::Reset)
+        );
+    }
+}
+
+fn added(line: &str) {
+    if !output::color() {
+        println!("+{line}");
+    } else {
+        println!(
+            "{}+{line}{}",
+            color::Fg(color::Green),
+            color::Fg(color::Reset)
+        );
+    }
+}
@@ -1,33 +1,450 @@
-use crate::vm::Simulated;
+use std::alloc::{self, Layout};
+use std::ffi::{c_char, c_void, CStr};
+use std::ops::ControlFlow;
+use std::ptr;
+
+use crate::backend::{Backend, Checkpoint, Dispatch, HaltReason, Hook, Options, Simulated};
+use crate::error::Error;
+use crate::vm::VmError;
+
+// a run's state on the C VM (see `Vm` in vm.c)
+#[repr(C)]
+struct Vm {
+    _private: [u8; 0],
+}
+
+// a state and the states and symbols it was passed (see `State` in vm.c)
+#[repr(C)]
+struct State {
+    address: u32,
+    states: *const State,
+    state_count: usize,
+    symbols: *const u32,
+    symbol_count: usize,
+}
+
+// where the C VM gets its memory from (see `Allocator` in vm.c)
+#[repr(C)]
+struct Allocator {
+    alloc: extern "C" fn(data: *mut c_void, size: usize, align: usize) -> *mut c_void,
+    realloc: extern "C" fn(
+        data: *mut c_void,
+        p: *mut c_void,
+        old_size: usize,
+        new_size: usize,
+        align: usize,
+    ) -> *mut c_void,
+    free: extern "C" fn(data: *mut c_void, p: *mut c_void, size: usize, align: usize),
+    data: *mut c_void,
+}
+
+// the tape comes from Rust's allocator, so it can become a `Vec` without a
+// copy. the states the VM makes on every move still use malloc, which is faster
+// without the extra calls
+const RUST_ALLOCATOR: Allocator = Allocator {
+    alloc: rust_alloc,
+    realloc: rust_realloc,
+    free: rust_free,
+    data: ptr::null_mut(),
+};
+
+// see `Hook` in vm.c
+type HookFn = extern "C" fn(data: *mut c_void, moves: usize, head: i64, address: u32) -> bool;
 
 extern "C" {
-    fn init_tape(tape: *const u16, len: usize);
-    fn run(bytes: *const u8, max_moves: usize);
-    fn get_final_address() -> u32;
-    fn get_tape() -> *const u16;
-    fn get_tape_len() -> usize;
-    fn get_tape_head_position() -> usize;
-    fn get_move_count() -> usize;
-    fn cleanup();
+    fn new_vm(allocator: *const Allocator) -> *mut Vm;
+    fn free_vm(vm: *mut Vm);
+    fn set_tape_allocator(vm: *mut Vm, allocator: *const Allocator);
+    fn init_tape(
+        vm: *mut Vm,
+        tape: *const u32,
+        len: usize,
+        two_sided: bool,
+        max_cells: usize,
+    ) -> bool;
+    fn set_dispatch(vm: *mut Vm, dispatch: u8) -> bool;
+    fn set_hook(vm: *mut Vm, hook: Option<HookFn>, data: *mut c_void, every: usize);
+    fn run(vm: *mut Vm, bytes: *const u8, len: usize, max_moves: usize) -> u8;
+    fn get_final_address(vm: *const Vm) -> u32;
+    fn get_final_state(vm: *mut Vm) -> State;
+    fn take_tape(vm: *mut Vm) -> *mut u32;
+    fn get_tape_len(vm: *const Vm) -> usize;
+    fn get_tape_head_position(vm: *const Vm) -> usize;
+    fn get_rightmost_head_position(vm: *const Vm) -> usize;
+    fn get_origin(vm: *const Vm) -> usize;
+    fn get_leftmost_index(vm: *const Vm) -> i64;
+    fn get_left_edge_contacts(vm: *const Vm) -> usize;
+    fn get_move_count(vm: *const Vm) -> usize;
+    fn get_cells_allocated(vm: *const Vm) -> usize;
+    fn get_error_message(vm: *const Vm) -> *const c_char;
+    fn get_error_address(vm: *const Vm) -> u32;
+    #[link_name = "interrupt"]
+    fn interrupt_c_vm();
+    #[link_name = "clear_interrupt"]
+    fn clear_c_vm_interrupt();
 }
 
-pub fn simulate(bytes: &[u8], tape: &[u16], max_moves: usize) -> Simulated {
-    unsafe {
-        init_tape(tape.as_ptr(), tape.len());
-        run(bytes.as_ptr(), max_moves);
+// what `run` returns (see vm.c)
+const HALTED: u8 = 0;
+const MAX_MOVES: u8 = 1;
+const LEFT_EDGE: u8 = 2;
+const TAPE_LIMIT: u8 = 3;
+const INTERRUPTED: u8 = 4;
+const INVALID_BYTECODE: u8 = 5;
+const OUT_OF_MEMORY: u8 = 6;
+
+// what `set_dispatch` takes (see vm.c)
+const DISPATCH_GOTO: u8 = 0;
+const DISPATCH_SWITCH: u8 = 1;
+const DISPATCH_THREADED: u8 = 2;
+
+// stops every C VM run at its next check (see `backend::catch_interrupts`)
+pub fn interrupt() {
+    unsafe { interrupt_c_vm() }
+}
+
+// lets C VM runs go on again after `interrupt`
+pub fn clear_interrupt() {
+    unsafe { clear_c_vm_interrupt() }
+}
+
+pub struct CVm;
+
+impl Backend for CVm {
+    fn execute(&self, bytes: &[u8], tape: &[u32], options: &Options) -> Result<Simulated, Error> {
+        simulate(bytes, tape, options, None)
+    }
+
+    fn execute_hooked(
+        &self,
+        bytes: &[u8],
+        tape: &[u32],
+        options: &Options,
+        hook: Hook,
+    ) -> Result<Simulated, Error> {
+        simulate(bytes, tape, options, Some(hook))
+    }
+}
+
+// owns a `Vm`, so it's freed however the run ends
+struct Handle(*mut Vm);
 
-        let mut tape = std::slice::from_raw_parts(get_tape(), get_tape_len()).to_vec();
-        while let Some(0) = tape.last() {
-            tape.pop();
+impl Handle {
+    fn new() -> Result<Handle, Error> {
+        let vm = unsafe { new_vm(ptr::null()) };
+        if vm.is_null() {
+            return Err(out_of_memory(0));
         }
+        unsafe { set_tape_allocator(vm, &RUST_ALLOCATOR) };
+        Ok(Handle(vm))
+    }
+}
 
-        cleanup();
+impl Drop for Handle {
+    fn drop(&mut self) {
+        unsafe { free_vm(self.0) }
+    }
+}
 
-        Simulated {
+// every run gets its own `Vm`, so runs on different threads don't interfere
+fn simulate(
+    bytes: &[u8],
+    tape: &[u32],
+    options: &Options,
+    mut hook: Option<Hook>,
+) -> Result<Simulated, Error> {
+    let handle = Handle::new()?;
+    let vm = handle.0;
+    unsafe {
+        if let Some(dispatch) = options.dispatch {
+            let code = match dispatch {
+                Dispatch::Goto => DISPATCH_GOTO,
+                Dispatch::Switch => DISPATCH_SWITCH,
+                Dispatch::Threaded => DISPATCH_THREADED,
+            };
+            if !set_dispatch(vm, code) {
+                return Err(Error::new(
+                    "the C VM was compiled without computed gotos".to_string(),
+                    None,
+                )
+                .with_help(Some(
+                    "use `--dispatch switch`, or rebuild tml with GCC or Clang".to_string(),
+                )));
+            }
+        }
+        // the C VM gets a pointer to the callback, which outlives the run
+        if let Some(hook) = &mut hook {
+            let data = &mut hook.callback as *mut &mut dyn FnMut(Checkpoint) -> ControlFlow<()>;
+            set_hook(vm, Some(call_hook), data.cast(), hook.every);
+        }
+        if !init_tape(
+            vm,
+            tape.as_ptr(),
+            tape.len(),
+            options.two_sided,
+            options.max_tape_cells,
+        ) {
+            return Err(out_of_memory(0));
+        }
+        let status = run(vm, bytes.as_ptr(), bytes.len(), options.max_moves);
+
+        let halt_reason = match status {
+            HALTED => HaltReason::Halted,
+            MAX_MOVES => HaltReason::MaxMoves,
+            LEFT_EDGE => HaltReason::LeftEdge,
+            TAPE_LIMIT => HaltReason::TapeLimit,
+            INTERRUPTED => HaltReason::Interrupted,
+            INVALID_BYTECODE => {
+                return Err(VmError {
+                    message: CStr::from_ptr(get_error_message(vm))
+                        .to_string_lossy()
+                        .into_owned(),
+                    address: get_error_address(vm),
+                    moves: get_move_count(vm),
+                }
+                .into())
+            }
+            OUT_OF_MEMORY => return Err(out_of_memory(get_move_count(vm))),
+            _ => {
+                return Err(Error::new(
+                    format!("the C VM stopped with unknown status {status}"),
+                    None,
+                ))
+            }
+        };
+        // the tape was allocated as an array of exactly this many cells
+        let len = get_tape_len(vm);
+        let tape = Vec::from_raw_parts(take_tape(vm), len, len);
+        Ok(Simulated {
+            peak_tape_len: tape.len(),
             tape,
-            head_position: get_tape_head_position(),
-            final_address: get_final_address(),
-            moves: get_move_count(),
+            head_position: get_tape_head_position(vm),
+            rightmost_head_position: get_rightmost_head_position(vm),
+            origin: get_origin(vm),
+            leftmost_index: get_leftmost_index(vm),
+            left_edge_contacts: get_left_edge_contacts(vm),
+            final_address: get_final_address(vm),
+            final_state: write_state(&get_final_state(vm)),
+            moves: get_move_count(vm),
+            cells_allocated: get_cells_allocated(vm),
+            halt_reason,
+        })
+    }
+}
+
+// `state` written the way `Vm::write_state` writes it, which (like it) doesn't
+// recurse, since states can be nested deeper than the stack allows
+unsafe fn write_state(state: &State) -> Vec<u32> {
+    let mut out = Vec::new();
+    let mut pending = vec![state];
+    while let Some(state) = pending.pop() {
+        let states = slice(state.states, state.state_count);
+        let symbols = slice(state.symbols, state.symbol_count);
+        out.push(state.address);
+        out.push(symbols.len() as u32);
+        out.extend(symbols);
+        out.push(states.len() as u32);
+        pending.extend(states.iter().rev());
+    }
+    out
+}
+
+// the C VM doesn't set the pointer when there's nothing for it to point at
+unsafe fn slice<'a, T>(p: *const T, len: usize) -> &'a [T] {
+    if len == 0 {
+        &[]
+    } else {
+        std::slice::from_raw_parts(p, len)
+    }
+}
+
+extern "C" fn rust_alloc(_data: *mut c_void, size: usize, align: usize) -> *mut c_void {
+    match Layout::from_size_align(size, align) {
+        Ok(layout) if size > 0 => unsafe { alloc::alloc(layout).cast() },
+        _ => ptr::null_mut(),
+    }
+}
+
+extern "C" fn rust_realloc(
+    _data: *mut c_void,
+    p: *mut c_void,
+    old_size: usize,
+    new_size: usize,
+    align: usize,
+) -> *mut c_void {
+    // `p` came from `rust_alloc`, so its layout is valid
+    let layout = unsafe { Layout::from_size_align_unchecked(old_size, align) };
+    match Layout::from_size_align(new_size, align) {
+        Ok(_) if new_size > 0 => unsafe { alloc::realloc(p.cast(), layout, new_size).cast() },
+        _ => ptr::null_mut(),
+    }
+}
+
+extern "C" fn rust_free(_data: *mut c_void, p: *mut c_void, size: usize, align: usize) {
+    unsafe { alloc::dealloc(p.cast(), Layout::from_size_align_unchecked(size, align)) }
+}
+
+extern "C" fn call_hook(data: *mut c_void, moves: usize, head: i64, address: u32) -> bool {
+    let callback = unsafe { &mut *data.cast::<&mut dyn FnMut(Checkpoint) -> ControlFlow<()>>() };
+    callback(Checkpoint {
+        moves,
+        head,
+        address,
+    })
+    .is_break()
+}
+
+fn out_of_memory(moves: usize) -> Error {
+    Error::new(
+        format!("the C VM ran out of memory after {moves} moves"),
+        None,
+    )
+    .with_help(Some(
+        "use `--max-tape-cells` to stop the run before the tape gets this big".to_string(),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bytecode as bc;
+
+    // bytecode whose entry code goes to a state at 12 made of `state`
+    fn program(state: &[u8]) -> Vec<u8> {
+        let mut bytes = bc::header(bc::Version::V1, 0);
+        bytes[2..6].copy_from_slice(&7u32.to_le_bytes());
+        bytes.push(bc::FINAL_STATE);
+        bytes.extend(12u32.to_le_bytes());
+        bytes.extend(state);
+        bytes
+    }
+
+    // writes `1` and goes right forever
+    fn writes_ones() -> Vec<u8> {
+        let mut state = vec![bc::OTHER, bc::WRITE_VAL, 1, 0, bc::RIGHT, bc::FINAL_STATE];
+        state.extend(12u32.to_le_bytes());
+        program(&state)
+    }
+
+    // keeps track of the bytes it has out, and fails once that would go over
+    // `limit`
+    struct Counter {
+        live: usize,
+        limit: usize,
+    }
+
+    extern "C" fn counted_alloc(data: *mut c_void, size: usize, align: usize) -> *mut c_void {
+        let counter = unsafe { &mut *data.cast::<Counter>() };
+        if counter.live + size > counter.limit {
+            return ptr::null_mut();
+        }
+        counter.live += size;
+        rust_alloc(data, size, align)
+    }
+
+    extern "C" fn counted_realloc(
+        data: *mut c_void,
+        p: *mut c_void,
+        old_size: usize,
+        new_size: usize,
+        align: usize,
+    ) -> *mut c_void {
+        let counter = unsafe { &mut *data.cast::<Counter>() };
+        if counter.live - old_size + new_size > counter.limit {
+            return ptr::null_mut();
+        }
+        counter.live = counter.live - old_size + new_size;
+        rust_realloc(data, p, old_size, new_size, align)
+    }
+
+    extern "C" fn counted_free(data: *mut c_void, p: *mut c_void, size: usize, align: usize) {
+        let counter = unsafe { &mut *data.cast::<Counter>() };
+        counter.live -= size;
+        rust_free(data, p, size, align)
+    }
+
+    fn counted(counter: &mut Counter) -> Allocator {
+        Allocator {
+            alloc: counted_alloc,
+            realloc: counted_realloc,
+            free: counted_free,
+            data: (counter as *mut Counter).cast(),
         }
     }
+
+    #[test]
+    fn recovers_from_invalid_bytecode() {
+        let mut state = vec![bc::OTHER, bc::RIGHT, bc::FINAL_STATE];
+        state.extend(19u32.to_le_bytes());
+        state.push(0xfe);
+        let bytes = program(&state);
+
+        let mut counter = Counter {
+            live: 0,
+            limit: usize::MAX,
+        };
+        unsafe {
+            let vm = new_vm(&counted(&mut counter));
+            assert!(init_tape(vm, ptr::null(), 0, false, usize::MAX));
+            let status = run(vm, bytes.as_ptr(), bytes.len(), 1000);
+            assert_eq!(status, INVALID_BYTECODE);
+            let message = CStr::from_ptr(get_error_message(vm));
+            assert_eq!(message.to_str().unwrap(), "unknown opcode 0xfe");
+            assert_eq!((get_error_address(vm), get_move_count(vm)), (19, 1));
+            assert_eq!(get_tape_head_position(vm), 1);
+            free_vm(vm);
+        }
+        assert_eq!(counter.live, 0);
+    }
+
+    #[test]
+    fn recovers_from_running_out_of_memory() {
+        let bytes = writes_ones();
+        let mut memory = Counter {
+            live: 0,
+            limit: usize::MAX,
+        };
+        let mut tape_memory = Counter {
+            live: 0,
+            limit: 1 << 16,
+        };
+        unsafe {
+            let vm = new_vm(&counted(&mut memory));
+            set_tape_allocator(vm, &counted(&mut tape_memory));
+            assert!(init_tape(vm, ptr::null(), 0, false, usize::MAX));
+            let status = run(vm, bytes.as_ptr(), bytes.len(), usize::MAX);
+            assert_eq!(status, OUT_OF_MEMORY);
+            // the tape it had before the failed allocation is still whole
+            let len = get_tape_len(vm);
+            assert!(len * 4 <= 1 << 16);
+            assert_eq!(get_move_count(vm), len);
+            free_vm(vm);
+        }
+        assert_eq!((memory.live, tape_memory.live), (0, 0));
+    }
+
+    #[test]
+    fn takes_the_tape() {
+        let bytes = writes_ones();
+        let mut memory = Counter {
+            live: 0,
+            limit: usize::MAX,
+        };
+        let tape = unsafe {
+            let vm = new_vm(&counted(&mut memory));
+            set_tape_allocator(vm, &RUST_ALLOCATOR);
+            assert!(init_tape(vm, ptr::null(), 0, false, usize::MAX));
+            assert_eq!(run(vm, bytes.as_ptr(), bytes.len(), 1000), MAX_MOVES);
+            let len = get_tape_len(vm);
+            let tape = Vec::from_raw_parts(take_tape(vm), len, len);
+            // the getters still read the tape after it's taken
+            assert_eq!(get_tape_len(vm), len);
+            free_vm(vm);
+            tape
+        };
+        assert_eq!(memory.live, 0);
+        assert!(tape.len() > 1000);
+        assert!(tape[..1000].iter().all(|&cell| cell == 1));
+        assert!(tape[1000..].iter().all(|&cell| cell == 0));
+    }
 }
@@ -0,0 +1,418 @@
+use std::fmt::Write;
+
+use unicode_segmentation::UnicodeSegmentation;
+
+use crate::lex::Span;
+use crate::parse::{Alphabet, Arm, Op, Pattern, State, ToState, Unit};
+
+const MAX_WIDTH: usize = 100;
+
+pub fn format(unit: &Unit, comments: &[Span]) -> String {
+    let mut comments = Comments { comments };
+    let mut output = String::new();
+    if let Some(alphabet) = &unit.alphabet {
+        format_alphabet(&mut output, alphabet, &mut comments);
+    }
+    for state in &unit.states {
+        if !output.is_empty() {
+            output.push('\n');
+        }
+        format_state(&mut output, state, &mut comments);
+    }
+
+    if !comments.comments.is_empty() {
+        if !output.is_empty() {
+            output.push('\n');
+        }
+        write_block(&mut output, comments.comments, None);
+    }
+    output
+}
+
+// comments are handed out in source order as the formatter reaches them
+struct Comments<'a, 'b> {
+    comments: &'a [Span<'b>],
+}
+
+impl<'b> Comments<'_, 'b> {
+    fn before(&mut self, span: Span) -> Vec<Span<'b>> {
+        let count = self
+            .comments
+            .iter()
+            .take_while(|comment| {
+                (comment.line, comment.prefix.len()) < (span.line, span.prefix.len())
+            })
+            .count();
+        let (before, rest) = self.comments.split_at(count);
+        self.comments = rest;
+        before.to_vec()
+    }
+
+    fn on_line(&mut self, line: usize) -> Vec<Span<'b>> {
+        let count = self
+            .comments
+            .iter()
+            .take_while(|comment| comment.line == line)
+            .count();
+        let (on_line, rest) = self.comments.split_at(count);
+        self.comments = rest;
+        on_line.to_vec()
+    }
+}
+
+fn is_own_line(comment: &Span) -> bool {
+    comment.prefix.trim().is_empty()
+}
+
+// writes comments that sit on their own lines, keeping single blank lines between
+// them (and before `next_line`, if there is one)
+fn write_block(output: &mut String, comments: &[Span], next_line: Option<usize>) {
+    for (i, comment) in comments.iter().enumerate() {
+        if i > 0 && comment.line > comments[i - 1].line + 1 {
+            output.push('\n');
+        }
+        writeln!(output, "{}", comment.text.trim_end()).unwrap();
+    }
+    if let (Some(last), Some(next_line)) = (comments.last(), next_line) {
+        if next_line > last.line + 1 {
+            output.push('\n');
+        }
+    }
+}
+
+fn write_indented(output: &mut String, comments: &[Span]) {
+    for comment in comments {
+        writeln!(output, "    {}", comment.text.trim_end()).unwrap();
+    }
+}
+
+// puts trailing comments at the end of the line that was just written
+fn write_trailing(output: &mut String, comments: &[Span]) {
+    if comments.is_empty() {
+        return;
+    }
+    output.pop();
+    for comment in comments {
+        write!(output, " {}", comment.text.trim_end()).unwrap();
+    }
+    output.push('\n');
+}
+
+fn format_alphabet(output: &mut String, alphabet: &Alphabet, comments: &mut Comments) {
+    let leading = comments.before(alphabet.span);
+    write_block(output, &leading, Some(alphabet.span.line));
+
+    let mut line = "alphabet |".to_string();
+    for symbol in &alphabet.symbols {
+        if width(&line) + 1 + width(symbol.span.text) > MAX_WIDTH {
+            writeln!(output, "{line}").unwrap();
+            line = "   ".to_string();
+        }
+        write!(line, " {}", symbol.span.text).unwrap();
+    }
+    writeln!(output, "{line}").unwrap();
+
+    // comments between the symbols end up after the last one
+    let last_line = alphabet
+        .symbols
+        .last()
+        .map_or(alphabet.span, |symbol| symbol.span)
+        .line;
+    for line in alphabet.span.line..=last_line {
+        write_trailing(output, &comments.on_line(line));
+    }
+}
+
+fn format_state(output: &mut String, state: &State, comments: &mut Comments) {
+    let leading = comments.before(state.first);
+    write_block(output, &leading, Some(state.first.line));
+
+    for line in &state.doc {
+        if line.is_empty() {
+            output.push_str("///\n");
+        } else {
+            writeln!(output, "/// {line}").unwrap();
+        }
+    }
+    write_block(output, &comments.before(state.name.span), None);
+
+    let state_params: Vec<_> = state.state_params.iter().map(|p| p.name).collect();
+    let symbol_params: Vec<_> = state.symbol_params.iter().map(|p| p.name).collect();
+    write!(
+        output,
+        "{}{}",
+        state.name.name,
+        parens(&state_params, &symbol_params)
+    )
+    .unwrap();
+
+    let first_anchor = state
+        .arms
+        .first()
+        .map(|arm| pattern_span(&arm.pattern))
+        .unwrap_or(state.last);
+    let (mut leading, trailing): (Vec<_>, Vec<_>) = comments
+        .before(first_anchor)
+        .into_iter()
+        .partition(is_own_line);
+
+    if state.arms.is_empty() && leading.is_empty() {
+        output.push_str(" {}\n");
+        write_trailing(output, &trailing);
+        write_trailing(output, &comments.on_line(state.last.line));
+        return;
+    }
+    output.push_str(" {\n");
+    write_trailing(output, &trailing);
+
+    let arms: Vec<_> = state
+        .arms
+        .iter()
+        .map(|arm| {
+            (
+                format_pattern(arm),
+                format_ops(&arm.ops),
+                format_to_state(&arm.to_state),
+            )
+        })
+        .collect();
+
+    // only arms that fit on one line take part in the column alignment
+    let fits = |(pattern, ops, to_state): &(String, Vec<String>, String)| {
+        width(pattern) + width(&ops.join(" ")) + width(to_state) + 11 <= MAX_WIDTH
+    };
+    let pattern_width = arms
+        .iter()
+        .filter(|arm| fits(arm))
+        .map(|(pattern, _, _)| width(pattern))
+        .max()
+        .unwrap_or(0);
+    let ops_width = arms
+        .iter()
+        .filter(|arm| fits(arm))
+        .map(|(_, ops, _)| width(&ops.join(" ")))
+        .max()
+        .unwrap_or(0);
+
+    for (i, arm) in arms.iter().enumerate() {
+        write_indented(output, &leading);
+
+        let (pattern, ops, to_state) = arm;
+        if fits(arm) {
+            let ops = ops.join(" ");
+            write!(output, "    {pattern}{} |", pad(pattern, pattern_width)).unwrap();
+            if ops_width > 0 {
+                write!(output, " {ops}{}", pad(&ops, ops_width)).unwrap();
+            }
+            writeln!(output, " | {to_state},").unwrap();
+        } else {
+            writeln!(output, "    {pattern} |").unwrap();
+            let mut line = String::new();
+            for op in ops {
+                if !line.is_empty() && 8 + width(&line) + 1 + width(op) > MAX_WIDTH {
+                    writeln!(output, "        {line}").unwrap();
+                    line.clear();
+                }
+                if !line.is_empty() {
+                    line.push(' ');
+                }
+                line.push_str(op);
+            }
+            if !line.is_empty() {
+                writeln!(output, "        {line}").unwrap();
+            }
+            writeln!(output, "    | {to_state},").unwrap();
+        }
+
+        let next_anchor = state
+            .arms
+            .get(i + 1)
+            .map(|arm| pattern_span(&arm.pattern))
+            .unwrap_or(state.last);
+        let trailing;
+        (leading, trailing) = comments
+            .before(next_anchor)
+            .into_iter()
+            .partition(is_own_line);
+        write_trailing(output, &trailing);
+    }
+
+    write_indented(output, &leading);
+    output.push_str("}\n");
+    write_trailing(output, &comments.on_line(state.last.line));
+}
+
+pub fn format_pattern(arm: &Arm) -> String {
+    match &arm.lookahead {
+        Some(lookahead) => format!(
+            "{} then {}",
+            pattern_text(&arm.pattern),
+            pattern_text(lookahead)
+        ),
+        None => pattern_text(&arm.pattern).to_string(),
+    }
+}
+
+fn pattern_span<'a>(pattern: &Pattern<'a>) -> Span<'a> {
+    match pattern {
+        Pattern::Symbol(symbol) => symbol.span,
+        Pattern::Name(name) => name.span,
+    }
+}
+
+fn pattern_text<'a>(pattern: &Pattern<'a>) -> &'a str {
+    match pattern {
+        Pattern::Symbol(symbol) => symbol.span.text,
+        Pattern::Name(name) => name.name,
+    }
+}
+
+// moves are attached to the op before them, so `'1' >> '0'` stays together
+fn format_ops(ops: &[Op]) -> Vec<String> {
+    let mut groups: Vec<String> = Vec::new();
+    let mut last_was_move = false;
+    for op in ops {
+        let (text, is_move) = match op {
+            Op::Left(span) | Op::Right(span) => (span.text, true),
+            Op::Name(name) => (name.name, false),
+            Op::Symbol(symbol) => (symbol.span.text, false),
+        };
+
+        match groups.last_mut() {
+            Some(group) if is_move => {
+                if !last_was_move {
+                    group.push(' ');
+                }
+                group.push_str(text);
+            }
+            _ => groups.push(text.to_string()),
+        }
+        last_was_move = is_move;
+    }
+    groups
+}
+
+pub fn format_to_state(to_state: &ToState) -> String {
+    match to_state {
+        ToState::State {
+            name,
+            state_args,
+            symbol_args,
+        } => {
+            let state_args: Vec<_> = state_args.iter().map(format_to_state).collect();
+            let symbol_args: Vec<_> = symbol_args.iter().map(pattern_text).collect();
+            format!("{}{}", name.name, parens(&state_args, &symbol_args))
+        }
+        ToState::Halt { span } => span.text.to_string(),
+    }
+}
+
+fn parens(before: &[impl AsRef<str>], after: &[impl AsRef<str>]) -> String {
+    match (before.is_empty(), after.is_empty()) {
+        (true, true) => String::new(),
+        (false, true) => format!("({})", join(before)),
+        (true, false) => format!("(; {})", join(after)),
+        (false, false) => format!("({}; {})", join(before), join(after)),
+    }
+}
+
+fn join(items: &[impl AsRef<str>]) -> String {
+    let items: Vec<_> = items.iter().map(AsRef::as_ref).collect();
+    items.join(", ")
+}
+
+fn width(text: &str) -> usize {
+    text.graphemes(true).count()
+}
+
+fn pad(text: &str, width: usize) -> String {
+    " ".repeat(width - self::width(text))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::{Path, PathBuf};
+
+    use super::*;
+    use crate::compile;
+    use crate::lex::{self, Edition, Tokens};
+    use crate::parse::{self, StartArgs};
+
+    const OPTIONS: lex::Options = lex::Options {
+        allow_tabs: false,
+        bare_digits: true,
+        edition: Edition::E2023,
+    };
+
+    fn examples() -> Vec<PathBuf> {
+        let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("examples");
+        let mut paths: Vec<_> = fs::read_dir(dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .filter(|path| path.extension().is_some_and(|ext| ext == "tml"))
+            .collect();
+        paths.sort();
+        paths
+    }
+
+    fn format_code(code: &str, path: &Path) -> String {
+        let unit = parse::parse(Tokens::new(code, path, OPTIONS).unwrap()).unwrap();
+        let comments = Tokens::new(code, path, OPTIONS)
+            .unwrap()
+            .comments()
+            .unwrap();
+        format(&unit, &comments)
+    }
+
+    // formatting reparses to the same syntax tree and bytecode, and formatting
+    // the result again changes nothing
+    fn check_round_trip(code: &str, path: &Path) {
+        let formatted = format_code(code, path);
+        let unit = parse::parse(Tokens::new(code, path, OPTIONS).unwrap()).unwrap();
+        let reparsed = parse::parse(Tokens::new(&formatted, path, OPTIONS).unwrap()).unwrap();
+        assert_eq!(
+            format!("{unit:?}"),
+            format!("{reparsed:?}"),
+            "{}",
+            path.display()
+        );
+
+        let bytes = |unit| {
+            compile::compile(unit, Vec::new(), StartArgs::default())
+                .unwrap()
+                .bytes
+        };
+        assert_eq!(bytes(unit), bytes(reparsed), "{}", path.display());
+
+        assert_eq!(
+            format_code(&formatted, path),
+            formatted,
+            "{}",
+            path.display()
+        );
+    }
+
+    #[test]
+    fn examples_round_trip() {
+        let paths = examples();
+        assert!(!paths.is_empty());
+        for path in paths {
+            let code = fs::read_to_string(&path).unwrap();
+            check_round_trip(&code, &path);
+        }
+    }
+
+    #[test]
+    fn messy_machine_round_trips() {
+        let code = "\
+// a comment
+start{_|'1' >|f(g;'1'),}
+/// doc comment
+f(k;x){x|<'0'| k,   '' | > | f(k; x),
+}
+g {'0'||!,_ | | g,}
+";
+        check_round_trip(code, Path::new("messy.tml"));
+    }
+}
@@ -0,0 +1,102 @@
+use std::collections::HashMap;
+
+use termion::{color, style};
+
+use crate::analysis;
+use crate::compile::Signature;
+use crate::output;
+use crate::parse::{StartArgs, State};
+
+pub fn print(unit: &[State]) {
+    for (signature, callees) in analysis::call_graph(unit) {
+        if callees.is_empty() {
+            println!("{signature}");
+        } else {
+            let callees: Vec<_> = callees.iter().map(Signature::to_string).collect();
+            println!("{signature} -> {}", callees.join(", "));
+        }
+    }
+}
+
+pub fn print_reachability(unit: &[State], start_args: &StartArgs) {
+    let start = Signature::start(start_args);
+    let locations: HashMap<_, _> = unit
+        .iter()
+        .map(|state| {
+            let span = state.name.span;
+            (
+                Signature::of(state),
+                format!("{}:{}", span.path.display(), span.line + 1),
+            )
+        })
+        .collect();
+    // list states in source order
+    let order: HashMap<_, _> = unit
+        .iter()
+        .enumerate()
+        .map(|(i, state)| (Signature::of(state), i))
+        .collect();
+
+    let reachable = analysis::reachable(unit, start_args);
+    let (live, dead): (Vec<_>, Vec<_>) = unit
+        .iter()
+        .map(Signature::of)
+        .partition(|signature| reachable.contains(signature));
+
+    heading(&format!("reachable from `{start}` ({})", live.len()));
+    for signature in &live {
+        println!("    {signature} ({})", locations[signature]);
+    }
+
+    println!();
+    heading(&format!("dead ({})", dead.len()));
+    for signature in &dead {
+        println!("    {signature} ({})", locations[signature]);
+    }
+
+    // a single state is only interesting if it calls itself
+    let graph = analysis::call_graph(unit);
+    let calls_itself = |signature: &Signature| {
+        graph
+            .iter()
+            .any(|(caller, callees)| caller == signature && callees.contains(signature))
+    };
+    let mut components: Vec<_> = analysis::strongly_connected(&graph)
+        .into_iter()
+        .filter(|component| component.len() > 1 || calls_itself(&component[0]))
+        .map(|mut component| {
+            component.sort_by_key(|signature| order[signature]);
+            component
+        })
+        .collect();
+    components.sort_by_key(|component| order[&component[0]]);
+
+    println!();
+    heading(&format!(
+        "strongly connected components ({})",
+        components.len()
+    ));
+    for component in &components {
+        let names: Vec<_> = component.iter().map(Signature::to_string).collect();
+        let status = if reachable.contains(&component[0]) {
+            ""
+        } else {
+            " (dead)"
+        };
+        println!("    {}{status}", names.join(", "));
+    }
+}
+
+fn heading(label: &str) {
+    if !output::color() {
+        println!("{label}:");
+    } else {
+        println!(
+            "{}{}{label}:{}{}",
+            style::Bold,
+            color::Fg(color::Green),
+            style::Reset,
+            color::Fg(color::Reset)
+        );
+    }
+}
@@ -189,24 +189,24 @@ impl Mul for &Int {
 
 impl PartialOrd for Int {
     fn partial_cmp(&self, rhs: &Int) -> Option<Ordering> {
+        Some(self.cmp(rhs))
+    }
+}
+
+impl Ord for Int {
+    fn cmp(&self, rhs: &Int) -> Ordering {
         let cmp = self.0.len().cmp(&rhs.0.len());
         if cmp != Ordering::Equal {
-            return Some(cmp);
+            return cmp;
         }
 
         for (a, b) in self.0.iter().zip(rhs.0.iter()).rev() {
             if a.cmp(b) != Ordering::Equal {
-                return Some(a.cmp(b));
+                return a.cmp(b);
             }
         }
 
-        Some(Ordering::Equal)
-    }
-}
-
-impl Ord for Int {
-    fn cmp(&self, rhs: &Int) -> Ordering {
-        self.partial_cmp(rhs).unwrap()
+        Ordering::Equal
     }
 }
 
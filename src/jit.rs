@@ -0,0 +1,520 @@
+use std::mem;
+use std::ops::ControlFlow;
+
+use cranelift_codegen::ir::condcodes::IntCC;
+use cranelift_codegen::ir::{types, AbiParam, Block, InstBuilder, MemFlags, Value};
+use cranelift_codegen::settings::{self, Configurable};
+use cranelift_frontend::{FunctionBuilder, FunctionBuilderContext, Switch, Variable};
+use cranelift_jit::{JITBuilder, JITModule};
+use cranelift_module::{default_libcall_names, Linkage, Module};
+
+use crate::backend::{self, HaltReason, Options, Simulated};
+use crate::error::Error;
+use crate::table::{self, Arm, Op, Table, HALT};
+use crate::vm::{DenseTape, Tape};
+
+// tables with more arms than this take longer to compile than to interpret
+const MAX_ARMS: usize = 1 << 16;
+
+// why the native code returned
+const LIMIT: u32 = 0;
+const HALTED: u32 = 1;
+// the next move could leave the cells it may touch (see `Context`), so it has
+// to be made on the `DenseTape`
+const SLOW: u32 = 2;
+
+/// Runs machines that fit in a transition table (see `table::Table`) as native
+/// code, compiled with Cranelift. Each state is a block that reads the cell
+/// under the head and jumps to one of its arms, and each arm jumps straight to
+/// the next state's block, so there's no dispatch loop. It makes the same moves
+/// as the other backends, so the result is the same
+pub struct JitVm;
+
+impl JitVm {
+    /// Machines that don't fit in a table run on the Rust VM, and ones with
+    /// too many arms run on the table
+    pub fn run(&self, bytes: &[u8], tape: &[u32], options: &Options) -> Result<Simulated, Error> {
+        let Some(table) = Table::build(bytes, tape) else {
+            return backend::select(true).run(bytes, tape, options);
+        };
+        let mut dense = DenseTape::new(tape.to_vec(), options.two_sided);
+        dense.max_cells = options.max_tape_cells;
+        let mut simulated = match table.arms.len() <= MAX_ARMS {
+            true => Native::new(&table)?.run(&table, dense, options),
+            false => table.run(dense, options),
+        };
+        backend::trim(&mut simulated, tape.len(), options.trim);
+        Ok(simulated)
+    }
+}
+
+// what the native code works on. it only moves the head between `low` and
+// `high` (indices into `cells`), which are picked so that the moves can't grow
+// the tape, fall off the left edge or go past the tape cell limit. a move that
+// would go further returns `SLOW` without making it
+#[repr(C)]
+struct Context {
+    cells: *mut u32,
+    head: i64,
+    state: i64,
+    moves: i64,
+    // the native code returns `LIMIT` once `moves` gets to this
+    limit: i64,
+    low: i64,
+    high: i64,
+    origin: i64,
+    // the `DenseTape` fields, as indices into `cells`
+    rightmost: i64,
+    leftmost: i64,
+    left_edge_contacts: i64,
+}
+
+// the fields `Context` keeps in registers while it runs, in order
+const FIELDS: [(Field, i32); 5] = [
+    (Field::Head, mem::offset_of!(Context, head) as i32),
+    (Field::Moves, mem::offset_of!(Context, moves) as i32),
+    (Field::Rightmost, mem::offset_of!(Context, rightmost) as i32),
+    (Field::Leftmost, mem::offset_of!(Context, leftmost) as i32),
+    (
+        Field::Contacts,
+        mem::offset_of!(Context, left_edge_contacts) as i32,
+    ),
+];
+
+#[derive(Clone, Copy)]
+enum Field {
+    Head,
+    Moves,
+    Rightmost,
+    Leftmost,
+    Contacts,
+    // the symbol the current move started on, which isn't stored back
+    Symbol,
+}
+
+impl Field {
+    fn var(self) -> Variable {
+        Variable::from_u32(self as u32)
+    }
+}
+
+struct Native {
+    // owns the code `function` points into
+    module: Option<JITModule>,
+    function: Function,
+}
+
+type Function = unsafe extern "C" fn(*mut Context) -> u32;
+
+impl Native {
+    fn new(table: &Table) -> Result<Self, Error> {
+        let error = |err: String| {
+            Error::new(
+                format!("couldn't compile the machine to native code: {err}"),
+                None,
+            )
+        };
+        let mut flags = settings::builder();
+        flags
+            .set("opt_level", "speed")
+            .map_err(|err| error(err.to_string()))?;
+        let isa = cranelift_native::builder()
+            .map_err(|err| error(err.to_string()))?
+            .finish(settings::Flags::new(flags))
+            .map_err(|err| error(err.to_string()))?;
+        let mut module = JITModule::new(JITBuilder::with_isa(isa, default_libcall_names()));
+
+        let mut context = module.make_context();
+        let pointer = module.target_config().pointer_type();
+        context.func.signature.params.push(AbiParam::new(pointer));
+        context
+            .func
+            .signature
+            .returns
+            .push(AbiParam::new(types::I32));
+        let mut builder_context = FunctionBuilderContext::new();
+        Builder::build(
+            table,
+            FunctionBuilder::new(&mut context.func, &mut builder_context),
+            pointer,
+        );
+
+        let id = module
+            .declare_function("run", Linkage::Local, &context.func.signature)
+            .map_err(|err| error(err.to_string()))?;
+        module
+            .define_function(id, &mut context)
+            .map_err(|err| error(err.to_string()))?;
+        module.clear_context(&mut context);
+        module
+            .finalize_definitions()
+            .map_err(|err| error(err.to_string()))?;
+        let code = module.get_finalized_function(id);
+        Ok(Native {
+            module: Some(module),
+            // SAFETY: the function was declared with this signature, and the
+            // module keeps it alive until it's dropped
+            function: unsafe { mem::transmute::<*const u8, Function>(code) },
+        })
+    }
+
+    // runs like `Table::run`, but a stretch of moves at a time in native code.
+    // between stretches, it checks for interrupts and makes the moves the native
+    // code can't on the tape
+    fn run(&self, table: &Table, mut tape: DenseTape, options: &Options) -> Simulated {
+        let mut moves = 0;
+        if let ControlFlow::Break(reason) = table::run_arm(&table.entry, &mut tape, 0) {
+            return tape.finish(table.entry_address, moves, reason);
+        }
+        let mut state = table.entry.next;
+        // checked in the same order as `Table::run`
+        let reason = loop {
+            if moves == options.max_moves {
+                break HaltReason::MaxMoves;
+            }
+            if moves.is_multiple_of(backend::INTERRUPT_CHECK_INTERVAL) && backend::interrupted() {
+                break HaltReason::Interrupted;
+            }
+            if state == HALT {
+                break HaltReason::Halted;
+            }
+            // up to the next interrupt check
+            let limit =
+                (moves / backend::INTERRUPT_CHECK_INTERVAL + 1) * backend::INTERRUPT_CHECK_INTERVAL;
+            match self.enter(
+                &mut tape,
+                &mut state,
+                &mut moves,
+                limit.min(options.max_moves),
+            ) {
+                LIMIT => continue,
+                HALTED if state == HALT => continue,
+                HALTED => break HaltReason::Halted,
+                _ => {}
+            }
+            let symbol = tape.read();
+            let cell = table.cells[state as usize * table.width + symbol as usize];
+            if cell == HALT {
+                break HaltReason::Halted;
+            }
+            let arm = &table.arms[cell as usize];
+            // so the native code can make the next moves like it
+            tape.reserve(tape.head() + reach(arm).1);
+            if let ControlFlow::Break(reason) = table::run_arm(arm, &mut tape, symbol) {
+                break reason;
+            }
+            state = arm.next;
+            moves += 1;
+        };
+        tape.finish(table.address(state), moves, reason)
+    }
+
+    // runs the native code from `state` until it returns, and returns why
+    fn enter(&self, tape: &mut DenseTape, state: &mut u32, moves: &mut usize, limit: usize) -> u32 {
+        tape.reserve(tape.head());
+        let origin = tape.origin as i64;
+        let (mut low, mut high) = (0, tape.tape.len() as i64 - 1);
+        // split what's left before the tape cell limit between the two ends, so
+        // that the native code can't go past it however it moves
+        if tape.max_cells != usize::MAX {
+            let room = (tape.max_cells - tape.span()) as i64;
+            high = high.min(tape.rightmost as i64 + (room + 1) / 2);
+            low = low.max(origin + tape.leftmost - room / 2);
+        }
+        let mut context = Context {
+            cells: tape.tape.as_mut_ptr(),
+            head: tape.head as i64,
+            state: *state as i64,
+            moves: *moves as i64,
+            limit: limit as i64,
+            low,
+            high,
+            origin,
+            rightmost: tape.rightmost as i64,
+            leftmost: origin + tape.leftmost,
+            left_edge_contacts: tape.left_edge_contacts as i64,
+        };
+        // SAFETY: the native code only touches the cells from `low` to `high`,
+        // which are all in the tape's buffer
+        let code = unsafe { (self.function)(&mut context) };
+        tape.head = context.head as usize;
+        tape.rightmost = context.rightmost as usize;
+        tape.leftmost = context.leftmost - origin;
+        tape.left_edge_contacts = context.left_edge_contacts as usize;
+        *state = context.state as u32;
+        *moves = context.moves as usize;
+        code
+    }
+}
+
+impl Drop for Native {
+    fn drop(&mut self) {
+        if let Some(module) = self.module.take() {
+            // SAFETY: `function` isn't called after this
+            unsafe { module.free_memory() };
+        }
+    }
+}
+
+// the furthest an arm moves the head each way from where it starts, including
+// in the middle of the arm
+fn reach(arm: &Arm) -> (i64, i64) {
+    let (mut position, mut low, mut high) = (0i64, 0i64, 0i64);
+    for &op in &arm.ops {
+        match op {
+            Op::Left(n) => position -= n as i64,
+            Op::Right(n) => position += n as i64,
+            Op::Write(_) | Op::WriteRead => {}
+        }
+        low = low.min(position);
+        high = high.max(position);
+    }
+    (low, high)
+}
+
+struct Builder<'a> {
+    builder: FunctionBuilder<'a>,
+    context: Value,
+    // blocks that jump to the exit block with a return code and state. they're
+    // filled in last, since a block has to be finished before switching away
+    exits: Vec<(Block, u32, i64)>,
+}
+
+impl<'a> Builder<'a> {
+    fn build(table: &Table, mut builder: FunctionBuilder<'a>, pointer: types::Type) {
+        let entry = builder.create_block();
+        builder.append_block_params_for_function_params(entry);
+        builder.switch_to_block(entry);
+        builder.seal_block(entry);
+        let context = builder.block_params(entry)[0];
+        for (field, offset) in FIELDS {
+            builder.declare_var(field.var(), types::I64);
+            let value = builder
+                .ins()
+                .load(types::I64, MemFlags::trusted(), context, offset);
+            builder.def_var(field.var(), value);
+        }
+        builder.declare_var(Field::Symbol.var(), types::I64);
+        let zero = builder.ins().iconst(types::I64, 0);
+        builder.def_var(Field::Symbol.var(), zero);
+
+        // stores the registers back and returns. it takes the return code and
+        // the state
+        let exit = builder.create_block();
+        builder.append_block_param(exit, types::I32);
+        builder.append_block_param(exit, types::I64);
+        let mut this = Builder {
+            builder,
+            context,
+            exits: Vec::new(),
+        };
+
+        let states: Vec<Block> = (0..table.cells.len() / table.width)
+            .map(|_| this.builder.create_block())
+            .collect();
+        let state = this.load(mem::offset_of!(Context, state));
+        let mut switch = Switch::new();
+        for (index, &block) in states.iter().enumerate() {
+            switch.set_entry(index as u128, block);
+        }
+        let fallback = this.exit_block(LIMIT, -1);
+        switch.emit(&mut this.builder, state, fallback);
+
+        for (index, &block) in states.iter().enumerate() {
+            this.state(table, index, block, &states, pointer);
+        }
+
+        for (block, code, state) in mem::take(&mut this.exits) {
+            this.builder.switch_to_block(block);
+            let code = this.builder.ins().iconst(types::I32, code as i64);
+            let state = this.builder.ins().iconst(types::I64, state);
+            this.builder.ins().jump(exit, &[code, state]);
+        }
+        this.builder.switch_to_block(exit);
+        let (code, state) = {
+            let params = this.builder.block_params(exit);
+            (params[0], params[1])
+        };
+        for (field, offset) in FIELDS {
+            let value = this.builder.use_var(field.var());
+            this.builder
+                .ins()
+                .store(MemFlags::trusted(), value, context, offset);
+        }
+        this.builder.ins().store(
+            MemFlags::trusted(),
+            state,
+            context,
+            mem::offset_of!(Context, state) as i32,
+        );
+        this.builder.ins().return_(&[code]);
+        this.builder.seal_all_blocks();
+        this.builder.finalize();
+    }
+
+    // a state's block: stops at the move limit, and otherwise reads the cell
+    // under the head and jumps to the arm for it
+    fn state(
+        &mut self,
+        table: &Table,
+        index: usize,
+        block: Block,
+        states: &[Block],
+        pointer: types::Type,
+    ) {
+        self.builder.switch_to_block(block);
+        let moves = self.builder.use_var(Field::Moves.var());
+        let limit = self.load(mem::offset_of!(Context, limit));
+        let done = self.builder.ins().icmp(IntCC::Equal, moves, limit);
+        let read = self.builder.create_block();
+        let stop = self.exit_block(LIMIT, index as i64);
+        self.builder.ins().brif(done, stop, &[], read, &[]);
+
+        self.builder.switch_to_block(read);
+        let address = self.cell_address(pointer);
+        let symbol = self.builder.ins().uload32(MemFlags::trusted(), address, 0);
+        self.builder.def_var(Field::Symbol.var(), symbol);
+
+        let row = &table.cells[index * table.width..(index + 1) * table.width];
+        let halt = self.exit_block(HALTED, index as i64);
+        let mut arms = Vec::new();
+        let mut switch = Switch::new();
+        for (symbol, &cell) in row.iter().enumerate() {
+            let target = match cell {
+                HALT => halt,
+                _ => match arms.iter().find(|&&(arm, _)| arm == cell) {
+                    Some(&(_, block)) => block,
+                    None => {
+                        let block = self.builder.create_block();
+                        arms.push((cell, block));
+                        block
+                    }
+                },
+            };
+            switch.set_entry(symbol as u128, target);
+        }
+        // the symbols on the tape all have columns
+        switch.emit(&mut self.builder, symbol, halt);
+
+        for (cell, block) in arms {
+            self.arm(&table.arms[cell as usize], index, block, states, pointer);
+        }
+    }
+
+    // an arm's block: makes its moves and jumps to the next state, unless the
+    // head could leave the cells the native code may touch
+    fn arm(
+        &mut self,
+        arm: &Arm,
+        state: usize,
+        block: Block,
+        states: &[Block],
+        pointer: types::Type,
+    ) {
+        self.builder.switch_to_block(block);
+        let (low, high) = reach(arm);
+        let head = self.builder.use_var(Field::Head.var());
+        let lowest = self.builder.ins().iadd_imm(head, low);
+        let highest = self.builder.ins().iadd_imm(head, high);
+        let low = self.load(mem::offset_of!(Context, low));
+        let high = self.load(mem::offset_of!(Context, high));
+        let above = self
+            .builder
+            .ins()
+            .icmp(IntCC::SignedGreaterThanOrEqual, lowest, low);
+        let below = self
+            .builder
+            .ins()
+            .icmp(IntCC::SignedLessThanOrEqual, highest, high);
+        let inside = self.builder.ins().band(above, below);
+        let run = self.builder.create_block();
+        let slow = self.exit_block(SLOW, state as i64);
+        self.builder.ins().brif(inside, run, &[], slow, &[]);
+
+        self.builder.switch_to_block(run);
+        let origin = self.load(mem::offset_of!(Context, origin));
+        for &op in &arm.ops {
+            match op {
+                Op::Write(symbol) => {
+                    let symbol = self.builder.ins().iconst(types::I32, symbol as i64);
+                    self.write(symbol, pointer);
+                }
+                Op::WriteRead => {
+                    let symbol = self.builder.use_var(Field::Symbol.var());
+                    let symbol = self.builder.ins().ireduce(types::I32, symbol);
+                    self.write(symbol, pointer);
+                }
+                Op::Left(n) => {
+                    let head = self.builder.use_var(Field::Head.var());
+                    let head = self.builder.ins().iadd_imm(head, -(n as i64));
+                    self.builder.def_var(Field::Head.var(), head);
+                    // like `DenseTape::left`
+                    let leftmost = self.builder.use_var(Field::Leftmost.var());
+                    let leftmost = self.builder.ins().smin(leftmost, head);
+                    self.builder.def_var(Field::Leftmost.var(), leftmost);
+                    let contact =
+                        self.builder
+                            .ins()
+                            .icmp(IntCC::SignedLessThanOrEqual, head, origin);
+                    let contact = self.builder.ins().uextend(types::I64, contact);
+                    let contacts = self.builder.use_var(Field::Contacts.var());
+                    let contacts = self.builder.ins().iadd(contacts, contact);
+                    self.builder.def_var(Field::Contacts.var(), contacts);
+                }
+                Op::Right(n) => {
+                    let head = self.builder.use_var(Field::Head.var());
+                    let head = self.builder.ins().iadd_imm(head, n as i64);
+                    self.builder.def_var(Field::Head.var(), head);
+                    let rightmost = self.builder.use_var(Field::Rightmost.var());
+                    let rightmost = self.builder.ins().smax(rightmost, head);
+                    self.builder.def_var(Field::Rightmost.var(), rightmost);
+                }
+            }
+        }
+        let moves = self.builder.use_var(Field::Moves.var());
+        let moves = self.builder.ins().iadd_imm(moves, 1);
+        self.builder.def_var(Field::Moves.var(), moves);
+        match arm.next {
+            HALT => {
+                let halted = self.exit_block(HALTED, HALT as i64);
+                self.builder.ins().jump(halted, &[]);
+            }
+            next => {
+                self.builder.ins().jump(states[next as usize], &[]);
+            }
+        }
+    }
+
+    // a block that jumps to the exit block with a return code and a state
+    fn exit_block(&mut self, code: u32, state: i64) -> Block {
+        let block = self.builder.create_block();
+        self.exits.push((block, code, state));
+        block
+    }
+
+    fn load(&mut self, offset: usize) -> Value {
+        self.builder
+            .ins()
+            .load(types::I64, MemFlags::trusted(), self.context, offset as i32)
+    }
+
+    // the address of the cell under the head
+    fn cell_address(&mut self, pointer: types::Type) -> Value {
+        let cells = self.builder.ins().load(
+            pointer,
+            MemFlags::trusted(),
+            self.context,
+            mem::offset_of!(Context, cells) as i32,
+        );
+        let head = self.builder.use_var(Field::Head.var());
+        let offset = self.builder.ins().ishl_imm(head, 2);
+        self.builder.ins().iadd(cells, offset)
+    }
+
+    fn write(&mut self, symbol: Value, pointer: types::Type) {
+        let address = self.cell_address(pointer);
+        self.builder
+            .ins()
+            .store(MemFlags::trusted(), symbol, address, 0);
+    }
+}
@@ -2,46 +2,123 @@ use std::path::{Path, PathBuf};
 use std::str::Lines;
 use std::{cmp, fs};
 
+use clap::ValueEnum;
+use serde::Deserialize;
 use termion::{color, style};
+use unicode_normalization::char::is_combining_mark;
 use unicode_segmentation::UnicodeSegmentation;
 
 use crate::error::Error;
+use crate::output;
 
 #[derive(Clone, Copy)]
-pub struct Span {
-    pub text: &'static str,
-    pub prefix: &'static str,
-    pub suffix: &'static str,
+pub struct Span<'a> {
+    pub text: &'a str,
+    pub prefix: &'a str,
+    pub suffix: &'a str,
     pub line: usize,
     pub column: usize,
-    pub path: &'static Path,
+    pub path: &'a Path,
 }
 
-impl Span {
-    pub fn print(self, no_color: bool) {
+/// A `Span` that owns its text, for diagnostics and debug info that outlive the
+/// source they point into
+#[derive(Clone, Debug)]
+pub struct SpanBuf {
+    pub text: String,
+    pub prefix: String,
+    pub suffix: String,
+    pub line: usize,
+    pub column: usize,
+    pub path: PathBuf,
+}
+
+impl SpanBuf {
+    pub fn as_span(&self) -> Span<'_> {
+        Span {
+            text: &self.text,
+            prefix: &self.prefix,
+            suffix: &self.suffix,
+            line: self.line,
+            column: self.column,
+            path: &self.path,
+        }
+    }
+}
+
+impl Span<'_> {
+    pub fn to_buf(self) -> SpanBuf {
+        SpanBuf {
+            text: self.text.to_string(),
+            prefix: self.prefix.to_string(),
+            suffix: self.suffix.to_string(),
+            line: self.line,
+            column: self.column,
+            path: self.path.to_path_buf(),
+        }
+    }
+
+    // the whole line the span is on, without indentation
+    pub fn source_line(self) -> String {
+        format!("{}{}{}", self.prefix, self.text, self.suffix)
+            .trim()
+            .to_string()
+    }
+
+    pub fn gutter_width(self) -> usize {
+        format!("{}", self.line + 1).len()
+    }
+
+    pub fn print(self, label: Option<&str>, gutter_width: usize) {
+        self.print_snippet("-->", '^', color::Red, label, gutter_width);
+    }
+
+    pub fn print_note(self, label: &str, gutter_width: usize) {
+        self.print_snippet(":::", '-', color::Blue, Some(label), gutter_width);
+    }
+
+    pub fn print_gap(gutter_width: usize) {
+        if !output::color() {
+            println!("{} |", " ".repeat(gutter_width));
+        } else {
+            println!(
+                "{} {}{}|{}{}",
+                " ".repeat(gutter_width),
+                style::Bold,
+                color::Fg(color::Blue),
+                style::Reset,
+                color::Fg(color::Reset)
+            );
+        }
+    }
+
+    fn print_snippet(
+        self,
+        arrow: &str,
+        marker: char,
+        marker_color: impl color::Color + Copy,
+        label: Option<&str>,
+        gutter_width: usize,
+    ) {
         let prefix_len = self.prefix.graphemes(true).count();
         let text_len = cmp::max(1, self.text.graphemes(true).count());
-        let line_str = format!("{}", self.line + 1);
-        if no_color {
+        let line_str = format!("{:>gutter_width$}", self.line + 1);
+        let gutter = " ".repeat(gutter_width);
+        let markers = marker.to_string().repeat(text_len);
+        let label = label.map(|label| format!(" {label}")).unwrap_or_default();
+        if !output::color() {
             println!(
-                "{}--> {}:{}:{}",
-                " ".repeat(line_str.len()),
+                "{gutter}{arrow} {}:{}:{}",
                 self.path.display(),
                 self.line + 1,
                 self.column + 1
             );
-            println!("{} |", " ".repeat(line_str.len()));
+            println!("{gutter} |");
             println!("{line_str} | {}{}{}", self.prefix, self.text, self.suffix);
-            println!(
-                "{} | {}{}",
-                " ".repeat(line_str.len()),
-                " ".repeat(prefix_len),
-                "^".repeat(text_len)
-            );
+            println!("{gutter} | {}{markers}{label}", " ".repeat(prefix_len));
         } else {
             println!(
-                "{}{}{}-->{}{} {}:{}:{}",
-                " ".repeat(line_str.len()),
+                "{gutter}{}{}{arrow}{}{} {}:{}:{}",
                 style::Bold,
                 color::Fg(color::Blue),
                 style::Reset,
@@ -51,8 +128,7 @@ impl Span {
                 self.column + 1
             );
             println!(
-                "{} {}{}|{}{}",
-                " ".repeat(line_str.len()),
+                "{gutter} {}{}|{}{}",
                 style::Bold,
                 color::Fg(color::Blue),
                 style::Reset,
@@ -69,16 +145,14 @@ impl Span {
                 self.suffix
             );
             println!(
-                "{} {}{}|{}{} {}{}{}{}{}{}",
-                " ".repeat(line_str.len()),
+                "{gutter} {}{}|{}{} {}{}{}{markers}{label}{}{}",
                 style::Bold,
                 color::Fg(color::Blue),
                 style::Reset,
                 color::Fg(color::Reset),
                 " ".repeat(prefix_len),
                 style::Bold,
-                color::Fg(color::Red),
-                "^".repeat(text_len),
+                color::Fg(marker_color),
                 style::Reset,
                 color::Fg(color::Reset),
             );
@@ -86,7 +160,7 @@ impl Span {
     }
 }
 
-impl std::fmt::Debug for Span {
+impl std::fmt::Debug for Span<'_> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(f, "Span({:?})", self.text)
     }
@@ -105,10 +179,83 @@ pub enum TokenKind {
     Left,
     Right,
     Name,
+    Keyword(Keyword),
     Symbol(String),
+    DocComment,
     Eof,
 }
 
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Keyword {
+    Halt,
+    Alphabet,
+    // reserved for future use
+    Import,
+    Const,
+    Module,
+}
+
+impl Keyword {
+    const ALL: [Keyword; 5] = [
+        Keyword::Halt,
+        Keyword::Alphabet,
+        Keyword::Import,
+        Keyword::Const,
+        Keyword::Module,
+    ];
+
+    pub fn from_name(name: &str, edition: Edition) -> Option<Keyword> {
+        Keyword::ALL
+            .into_iter()
+            .find(|keyword| keyword.as_str() == name && edition >= keyword.since())
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Keyword::Halt => "halt",
+            Keyword::Alphabet => "alphabet",
+            Keyword::Import => "import",
+            Keyword::Const => "const",
+            Keyword::Module => "module",
+        }
+    }
+
+    // the edition that made this word a keyword
+    pub fn since(self) -> Edition {
+        Edition::E2026
+    }
+
+    pub fn is_reserved(self) -> bool {
+        matches!(self, Keyword::Import | Keyword::Const | Keyword::Module)
+    }
+}
+
+// new keywords only take effect in a new edition, so old machines that use them
+// as names keep working
+#[derive(
+    Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash, ValueEnum, Deserialize,
+)]
+pub enum Edition {
+    // no keywords
+    #[default]
+    #[value(name = "2023")]
+    #[serde(rename = "2023")]
+    E2023,
+    // `halt` and `alphabet` are keywords and `import`, `const` and `module` are reserved
+    #[value(name = "2026")]
+    #[serde(rename = "2026")]
+    E2026,
+}
+
+impl std::fmt::Display for Edition {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Edition::E2023 => write!(f, "2023"),
+            Edition::E2026 => write!(f, "2026"),
+        }
+    }
+}
+
 impl TokenKind {
     pub fn desc(&self) -> &'static str {
         match self {
@@ -123,42 +270,62 @@ impl TokenKind {
             TokenKind::Left => "`<`",
             TokenKind::Right => "`>`",
             TokenKind::Name => "name",
+            TokenKind::Keyword(Keyword::Halt) => "`halt`",
+            TokenKind::Keyword(Keyword::Alphabet) => "`alphabet`",
+            TokenKind::Keyword(Keyword::Import) => "`import`",
+            TokenKind::Keyword(Keyword::Const) => "`const`",
+            TokenKind::Keyword(Keyword::Module) => "`module`",
             TokenKind::Symbol(_) => "symbol",
+            TokenKind::DocComment => "doc comment",
             TokenKind::Eof => "end of file",
         }
     }
 }
 
 #[derive(Clone, Debug)]
-pub struct Token {
+pub struct Token<'a> {
     pub kind: TokenKind,
-    pub span: Span,
+    pub span: Span<'a>,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Options {
+    pub allow_tabs: bool,
+    pub bare_digits: bool,
+    pub edition: Edition,
 }
 
-pub struct Tokens {
-    suffix: &'static str,
-    line: &'static str,
-    lines: Lines<'static>,
+pub struct Tokens<'a> {
+    suffix: &'a str,
+    line: &'a str,
+    lines: Lines<'a>,
     line_number: usize,
     column_number: usize,
-    path: &'static Path,
-    eof: Option<Span>,
-    allow_tabs: bool,
+    path: &'a Path,
+    eof: Option<Span<'a>>,
+    options: Options,
+    comments: Vec<Span<'a>>,
 }
 
-impl Tokens {
-    pub fn from_path_buf(path: PathBuf, allow_tabs: bool) -> Result<Self, Error> {
+impl Tokens<'static> {
+    /// Reads the file at `path` for good, for commands that only read it once
+    pub fn from_path_buf(path: PathBuf, options: Options) -> Result<Self, Error> {
         let path: &'static Path = Box::leak(Box::new(path));
 
         let Ok(code) = fs::read_to_string(path) else {
-            return Err(Error::new(format!("couldn't read file {}", path.display()), None));
+            return Err(Error::new(
+                format!("couldn't read file {}", path.display()),
+                None,
+            ));
         };
         let code = Box::leak(Box::new(code));
 
-        Tokens::new(code, path, allow_tabs)
+        Tokens::new(code, path, options)
     }
+}
 
-    pub fn new(code: &'static str, path: &'static Path, allow_tabs: bool) -> Result<Self, Error> {
+impl<'a> Tokens<'a> {
+    pub fn new(code: &'a str, path: &'a Path, options: Options) -> Result<Self, Error> {
         let mut lines = code.lines();
         let line = lines
             .next()
@@ -171,11 +338,18 @@ impl Tokens {
             column_number: 0,
             path,
             eof: None,
-            allow_tabs,
+            options,
+            comments: Vec::new(),
         })
     }
 
-    fn make_span(&mut self, len: usize) -> Span {
+    // regular comments are skipped by `next`, so this lexes the whole file to find them
+    pub fn comments(mut self) -> Result<Vec<Span<'a>>, Error> {
+        while self.next()?.kind != TokenKind::Eof {}
+        Ok(self.comments)
+    }
+
+    fn make_span(&mut self, len: usize) -> Span<'a> {
         let offset = self.suffix.as_ptr() as usize - self.line.as_ptr() as usize;
         let prefix = &self.line[..offset];
         let text = &self.suffix[..len];
@@ -194,7 +368,8 @@ impl Tokens {
         }
     }
 
-    pub fn next(&mut self) -> Result<Token, Error> {
+    #[allow(clippy::should_implement_trait)]
+    pub fn next(&mut self) -> Result<Token<'a>, Error> {
         if let Some(span) = self.eof {
             return Ok(Token {
                 kind: TokenKind::Eof,
@@ -225,16 +400,18 @@ impl Tokens {
             Some('<') => token!(Left),
             Some('>') => token!(Right),
             Some('\'') => self.symbol(),
+            Some('/') => self.comment(),
             Some('_') => Ok(self.name()),
-            Some(c) if c.is_alphabetic() => Ok(self.name()),
+            Some(c) if c.is_ascii_digit() && self.options.bare_digits => Ok(self.digits()),
+            Some(c) if c.is_alphabetic() || c.is_ascii_digit() => Ok(self.name()),
             Some(c) => Err(Error::new(
                 format!("unexpected character {c:?}"),
-                Some(self.make_span(1)),
+                Some(self.make_span(c.len_utf8())),
             )),
         }
     }
 
-    fn eol(&mut self) -> Result<Token, Error> {
+    fn eol(&mut self) -> Result<Token<'a>, Error> {
         if let Some(line) = self.lines.next() {
             self.suffix = line;
             self.line = line;
@@ -251,7 +428,7 @@ impl Tokens {
         for c in self.suffix.chars() {
             if !c.is_whitespace() {
                 break;
-            } else if !self.allow_tabs && c == '\t' {
+            } else if !self.options.allow_tabs && c == '\t' {
                 self.make_span(len);
                 return Err(Error::new(
                     "tab characters are not allowed".to_string(),
@@ -264,24 +441,57 @@ impl Tokens {
         Ok(())
     }
 
-    fn name(&mut self) -> Token {
+    fn comment(&mut self) -> Result<Token<'a>, Error> {
+        if self.suffix.starts_with("///") {
+            Ok(Token {
+                kind: TokenKind::DocComment,
+                span: self.make_span(self.suffix.len()),
+            })
+        } else if self.suffix.starts_with("//") {
+            let span = self.make_span(self.suffix.len());
+            self.comments.push(span);
+            self.next()
+        } else {
+            Err(Error::new(
+                "unexpected character '/'".to_string(),
+                Some(self.make_span(1)),
+            ))
+        }
+    }
+
+    fn name(&mut self) -> Token<'a> {
         let mut len = 0;
         for c in self.suffix.chars() {
-            if c.is_alphanumeric() || c == '_' {
+            // combining marks keep decomposed names (e.g. `e` + U+0301) in one token
+            if c.is_alphanumeric() || c == '_' || (len > 0 && is_combining_mark(c)) {
                 len += c.len_utf8();
             } else {
                 break;
             }
         }
 
+        let span = self.make_span(len);
+        let kind = match Keyword::from_name(span.text, self.options.edition) {
+            Some(keyword) => TokenKind::Keyword(keyword),
+            None => TokenKind::Name,
+        };
+        Token { kind, span }
+    }
+
+    fn digits(&mut self) -> Token<'a> {
+        let len = self
+            .suffix
+            .find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(self.suffix.len());
+
         let span = self.make_span(len);
         Token {
-            kind: TokenKind::Name,
+            kind: TokenKind::Symbol(span.text.to_string()),
             span,
         }
     }
 
-    fn symbol(&mut self) -> Result<Token, Error> {
+    fn symbol(&mut self) -> Result<Token<'a>, Error> {
         let mut start = 1;
         let mut string = String::new();
 
@@ -0,0 +1,54 @@
+pub mod analysis;
+pub mod animate;
+pub mod asm;
+pub mod backend;
+pub mod batch;
+pub mod bytecode;
+pub mod certificate;
+#[cfg(feature = "c-vm")]
+pub mod compare;
+pub mod compile;
+pub mod config;
+pub mod coverage;
+pub mod cycle;
+pub mod daemon;
+pub mod debug;
+mod decimal;
+pub mod describe;
+mod digit;
+pub mod emit;
+pub mod error;
+pub mod events;
+pub mod expect;
+#[cfg(feature = "c-vm")]
+mod ffi;
+pub mod fmt;
+pub mod graph;
+mod int;
+#[cfg(feature = "jit")]
+pub mod jit;
+pub mod lex;
+pub mod lsp;
+pub mod metrics;
+pub mod mmap;
+pub mod optimize;
+pub mod output;
+pub mod parse;
+pub mod pass;
+pub mod profile;
+pub mod random;
+pub mod refactor;
+pub mod repl;
+pub mod rle;
+pub mod spacetime;
+pub mod spec;
+pub mod stats;
+mod suggest;
+pub mod symbols;
+mod table;
+pub mod tape;
+pub mod tmb;
+pub mod transposition;
+pub mod vm;
+pub mod warning;
+pub mod watch;
@@ -0,0 +1,475 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+use lsp_server::{Connection, ErrorCode, Message, Notification, Request, Response};
+use lsp_types::notification::{
+    DidChangeTextDocument, DidCloseTextDocument, DidOpenTextDocument,
+    Notification as NotificationTrait, PublishDiagnostics,
+};
+use lsp_types::request::Request as RequestTrait;
+use lsp_types::request::{GotoDefinition, HoverRequest, PrepareRenameRequest, Rename};
+use lsp_types::{
+    DiagnosticRelatedInformation, DiagnosticSeverity, GotoDefinitionParams, GotoDefinitionResponse,
+    Hover, HoverContents, HoverParams, HoverProviderCapability, Location, MarkupContent,
+    MarkupKind, OneOf, Position, PrepareRenameResponse, PublishDiagnosticsParams, Range,
+    RenameOptions, RenameParams, ServerCapabilities, TextDocumentPositionParams,
+    TextDocumentSyncCapability, TextDocumentSyncKind, TextEdit, Uri, WorkDoneProgressOptions,
+    WorkspaceEdit,
+};
+use serde_json::Value;
+
+use crate::analysis;
+use crate::compile::{self, Signature};
+use crate::error::{Error, Level};
+use crate::lex::{self, Span};
+use crate::parse::{self, Name, Op, Pattern, StartArgs, State, ToState, Unit};
+use crate::refactor;
+use crate::warning;
+
+pub fn run(options: lex::Options, start_args: StartArgs) -> Result<(), Error> {
+    let (connection, io_threads) = Connection::stdio();
+
+    let capabilities = ServerCapabilities {
+        text_document_sync: Some(TextDocumentSyncCapability::Kind(TextDocumentSyncKind::FULL)),
+        definition_provider: Some(OneOf::Left(true)),
+        hover_provider: Some(HoverProviderCapability::Simple(true)),
+        rename_provider: Some(OneOf::Right(RenameOptions {
+            prepare_provider: Some(true),
+            work_done_progress_options: WorkDoneProgressOptions::default(),
+        })),
+        ..ServerCapabilities::default()
+    };
+    connection
+        .initialize(serde_json::to_value(capabilities).unwrap())
+        .map_err(|error| Error::new(format!("couldn't start language server: {error}"), None))?;
+
+    let mut server = Server {
+        connection: &connection,
+        documents: HashMap::new(),
+        options,
+        start_args,
+    };
+    for message in &connection.receiver {
+        match message {
+            Message::Request(request) => {
+                if connection.handle_shutdown(&request).unwrap_or(true) {
+                    break;
+                }
+                server.request(request);
+            }
+            Message::Notification(notification) => server.notification(notification),
+            Message::Response(_) => {}
+        }
+    }
+
+    drop(connection);
+    io_threads
+        .join()
+        .map_err(|error| Error::new(format!("language server failed: {error}"), None))
+}
+
+struct Server<'a> {
+    connection: &'a Connection,
+    documents: HashMap<Uri, Document>,
+    options: lex::Options,
+    start_args: StartArgs<'a>,
+}
+
+// the latest version of an open document. it's parsed again for each request, so
+// nothing has to borrow it between them
+struct Document {
+    path: PathBuf,
+    text: String,
+}
+
+// what the name under the cursor refers to
+enum Target<'a> {
+    State(Signature<'a>),
+    Param(Span<'a>),
+}
+
+impl Server<'_> {
+    fn notification(&mut self, notification: Notification) {
+        match notification.method.as_str() {
+            DidOpenTextDocument::METHOD => {
+                let Some(params) = params::<lsp_types::DidOpenTextDocumentParams>(notification)
+                else {
+                    return;
+                };
+                let uri = params.text_document.uri;
+                let path = PathBuf::from(uri.path().as_str());
+                self.update(uri, path, params.text_document.text);
+            }
+            DidChangeTextDocument::METHOD => {
+                let Some(params) = params::<lsp_types::DidChangeTextDocumentParams>(notification)
+                else {
+                    return;
+                };
+                let uri = params.text_document.uri;
+                let (Some(document), Some(change)) = (
+                    self.documents.remove(&uri),
+                    params.content_changes.into_iter().last(),
+                ) else {
+                    return;
+                };
+                self.update(uri, document.path, change.text);
+            }
+            DidCloseTextDocument::METHOD => {
+                let Some(params) = params::<lsp_types::DidCloseTextDocumentParams>(notification)
+                else {
+                    return;
+                };
+                self.documents.remove(&params.text_document.uri);
+                self.publish(params.text_document.uri, Vec::new());
+            }
+            _ => {}
+        }
+    }
+
+    fn update(&mut self, uri: Uri, path: PathBuf, text: String) {
+        let document = Document { path, text };
+        let mut errors = Vec::new();
+        match document.parse(self.options) {
+            Ok(unit) => {
+                for warning in warning::check(&unit, &self.start_args, &[]) {
+                    if warning.lint.enabled_by_default() {
+                        errors.push(warning.diagnostic);
+                    }
+                }
+                if let Err(error) = compile::compile(unit, Vec::new(), self.start_args.clone()) {
+                    errors.push(error);
+                }
+            }
+            Err(error) => errors.push(error),
+        }
+
+        let diagnostics = Error::combine(errors)
+            .diagnostics()
+            .iter()
+            .map(|diagnostic| {
+                let mut message = diagnostic.msg.clone();
+                if let Some(label) = &diagnostic.label {
+                    message = format!("{message}: {label}");
+                }
+                if let Some(help) = &diagnostic.help {
+                    message = format!("{message}\nhelp: {help}");
+                }
+                let related = diagnostic
+                    .notes
+                    .iter()
+                    .map(|(span, note)| DiagnosticRelatedInformation {
+                        location: Location::new(uri.clone(), range(span.as_span())),
+                        message: note.clone(),
+                    })
+                    .collect::<Vec<_>>();
+                lsp_types::Diagnostic {
+                    range: diagnostic
+                        .span
+                        .as_ref()
+                        .map_or_else(Range::default, |span| range(span.as_span())),
+                    severity: Some(match diagnostic.level {
+                        Level::Error => DiagnosticSeverity::ERROR,
+                        Level::Warning => DiagnosticSeverity::WARNING,
+                    }),
+                    source: Some("tml".to_string()),
+                    message,
+                    related_information: (!related.is_empty()).then_some(related),
+                    ..lsp_types::Diagnostic::default()
+                }
+            })
+            .collect();
+
+        self.documents.insert(uri.clone(), document);
+        self.publish(uri, diagnostics);
+    }
+
+    fn publish(&self, uri: Uri, diagnostics: Vec<lsp_types::Diagnostic>) {
+        let params = PublishDiagnosticsParams {
+            uri,
+            diagnostics,
+            version: None,
+        };
+        let notification = Notification::new(PublishDiagnostics::METHOD.to_string(), params);
+        let _ = self.connection.sender.send(notification.into());
+    }
+
+    fn request(&self, request: Request) {
+        let id = request.id.clone();
+        let result = match request.method.as_str() {
+            GotoDefinition::METHOD => self.handle(request, Server::definition),
+            HoverRequest::METHOD => self.handle(request, Server::hover),
+            PrepareRenameRequest::METHOD => self.handle(request, Server::prepare_rename),
+            Rename::METHOD => self.handle(request, Server::rename),
+            method => Err((
+                ErrorCode::MethodNotFound,
+                format!("unknown request `{method}`"),
+            )),
+        };
+
+        let response = match result {
+            Ok(result) => Response::new_ok(id, result),
+            Err((code, message)) => Response::new_err(id, code as i32, message),
+        };
+        let _ = self.connection.sender.send(response.into());
+    }
+
+    fn handle<P: serde::de::DeserializeOwned, R: serde::Serialize>(
+        &self,
+        request: Request,
+        f: impl FnOnce(&Self, P) -> Result<R, String>,
+    ) -> Result<Value, (ErrorCode, String)> {
+        let params = serde_json::from_value(request.params)
+            .map_err(|error| (ErrorCode::InvalidParams, error.to_string()))?;
+        let result = f(self, params).map_err(|message| (ErrorCode::RequestFailed, message))?;
+        Ok(serde_json::to_value(result).unwrap())
+    }
+
+    // `None` if the document isn't open or doesn't parse
+    fn unit(&self, uri: &Uri) -> Option<Vec<State<'_>>> {
+        let unit = self.documents.get(uri)?.parse(self.options).ok()?;
+        Some(unit.states)
+    }
+
+    fn definition(
+        &self,
+        params: GotoDefinitionParams,
+    ) -> Result<Option<GotoDefinitionResponse>, String> {
+        let TextDocumentPositionParams {
+            text_document,
+            position,
+        } = params.text_document_position_params;
+        let Some(unit) = self.unit(&text_document.uri) else {
+            return Ok(None);
+        };
+
+        let spans: Vec<_> = match target_at(&unit, position) {
+            Some((_, Target::State(signature))) => unit
+                .iter()
+                .filter(|state| Signature::of(state) == signature)
+                .map(|state| state.name.span)
+                .collect(),
+            Some((_, Target::Param(span))) => vec![span],
+            None => Vec::new(),
+        };
+        let locations: Vec<_> = spans
+            .into_iter()
+            .map(|span| Location::new(text_document.uri.clone(), range(span)))
+            .collect();
+        Ok((!locations.is_empty()).then_some(GotoDefinitionResponse::Array(locations)))
+    }
+
+    fn hover(&self, params: HoverParams) -> Result<Option<Hover>, String> {
+        let TextDocumentPositionParams {
+            text_document,
+            position,
+        } = params.text_document_position_params;
+        let Some(unit) = self.unit(&text_document.uri) else {
+            return Ok(None);
+        };
+        let Some((span, target)) = target_at(&unit, position) else {
+            return Ok(None);
+        };
+
+        let value = match target {
+            Target::State(signature) => {
+                let arity = format!(
+                    "{} state parameter{}, {} symbol parameter{}",
+                    signature.states,
+                    if signature.states == 1 { "" } else { "s" },
+                    signature.symbols,
+                    if signature.symbols == 1 { "" } else { "s" },
+                );
+                match unit.iter().find(|state| Signature::of(state) == signature) {
+                    Some(state) if state.doc.is_empty() => {
+                        format!("```tml\n{signature}\n```\n{arity}")
+                    }
+                    Some(state) => format!(
+                        "```tml\n{signature}\n```\n{arity}\n\n---\n\n{}",
+                        state.doc.join("\n")
+                    ),
+                    None => format!("```tml\n{signature}\n```\n{arity} (undefined)"),
+                }
+            }
+            Target::Param(param) => {
+                let kind = unit
+                    .iter()
+                    .find(|state| state.state_params.iter().any(|p| same(p.span, param)))
+                    .map_or("symbol", |_| "state");
+                format!("{kind} parameter `{}`", param.text)
+            }
+        };
+
+        Ok(Some(Hover {
+            contents: HoverContents::Markup(MarkupContent {
+                kind: MarkupKind::Markdown,
+                value,
+            }),
+            range: Some(range(span)),
+        }))
+    }
+
+    fn prepare_rename(
+        &self,
+        params: TextDocumentPositionParams,
+    ) -> Result<Option<PrepareRenameResponse>, String> {
+        let Some(unit) = self.unit(&params.text_document.uri) else {
+            return Ok(None);
+        };
+        match target_at(&unit, params.position) {
+            Some((span, Target::State(signature))) => {
+                if signature.name == "start" {
+                    Err("the `start` state can't be renamed".to_string())
+                } else {
+                    Ok(Some(PrepareRenameResponse::Range(range(span))))
+                }
+            }
+            Some((_, Target::Param(_))) => Err("only states can be renamed".to_string()),
+            None => Ok(None),
+        }
+    }
+
+    fn rename(&self, params: RenameParams) -> Result<Option<WorkspaceEdit>, String> {
+        let TextDocumentPositionParams {
+            text_document,
+            position,
+        } = params.text_document_position;
+        let Some(unit) = self.unit(&text_document.uri) else {
+            return Ok(None);
+        };
+        let Some((_, Target::State(signature))) = target_at(&unit, position) else {
+            return Err("only states can be renamed".to_string());
+        };
+
+        let (_, edits) = refactor::rename(
+            &unit,
+            signature.name,
+            &params.new_name,
+            Some(signature.states),
+            Some(signature.symbols),
+            self.options.edition,
+        )
+        .map_err(|error| {
+            let diagnostic = &error.diagnostics()[0];
+            match &diagnostic.help {
+                Some(help) => format!("{} ({help})", diagnostic.msg),
+                None => diagnostic.msg.clone(),
+            }
+        })?;
+
+        let edits = edits
+            .into_iter()
+            .map(|(span, new_text)| TextEdit {
+                range: range(span),
+                new_text,
+            })
+            .collect();
+        Ok(Some(WorkspaceEdit {
+            changes: Some(HashMap::from([(text_document.uri, edits)])),
+            ..WorkspaceEdit::default()
+        }))
+    }
+}
+
+impl Document {
+    fn parse(&self, options: lex::Options) -> Result<Unit<'_>, Error> {
+        parse::parse(lex::Tokens::new(&self.text, &self.path, options)?)
+    }
+}
+
+fn params<P: serde::de::DeserializeOwned>(notification: Notification) -> Option<P> {
+    serde_json::from_value(notification.params).ok()
+}
+
+fn target_at<'a>(unit: &[State<'a>], position: Position) -> Option<(Span<'a>, Target<'a>)> {
+    for state in unit {
+        if contains(state.name.span, position) {
+            return Some((state.name.span, Target::State(Signature::of(state))));
+        }
+
+        let params = state.state_params.iter().chain(&state.symbol_params);
+        for param in params.clone() {
+            if contains(param.span, position) {
+                return Some((param.span, Target::Param(param.span)));
+            }
+        }
+
+        let mut found = None;
+        analysis::for_each_call(state, |name, signature| {
+            if contains(name.span, position) {
+                found = Some((name.span, Target::State(signature)));
+            }
+        });
+        if found.is_some() {
+            return found;
+        }
+
+        let mut names = Vec::new();
+        for arm in &state.arms {
+            names_in_arm(arm, &mut names);
+        }
+        for name in names {
+            if contains(name.span, position) {
+                let param = params.clone().find(|param| param.name == name.name)?;
+                return Some((name.span, Target::Param(param.span)));
+            }
+        }
+    }
+    None
+}
+
+fn names_in_arm<'a, 'b>(arm: &'a parse::Arm<'b>, names: &mut Vec<&'a Name<'b>>) {
+    for pattern in [Some(&arm.pattern), arm.lookahead.as_ref()]
+        .into_iter()
+        .flatten()
+    {
+        if let Pattern::Name(name) = pattern {
+            names.push(name);
+        }
+    }
+    for op in &arm.ops {
+        if let Op::Name(name) = op {
+            names.push(name);
+        }
+    }
+    names_in_to_state(&arm.to_state, names);
+}
+
+fn names_in_to_state<'a, 'b>(to_state: &'a ToState<'b>, names: &mut Vec<&'a Name<'b>>) {
+    if let ToState::State {
+        name,
+        state_args,
+        symbol_args,
+    } = to_state
+    {
+        names.push(name);
+        for state_arg in state_args {
+            names_in_to_state(state_arg, names);
+        }
+        for symbol_arg in symbol_args {
+            if let Pattern::Name(name) = symbol_arg {
+                names.push(name);
+            }
+        }
+    }
+}
+
+// lsp positions count utf-16 code units
+fn range(span: Span) -> Range {
+    let start = span.prefix.encode_utf16().count() as u32;
+    let end = start + span.text.encode_utf16().count() as u32;
+    Range::new(
+        Position::new(span.line as u32, start),
+        Position::new(span.line as u32, end),
+    )
+}
+
+fn contains(span: Span, position: Position) -> bool {
+    let range = range(span);
+    range.start.line == position.line
+        && range.start.character <= position.character
+        && position.character <= range.end.character
+}
+
+fn same(a: Span, b: Span) -> bool {
+    a.line == b.line && a.prefix.len() == b.prefix.len()
+}
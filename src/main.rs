@@ -1,127 +1,2204 @@
-use std::path::PathBuf;
+use std::fs;
+use std::ops::ControlFlow;
+use std::path::{Path, PathBuf};
 use std::process::ExitCode;
 use std::time::Instant;
 
-use clap::Parser;
+use clap::parser::ValueSource;
+use clap::{ArgMatches, Args, CommandFactory, FromArgMatches, Parser, Subcommand, ValueEnum};
+use serde_json::json;
 use termion::{color, style};
 
-mod bytecode;
-mod compile;
-mod decimal;
-mod digit;
-mod error;
-mod ffi;
-mod int;
-mod lex;
-mod parse;
-mod tape;
-mod vm;
+use tml::{
+    animate, asm, backend, batch, bytecode, certificate, compile, config, coverage, cycle, daemon,
+    debug, describe, emit, error, events, expect, fmt, graph, lex, lsp, metrics, optimize, output,
+    parse, pass, profile, random, refactor, repl, rle, spacetime, spec, stats, symbols, tape, tmb,
+    transposition, warning,
+};
 
 #[derive(Parser, Debug)]
-struct Arguments {
+#[command(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
+struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    #[command(flatten)]
+    run: Arguments,
+
+    /// Don't color output
+    #[arg(long = "no-color", global = true)]
+    no_color: bool,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Assemble a .tasm file into a .tmb file
+    Asm(AsmArguments),
+    /// Run a machine on many tapes in parallel, compiling it once
+    Batch(BatchArguments),
+    /// Compile a machine and its tape into a .tmb file
+    Build(BuildArguments),
+    /// Compile a machine without running it
+    Check(CheckArguments),
+    /// Compile a machine and its tape into a standalone C program
+    CompileC(CompileArguments),
+    /// Compile a machine and its tape into a WebAssembly module
+    CompileWasm(CompileWasmArguments),
+    /// Serve check and run requests over a socket, caching compiled machines
+    Daemon(DaemonArguments),
+    /// Step through a run interactively
+    Debug(DebugArguments),
+    /// Print the signature, arms and transitions of a state
+    Describe(DescribeArguments),
+    /// Print a machine's bytecode as assembly that `tml asm` reads back
+    Disasm(DisasmArguments),
+    /// Print a machine in the standard format
+    Fmt(FmtArguments),
+    /// Remove states that can't be reached from `start`
+    Gc(GcArguments),
+    /// Print the call graph of a machine
+    Graph(GraphArguments),
+    /// Report every finding of the static analyses without running the machine
+    Lint(LintArguments),
+    /// Run a language server over stdin and stdout
+    Lsp(LspArguments),
+    /// Print static metrics for a machine
+    Metrics(MetricsArguments),
+    /// Rename a state and all of its call sites
+    Rename(RenameArguments),
+    /// Compile a machine once, then run it on each tape typed at a prompt
+    Repl(ReplArguments),
+    /// Run a machine from a .tml, .tmb or .tasm file
+    Run(Box<Arguments>),
+    /// Run the test cases in a spec file against a machine
+    Test(TestArguments),
+    /// Replay a run and check it against a certificate
+    VerifyCertificate(VerifyCertificateArguments),
+    /// Run a machine, and run it again whenever it or its tape is saved
+    Watch(Box<Arguments>),
+}
+
+#[derive(Args, Clone, Debug)]
+struct SourceArguments {
+    /// Arguments for the `start` state (e.g. "left(!), '1', '0'")
+    #[arg(long = "start-args")]
+    start_args: Option<String>,
+
+    /// Allow tab characters in machine and tape files
+    #[arg(long = "allow-tabs")]
+    allow_tabs: bool,
+
+    /// Lex unquoted digits as names instead of symbols
+    #[arg(long = "no-bare-digits")]
+    no_bare_digits: bool,
+
+    /// Language edition (2023, 2026)
+    #[arg(
+        long = "edition",
+        default_value = "2023",
+        hide_default_value = true,
+        hide_possible_values = true
+    )]
+    edition: lex::Edition,
+
+    /// Take symbol ids from a JSON symbol table
+    #[arg(long = "symbols-file", value_name = "FILE")]
+    symbols_file: Option<PathBuf>,
+}
+
+#[derive(Args, Debug)]
+struct AsmArguments {
+    /// File containing the assembly
+    file: PathBuf,
+
+    /// Output file (FILE with a .tmb extension by default)
+    #[arg(short = 'o', long = "output", value_name = "FILE")]
+    output: Option<PathBuf>,
+}
+
+#[derive(Args, Debug)]
+struct BatchArguments {
+    /// File containing the Turing machine
+    file: PathBuf,
+    /// Files containing the initial tapes
+    #[arg(required = true)]
+    tapes: Vec<PathBuf>,
+
+    /// Number of tapes to run at once (one per core by default)
+    #[arg(short = 'j', long = "jobs", value_parser = clap::value_parser!(u64).range(1..))]
+    jobs: Option<u64>,
+
+    /// Result format (table, jsonl)
+    #[arg(
+        long = "format",
+        default_value = "table",
+        hide_default_value = true,
+        hide_possible_values = true
+    )]
+    format: batch::Format,
+
+    /// Maximum number of moves for each tape
+    #[arg(short = 'm', long = "max-moves")]
+    max_moves: Option<usize>,
+
+    /// Stop once the head has spanned more than this many tape cells
+    #[arg(
+        long = "max-tape-cells",
+        value_name = "CELLS",
+        value_parser = clap::value_parser!(u64).range(1..)
+    )]
+    max_tape_cells: Option<u64>,
+
+    /// Grow the tape to the left instead of halting when the head moves off cell 0
+    #[arg(long = "two-sided")]
+    two_sided: bool,
+
+    /// Don't print the decimal interpretation of the final tapes
+    #[arg(long = "hide-decimal")]
+    hide_decimal: bool,
+
+    /// Radix for the final decimals
+    #[arg(short = 'r', long = "decimal-radix", default_value_t = 2, value_parser = clap::value_parser!(u32).range(1..=36))]
+    decimal_radix: u32,
+
+    /// Digits in the final decimals
+    #[arg(short = 'd', long = "decimal-digits", value_parser = clap::value_parser!(u32).range(3..))]
+    decimal_digits: Option<u32>,
+
+    /// Start position for the final decimals
+    #[arg(short = 's', long = "decimal-start", default_value_t = 2)]
+    decimal_start: u32,
+
+    /// Stride for the final decimals
+    #[arg(short = 'S', long = "decimal-stride", default_value_t = 2, value_parser = clap::value_parser!(u32).range(1..))]
+    decimal_stride: u32,
+
+    /// Use Rust VM
+    #[arg(long = "rust-vm")]
+    rust_vm: bool,
+
+    #[command(flatten)]
+    optimize: OptimizeArguments,
+
+    #[command(flatten)]
+    source: SourceArguments,
+
+    #[command(flatten)]
+    warnings: WarningArguments,
+}
+
+#[derive(Args, Debug)]
+struct BuildArguments {
+    /// File containing the Turing machine
+    file: PathBuf,
+    /// File containing the initial tape
+    tape: Option<PathBuf>,
+
+    /// Output file (FILE with a .tmb extension by default)
+    #[arg(short = 'o', long = "output", value_name = "FILE")]
+    output: Option<PathBuf>,
+    /// Leave out the debug info that maps the bytecode back to the source
+    #[arg(long = "strip")]
+    strip: bool,
+
+    #[command(flatten)]
+    optimize: OptimizeArguments,
+
+    #[command(flatten)]
+    source: SourceArguments,
+
+    #[command(flatten)]
+    warnings: WarningArguments,
+}
+
+#[derive(Args, Debug)]
+struct CompileArguments {
+    /// File containing the Turing machine
+    file: PathBuf,
+    /// File containing the initial tape
+    tape: Option<PathBuf>,
+
+    /// Output file (FILE with a .c, .wasm or .wat extension by default)
+    #[arg(short = 'o', long = "output", value_name = "FILE")]
+    output: Option<PathBuf>,
+
+    #[command(flatten)]
+    optimize: OptimizeArguments,
+
+    #[command(flatten)]
+    source: SourceArguments,
+
+    #[command(flatten)]
+    warnings: WarningArguments,
+}
+
+#[derive(Args, Debug)]
+struct CompileWasmArguments {
+    #[command(flatten)]
+    compile: CompileArguments,
+
+    /// Write the module in the text format
+    #[arg(long = "wat")]
+    wat: bool,
+}
+
+#[derive(Args, Debug)]
+struct CheckArguments {
+    /// File containing the Turing machine
+    file: PathBuf,
+
+    #[command(flatten)]
+    source: SourceArguments,
+
+    #[command(flatten)]
+    warnings: WarningArguments,
+}
+
+#[derive(Args, Debug)]
+struct DaemonArguments {
+    /// Where to listen (unix:PATH or tcp:HOST:PORT)
+    address: events::Address,
+}
+
+#[derive(Args, Debug)]
+struct DebugArguments {
+    /// File containing the Turing machine (.tml or .tmb)
+    file: PathBuf,
+    /// File containing the initial tape
+    tape: Option<PathBuf>,
+
+    /// Grow the tape to the left instead of halting at cell 0
+    #[arg(long = "two-sided")]
+    two_sided: bool,
+
+    #[command(flatten)]
+    source: SourceArguments,
+}
+
+#[derive(Args, Debug)]
+struct DescribeArguments {
+    /// File containing the Turing machine
+    file: PathBuf,
+    /// Name of the state to describe
+    state: String,
+
+    #[command(flatten)]
+    source: SourceArguments,
+}
+
+#[derive(Args, Debug)]
+struct DisasmArguments {
+    /// File containing the Turing machine (.tml or .tmb)
+    file: PathBuf,
+    /// File containing the initial tape
+    tape: Option<PathBuf>,
+
+    /// Write the assembly to a file instead of printing it
+    #[arg(short = 'o', long = "output", value_name = "FILE")]
+    output: Option<PathBuf>,
+
+    #[command(flatten)]
+    optimize: OptimizeArguments,
+
+    #[command(flatten)]
+    source: SourceArguments,
+}
+
+#[derive(Args, Debug)]
+struct FmtArguments {
+    /// File containing the Turing machine
+    file: PathBuf,
+
+    /// Check that the output parses and compiles to the same machine
+    #[arg(long = "verify")]
+    verify: bool,
+
+    /// Format the file in place
+    #[arg(short = 'w', long = "write")]
+    write: bool,
+
+    /// Fail if the file isn't already formatted
+    #[arg(long = "check", conflicts_with = "write")]
+    check: bool,
+
+    #[command(flatten)]
+    source: SourceArguments,
+}
+
+#[derive(Args, Debug)]
+struct GcArguments {
+    /// File containing the Turing machine
+    file: PathBuf,
+
+    /// Print the unreachable states without removing them
+    #[arg(long = "dry-run")]
+    dry_run: bool,
+
+    #[command(flatten)]
+    source: SourceArguments,
+}
+
+#[derive(Args, Clone, Debug)]
+struct WarningArguments {
+    /// Warn about a lint even if it was allowed
+    #[arg(
+        short = 'W',
+        long = "warn",
+        value_name = "LINT",
+        hide_possible_values = true
+    )]
+    warn: Vec<warning::Lint>,
+
+    /// Don't warn about a lint
+    #[arg(
+        short = 'A',
+        long = "allow",
+        value_name = "LINT",
+        hide_possible_values = true
+    )]
+    allow: Vec<warning::Lint>,
+
+    /// Report a lint as an error (even if it was allowed)
+    #[arg(
+        short = 'D',
+        long = "deny",
+        value_name = "LINT",
+        hide_possible_values = true
+    )]
+    deny: Vec<warning::Lint>,
+
+    /// Treat warnings as errors
+    #[arg(long = "deny-warnings")]
+    deny_warnings: bool,
+
+    /// Warn about mixed-script and confusable state names
+    #[arg(long = "strict-idents")]
+    strict_idents: bool,
+
+    /// Warn about states that share a name but take different arguments
+    #[arg(long = "no-overload")]
+    no_overload: bool,
+}
+
+#[derive(Args, Clone, Debug)]
+struct OptimizeArguments {
+    /// Optimize the bytecode (`-O1` cleans up arms, `-O` means `-O2`)
+    #[arg(
+        short = 'O',
+        long = "optimize",
+        value_name = "LEVEL",
+        num_args = 0..=1,
+        require_equals = true,
+        default_value = "0",
+        hide_default_value = true,
+        default_missing_value = "2",
+        value_parser = clap::value_parser!(u8).range(0..=2)
+    )]
+    optimize: u8,
+
+    /// Merge states that compile to the same code
+    #[arg(long = "dedup-states")]
+    dedup_states: bool,
+
+    /// Print how many bytes each optimization saves, and the address of the
+    /// state a run stops in
+    #[arg(short = 'v', long = "verbose")]
+    verbose: bool,
+
+    /// Dump the bytecode after a pass, or after `lower` or `finalize`
+    #[arg(long = "print-after-pass", value_name = "NAME")]
+    print_after_pass: Option<String>,
+}
+
+#[derive(Args, Debug)]
+struct GraphArguments {
+    /// File containing the Turing machine
+    file: PathBuf,
+
+    /// Print reachable and dead states and strongly connected components
+    #[arg(long = "reachability")]
+    reachability: bool,
+
+    #[command(flatten)]
+    source: SourceArguments,
+}
+
+#[derive(Args, Debug)]
+struct LintArguments {
+    /// File containing the Turing machine
+    file: PathBuf,
+
+    /// Don't report a lint
+    #[arg(
+        short = 'A',
+        long = "allow",
+        value_name = "LINT",
+        hide_possible_values = true
+    )]
+    allow: Vec<warning::Lint>,
+
+    /// Report a lint as an error (even if it was allowed)
+    #[arg(
+        short = 'D',
+        long = "deny",
+        value_name = "LINT",
+        hide_possible_values = true
+    )]
+    deny: Vec<warning::Lint>,
+
+    /// Fail if there are findings at or above this level (warning, error)
+    #[arg(
+        long = "severity",
+        value_name = "LEVEL",
+        default_value = "warning",
+        hide_default_value = true,
+        hide_possible_values = true
+    )]
+    severity: error::Level,
+
+    /// How to print findings (human, json)
+    #[arg(
+        long = "error-format",
+        value_name = "FORMAT",
+        default_value = "human",
+        hide_default_value = true,
+        hide_possible_values = true
+    )]
+    error_format: error::ErrorFormat,
+
+    #[command(flatten)]
+    source: SourceArguments,
+}
+
+#[derive(Args, Debug)]
+struct LspArguments {
+    #[command(flatten)]
+    source: SourceArguments,
+}
+
+#[derive(Args, Debug)]
+struct MetricsArguments {
+    /// File containing the Turing machine
+    file: PathBuf,
+
+    #[command(flatten)]
+    source: SourceArguments,
+}
+
+#[derive(Args, Debug)]
+struct RenameArguments {
+    /// File containing the Turing machine
+    file: PathBuf,
+    /// Current name of the state
+    old_name: String,
+    /// New name for the state
+    new_name: String,
+
+    /// Number of state parameters of the state to rename
+    #[arg(long = "states")]
+    states: Option<u16>,
+
+    /// Number of symbol parameters of the state to rename
+    #[arg(long = "symbols")]
+    symbols: Option<u16>,
+
+    /// Print a diff instead of writing the file
+    #[arg(long = "diff")]
+    diff: bool,
+
+    #[command(flatten)]
+    source: SourceArguments,
+}
+
+#[derive(Args, Debug)]
+struct ReplArguments {
+    /// File containing the Turing machine
+    file: PathBuf,
+
+    /// Maximum number of moves for each tape
+    #[arg(short = 'm', long = "max-moves")]
+    max_moves: Option<usize>,
+
+    /// Stop once the head has spanned more than this many tape cells
+    #[arg(
+        long = "max-tape-cells",
+        value_name = "CELLS",
+        value_parser = clap::value_parser!(u64).range(1..)
+    )]
+    max_tape_cells: Option<u64>,
+
+    /// Grow the tape to the left instead of halting when the head moves off cell 0
+    #[arg(long = "two-sided")]
+    two_sided: bool,
+
+    /// Don't print the decimal interpretation of the final tapes
+    #[arg(long = "hide-decimal")]
+    hide_decimal: bool,
+
+    /// Radix for the final decimals
+    #[arg(short = 'r', long = "decimal-radix", default_value_t = 2, value_parser = clap::value_parser!(u32).range(1..=36))]
+    decimal_radix: u32,
+
+    /// Digits in the final decimals
+    #[arg(short = 'd', long = "decimal-digits", value_parser = clap::value_parser!(u32).range(3..))]
+    decimal_digits: Option<u32>,
+
+    /// Start position for the final decimals
+    #[arg(short = 's', long = "decimal-start", default_value_t = 2)]
+    decimal_start: u32,
+
+    /// Stride for the final decimals
+    #[arg(short = 'S', long = "decimal-stride", default_value_t = 2, value_parser = clap::value_parser!(u32).range(1..))]
+    decimal_stride: u32,
+
+    /// Use Rust VM
+    #[arg(long = "rust-vm")]
+    rust_vm: bool,
+
+    #[command(flatten)]
+    optimize: OptimizeArguments,
+
+    #[command(flatten)]
+    source: SourceArguments,
+
+    #[command(flatten)]
+    warnings: WarningArguments,
+}
+
+#[derive(Args, Debug)]
+struct TestArguments {
+    /// File containing the Turing machine
+    file: PathBuf,
+    /// File containing the test cases
+    spec: PathBuf,
+
+    /// Maximum number of moves for test cases without a `max_moves` limit
+    #[arg(short = 'm', long = "max-moves", default_value_t = 1_000_000)]
+    max_moves: usize,
+
+    #[command(flatten)]
+    source: SourceArguments,
+
+    /// Use Rust VM
+    #[arg(long = "rust-vm")]
+    rust_vm: bool,
+}
+
+#[derive(Args, Debug)]
+struct VerifyCertificateArguments {
+    /// File containing the certificate
+    certificate: PathBuf,
     /// File containing the Turing machine
     file: PathBuf,
     /// File containing the initial tape
     tape: Option<PathBuf>,
 
-    /// Maximum number of moves
-    #[arg(short = 'm', long = "max-moves")]
-    max_moves: Option<usize>,
+    #[command(flatten)]
+    source: SourceArguments,
+}
+
+#[derive(Args, Clone, Debug)]
+struct Arguments {
+    /// File containing the Turing machine
+    #[arg(required = true)]
+    file: Option<PathBuf>,
+    /// File containing the initial tape
+    tape: Option<PathBuf>,
+
+    /// Run on a random initial tape instead, like `len=100 alphabet="'0','1'"`
+    #[arg(
+        long = "random-tape",
+        value_name = "KEY=VALUE",
+        num_args = 1..=2,
+        conflicts_with = "tape"
+    )]
+    random_tape: Option<Vec<String>>,
+
+    /// Seed for `--random-tape`, so the same tape can be made again
+    #[arg(long = "seed", requires = "random_tape")]
+    seed: Option<u64>,
+
+    /// Maximum number of moves
+    #[arg(short = 'm', long = "max-moves")]
+    max_moves: Option<usize>,
+
+    /// Stop once the head has spanned more than this many tape cells
+    #[arg(
+        long = "max-tape-cells",
+        value_name = "CELLS",
+        value_parser = clap::value_parser!(u64).range(1..),
+        conflicts_with = "certificate"
+    )]
+    max_tape_cells: Option<u64>,
+
+    /// Start with the head on this cell instead of 0, or on the initial tape's last cell with `end`
+    #[arg(
+        long = "head-start",
+        value_name = "CELL",
+        allow_negative_numbers = true
+    )]
+    head_start: Option<tape::HeadStart>,
+
+    /// Print the initial tape before running
+    #[arg(long = "show-initial-tape", conflicts_with_all = ["quiet", "output", "format", "emit"])]
+    show_initial_tape: bool,
+
+    /// Only exit with 0 if the machine halts in one of these states (`!` for an explicit halt)
+    #[arg(long = "accept-state", value_name = "STATE")]
+    accept_state: Vec<String>,
+
+    /// Exit with 9 and print a diff if the final tape isn't the one in this file
+    #[arg(long = "expect-tape", value_name = "FILE", conflicts_with_all = ["emit", "format"])]
+    expect_tape: Option<PathBuf>,
+
+    /// Exit with 9 if the final tape isn't read as this decimal
+    #[arg(
+        long = "expect-decimal",
+        value_name = "DECIMAL",
+        value_parser = expect::parse_decimal,
+        conflicts_with_all = ["emit", "format"]
+    )]
+    expect_decimal: Option<String>,
+
+    /// Print nothing but errors, and exit with the usual code
+    #[arg(
+        short = 'q',
+        long = "quiet",
+        conflicts_with_all = ["time", "stats", "coverage", "bytecode_stats", "vm_stats", "trace_states", "space_time", "animate", "break_state", "break_symbol", "dump_bytecode", "dump_symbols", "dump_states", "verbose", "print_after_pass"]
+    )]
+    quiet: bool,
+
+    /// Print only this value of the run, undecorated (tape, decimal, moves)
+    #[arg(
+        long = "output",
+        value_name = "VALUE",
+        hide_possible_values = true,
+        conflicts_with_all = ["quiet", "format", "emit", "time", "stats", "coverage", "bytecode_stats", "vm_stats", "trace_states", "space_time", "animate", "break_state", "break_symbol", "dump_bytecode", "dump_symbols", "dump_states", "verbose", "print_after_pass"]
+    )]
+    output: Option<output::Value>,
+
+    /// How to print the result (human, json)
+    #[arg(
+        long = "format",
+        value_name = "FORMAT",
+        default_value = "human",
+        hide_default_value = true,
+        hide_possible_values = true,
+        conflicts_with_all = ["quiet", "emit", "time", "stats", "coverage", "bytecode_stats", "vm_stats", "trace_states", "space_time", "animate", "break_state", "break_symbol", "dump_bytecode", "dump_symbols", "dump_states", "verbose", "print_after_pass"]
+    )]
+    format: output::Format,
+
+    /// Don't print the final tape
+    #[arg(long = "hide-tape")]
+    hide_tape: bool,
+
+    /// Final tape trimming (trailing-blanks, none, window)
+    #[arg(
+        long = "trim",
+        value_name = "POLICY",
+        default_value = "trailing-blanks",
+        hide_default_value = true,
+        hide_possible_values = true
+    )]
+    trim: backend::Trim,
+
+    /// Don't print the decimal interpretation of the final tape
+    #[arg(long = "hide-decimal")]
+    hide_decimal: bool,
+
+    /// Radix for the final decimal
+    #[arg(short = 'r', long = "decimal-radix", default_value_t = 2, value_parser = clap::value_parser!(u32).range(1..=36))]
+    decimal_radix: u32,
+
+    /// Digits in the final decimal
+    #[arg(short = 'd', long = "decimal-digits", value_parser = clap::value_parser!(u32).range(3..))]
+    decimal_digits: Option<u32>,
+
+    /// Start position for the final decimal
+    #[arg(short = 's', long = "decimal-start", default_value_t = 2)]
+    decimal_start: u32,
+
+    /// Stride for the final decimal
+    #[arg(short = 'S', long = "decimal-stride", default_value_t = 2, value_parser = clap::value_parser!(u32).range(1..))]
+    decimal_stride: u32,
+
+    #[command(flatten)]
+    source: SourceArguments,
+
+    #[command(flatten)]
+    warnings: WarningArguments,
+
+    #[command(flatten)]
+    optimize: OptimizeArguments,
+
+    /// Dump bytecode
+    #[arg(short = 'b', long = "dump-bytecode")]
+    dump_bytecode: bool,
+
+    /// Print the symbol table
+    #[arg(long = "dump-symbols")]
+    dump_symbols: bool,
+
+    /// Print each state's address and size
+    #[arg(long = "dump-states")]
+    dump_states: bool,
+
+    /// Write the compiled machine to a file (kinds: c, wat, wasm, symbols)
+    #[arg(long = "emit", num_args = 2, value_names = ["KIND", "FILE"])]
+    emit: Option<Vec<String>>,
+
+    /// Use Rust VM
+    #[arg(long = "rust-vm")]
+    rust_vm: bool,
+
+    /// Use C VM, which is the default when tml is built with the `c-vm` feature
+    #[arg(
+        long = "c-vm",
+        conflicts_with_all = ["rust_vm", "jit", "rle_tape", "tape_repr", "tape_backing", "transpositions", "compare_vms", "events", "certificate", "coverage", "bytecode_stats", "vm_stats", "trace_states", "detect_cycles", "space_time", "animate", "break_state", "break_symbol"]
+    )]
+    c_vm: bool,
+
+    /// How the C VM dispatches instructions (goto, switch, threaded); runs the bytecode
+    #[arg(
+        long = "dispatch",
+        value_name = "STRATEGY",
+        hide_possible_values = true,
+        conflicts_with_all = ["rust_vm", "jit", "accelerate", "rle_tape", "tape_repr", "tape_backing", "transpositions", "events", "certificate", "coverage", "bytecode_stats", "vm_stats", "trace_states", "detect_cycles", "space_time", "animate", "break_state", "break_symbol"]
+    )]
+    dispatch: Option<backend::Dispatch>,
+
+    /// Run the bytecode even if the machine fits in a transition table
+    #[arg(long = "no-table")]
+    no_table: bool,
+
+    /// Run the transition table a block of cells at a time, reusing what it did to each block
+    #[arg(long = "accelerate", conflicts_with = "no_table")]
+    accelerate: bool,
+
+    /// Cells in each block with `--accelerate`
+    #[arg(
+        long = "block-size",
+        value_name = "CELLS",
+        default_value_t = 8,
+        requires = "accelerate",
+        value_parser = clap::value_parser!(u64).range(1..=1024)
+    )]
+    block_size: u64,
+
+    /// Run on a run-length encoded tape, crossing runs of one symbol in a single step
+    #[arg(
+        long = "rle-tape",
+        conflicts_with_all = ["no_table", "accelerate", "events", "certificate", "coverage", "bytecode_stats", "vm_stats", "trace_states", "detect_cycles", "space_time", "animate", "break_state", "break_symbol"]
+    )]
+    rle_tape: bool,
+
+    /// Compile machines that fit in a transition table to native code (needs the `jit` feature)
+    #[arg(
+        long = "jit",
+        conflicts_with_all = ["no_table", "accelerate", "rle_tape", "tape_repr", "tape_backing", "transpositions", "events", "certificate", "coverage", "bytecode_stats", "vm_stats", "trace_states", "detect_cycles", "space_time", "animate", "break_state", "break_symbol"]
+    )]
+    jit: bool,
+
+    /// Run the bytecode on both VMs and fail if they stop in different configurations
+    #[arg(
+        long = "compare-vms",
+        conflicts_with_all = ["rust_vm", "jit", "accelerate", "rle_tape", "tape_repr", "tape_backing", "transpositions", "events", "certificate", "coverage", "bytecode_stats", "vm_stats", "trace_states", "detect_cycles", "space_time", "animate", "break_state", "break_symbol"]
+    )]
+    compare_vms: bool,
+
+    /// Also compare the VMs every N moves with `--compare-vms`, running both from the start each time
+    #[arg(
+        long = "compare-every",
+        value_name = "N",
+        requires = "compare_vms",
+        value_parser = clap::value_parser!(u64).range(1..)
+    )]
+    compare_every: Option<u64>,
+
+    /// How to keep the tape (dense, rle); rle runs on the Rust VM
+    #[arg(
+        long = "tape-repr",
+        value_name = "REPR",
+        default_value = "dense",
+        hide_default_value = true,
+        hide_possible_values = true,
+        conflicts_with_all = ["accelerate", "rle_tape", "detect_cycles", "space_time", "animate", "break_state", "break_symbol"]
+    )]
+    tape_repr: backend::TapeRepr,
+
+    /// Where to keep a dense tape (memory, mmap:PATH); mmap runs on the Rust VM
+    #[arg(
+        long = "tape-backing",
+        value_name = "BACKING",
+        default_value = "memory",
+        hide_default_value = true,
+        conflicts_with_all = ["tape_repr", "accelerate", "rle_tape", "detect_cycles", "space_time", "animate", "break_state", "break_symbol"]
+    )]
+    tape_backing: backend::TapeBacking,
+
+    /// Remember what the Rust VM did to small windows of tape, and skip the moves next time
+    #[arg(
+        long = "transpositions",
+        conflicts_with_all = ["accelerate", "rle_tape", "max_tape_cells", "events", "certificate", "coverage", "bytecode_stats", "trace_states", "detect_cycles", "space_time", "animate", "break_state", "break_symbol"]
+    )]
+    transpositions: bool,
+
+    /// Cells on each side of the head in a `--transpositions` window
+    #[arg(
+        long = "transposition-window",
+        value_name = "CELLS",
+        default_value_t = 4,
+        requires = "transpositions",
+        value_parser = clap::value_parser!(u64).range(1..=64)
+    )]
+    transposition_window: u64,
+
+    /// The most windows `--transpositions` remembers
+    #[arg(
+        long = "transposition-capacity",
+        value_name = "WINDOWS",
+        default_value_t = 1 << 20,
+        requires = "transpositions",
+        value_parser = clap::value_parser!(u64).range(1..)
+    )]
+    transposition_capacity: u64,
+
+    /// Grow the tape to the left instead of halting when the head moves off cell 0
+    #[arg(long = "two-sided", conflicts_with_all = ["events", "certificate"])]
+    two_sided: bool,
+
+    /// Time execution
+    #[arg(short = 't', long = "time")]
+    time: bool,
+
+    /// Print the move count, head position and state to stderr every N moves, on either VM
+    #[arg(
+        long = "progress",
+        value_name = "N",
+        value_parser = clap::value_parser!(u64).range(1..),
+        conflicts_with_all = ["jit", "accelerate", "rle_tape", "compare_vms", "events", "certificate", "coverage", "bytecode_stats", "vm_stats", "trace_states", "detect_cycles", "space_time", "animate", "break_state", "break_symbol"]
+    )]
+    progress: Option<u64>,
+
+    /// Print statistics about the head's movement and the final tape
+    #[arg(long = "stats")]
+    stats: bool,
+
+    /// Report which arms the run took and which it never did
+    #[arg(long = "coverage")]
+    coverage: bool,
+
+    /// Print each state's size and opcodes, and how often each opcode ran
+    #[arg(long = "bytecode-stats")]
+    bytecode_stats: bool,
+
+    /// Print how many states the VM made to pass to other states
+    #[arg(long = "vm-stats")]
+    vm_stats: bool,
+
+    /// Print each state the run enters and how many moves it spends there
+    #[arg(long = "trace-states")]
+    trace_states: bool,
+
+    /// Stop with exit code 7 if the machine repeats a configuration, in place or shifted
+    #[arg(long = "detect-cycles")]
+    detect_cycles: bool,
+
+    /// Moves between checks for a configuration repeated in place
+    #[arg(
+        long = "cycle-interval",
+        value_name = "MOVES",
+        default_value_t = 1,
+        hide_default_value = true,
+        requires = "detect_cycles",
+        value_parser = clap::value_parser!(u64).range(1..)
+    )]
+    cycle_interval: u64,
+
+    /// Draw the run as a space-time diagram, one row per move
+    #[arg(long = "space-time")]
+    space_time: bool,
+
+    /// Only draw every Nth move in the space-time diagram
+    #[arg(
+        long = "space-time-every",
+        value_name = "N",
+        default_value_t = 1,
+        hide_default_value = true,
+        requires = "space_time",
+        value_parser = clap::value_parser!(u64).range(1..)
+    )]
+    space_time_every: u64,
+
+    /// Redraw the state and the tape around the head after every move
+    #[arg(long = "animate", conflicts_with_all = ["space_time", "trace_states"])]
+    animate: bool,
+
+    /// Frames per second for `--animate`
+    #[arg(
+        long = "fps",
+        default_value_t = 30,
+        requires = "animate",
+        value_parser = clap::value_parser!(u32).range(1..)
+    )]
+    fps: u32,
+
+    /// Only redraw every Nth move with `--animate`
+    #[arg(
+        long = "animate-every",
+        value_name = "N",
+        default_value_t = 1,
+        hide_default_value = true,
+        requires = "animate",
+        value_parser = clap::value_parser!(u64).range(1..)
+    )]
+    animate_every: u64,
+
+    /// Open the debugger whenever the machine enters this state
+    #[arg(
+        long = "break-state",
+        value_name = "STATE",
+        conflicts_with_all = ["events", "certificate", "coverage", "bytecode_stats", "vm_stats", "trace_states", "detect_cycles", "space_time", "animate"]
+    )]
+    break_state: Vec<String>,
+
+    /// Open the debugger whenever the machine writes this symbol
+    #[arg(
+        long = "break-symbol",
+        value_name = "SYMBOL",
+        conflicts_with_all = ["events", "certificate", "coverage", "bytecode_stats", "vm_stats", "trace_states", "detect_cycles", "space_time", "animate"]
+    )]
+    break_symbol: Vec<String>,
+
+    /// Stream JSON events to a visualizer (unix:PATH or tcp:HOST:PORT)
+    #[arg(long = "events", value_name = "ADDRESS")]
+    events: Option<events::Address>,
+
+    /// Moves per event batch
+    #[arg(
+        long = "event-batch",
+        value_name = "MOVES",
+        default_value_t = 1000,
+        hide_default_value = true,
+        value_parser = clap::value_parser!(u64).range(1..)
+    )]
+    event_batch: u64,
+
+    /// Write a certificate for a halting run to a file
+    #[arg(long = "certificate", value_name = "FILE")]
+    certificate: Option<PathBuf>,
+
+    /// Moves between configuration hashes in a certificate
+    #[arg(
+        long = "checkpoint-interval",
+        value_name = "MOVES",
+        default_value_t = 1_000_000,
+        value_parser = clap::value_parser!(u64).range(1..)
+    )]
+    checkpoint_interval: u64,
+
+    /// Maximum width when printing the final tape or the space-time diagram
+    #[arg(short = 'w', long = "terminal_width", value_parser = clap::value_parser!(u16).range(5..))]
+    terminal_width: Option<u16>,
+}
+
+// fills in the options the command line left out from the config, in an
+// `Arguments` or a `BatchArguments`, which name them the same
+macro_rules! configure {
+    ($args:expr, $config:expr, $command:expr, $matches:expr) => {{
+        let (args, config): (_, &config::Config) = ($args, $config);
+        let unset = |id: &str| unset($command, $matches, id);
+        if unset("max_moves") {
+            args.max_moves = config.max_moves.or(args.max_moves);
+        }
+        if unset("max_tape_cells") {
+            args.max_tape_cells = config.max_tape_cells.or(args.max_tape_cells);
+        }
+        if unset("two_sided") {
+            args.two_sided = config.two_sided.unwrap_or(args.two_sided);
+        }
+        if unset("decimal_radix") {
+            args.decimal_radix = config.decimal_radix.unwrap_or(args.decimal_radix);
+        }
+        if unset("decimal_digits") {
+            args.decimal_digits = config.decimal_digits.or(args.decimal_digits);
+        }
+        if unset("decimal_start") {
+            args.decimal_start = config.decimal_start.unwrap_or(args.decimal_start);
+        }
+        if unset("decimal_stride") {
+            args.decimal_stride = config.decimal_stride.unwrap_or(args.decimal_stride);
+        }
+        if unset("rust_vm") {
+            args.rust_vm = config.vm == Some(config::Vm::Rust);
+        }
+    }};
+}
+
+fn main() -> ExitCode {
+    let mut command = Cli::command();
+    let parsed = command
+        .clone()
+        .try_get_matches_from(std::env::args_os().map(optimization_level))
+        .and_then(|matches| Cli::from_arg_matches(&matches).map(|cli| (matches, cli)))
+        .map_err(|error| error.format(&mut command));
+    let (matches, mut cli) = match parsed {
+        Ok(parsed) => parsed,
+        // clap exits with 2 for a bad command line, which is the code for a
+        // machine that didn't compile
+        Err(error) => {
+            error.print().ok();
+            return if error.use_stderr() {
+                ExitCode::FAILURE
+            } else {
+                ExitCode::SUCCESS
+            };
+        }
+    };
+    let config = match config::Config::load() {
+        Ok(config) => config,
+        Err(error) => {
+            error.print();
+            return ExitCode::FAILURE;
+        }
+    };
+    if cli.no_color {
+        output::set_color(false);
+    } else if let Some(color) = config.color {
+        output::set_color(color);
+    }
+    match &mut cli.command {
+        Some(Command::Run(args)) | Some(Command::Watch(args)) => {
+            let (name, matches) = matches.subcommand().unwrap();
+            configure!(
+                args,
+                &config,
+                command.find_subcommand(name).unwrap(),
+                matches
+            );
+        }
+        Some(Command::Batch(args)) => {
+            let (name, matches) = matches.subcommand().unwrap();
+            configure!(
+                args,
+                &config,
+                command.find_subcommand(name).unwrap(),
+                matches
+            );
+        }
+        Some(Command::Repl(args)) => {
+            let (name, matches) = matches.subcommand().unwrap();
+            configure!(
+                args,
+                &config,
+                command.find_subcommand(name).unwrap(),
+                matches
+            );
+        }
+        Some(_) => {}
+        None => configure!(&mut cli.run, &config, &command, &matches),
+    }
+    let result = match cli.command {
+        Some(Command::Asm(args)) => return exit_code(assemble(args)),
+        Some(Command::Batch(args)) => run_batch(args),
+        Some(Command::Build(args)) => return exit_code(build(args)),
+        Some(Command::Check(args)) => return exit_code(check(args)),
+        Some(Command::CompileC(args)) => return exit_code(compile_c(args)),
+        Some(Command::CompileWasm(args)) => return exit_code(compile_wasm(args)),
+        Some(Command::Daemon(args)) => daemon::run(args.address),
+        Some(Command::Debug(args)) => debug(args),
+        Some(Command::Describe(args)) => describe(args),
+        Some(Command::Disasm(args)) => disassemble(args),
+        Some(Command::Fmt(args)) => fmt(args),
+        Some(Command::Gc(args)) => gc(args),
+        Some(Command::Graph(args)) => graph(args),
+        Some(Command::Lint(args)) => return lint(args),
+        Some(Command::Lsp(args)) => lsp(args),
+        Some(Command::Metrics(args)) => metrics(args),
+        Some(Command::Rename(args)) => rename(args),
+        Some(Command::Repl(args)) => repl(args),
+        Some(Command::Run(args)) => return run(*args),
+        Some(Command::Test(args)) => return exit_code(test(args)),
+        Some(Command::VerifyCertificate(args)) => verify_certificate(args),
+        Some(Command::Watch(args)) => watch(*args),
+        None => return run(cli.run),
+    };
+    match result {
+        Ok(_) => ExitCode::SUCCESS,
+        Err(error) => {
+            error.print();
+            ExitCode::FAILURE
+        }
+    }
+}
+
+// whether a config setting can fill in `id`: the command line didn't give it, or
+// anything it conflicts with, so the setting can't make a valid command invalid
+fn unset(command: &clap::Command, matches: &ArgMatches, id: &str) -> bool {
+    let given = |arg: &clap::Arg| {
+        matches.value_source(arg.get_id().as_str()) == Some(ValueSource::CommandLine)
+    };
+    let Some(arg) = command.get_arguments().find(|arg| arg.get_id() == id) else {
+        return false;
+    };
+    !given(arg)
+        && command
+            .get_arguments()
+            .filter(|other| given(other))
+            .all(|other| {
+                !command.get_arg_conflicts_with(arg).contains(&other)
+                    && !command.get_arg_conflicts_with(other).contains(&arg)
+            })
+}
+
+impl SourceArguments {
+    fn lex_options(&self) -> lex::Options {
+        lex::Options {
+            allow_tabs: self.allow_tabs,
+            bare_digits: !self.no_bare_digits,
+            edition: self.edition,
+        }
+    }
+
+    fn parse(&self, file: PathBuf) -> Result<parse::Unit<'static>, error::Error> {
+        let tokens = lex::Tokens::from_path_buf(file, self.lex_options())?;
+        parse::parse(tokens)
+    }
+
+    // like `parse`, but borrows the source, for `do_it`, which `tml watch` runs
+    // again on every save
+    fn parse_code<'a>(
+        &self,
+        code: &'a str,
+        path: &'a Path,
+    ) -> Result<parse::Unit<'a>, error::Error> {
+        parse::parse(lex::Tokens::new(code, path, self.lex_options())?)
+    }
+
+    fn start_args(&self) -> Result<parse::StartArgs<'_>, error::Error> {
+        match &self.start_args {
+            Some(start_args) if !start_args.trim().is_empty() => {
+                let path = Path::new("<start-args>");
+                let tokens = lex::Tokens::new(start_args, path, self.lex_options())?;
+                parse::parse_start_args(tokens)
+            }
+            _ => Ok(parse::StartArgs::default()),
+        }
+    }
+
+    fn parse_tape(
+        &self,
+        tape: Option<PathBuf>,
+    ) -> Result<Vec<parse::Symbol<'static>>, error::Error> {
+        if let Some(path) = tape {
+            let tokens = lex::Tokens::from_path_buf(path, self.lex_options())?;
+            parse::parse_tape(tokens)
+        } else {
+            Ok(Vec::new())
+        }
+    }
+
+    fn parse_tape_code<'a>(
+        &self,
+        code: &'a str,
+        path: &'a Path,
+    ) -> Result<Vec<parse::Symbol<'a>>, error::Error> {
+        parse::parse_tape(lex::Tokens::new(code, path, self.lex_options())?)
+    }
+
+    fn passes(&self) -> Result<pass::PassManager, error::Error> {
+        let mut passes = pass::PassManager::new();
+        if let Some(path) = &self.symbols_file {
+            passes.symbol_table(symbols::read(path)?);
+        }
+        Ok(passes)
+    }
+
+    fn compile(
+        &self,
+        unit: parse::Unit,
+        tape: Vec<parse::Symbol>,
+    ) -> Result<compile::Compiled, error::Error> {
+        self.passes()?.compile(unit, tape, self.start_args()?)
+    }
+
+    fn compile_optimized(
+        &self,
+        unit: parse::Unit,
+        tape: Vec<parse::Symbol>,
+        optimize: &OptimizeArguments,
+    ) -> Result<compile::Compiled, error::Error> {
+        let mut passes = self.passes()?;
+        if optimize.dedup_states {
+            passes.add_bytecode_pass(optimize::DedupStates::default());
+        }
+        optimize::add_passes(&mut passes, optimize.optimize);
+        if let Some(name) = &optimize.print_after_pass {
+            passes.print_after(name.clone());
+        }
+        let compiled = passes.compile(unit, tape, self.start_args()?)?;
+
+        // without `-v`, only passes with a summary are reported
+        let mut reported = false;
+        for report in passes.reports() {
+            let saved = report.before as isize - report.after as isize;
+            let value = match (optimize.verbose, &report.summary) {
+                (true, Some(summary)) => format!(
+                    "{saved} bytes saved ({} -> {}), {summary}",
+                    report.before, report.after
+                ),
+                (true, None) => format!(
+                    "{saved} bytes saved ({} -> {})",
+                    report.before, report.after
+                ),
+                (false, Some(summary)) => summary.clone(),
+                (false, None) => continue,
+            };
+            if !output::quiet() {
+                metrics::metric(&report.name, value);
+                reported = true;
+            }
+        }
+        if reported {
+            println!();
+        }
+        Ok(compiled)
+    }
+}
+
+// clap can't read `-O2` without also letting a bare `-O` take the file after it as
+// its level, so `-O<level>` is spelled out as `--optimize=<level>` before parsing
+fn optimization_level(arg: std::ffi::OsString) -> std::ffi::OsString {
+    match arg.to_str().and_then(|arg| arg.strip_prefix("-O")) {
+        Some(level) if !level.is_empty() && level.bytes().all(|byte| byte.is_ascii_digit()) => {
+            format!("--optimize={level}").into()
+        }
+        _ => arg,
+    }
+}
+
+fn assemble(args: AsmArguments) -> Result<(), Failure> {
+    let output = args
+        .output
+        .unwrap_or_else(|| args.file.with_extension("tmb"));
+    let compiled = asm::read(&args.file).map_err(Failure::Compile)?;
+    Ok(tmb::write(&compiled, &output, false)?)
+}
+
+fn build(args: BuildArguments) -> Result<(), Failure> {
+    let output = args
+        .output
+        .unwrap_or_else(|| args.file.with_extension("tmb"));
+    let compile = || {
+        let unit = args.source.parse(args.file)?;
+        let tape = args.source.parse_tape(args.tape)?;
+        let warnings = warning::check(&unit, &args.source.start_args()?, &tape);
+        let compiled = args.source.compile_optimized(unit, tape, &args.optimize)?;
+        args.warnings.report(warnings)?;
+        Ok(compiled)
+    };
+    let compiled = compile().map_err(Failure::Compile)?;
+    Ok(tmb::write(&compiled, &output, !args.strip)?)
+}
+
+fn compile_c(args: CompileArguments) -> Result<(), Failure> {
+    let (compiled, output) = compile_standalone(args, "c")?;
+    Ok(write_output(&output, emit::c(&compiled).into_bytes())?)
+}
+
+fn compile_wasm(args: CompileWasmArguments) -> Result<(), Failure> {
+    let extension = if args.wat { "wat" } else { "wasm" };
+    let (compiled, output) = compile_standalone(args.compile, extension)?;
+    let module = match args.wat {
+        true => emit::wat(&compiled)?.into_bytes(),
+        false => emit::wasm(&compiled)?,
+    };
+    Ok(write_output(&output, module)?)
+}
+
+// compiles the machine for `compile-c` and `compile-wasm`, and returns where to
+// write it
+fn compile_standalone(
+    args: CompileArguments,
+    extension: &str,
+) -> Result<(compile::Compiled, PathBuf), Failure> {
+    let output = args
+        .output
+        .unwrap_or_else(|| args.file.with_extension(extension));
+    let compile = || {
+        let unit = args.source.parse(args.file)?;
+        let tape = args.source.parse_tape(args.tape)?;
+        let warnings = warning::check(&unit, &args.source.start_args()?, &tape);
+        let compiled = args.source.compile_optimized(unit, tape, &args.optimize)?;
+        args.warnings.report(warnings)?;
+        Ok(compiled)
+    };
+    Ok((compile().map_err(Failure::Compile)?, output))
+}
+
+fn write_output(path: &Path, bytes: Vec<u8>) -> Result<(), error::Error> {
+    fs::write(path, bytes)
+        .map_err(|_| error::Error::new(format!("couldn't write file {}", path.display()), None))
+}
+
+fn check(args: CheckArguments) -> Result<(), Failure> {
+    let compile = || {
+        let unit = args.source.parse(args.file)?;
+        let warnings = warning::check(&unit, &args.source.start_args()?, &[]);
+        let states = unit.states.len();
+        let compiled = args.source.compile(unit, Vec::new())?;
+        Ok((states, compiled, args.warnings.report(warnings)?))
+    };
+    let (states, compiled, reported) = compile().map_err(Failure::Compile)?;
+    if reported > 0 {
+        println!();
+    }
+
+    let plural = if states == 1 { "" } else { "s" };
+    println!(
+        "ok, {states} state{plural}, {} bytes of bytecode",
+        compiled.bytes.len()
+    );
+    Ok(())
+}
+
+// unlike `check`, this keeps going after errors where it can, reports lints
+// that are off by default and prints its own findings
+fn lint(args: LintArguments) -> ExitCode {
+    // every lint is on unless it's allowed
+    let lints = warning::Lints {
+        warn: warning::Lint::value_variants()
+            .iter()
+            .copied()
+            .filter(|lint| !args.allow.contains(lint))
+            .collect(),
+        allow: args.allow,
+        deny: args.deny,
+    };
+    let mut findings = Vec::new();
+    match args.source.parse(args.file) {
+        Ok(unit) => match args.source.start_args() {
+            Ok(start_args) => {
+                for warning in warning::check(&unit, &start_args, &[]) {
+                    findings.extend(lints.apply(warning));
+                }
+                if let Err(error) = args.source.compile(unit, Vec::new()) {
+                    findings.push(error);
+                }
+            }
+            Err(error) => findings.push(error),
+        },
+        Err(error) => findings.push(error),
+    }
+    let findings = error::Error::combine(findings);
+
+    match args.error_format {
+        error::ErrorFormat::Human if findings.diagnostics().is_empty() => {
+            println!("ok, no findings");
+        }
+        error::ErrorFormat::Human => findings.print(),
+        error::ErrorFormat::Json => {
+            for diagnostic in findings.diagnostics() {
+                println!("{}", diagnostic.to_json());
+            }
+        }
+    }
+
+    let fails = |diagnostic: &error::Diagnostic| {
+        diagnostic.level == error::Level::Error || args.severity == error::Level::Warning
+    };
+    if findings.diagnostics().iter().any(fails) {
+        ExitCode::FAILURE
+    } else {
+        ExitCode::SUCCESS
+    }
+}
+
+fn debug(args: DebugArguments) -> Result<(), error::Error> {
+    // unoptimized, so each move is one the source describes
+    let compiled = if tmb::is_tmb(&args.file) {
+        let mut compiled = tmb::read(&args.file)?;
+        if args.tape.is_some() {
+            tmb::set_tape(&mut compiled, args.source.parse_tape(args.tape)?)?;
+        }
+        compiled
+    } else {
+        let unit = args.source.parse(args.file)?;
+        let tape = args.source.parse_tape(args.tape)?;
+        args.source.compile(unit, tape)?
+    };
+    debug::run(&compiled, args.two_sided)
+}
+
+fn describe(args: DescribeArguments) -> Result<(), error::Error> {
+    let unit = args.source.parse(args.file)?;
+    let compiled = args.source.compile(unit.clone(), Vec::new())?;
+    describe::print(&unit.states, &compiled, &args.state)
+}
+
+fn disassemble(args: DisasmArguments) -> Result<(), error::Error> {
+    let compiled = if tmb::is_tmb(&args.file) {
+        let mut compiled = tmb::read(&args.file)?;
+        if args.tape.is_some() {
+            tmb::set_tape(&mut compiled, args.source.parse_tape(args.tape)?)?;
+        }
+        compiled
+    } else {
+        let unit = args.source.parse(args.file)?;
+        let tape = args.source.parse_tape(args.tape)?;
+        args.source.compile_optimized(unit, tape, &args.optimize)?
+    };
+
+    let assembly = asm::disassemble(&compiled);
+    match args.output {
+        Some(path) => fs::write(&path, assembly).map_err(|_| {
+            error::Error::new(format!("couldn't write file {}", path.display()), None)
+        }),
+        None => {
+            print!("{assembly}");
+            Ok(())
+        }
+    }
+}
+
+fn graph(args: GraphArguments) -> Result<(), error::Error> {
+    let unit = args.source.parse(args.file)?;
+    let start_args = args.source.start_args()?;
+    compile::compile(unit.clone(), Vec::new(), start_args.clone())?;
+
+    if args.reachability {
+        graph::print_reachability(&unit.states, &start_args);
+    } else {
+        graph::print(&unit.states);
+    }
+    Ok(())
+}
+
+fn lsp(args: LspArguments) -> Result<(), error::Error> {
+    lsp::run(args.source.lex_options(), args.source.start_args()?)
+}
 
-    /// Don't print the final tape
-    #[arg(long = "hide-tape")]
-    hide_tape: bool,
+fn fmt(args: FmtArguments) -> Result<(), error::Error> {
+    let unit = args.source.parse(args.file.clone())?;
+    let comments =
+        lex::Tokens::from_path_buf(args.file.clone(), args.source.lex_options())?.comments()?;
+    let formatted = fmt::format(&unit, &comments);
+    if args.verify {
+        verify_format(&args.source, unit, &comments, &formatted)?;
+    }
 
-    /// Don't print the decimal interpretation of the final tape
-    #[arg(long = "hide-decimal")]
-    hide_decimal: bool,
+    if !args.write && !args.check {
+        print!("{formatted}");
+        return Ok(());
+    }
 
-    /// Radix for the final decimal
-    #[arg(short = 'r', long = "decimal-radix", default_value_t = 2, value_parser = clap::value_parser!(u32).range(1..=36))]
-    decimal_radix: u32,
+    let Ok(source) = fs::read_to_string(&args.file) else {
+        return Err(error::Error::new(
+            format!("couldn't read file {}", args.file.display()),
+            None,
+        ));
+    };
+    if source == formatted {
+        return Ok(());
+    }
 
-    /// Digits in the final decimal
-    #[arg(short = 'd', long = "decimal-digits", value_parser = clap::value_parser!(u32).range(3..))]
-    decimal_digits: Option<u32>,
+    if args.check {
+        return Err(
+            error::Error::new(format!("{} is not formatted", args.file.display()), None).with_help(
+                Some(format!("run `tml fmt --write {}`", args.file.display())),
+            ),
+        );
+    }
+    if fs::write(&args.file, formatted).is_err() {
+        return Err(error::Error::new(
+            format!("couldn't write file {}", args.file.display()),
+            None,
+        ));
+    }
+    Ok(())
+}
 
-    /// Start position for the final decimal
-    #[arg(short = 's', long = "decimal-start", default_value_t = 2)]
-    decimal_start: u32,
+fn verify_format(
+    source: &SourceArguments,
+    unit: parse::Unit,
+    comments: &[lex::Span],
+    formatted: &str,
+) -> Result<(), error::Error> {
+    let code = Box::leak(formatted.to_string().into_boxed_str());
+    let path = Path::new("<formatted>");
+    let tokens = lex::Tokens::new(code, path, source.lex_options())?;
+    let reparsed = parse::parse(tokens)?;
 
-    /// Stride for the final decimal
-    #[arg(short = 'S', long = "decimal-stride", default_value_t = 2, value_parser = clap::value_parser!(u32).range(1..))]
-    decimal_stride: u32,
+    // spans only print their text, so this ignores where things are
+    if format!("{unit:?}") != format!("{reparsed:?}") {
+        return Err(error::Error::new(
+            "formatted machine doesn't parse to the same syntax tree".to_string(),
+            None,
+        ));
+    }
 
-    /// Don't color output
-    #[arg(long = "no-color")]
-    no_color: bool,
+    let text = |comments: &[lex::Span]| -> Vec<_> {
+        comments
+            .iter()
+            .map(|comment| comment.text.trim_end().to_string())
+            .collect()
+    };
+    let kept = lex::Tokens::new(code, path, source.lex_options())?.comments()?;
+    if text(comments) != text(&kept) {
+        return Err(error::Error::new(
+            "formatted machine doesn't keep every comment".to_string(),
+            None,
+        ));
+    }
 
-    /// Allow tab characters in machine and tape files
-    #[arg(long = "allow-tabs")]
-    allow_tabs: bool,
+    let bytes = |unit| {
+        source
+            .compile(unit, Vec::new())
+            .ok()
+            .map(|compiled| compiled.bytes)
+    };
+    if bytes(unit) != bytes(reparsed) {
+        return Err(error::Error::new(
+            "formatted machine doesn't compile to the same bytecode".to_string(),
+            None,
+        ));
+    }
 
-    /// Dump bytecode
-    #[arg(short = 'b', long = "dump-bytecode")]
-    dump_bytecode: bool,
+    Ok(())
+}
 
-    /// Use Rust VM
-    #[arg(long = "rust-vm")]
-    rust_vm: bool,
+fn gc(args: GcArguments) -> Result<(), error::Error> {
+    // the states' spans point into this, so the file is only read once
+    let source = read_file(&args.file)?;
+    let unit = args.source.parse_code(&source, &args.file)?;
+    let start_args = args.source.start_args()?;
 
-    /// Time execution
-    #[arg(short = 't', long = "time")]
-    time: bool,
+    // make sure the machine compiles so we don't delete anything from a broken file
+    compile::compile(unit.clone(), Vec::new(), start_args.clone())?;
 
-    /// Maximum width when printing the final tape
-    #[arg(short = 'w', long = "terminal_width", value_parser = clap::value_parser!(u16).range(5..))]
-    terminal_width: Option<u16>,
+    let unreachable = refactor::unreachable(&unit.states, &start_args);
+    let label = if args.dry_run {
+        "unreachable"
+    } else {
+        "removed"
+    };
+    for state in &unreachable {
+        let span = state.name.span;
+        let state = format!(
+            "`{}` ({}:{})",
+            compile::Signature::of(state),
+            span.path.display(),
+            span.line + 1
+        );
+        if !output::color() {
+            println!("{label}: {state}");
+        } else {
+            println!(
+                "{}{}{label}:{}{} {state}",
+                style::Bold,
+                color::Fg(color::Red),
+                style::Reset,
+                color::Fg(color::Reset)
+            );
+        }
+    }
+
+    if unreachable.is_empty() {
+        println!("no unreachable states");
+    } else if !args.dry_run
+        && fs::write(&args.file, refactor::remove(&source, &unreachable)).is_err()
+    {
+        return Err(error::Error::new(
+            format!("couldn't write file {}", args.file.display()),
+            None,
+        ));
+    }
+    Ok(())
 }
 
-fn main() -> ExitCode {
-    let args = Arguments::parse();
-    let no_color = args.no_color;
-    match do_it(args) {
-        Ok(_) => ExitCode::SUCCESS,
-        Err(error) => {
-            error.print(no_color);
-            ExitCode::FAILURE
+impl WarningArguments {
+    fn report(&self, warnings: Vec<warning::Warning>) -> Result<usize, error::Error> {
+        let mut warn = self.warn.clone();
+        let allowed = |lint: &warning::Lint| self.allow.contains(lint);
+        if self.strict_idents {
+            warn.extend(
+                warning::Lint::STRICT_IDENTS
+                    .into_iter()
+                    .filter(|lint| !allowed(lint)),
+            );
+        }
+        if self.no_overload && !allowed(&warning::Lint::OverloadedState) {
+            warn.push(warning::Lint::OverloadedState);
+        }
+        let lints = warning::Lints {
+            warn,
+            allow: self.allow.clone(),
+            deny: self.deny.clone(),
+        };
+        warning::report(warnings, &lints, self.deny_warnings)
+    }
+}
+
+fn metrics(args: MetricsArguments) -> Result<(), error::Error> {
+    let unit = args.source.parse(args.file)?;
+    let compiled = args.source.compile(unit.clone(), Vec::new())?;
+    metrics::print(&unit.states, &compiled);
+    Ok(())
+}
+
+fn rename(args: RenameArguments) -> Result<(), error::Error> {
+    let unit = args.source.parse(args.file.clone())?;
+    let (signature, edits) = refactor::rename(
+        &unit.states,
+        &args.old_name,
+        &args.new_name,
+        args.states,
+        args.symbols,
+        args.source.edition,
+    )?;
+
+    let Ok(source) = fs::read_to_string(&args.file) else {
+        return Err(error::Error::new(
+            format!("couldn't read file {}", args.file.display()),
+            None,
+        ));
+    };
+    let count = edits.len();
+    let renamed = refactor::apply(&source, edits);
+
+    if args.diff {
+        refactor::print_rename_diff(&args.file, &source, &renamed);
+    } else if fs::write(&args.file, renamed).is_ok() {
+        println!(
+            "renamed `{signature}` to `{}` ({count} occurrence{})",
+            args.new_name,
+            if count == 1 { "" } else { "s" }
+        );
+    } else {
+        return Err(error::Error::new(
+            format!("couldn't write file {}", args.file.display()),
+            None,
+        ));
+    }
+    Ok(())
+}
+
+fn run_batch(args: BatchArguments) -> Result<(), error::Error> {
+    let unit = args.source.parse(args.file)?;
+    let warnings = warning::check(&unit, &args.source.start_args()?, &[]);
+    let compiled = args
+        .source
+        .compile_optimized(unit, Vec::new(), &args.optimize)?;
+    args.warnings.report(warnings)?;
+
+    let options = batch::Options {
+        jobs: args.jobs.map(|jobs| jobs as usize),
+        format: args.format,
+        rust_vm: args.rust_vm,
+        decimal: (!args.hide_decimal).then(|| batch::DecimalOptions {
+            radix: args.decimal_radix as usize,
+            digits: args.decimal_digits.map(|d| d as usize),
+            start: args.decimal_start as usize,
+            stride: args.decimal_stride as usize,
+        }),
+        backend: backend::Options {
+            max_moves: args.max_moves.unwrap_or(usize::MAX),
+            trim: backend::Trim::TrailingBlanks,
+            table: true,
+            two_sided: args.two_sided,
+            max_tape_cells: args
+                .max_tape_cells
+                .map_or(usize::MAX, |cells| cells as usize),
+            block_size: None,
+            tape_repr: backend::TapeRepr::Dense,
+            tape_backing: backend::TapeBacking::Memory,
+            transpositions: None,
+            dispatch: None,
+        },
+    };
+    backend::catch_interrupts()?;
+    batch::run(&compiled, &args.tapes, args.source.lex_options(), &options)
+}
+
+fn repl(args: ReplArguments) -> Result<(), error::Error> {
+    let unit = args.source.parse(args.file)?;
+    let warnings = warning::check(&unit, &args.source.start_args()?, &[]);
+    let compiled = args
+        .source
+        .compile_optimized(unit, Vec::new(), &args.optimize)?;
+    args.warnings.report(warnings)?;
+
+    let options = repl::Options {
+        rust_vm: args.rust_vm,
+        decimal: (!args.hide_decimal).then(|| batch::DecimalOptions {
+            radix: args.decimal_radix as usize,
+            digits: args.decimal_digits.map(|d| d as usize),
+            start: args.decimal_start as usize,
+            stride: args.decimal_stride as usize,
+        }),
+        backend: backend::Options {
+            max_moves: args.max_moves.unwrap_or(usize::MAX),
+            trim: backend::Trim::TrailingBlanks,
+            table: true,
+            two_sided: args.two_sided,
+            max_tape_cells: args
+                .max_tape_cells
+                .map_or(usize::MAX, |cells| cells as usize),
+            block_size: None,
+            tape_repr: backend::TapeRepr::Dense,
+            tape_backing: backend::TapeBacking::Memory,
+            transpositions: None,
+            dispatch: None,
+        },
+        terminal_width: termion::terminal_size().map_or(80, |(width, _)| width as usize),
+    };
+    repl::run(&compiled, args.source.lex_options(), &options)
+}
+
+fn test(args: TestArguments) -> Result<(), Failure> {
+    let compile = || {
+        let unit = args.source.parse(args.file)?;
+        let start_args = args.source.start_args()?;
+        // each case compiles the machine with its own tape, so check it compiles
+        // on its own before running any
+        args.source.compile(unit.clone(), Vec::new())?;
+
+        // spec files always allow bare digits so limits can be written as numbers
+        let options = lex::Options {
+            bare_digits: true,
+            ..args.source.lex_options()
+        };
+        let tokens = lex::Tokens::from_path_buf(args.spec, options)?;
+        Ok((unit, start_args, parse::parse_spec(tokens)?))
+    };
+    let (unit, start_args, cases) = compile().map_err(Failure::Compile)?;
+
+    let options = spec::Options {
+        max_moves: args.max_moves,
+        rust_vm: args.rust_vm,
+    };
+    Ok(spec::run(&unit, cases, &start_args, &options)?)
+}
+
+// exit codes besides the halt reasons' (see `HaltReason::exit_code`)
+const ERROR: u8 = 1;
+const COMPILE_ERROR: u8 = 2;
+const VM_ERROR: u8 = 5;
+const REJECTED: u8 = 8;
+const MISMATCH: u8 = 9;
+
+// why `do_it` failed: the machine or its tape didn't compile, something else
+// went wrong before the machine ran, or the VM failed while it ran
+enum Failure {
+    Compile(error::Error),
+    Error(error::Error),
+    Vm(error::Error),
+}
+
+impl From<error::Error> for Failure {
+    fn from(error: error::Error) -> Self {
+        Failure::Error(error)
+    }
+}
+
+// the exit code says why the machine stopped, so scripts can tell a halt from a
+// cut off run without parsing the output
+fn run(args: Arguments) -> ExitCode {
+    let format = args.format;
+    let (error, code) = match do_it(args) {
+        Ok(code) => return ExitCode::from(code),
+        Err(Failure::Compile(error)) => (error, COMPILE_ERROR),
+        Err(Failure::Error(error)) => (error, ERROR),
+        Err(Failure::Vm(error)) => (error, VM_ERROR),
+    };
+    match format {
+        output::Format::Human => error.print(),
+        output::Format::Json => {
+            println!("{}", json!({ "ok": false, "diagnostics": error.to_json() }))
         }
     }
+    ExitCode::from(code)
+}
+
+// the exit code of a subcommand that compiles a machine but doesn't run it, which
+// follows `run`'s for the failures they share
+fn exit_code(result: Result<(), Failure>) -> ExitCode {
+    let (error, code) = match result {
+        Ok(()) => return ExitCode::SUCCESS,
+        Err(Failure::Compile(error)) => (error, COMPILE_ERROR),
+        Err(Failure::Error(error)) => (error, ERROR),
+        Err(Failure::Vm(error)) => (error, VM_ERROR),
+    };
+    error.print();
+    ExitCode::from(code)
 }
 
-fn do_it(args: Arguments) -> Result<(), error::Error> {
+fn watch(args: Arguments) -> Result<(), error::Error> {
+    let mut paths = vec![args.file.clone().unwrap()];
+    paths.extend(args.tape.clone());
+    tml::watch::watch(&paths, || {
+        run(args.clone());
+    })
+}
+
+fn read_file(path: &Path) -> Result<String, error::Error> {
+    fs::read_to_string(path)
+        .map_err(|_| error::Error::new(format!("couldn't read file {}", path.display()), None))
+}
+
+// returns the exit code for how the run went, which is 0 if it only emitted code
+fn do_it(args: Arguments) -> Result<u8, Failure> {
     let start = Instant::now();
+    output::set_quiet(args.quiet || args.output.is_some() || args.format == output::Format::Json);
+    if args.c_vm && !cfg!(feature = "c-vm") {
+        return Err(needs_c_vm("--c-vm").into());
+    }
+    if args.dispatch.is_some() && !cfg!(feature = "c-vm") {
+        return Err(needs_c_vm("--dispatch").into());
+    }
+    if args.compare_vms && !cfg!(feature = "c-vm") {
+        return Err(needs_c_vm("--compare-vms").into());
+    }
 
-    let tokens = lex::Tokens::from_path_buf(args.file, args.allow_tabs)?;
-    let unit = parse::parse(tokens)?;
+    let spec = match &args.random_tape {
+        Some(values) => Some(random::Spec::parse(values)?),
+        None => None,
+    };
+    let random_tape = match &spec {
+        Some(spec) => {
+            let seed = args.seed.unwrap_or_else(random::any_seed);
+            let tape = random::tape(spec, seed, args.source.lex_options())?;
+            // so a tape that turned up a bug can be made again
+            if args.seed.is_none() && !output::quiet() {
+                eprintln!("random tape seed: {seed}");
+            }
+            Some(tape)
+        }
+        None => None,
+    };
 
-    let compiled = if let Some(path) = args.tape {
-        let tokens = lex::Tokens::from_path_buf(path, args.allow_tabs)?;
-        let symbols = parse::parse_tape(tokens)?;
-        compile::compile(unit, symbols)?
+    let file = args.file.unwrap();
+    let is_tmb = tmb::is_tmb(&file);
+    let mut compiled = if is_tmb || asm::is_asm(&file) {
+        let kind = if is_tmb { ".tmb" } else { ".tasm" };
+        if args.source.start_args.is_some() {
+            return Err(error::Error::new(
+                format!("a {kind} file already has its start arguments"),
+                None,
+            )
+            .with_help(Some(
+                "pass `--start-args` to `tml build` instead".to_string(),
+            ))
+            .into());
+        }
+        let mut compiled = if is_tmb {
+            tmb::read(&file)?
+        } else {
+            asm::read(&file).map_err(Failure::Compile)?
+        };
+        if args.coverage && compiled.source_map.is_empty() {
+            return Err(error::Error::new(
+                format!(
+                    "`--coverage` needs the machine's source, which this {kind} file doesn't have"
+                ),
+                None,
+            )
+            .with_help(is_tmb.then(|| "build it again without `--strip`".to_string()))
+            .into());
+        }
+        match (random_tape, &args.tape) {
+            (Some(tape), _) => tmb::set_tape(&mut compiled, tape).map_err(Failure::Compile)?,
+            (None, Some(path)) => {
+                let code = read_file(path).map_err(Failure::Compile)?;
+                let tape = args
+                    .source
+                    .parse_tape_code(&code, path)
+                    .map_err(Failure::Compile)?;
+                tmb::set_tape(&mut compiled, tape).map_err(Failure::Compile)?;
+            }
+            (None, None) => {}
+        }
+        compiled
     } else {
-        compile::compile(unit, Vec::new())?
+        // denied warnings don't compile either. the sources only have to last
+        // until the machine is compiled, so every run `tml watch` makes frees them
+        let compile = || {
+            let code = read_file(&file)?;
+            let unit = args.source.parse_code(&code, &file)?;
+            let tape_code;
+            let tape = match (random_tape, &args.tape) {
+                (Some(tape), _) => tape,
+                (None, Some(path)) => {
+                    tape_code = read_file(path)?;
+                    args.source.parse_tape_code(&tape_code, path)?
+                }
+                (None, None) => Vec::new(),
+            };
+            let warnings = warning::check(&unit, &args.source.start_args()?, &tape);
+            let compiled = args.source.compile_optimized(unit, tape, &args.optimize)?;
+            args.warnings.report(warnings)?;
+            Ok(compiled)
+        };
+        compile().map_err(Failure::Compile)?
     };
 
+    if let Some(head_start) = args.head_start {
+        let head = head_start.cell(compiled.tape.len());
+        if head < 0 && !args.two_sided {
+            return Err(error::Error::new(
+                format!(
+                    "`--head-start {head}` is left of cell 0, which a one-way tape doesn't have"
+                ),
+                None,
+            )
+            .with_help(Some("add `--two-sided`".to_string()))
+            .into());
+        }
+        compiled.set_head_start(head);
+    }
+
     let compile_time = start.elapsed();
 
     if args.dump_bytecode {
-        bytecode::dump(&mut compiled.bytes.iter().copied(), args.no_color);
+        bytecode::dump(&compiled);
+    }
+    if args.dump_symbols {
+        bytecode::dump_symbols(&compiled);
+    }
+    if args.dump_states {
+        bytecode::dump_states(&compiled);
+    }
+    if args.bytecode_stats {
+        profile::print_static(&compiled);
+    }
+    check_accept_states(&compiled, &args.accept_state)?;
+    // read before the run, so a typo doesn't wait for it
+    let expected_code = match &args.expect_tape {
+        Some(path) => Some(read_file(path)?),
+        None => None,
+    };
+    let expected_tape = match (&args.expect_tape, &expected_code) {
+        (Some(path), Some(code)) => Some(args.source.parse_tape_code(code, path)?),
+        _ => None,
+    };
+
+    if let Some(emit) = args.emit {
+        let [kind, path] = &emit[..] else {
+            unreachable!()
+        };
+        let output = match kind.as_str() {
+            "c" => emit::c(&compiled).into_bytes(),
+            "wat" => emit::wat(&compiled)?.into_bytes(),
+            "wasm" => emit::wasm(&compiled)?,
+            "symbols" => symbols::to_json(&compiled.symbols).into_bytes(),
+            _ => {
+                return Err(error::Error::new(
+                    format!(
+                        "unknown emit kind `{kind}` (expected `c`, `wat`, `wasm` or `symbols`)"
+                    ),
+                    None,
+                )
+                .into())
+            }
+        };
+        if fs::write(path, output).is_err() {
+            return Err(error::Error::new(format!("couldn't write file {path}"), None).into());
+        }
+        return Ok(0);
+    }
+
+    let terminal_width = if let Some(width) = args.terminal_width {
+        width as usize
+    } else if let Ok((width, _)) = termion::terminal_size() {
+        width as usize
+    } else {
+        80
+    };
+    if args.show_initial_tape {
+        if !output::color() {
+            println!("initial tape:");
+        } else {
+            println!(
+                "{}{}initial tape:{}{}",
+                style::Bold,
+                color::Fg(color::Green),
+                style::Reset,
+                color::Fg(color::Reset)
+            );
+        }
+        let tape: Vec<_> = compiled
+            .tape
+            .iter()
+            .map(|&i| compiled.symbols[i as usize].as_str())
+            .collect();
+        tape::dump(&tape, terminal_width, None);
     }
 
+    backend::catch_interrupts()?;
     let start = Instant::now();
 
     let max_moves = args.max_moves.unwrap_or(usize::MAX);
-    let simulated = if args.rust_vm {
-        vm::simulate(&compiled.bytes, compiled.tape, max_moves)
+    let mut certificate = None;
+    let options = backend::Options {
+        max_moves,
+        trim: args.trim,
+        // the cache and the dispatch strategies are for the bytecode
+        table: !args.no_table && !args.transpositions && args.dispatch.is_none(),
+        two_sided: args.two_sided,
+        max_tape_cells: args
+            .max_tape_cells
+            .map_or(usize::MAX, |cells| cells as usize),
+        block_size: args.accelerate.then_some(args.block_size as usize),
+        tape_repr: args.tape_repr,
+        tape_backing: args.tape_backing.clone(),
+        transpositions: args.transpositions.then_some(transposition::CacheSize {
+            window: args.transposition_window as usize,
+            capacity: args.transposition_capacity as usize,
+        }),
+        dispatch: args.dispatch,
+    };
+    let mut coverage = None;
+    let mut profile = None;
+    let mut vm_stats = None;
+    let mut steps = None;
+    let simulated = if !args.break_state.is_empty() || !args.break_symbol.is_empty() {
+        let breakpoints = debug::Breakpoints {
+            states: args.break_state,
+            symbols: args.break_symbol,
+        };
+        debug::run_with_breakpoints(&compiled, &options, &breakpoints)?
+    } else if args.events.is_some()
+        || args.certificate.is_some()
+        || args.coverage
+        || args.bytecode_stats
+        || args.vm_stats
+        || args.trace_states
+        || args.detect_cycles
+        || args.space_time
+        || args.animate
+    {
+        // only the Rust VM can be watched move by move
+        let mut events = match &args.events {
+            Some(address) => Some(events::Events::new(
+                events::listen(address)?,
+                &compiled,
+                args.event_batch as usize,
+            )),
+            None => None,
+        };
+        let mut recorder = args
+            .certificate
+            .as_ref()
+            .map(|_| certificate::Recorder::new(&compiled, args.checkpoint_interval as usize));
+        coverage = args.coverage.then(|| coverage::Coverage::new(&compiled));
+        profile = args.bytecode_stats.then(profile::Profile::new);
+        vm_stats = args.vm_stats.then(profile::VmStats::default);
+        let mut trace = args
+            .trace_states
+            .then(|| profile::StateTrace::new(&compiled));
+        let observer = (
+            (&mut events, &mut recorder),
+            (&mut coverage, (&mut profile, (&mut vm_stats, &mut trace))),
+        );
+        let mut space_time = args.space_time.then(|| {
+            spacetime::SpaceTime::new(
+                &compiled,
+                args.space_time_every as usize,
+                args.two_sided,
+                terminal_width,
+            )
+        });
+        let mut animation = args.animate.then(|| {
+            animate::Animation::new(
+                &compiled,
+                args.animate_every as usize,
+                args.fps,
+                terminal_width,
+                args.two_sided,
+            )
+        });
+        let simulated = if args.detect_cycles || args.space_time || args.animate {
+            let detectors = args.detect_cycles.then(|| {
+                (
+                    cycle::Cycles::new(args.cycle_interval as usize),
+                    cycle::Translations::new(args.two_sided),
+                )
+            });
+            // the diagram goes first, so it gets the move a cycle is found on
+            cycle::observe(
+                &compiled.bytes,
+                &compiled.tape,
+                &options,
+                ((&mut space_time, &mut animation), detectors),
+                observer,
+            )
+            .map_err(Failure::Vm)?
+        } else {
+            backend::observe(&compiled.bytes, &compiled.tape, &options, observer)
+                .map_err(Failure::Vm)?
+        };
+        if let Some(events) = events {
+            events.finish(&simulated);
+        }
+        if let Some(trace) = trace {
+            trace.finish(&simulated);
+        }
+        if let Some(space_time) = space_time {
+            space_time.finish(&simulated);
+        }
+        if let Some(animation) = animation {
+            animation.finish(&simulated);
+        }
+        certificate = recorder.map(|recorder| recorder.finish(&compiled, &simulated));
+        simulated
+    } else if args.compare_vms {
+        compare_vms(
+            &compiled,
+            &options,
+            args.compare_every.map(|every| every as usize),
+        )
+        .map_err(Failure::Vm)?
+    } else if args.jit {
+        jit(&compiled, &options)?
+    } else if args.rle_tape {
+        let (simulated, taken) = rle::AcceleratedVm
+            .run(&compiled.bytes, &compiled.tape, &options)
+            .map_err(Failure::Vm)?;
+        steps = Some(taken);
+        simulated
     } else {
-        ffi::simulate(&compiled.bytes, &compiled.tape, max_moves)
+        // the C VM has its own tape, and no transposition cache
+        let rust_vm = args.rust_vm
+            || args.tape_repr != backend::TapeRepr::Dense
+            || args.tape_backing != backend::TapeBacking::Memory
+            || args.transpositions;
+        let backend = backend::select(rust_vm);
+        match args.progress {
+            Some(every) => {
+                let mut report = |checkpoint| {
+                    report_progress(&compiled, checkpoint);
+                    ControlFlow::Continue(())
+                };
+                let hook = backend::Hook {
+                    every: every as usize,
+                    callback: &mut report,
+                };
+                backend
+                    .run_hooked(&compiled.bytes, &compiled.tape, &options, hook)
+                    .map_err(Failure::Vm)?
+            }
+            None => backend
+                .run(&compiled.bytes, &compiled.tape, &options)
+                .map_err(Failure::Vm)?,
+        }
     };
 
     let exec_time = start.elapsed();
-    println!();
+    if !output::quiet() {
+        println!();
+    }
 
-    if args.time && args.no_color {
+    if args.time && !output::color() {
         println!("compile time: {compile_time:?}");
         println!("execution time: {exec_time:?}\n");
     } else if args.time {
@@ -146,17 +2223,46 @@ fn do_it(args: Arguments) -> Result<(), error::Error> {
         .iter()
         .map(|&i| compiled.symbols[i as usize].as_str())
         .collect();
+    let decimal = || {
+        tape::parse_decimal(
+            &tape[simulated.origin..],
+            args.decimal_radix as usize,
+            args.decimal_digits.map(|d| d as usize),
+            args.decimal_start as usize,
+            args.decimal_stride as usize,
+        )
+    };
 
-    if !args.hide_tape {
-        let terminal_width = if let Some(width) = args.terminal_width {
-            width as usize
-        } else if let Ok((width, _)) = termion::terminal_size() {
-            width as usize
-        } else {
-            80
-        };
+    match args.output {
+        Some(output::Value::Tape) => {
+            let symbols: Vec<_> = tape.iter().map(|symbol| tape::literal(symbol)).collect();
+            println!("{}", symbols.join(" "));
+        }
+        Some(output::Value::Decimal) => println!("{}", decimal()),
+        Some(output::Value::Moves) => println!("{}", simulated.moves),
+        None => {}
+    }
+
+    if args.format == output::Format::Json {
+        println!(
+            "{}",
+            json!({
+                "ok": true,
+                "tape": (!args.hide_tape).then_some(&tape),
+                "origin": simulated.origin,
+                "head": simulated.head(),
+                "state": state_name(&compiled, simulated.final_address),
+                "halt_reason": simulated.halt_reason.to_string(),
+                "moves": simulated.moves,
+                "decimal": (!args.hide_decimal).then(|| decimal().to_string()),
+                "compile_time": compile_time.as_secs_f64(),
+                "execution_time": exec_time.as_secs_f64(),
+            })
+        );
+    }
 
-        if args.no_color {
+    if !args.hide_tape && !output::quiet() {
+        if !output::color() {
             println!("final tape:");
         } else {
             println!(
@@ -168,18 +2274,14 @@ fn do_it(args: Arguments) -> Result<(), error::Error> {
             );
         }
 
-        tape::dump(&tape, terminal_width);
+        // only mark the first cell of the initial tape if there's anything left of it
+        let origin = (simulated.origin > 0).then_some(simulated.origin);
+        tape::dump(&tape, terminal_width, origin);
     }
 
-    if !args.hide_decimal {
-        let decimal = tape::parse_decimal(
-            &tape,
-            args.decimal_radix as usize,
-            args.decimal_digits.map(|d| d as usize),
-            args.decimal_start as usize,
-            args.decimal_stride as usize,
-        );
-        if args.no_color {
+    if !args.hide_decimal && !output::quiet() {
+        let decimal = decimal();
+        if !output::color() {
             println!("decimal: {decimal}\n");
         } else {
             println!(
@@ -192,9 +2294,20 @@ fn do_it(args: Arguments) -> Result<(), error::Error> {
         }
     }
 
-    if args.no_color {
+    if output::quiet() {
+        // `--output` and `--format json` printed all there is, and `--quiet`
+        // prints nothing
+    } else if !output::color() {
         println!("number of moves: {}", simulated.moves);
-        println!("final head position: {}\n", simulated.head_position);
+        println!("final head position: {}", simulated.head());
+        println!(
+            "final state: {}",
+            compiled.describe_state(&simulated.final_state)
+        );
+        if args.optimize.verbose {
+            println!("final address: {:#010x}", simulated.final_address);
+        }
+        println!("halt reason: {}\n", simulated.halt_reason);
     } else {
         println!(
             "{}{}number of moves:{}{} {}",
@@ -205,14 +2318,222 @@ fn do_it(args: Arguments) -> Result<(), error::Error> {
             simulated.moves
         );
         println!(
-            "{}{}final head position:{}{} {}\n",
+            "{}{}final head position:{}{} {}",
+            style::Bold,
+            color::Fg(color::Green),
+            style::Reset,
+            color::Fg(color::Reset),
+            simulated.head()
+        );
+        println!(
+            "{}{}final state:{}{} {}",
+            style::Bold,
+            color::Fg(color::Green),
+            style::Reset,
+            color::Fg(color::Reset),
+            compiled.describe_state(&simulated.final_state)
+        );
+        if args.optimize.verbose {
+            println!(
+                "{}{}final address:{}{} {:#010x}",
+                style::Bold,
+                color::Fg(color::Green),
+                style::Reset,
+                color::Fg(color::Reset),
+                simulated.final_address
+            );
+        }
+        println!(
+            "{}{}halt reason:{}{} {}\n",
             style::Bold,
             color::Fg(color::Green),
             style::Reset,
             color::Fg(color::Reset),
-            simulated.head_position
+            simulated.halt_reason
         );
     }
 
+    if args.stats {
+        stats::print(&simulated, &compiled.symbols);
+    }
+
+    if let Some(coverage) = &coverage {
+        coverage.print(&compiled);
+    }
+
+    if let Some(profile) = &profile {
+        profile.print();
+    }
+
+    if let Some(vm_stats) = &vm_stats {
+        vm_stats.print();
+    }
+
+    if let Some(steps) = steps.filter(|_| !output::quiet()) {
+        metrics::metric("simulated moves", simulated.moves);
+        metrics::metric("actual steps", steps);
+        println!();
+    }
+
+    if let (Some(path), Some(certificate)) = (&args.certificate, certificate) {
+        match simulated.halt_reason {
+            backend::HaltReason::MaxMoves => {
+                return Err(error::Error::new(
+                    format!(
+                    "the machine didn't halt within {max_moves} moves, so there's no certificate"
+                ),
+                    None,
+                )
+                .into())
+            }
+            backend::HaltReason::Interrupted => {
+                return Err(error::Error::new(
+                    "the run was interrupted, so there's no certificate".to_string(),
+                    None,
+                )
+                .into())
+            }
+            backend::HaltReason::Cycle { .. } => {
+                return Err(error::Error::new(
+                    "the machine never halts, so there's no certificate".to_string(),
+                    None,
+                )
+                .into())
+            }
+            _ => {}
+        }
+        certificate::write(&certificate, path)?;
+    }
+
+    // after everything else, so the diff is the last thing printed
+    let mut matched = true;
+    if let (Some(path), Some(expected)) = (&args.expect_tape, &expected_tape) {
+        let expected: Vec<_> = expected
+            .iter()
+            .map(|symbol| symbol.symbol.as_str())
+            .collect();
+        matched &= expect::tape(path, &expected, &tape, simulated.origin);
+    }
+    if let Some(expected) = &args.expect_decimal {
+        matched &= expect::decimal(expected, &decimal().to_string());
+    }
+    if !matched {
+        return Ok(MISMATCH);
+    }
+
+    let accepted = args.accept_state.is_empty()
+        || args
+            .accept_state
+            .contains(&state_name(&compiled, simulated.final_address));
+    if simulated.halt_reason == backend::HaltReason::Halted && !accepted {
+        return Ok(REJECTED);
+    }
+    Ok(simulated.halt_reason.exit_code())
+}
+
+fn report_progress(compiled: &compile::Compiled, checkpoint: backend::Checkpoint) {
+    let state = state_name(compiled, checkpoint.address);
+    eprintln!(
+        "{} moves, head at {}, in state {state}",
+        checkpoint.moves, checkpoint.head
+    );
+}
+
+// every `--accept-state` has to name a state the machine has
+fn check_accept_states(compiled: &compile::Compiled, names: &[String]) -> Result<(), error::Error> {
+    let mut states: Vec<_> = compiled
+        .states
+        .values()
+        .map(|info| info.name.as_str())
+        .collect();
+    states.sort();
+    states.dedup();
+    for name in names {
+        if name != "!" && states.binary_search(&name.as_str()).is_err() {
+            return Err(error::Error::new(
+                format!("`--accept-state`: there's no state named `{name}`"),
+                None,
+            ));
+        }
+    }
+    Ok(())
+}
+
+// the name of the state at `address`, or `!` once the machine has halted
+fn state_name(compiled: &compile::Compiled, address: u32) -> String {
+    match compiled.states.get(&address) {
+        Some(info) => info.name.clone(),
+        None if address == bytecode::HALT_ADDRESS => "!".to_string(),
+        None => format!("{address:#010x}"),
+    }
+}
+
+#[cfg(feature = "c-vm")]
+fn compare_vms(
+    compiled: &compile::Compiled,
+    options: &backend::Options,
+    every: Option<usize>,
+) -> Result<backend::Simulated, error::Error> {
+    tml::compare::run(compiled, options, every)
+}
+
+#[cfg(not(feature = "c-vm"))]
+fn compare_vms(
+    _compiled: &compile::Compiled,
+    _options: &backend::Options,
+    _every: Option<usize>,
+) -> Result<backend::Simulated, error::Error> {
+    Err(needs_c_vm("--compare-vms"))
+}
+
+fn needs_c_vm(flag: &str) -> error::Error {
+    error::Error::new(
+        format!("`{flag}` needs tml to be built with the `c-vm` feature"),
+        None,
+    )
+    .with_help(Some(
+        "rebuild it with a C compiler and without `--no-default-features`".to_string(),
+    ))
+}
+
+#[cfg(feature = "jit")]
+fn jit(
+    compiled: &compile::Compiled,
+    options: &backend::Options,
+) -> Result<backend::Simulated, error::Error> {
+    tml::jit::JitVm.run(&compiled.bytes, &compiled.tape, options)
+}
+
+#[cfg(not(feature = "jit"))]
+fn jit(
+    _compiled: &compile::Compiled,
+    _options: &backend::Options,
+) -> Result<backend::Simulated, error::Error> {
+    Err(error::Error::new(
+        "`--jit` needs tml to be built with the `jit` feature".to_string(),
+        None,
+    )
+    .with_help(Some(
+        "rebuild it with `cargo build --release --features jit`".to_string(),
+    )))
+}
+
+fn verify_certificate(args: VerifyCertificateArguments) -> Result<(), error::Error> {
+    let certificate = certificate::read(&args.certificate)?;
+    let unit = args.source.parse(args.file)?;
+    let tape = args.source.parse_tape(args.tape)?;
+    let compiled = args.source.compile(unit, tape)?;
+    certificate::verify(&certificate, &compiled)?;
+
+    let plural = if certificate.checkpoints.len() == 1 {
+        ""
+    } else {
+        "s"
+    };
+    println!(
+        "ok, halts after {} moves ({} checkpoint{plural})",
+        certificate.moves,
+        certificate.checkpoints.len()
+    );
     Ok(())
 }
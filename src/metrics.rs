@@ -0,0 +1,65 @@
+use std::cmp;
+
+use termion::{color, style};
+
+use crate::compile::Compiled;
+use crate::output;
+use crate::parse::{State, ToState};
+
+pub fn print(unit: &[State], compiled: &Compiled) {
+    let states = unit.len();
+    let arms: usize = unit.iter().map(|state| state.arms.len()).sum();
+    let average_arms = if states == 0 {
+        0.0
+    } else {
+        arms as f64 / states as f64
+    };
+
+    let parameterized = unit
+        .iter()
+        .filter(|state| !state.state_params.is_empty() || !state.symbol_params.is_empty())
+        .count();
+    let state_params: usize = unit.iter().map(|state| state.state_params.len()).sum();
+    let symbol_params: usize = unit.iter().map(|state| state.symbol_params.len()).sum();
+
+    let nesting_depth = unit
+        .iter()
+        .flat_map(|state| &state.arms)
+        .map(|arm| nesting_depth(&arm.to_state))
+        .max()
+        .unwrap_or(0);
+
+    metric("states", states);
+    metric("arms", arms);
+    metric("average arms per state", format!("{average_arms:.2}"));
+    metric("parameterized states", parameterized);
+    metric("state parameters", state_params);
+    metric("symbol parameters", symbol_params);
+    metric("max closure nesting depth", nesting_depth);
+    metric("unique symbols", compiled.symbols.len());
+    metric("bytecode size", compiled.bytes.len());
+}
+
+fn nesting_depth(to_state: &ToState) -> usize {
+    match to_state {
+        ToState::State { state_args, .. } => state_args
+            .iter()
+            .map(|arg| nesting_depth(arg) + 1)
+            .fold(0, cmp::max),
+        ToState::Halt { .. } => 0,
+    }
+}
+
+pub fn metric(label: &str, value: impl std::fmt::Display) {
+    if !output::color() {
+        println!("{label}: {value}");
+    } else {
+        println!(
+            "{}{}{label}:{}{} {value}",
+            style::Bold,
+            color::Fg(color::Green),
+            style::Reset,
+            color::Fg(color::Reset)
+        );
+    }
+}
@@ -0,0 +1,282 @@
+use std::fs::{self, File};
+use std::ops::ControlFlow;
+use std::path::Path;
+
+use memmap2::{MmapMut, MmapOptions};
+
+use crate::backend::{HaltReason, Simulated};
+use crate::error::Error;
+use crate::vm::Tape;
+
+// cells in each chunk of the file that gets mapped at once (4 MiB)
+const CHUNK_CELLS: usize = 1 << 20;
+const CHUNK_BYTES: usize = CHUNK_CELLS * 4;
+
+/// A tape kept in a file that's mapped into memory a chunk at a time, so a
+/// tape bigger than RAM pages out to disk instead of running out of memory.
+/// The file grows a chunk at a time as cells further out get written (moving
+/// the head onto blank cells doesn't grow it), and it keeps the same
+/// statistics as `vm::DenseTape`, stopping on the same moves
+pub struct MmapTape {
+    // `None` for a copy made by `clone`, which keeps its chunks in memory
+    file: Option<File>,
+    // where the next chunk goes in the file
+    file_len: u64,
+    // the chunks from cell 0 rightwards, and from cell -1 leftwards. past them,
+    // the tape is blank
+    right: Vec<MmapMut>,
+    left: Vec<MmapMut>,
+    // counting from the initial tape's first cell, like `Simulated::head`
+    position: i64,
+    rightmost: i64,
+    // see `Simulated`
+    leftmost: i64,
+    left_edge_contacts: usize,
+    two_sided: bool,
+    max_cells: usize,
+    initial_len: usize,
+}
+
+impl MmapTape {
+    /// Makes a tape in a new file at `path` (replacing whatever is there) with
+    /// the initial tape on it. The file is removed as soon as it's open, so it
+    /// doesn't outlive the run, even if the run is killed
+    pub fn new(
+        path: &Path,
+        tape: &[u32],
+        two_sided: bool,
+        max_cells: usize,
+    ) -> Result<Self, Error> {
+        let error = |err| {
+            Error::new(
+                format!("couldn't map tape file {}: {err}", path.display()),
+                None,
+            )
+        };
+        let file = File::options()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(path)
+            .map_err(error)?;
+        fs::remove_file(path).map_err(error)?;
+        let mut mapped = MmapTape {
+            file: Some(file),
+            file_len: 0,
+            right: Vec::new(),
+            left: Vec::new(),
+            position: 0,
+            rightmost: 0,
+            leftmost: 0,
+            left_edge_contacts: 0,
+            two_sided,
+            max_cells,
+            initial_len: tape.len(),
+        };
+        for (position, &symbol) in tape.iter().enumerate() {
+            if symbol != 0 {
+                mapped.write_at(position as i64, symbol);
+            }
+        }
+        Ok(mapped)
+    }
+
+    /// The number of cells the file has room for
+    pub fn mapped(&self) -> usize {
+        (self.right.len() + self.left.len()) * CHUNK_CELLS
+    }
+
+    fn write_at(&mut self, position: i64, value: u32) {
+        let (right, index) = locate(position);
+        let chunk = index / CHUNK_CELLS;
+        let chunks_len = match right {
+            true => self.right.len(),
+            false => self.left.len(),
+        };
+        if chunk >= chunks_len {
+            // blank cells past the chunks are already blank
+            if value == 0 {
+                return;
+            }
+            for _ in chunks_len..=chunk {
+                let map = self.map_chunk();
+                match right {
+                    true => self.right.push(map),
+                    false => self.left.push(map),
+                }
+            }
+        }
+        let chunks = match right {
+            true => &mut self.right,
+            false => &mut self.left,
+        };
+        let offset = index % CHUNK_CELLS * 4;
+        chunks[chunk][offset..offset + 4].copy_from_slice(&value.to_ne_bytes());
+    }
+
+    // a new chunk of blank cells. the run can't stop with an error from here,
+    // so a file that can't grow is as fatal as running out of memory
+    fn map_chunk(&mut self) -> MmapMut {
+        let map = match &self.file {
+            Some(file) => file
+                .set_len(self.file_len + CHUNK_BYTES as u64)
+                .and_then(|()| {
+                    // SAFETY: nothing else knows about the file, since it was
+                    // removed as soon as it was opened
+                    unsafe {
+                        MmapOptions::new()
+                            .offset(self.file_len)
+                            .len(CHUNK_BYTES)
+                            .map_mut(file)
+                    }
+                }),
+            None => MmapOptions::new().len(CHUNK_BYTES).map_anon(),
+        };
+        self.file_len += CHUNK_BYTES as u64;
+        map.unwrap_or_else(|err| panic!("couldn't grow the memory-mapped tape: {err}"))
+    }
+
+    // like `vm::DenseTape::span`
+    fn span(&self) -> u64 {
+        (self.rightmost + (-self.leftmost).max(0) + 1) as u64
+    }
+
+    // the first and last non-blank cells, if there are any
+    fn non_blank(&self) -> Option<(i64, i64)> {
+        let cells = self.left.len() as i64 * CHUNK_CELLS as i64;
+        let end = self.right.len() as i64 * CHUNK_CELLS as i64;
+        let mut non_blank = (-cells..end).filter(|&position| self.cell(position) != 0);
+        let first = non_blank.next()?;
+        let last = (first..end)
+            .rev()
+            .find(|&position| self.cell(position) != 0)
+            .unwrap_or(first);
+        Some((first, last))
+    }
+}
+
+impl Clone for MmapTape {
+    // copies the chunks into memory, since the file can only be mapped once
+    fn clone(&self) -> Self {
+        let copy = |chunks: &[MmapMut]| {
+            chunks
+                .iter()
+                .map(|chunk| {
+                    let mut map = MmapOptions::new()
+                        .len(CHUNK_BYTES)
+                        .map_anon()
+                        .unwrap_or_else(|err| {
+                            panic!("couldn't copy the memory-mapped tape: {err}")
+                        });
+                    map.copy_from_slice(chunk);
+                    map
+                })
+                .collect()
+        };
+        MmapTape {
+            file: None,
+            file_len: 0,
+            right: copy(&self.right),
+            left: copy(&self.left),
+            ..*self
+        }
+    }
+}
+
+impl Tape for MmapTape {
+    fn read(&self) -> u32 {
+        self.cell(self.position)
+    }
+
+    fn peek(&self) -> u32 {
+        self.cell(self.position + 1)
+    }
+
+    fn write(&mut self, value: u32) {
+        self.write_at(self.position, value);
+    }
+
+    fn left(&mut self, n: usize) -> ControlFlow<HaltReason> {
+        let target = self.position - n as i64;
+        let extended = target < self.leftmost;
+        if extended {
+            self.leftmost = target;
+        }
+        if target <= 0 {
+            self.left_edge_contacts += 1;
+        }
+        if target < 0 && !self.two_sided {
+            self.position = 0;
+            return ControlFlow::Break(HaltReason::LeftEdge);
+        }
+        self.position = target;
+        if extended && self.span() > self.max_cells as u64 {
+            return ControlFlow::Break(HaltReason::TapeLimit);
+        }
+        ControlFlow::Continue(())
+    }
+
+    fn right(&mut self, n: usize) -> ControlFlow<HaltReason> {
+        self.position += n as i64;
+        if self.position > self.rightmost {
+            self.rightmost = self.position;
+            if self.span() > self.max_cells as u64 {
+                return ControlFlow::Break(HaltReason::TapeLimit);
+            }
+        }
+        ControlFlow::Continue(())
+    }
+
+    fn head(&self) -> i64 {
+        self.position
+    }
+
+    fn cell(&self, position: i64) -> u32 {
+        let (right, index) = locate(position);
+        let chunks = match right {
+            true => &self.right,
+            false => &self.left,
+        };
+        let Some(chunk) = chunks.get(index / CHUNK_CELLS) else {
+            return 0;
+        };
+        let offset = index % CHUNK_CELLS * 4;
+        u32::from_ne_bytes(chunk[offset..offset + 4].try_into().unwrap())
+    }
+
+    fn finish(self, final_address: u32, moves: usize, halt_reason: HaltReason) -> Simulated {
+        // the cells from the first non-blank one (or cell 0, or the head, if
+        // they're further left) to the last non-blank one or the end of the
+        // initial tape, like `rle::RleTape`
+        let (first, last) = self.non_blank().unwrap_or((0, -1));
+        let low = first.min(0).min(self.position);
+        let high = (last + 1).max(self.initial_len as i64);
+        let tape: Vec<u32> = (low..high).map(|position| self.cell(position)).collect();
+
+        let origin = -low;
+        Simulated {
+            peak_tape_len: self.mapped().max(tape.len()),
+            cells_allocated: self.mapped(),
+            tape,
+            head_position: (self.position + origin) as usize,
+            rightmost_head_position: (self.rightmost + origin) as usize,
+            origin: origin as usize,
+            leftmost_index: self.leftmost,
+            left_edge_contacts: self.left_edge_contacts,
+            final_address,
+            final_state: vec![final_address, 0, 0],
+            moves,
+            halt_reason,
+        }
+    }
+}
+
+// which chunks a cell is in (the right ones or the left ones) and its index in
+// them
+fn locate(position: i64) -> (bool, usize) {
+    match position >= 0 {
+        true => (true, position as usize),
+        false => (false, (-1 - position) as usize),
+    }
+}
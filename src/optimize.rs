@@ -0,0 +1,1055 @@
+use std::collections::{BTreeSet, HashMap, HashSet};
+
+use crate::bytecode::{self as bc, Operand};
+use crate::compile::{ArmInfo, Compiled, SourceRange, StateInfo};
+use crate::error::Error;
+use crate::pass::{Pass, PassManager};
+use crate::tape;
+
+/// Adds the passes for an optimization level: none at 0, the cleanups that keep
+/// every move the machine makes (`Peephole` and `Fuse`) at 1, and all of them at 2
+pub fn add_passes(passes: &mut PassManager, level: u8) {
+    if level >= 2 {
+        passes
+            .add_bytecode_pass(ConstFold::default())
+            .add_bytecode_pass(JumpThreading { inline: true });
+    }
+    if level >= 1 {
+        passes.add_bytecode_pass(Peephole).add_bytecode_pass(Fuse);
+    }
+}
+
+/// Sends jumps to trampoline states (`_ | | next`) straight to where they end up,
+/// inlines single-arm states when that doesn't make the bytecode bigger and
+/// removes states that can no longer be reached. This changes the number of moves
+/// a machine takes, but not what it does to the tape
+pub struct JumpThreading {
+    pub inline: bool,
+}
+
+impl Pass for JumpThreading {
+    fn name(&self) -> &str {
+        "jump-threading"
+    }
+
+    fn run(&mut self, compiled: &mut Compiled) -> Result<(), Error> {
+        let mut program = Program::decode(compiled);
+        let simple = program.simple_states();
+
+        let trampolines: HashMap<u32, u32> = program
+            .states
+            .iter()
+            .filter(|state| simple.contains(&state.address))
+            .filter_map(|state| match &state.arms[..] {
+                [arm] if arm.is_catchall() => match &arm.body[..] {
+                    [jump] if jump.opcode == bc::FINAL_STATE => {
+                        Some((state.address, jump.operands[0]))
+                    }
+                    _ => None,
+                },
+                _ => None,
+            })
+            .collect();
+        program.for_each_target(|target| *target = resolve(&trampolines, *target));
+
+        if self.inline {
+            while let Some(address) = program
+                .states
+                .iter()
+                .find(|state| simple.contains(&state.address) && program.should_inline(state))
+                .map(|state| state.address)
+            {
+                program.inline(address);
+            }
+        }
+
+        program.remove_unreachable();
+        program.encode(compiled);
+        Ok(())
+    }
+}
+
+/// Gives each state whose symbol arguments are always literals a copy for every
+/// set of arguments it's called with, with the symbols filled in (so `COMPARE_ARG`
+/// becomes `COMPARE_VAL` and `WRITE_ARG` becomes `WRITE_VAL`), and sends each call
+/// to its copy. States that would need more than `MAX_COPIES` copies are left alone
+#[derive(Default)]
+pub struct ConstFold {
+    specialized: usize,
+    copies: usize,
+}
+
+const MAX_COPIES: usize = 16;
+
+impl Pass for ConstFold {
+    fn name(&self) -> &str {
+        "const-fold"
+    }
+
+    fn run(&mut self, compiled: &mut Compiled) -> Result<(), Error> {
+        let mut program = Program::decode(compiled);
+        let mut params: HashMap<u32, (u16, u16)> = HashMap::new();
+        for (signature, &address) in &compiled.signatures {
+            params.insert(address, (signature.states, signature.symbols));
+        }
+        let mut candidates: HashSet<u32> = params
+            .iter()
+            .filter(|&(_, &(_, symbols))| symbols > 0)
+            .map(|(&address, _)| address)
+            .collect();
+
+        let instances = loop {
+            program.known_arguments(&mut candidates);
+            match program.instances(&candidates) {
+                Ok(instances) => break instances,
+                Err(address) => {
+                    candidates.remove(&address);
+                }
+            }
+        };
+        let specialized: HashSet<u32> = instances.iter().map(|&(address, _)| address).collect();
+        self.specialized = specialized.len();
+        self.copies = instances.len();
+        if candidates.is_empty() {
+            return Ok(());
+        }
+
+        // the copies (and their arms) need addresses that no old code has
+        let mut fresh = compiled.bytes.len() as u32..;
+        let copies: HashMap<(u32, Vec<u32>), u32> = instances
+            .iter()
+            .map(|instance| (instance.clone(), fresh.next().unwrap()))
+            .collect();
+        let originals: HashMap<u32, &State> = program
+            .states
+            .iter()
+            .map(|state| (state.address, state))
+            .collect();
+        let mut new_states = Vec::new();
+        for (address, consts) in &instances {
+            let original = originals[address];
+            let copy = copies[&(*address, consts.clone())];
+            let mut arms = Vec::new();
+            for arm in &original.arms {
+                let mut new = Arm {
+                    address: fresh.next().unwrap(),
+                    body_address: fresh.next().unwrap(),
+                    pattern: arm.pattern.iter().map(|i| substitute(i, consts)).collect(),
+                    body: arm.body.iter().map(|i| substitute(i, consts)).collect(),
+                };
+                retarget(&mut new.body, &candidates, &copies);
+                copy_debug_info(compiled, arm, &new, copy);
+                arms.push(new);
+            }
+            let info = &compiled.states[address];
+            let info = StateInfo {
+                name: copy_name(&info.name, params[address].0, consts, &compiled.symbols),
+                doc: info.doc.clone(),
+            };
+            compiled.states.insert(copy, info);
+            new_states.push(State {
+                address: copy,
+                arms,
+            });
+        }
+
+        program
+            .states
+            .retain(|state| !candidates.contains(&state.address));
+        for code in program.code_mut() {
+            retarget(code, &candidates, &copies);
+        }
+        program.states.extend(new_states);
+        program.encode(compiled);
+        Ok(())
+    }
+
+    fn summary(&self) -> Option<String> {
+        if self.specialized == 0 {
+            return None;
+        }
+        let s = if self.specialized == 1 { "" } else { "s" };
+        let copies = if self.copies == 1 { "copy" } else { "copies" };
+        Some(format!(
+            "{} state{s} specialized into {} {copies}",
+            self.specialized, self.copies
+        ))
+    }
+}
+
+// fills in a copy's symbol arguments
+fn substitute(instruction: &Instruction, consts: &[u32]) -> Instruction {
+    let opcode = match bc::narrow(instruction.opcode) {
+        bc::COMPARE_ARG => bc::COMPARE_VAL,
+        bc::PEEK_ARG => bc::PEEK_VAL,
+        bc::WRITE_ARG => bc::WRITE_VAL,
+        bc::WRITE_ARG_LEFT => bc::WRITE_VAL_LEFT,
+        bc::WRITE_ARG_RIGHT => bc::WRITE_VAL_RIGHT,
+        bc::SYMBOL_ARG => bc::SYMBOL_VAL,
+        _ => return instruction.clone(),
+    };
+    let mut operands = instruction.operands.clone();
+    operands[0] = consts[operands[0] as usize];
+    Instruction { opcode, operands }
+}
+
+// sends each call to a specialized state to the copy for its arguments and drops
+// the instructions that pushed them
+fn retarget(
+    code: &mut Vec<Instruction>,
+    candidates: &HashSet<u32>,
+    copies: &HashMap<(u32, Vec<u32>), u32>,
+) {
+    let mut dropped = HashSet::new();
+    for call in calls(code) {
+        let address = code[call.jump].target().unwrap();
+        if !candidates.contains(&address) {
+            continue;
+        }
+        let consts = call.symbols.iter().map(|&i| code[i].operands[0]).collect();
+        *code[call.jump].target_mut().unwrap() = copies[&(address, consts)];
+        dropped.extend(call.symbols);
+    }
+    let mut i = 0;
+    code.retain(|_| {
+        i += 1;
+        !dropped.contains(&(i - 1))
+    });
+}
+
+// a copy's arms came from the same source as the original's
+fn copy_debug_info(compiled: &mut Compiled, arm: &Arm, new: &Arm, state: u32) {
+    let infos: Vec<_> = compiled
+        .arms
+        .iter()
+        .filter(|info| info.offset == arm.body_address)
+        .map(|info| ArmInfo {
+            offset: new.body_address,
+            state,
+            span: info.span.clone(),
+        })
+        .collect();
+    compiled.arms.extend(infos);
+    let ranges: Vec<_> = compiled
+        .source_map
+        .iter()
+        .filter(|range| range.start == arm.address)
+        .map(|range| SourceRange {
+            start: new.address,
+            end: new.address,
+            state: range.state,
+            arm: range.arm,
+            span: range.span.clone(),
+        })
+        .collect();
+    compiled.source_map.extend(ranges);
+}
+
+// like `f(_; 'a', 'b')`
+fn copy_name(name: &str, states: u16, consts: &[u32], symbols: &[String]) -> String {
+    let states = vec!["_"; states as usize].join(", ");
+    let consts: Vec<_> = consts
+        .iter()
+        .map(|&symbol| tape::literal(&symbols[symbol as usize]))
+        .collect();
+    format!("{name}({states}; {})", consts.join(", "))
+}
+
+/// Merges states that compile to the same code (jumping to states that are the
+/// same themselves) and sends every jump to the one that's kept. Only states with
+/// the same number of state and symbol parameters are merged
+#[derive(Default)]
+pub struct DedupStates {
+    merged: usize,
+}
+
+impl Pass for DedupStates {
+    fn name(&self) -> &str {
+        "dedup-states"
+    }
+
+    fn run(&mut self, compiled: &mut Compiled) -> Result<(), Error> {
+        let mut program = Program::decode(compiled);
+        let kept = program.duplicates(compiled);
+        self.merged = kept.len();
+        if kept.is_empty() {
+            return Ok(());
+        }
+
+        program.for_each_target(|target| {
+            if let Some(&to) = kept.get(target) {
+                *target = to;
+            }
+        });
+        program
+            .states
+            .retain(|state| !kept.contains_key(&state.address));
+        // the merged states' names now refer to the state that's kept
+        for address in compiled.signatures.values_mut() {
+            if let Some(&to) = kept.get(address) {
+                *address = to;
+            }
+        }
+        compiled
+            .states
+            .retain(|address, _| !kept.contains_key(address));
+        program.encode(compiled);
+        Ok(())
+    }
+
+    fn summary(&self) -> Option<String> {
+        let s = if self.merged == 1 { "" } else { "s" };
+        Some(format!("{} state{s} merged", self.merged))
+    }
+}
+
+/// Cleans up each right hand side: drops writes that are overwritten before the
+/// head moves and writes of the symbol the arm just matched, and merges moves in
+/// the same direction. Moves in opposite directions aren't merged because the
+/// head's furthest position decides how much of the tape is printed
+pub struct Peephole;
+
+impl Pass for Peephole {
+    fn name(&self) -> &str {
+        "peephole"
+    }
+
+    fn run(&mut self, compiled: &mut Compiled) -> Result<(), Error> {
+        let mut program = Program::decode(compiled);
+        for arm in program.states.iter_mut().flat_map(|state| &mut state.arms) {
+            arm.body = peephole(&arm.body, arm.matched());
+        }
+        program.entry = peephole(&program.entry, None);
+        program.encode(compiled);
+        Ok(())
+    }
+}
+
+/// Fuses each write that's followed by a move of one cell into a single
+/// instruction, so the VM only dispatches once
+pub struct Fuse;
+
+impl Pass for Fuse {
+    fn name(&self) -> &str {
+        "fuse"
+    }
+
+    fn run(&mut self, compiled: &mut Compiled) -> Result<(), Error> {
+        let mut program = Program::decode(compiled);
+        for code in program.code_mut() {
+            *code = fuse(code);
+        }
+        program.encode(compiled);
+        Ok(())
+    }
+}
+
+fn fuse(code: &[Instruction]) -> Vec<Instruction> {
+    let mut out = Vec::new();
+    let mut code = code.iter().peekable();
+    while let Some(instruction) = code.next() {
+        let fused = code
+            .peek()
+            .and_then(|next| bc::fused(instruction.opcode, next.opcode));
+        match fused {
+            Some(opcode) => {
+                code.next();
+                out.push(Instruction {
+                    opcode,
+                    operands: instruction.operands.clone(),
+                });
+            }
+            None => out.push(instruction.clone()),
+        }
+    }
+    out
+}
+
+// `current` is a write that wouldn't change the symbol under the head
+fn peephole(code: &[Instruction], mut current: Option<Instruction>) -> Vec<Instruction> {
+    let mut out: Vec<Instruction> = Vec::new();
+    // the last write since the head moved, which is dead if there's another
+    let mut last_write = None;
+    for instruction in code {
+        match instruction.opcode {
+            bc::WRITE_ARG | bc::WRITE_VAL | bc::WRITE_BOUND => {
+                if current.as_ref() == Some(instruction) {
+                    continue;
+                }
+                if let Some(i) = last_write {
+                    out.remove(i);
+                }
+                last_write = Some(out.len());
+                current = Some(instruction.clone());
+            }
+            bc::LEFT | bc::LEFT_N | bc::LEFT_N16 | bc::RIGHT | bc::RIGHT_N | bc::RIGHT_N16 => {
+                last_write = None;
+                current = None;
+                if let Some(distance) = out.last().and_then(|last| merge(last, instruction)) {
+                    out.pop();
+                    out.extend(moves(distance));
+                    continue;
+                }
+            }
+            // already fused, so the write can't be dropped or the move merged
+            bc::WRITE_ARG_LEFT..=bc::WRITE_BOUND_RIGHT => {
+                last_write = None;
+                current = None;
+            }
+            _ => {}
+        }
+        out.push(instruction.clone());
+    }
+    out
+}
+
+// how far two moves in the same direction go together (negative for left)
+fn merge(first: &Instruction, second: &Instruction) -> Option<isize> {
+    let distance = |instruction: &Instruction| match instruction.opcode {
+        bc::LEFT => Some(-1),
+        bc::RIGHT => Some(1),
+        bc::LEFT_N | bc::LEFT_N16 => Some(-(instruction.operands[0] as isize)),
+        bc::RIGHT_N | bc::RIGHT_N16 => Some(instruction.operands[0] as isize),
+        _ => None,
+    };
+    let (first, second) = (distance(first)?, distance(second)?);
+    let full = first.abs() == u16::MAX as isize;
+    (first.signum() == second.signum() && !full).then_some(first + second)
+}
+
+// the shortest way to move `distance` cells (negative for left)
+fn moves(distance: isize) -> Vec<Instruction> {
+    let (one, short, long) = if distance < 0 {
+        (bc::LEFT, bc::LEFT_N, bc::LEFT_N16)
+    } else {
+        (bc::RIGHT, bc::RIGHT_N, bc::RIGHT_N16)
+    };
+    let mut left = distance.unsigned_abs();
+    let mut moves = Vec::new();
+    while left > 0 {
+        let step = left.min(u16::MAX as usize);
+        moves.push(match step {
+            1 => Instruction {
+                opcode: one,
+                operands: Vec::new(),
+            },
+            2..=255 => Instruction {
+                opcode: short,
+                operands: vec![step as u32],
+            },
+            _ => Instruction {
+                opcode: long,
+                operands: vec![step as u32],
+            },
+        });
+        left -= step;
+    }
+    moves
+}
+
+// a jump to a state (`MAKE_STATE` or `FINAL_STATE`) and what it passes
+struct Call {
+    // index of the jump
+    jump: usize,
+    // indices of the instructions that push its symbol arguments
+    symbols: Vec<usize>,
+    // how many state arguments it takes
+    states: usize,
+}
+
+fn calls(code: &[Instruction]) -> Vec<Call> {
+    let mut calls = Vec::new();
+    let mut symbols = Vec::new();
+    let mut depth = 0;
+    for (i, instruction) in code.iter().enumerate() {
+        match instruction.opcode {
+            bc::SYMBOL_ARG | bc::SYMBOL_VAL | bc::SYMBOL_BOUND => symbols.push(i),
+            bc::TAKE_ARG | bc::CLONE_ARG => depth += 1,
+            bc::MAKE_STATE => {
+                let states = instruction.operands[0] as usize;
+                calls.push(Call {
+                    jump: i,
+                    symbols: std::mem::take(&mut symbols),
+                    states,
+                });
+                depth = depth - states + 1;
+            }
+            bc::FINAL_STATE => calls.push(Call {
+                jump: i,
+                symbols: std::mem::take(&mut symbols),
+                states: depth,
+            }),
+            _ => {}
+        }
+    }
+    calls
+}
+
+// follows a chain of trampolines, stopping if it goes around in a circle
+fn resolve(trampolines: &HashMap<u32, u32>, mut address: u32) -> u32 {
+    let mut seen = HashSet::new();
+    while let Some(&next) = trampolines.get(&address) {
+        if !seen.insert(address) {
+            break;
+        }
+        address = next;
+    }
+    address
+}
+
+/// Bytecode decoded into states, arms and instructions, so a pass can add and
+/// remove code without keeping track of addresses and skips
+struct Program {
+    version: bc::Version,
+    states: Vec<State>,
+    entry: Vec<Instruction>,
+}
+
+struct State {
+    // where the state was before the pass
+    address: u32,
+    arms: Vec<Arm>,
+}
+
+struct Arm {
+    // where the arm and its right hand side were before the pass
+    address: u32,
+    body_address: u32,
+    pattern: Vec<Instruction>,
+    body: Vec<Instruction>,
+}
+
+#[derive(Clone, PartialEq, Eq, Hash)]
+struct Instruction {
+    opcode: u8,
+    // in the order of `bc::operands`, with addresses from before the pass
+    operands: Vec<u32>,
+}
+
+impl Instruction {
+    fn size(&self, version: bc::Version) -> usize {
+        let operands = bc::operands(self.opcode).unwrap();
+        1 + operands
+            .iter()
+            .map(|operand| operand.size(version))
+            .sum::<usize>()
+    }
+
+    // the address of the state a `MAKE_STATE` or `FINAL_STATE` refers to
+    fn target(&self) -> Option<u32> {
+        let operands = bc::operands(self.opcode).unwrap();
+        let i = operands
+            .iter()
+            .position(|&operand| operand == Operand::Address)?;
+        Some(self.operands[i])
+    }
+
+    fn target_mut(&mut self) -> Option<&mut u32> {
+        let operands = bc::operands(self.opcode).unwrap();
+        let i = operands
+            .iter()
+            .position(|&operand| operand == Operand::Address)?;
+        Some(&mut self.operands[i])
+    }
+}
+
+impl Arm {
+    fn is_catchall(&self) -> bool {
+        matches!(&self.pattern[..], [other] if other.opcode == bc::OTHER)
+    }
+
+    fn size(&self, version: bc::Version) -> usize {
+        self.pattern
+            .iter()
+            .chain(&self.body)
+            .map(|instruction| instruction.size(version))
+            .sum()
+    }
+
+    // uses 16-bit skips in the arm's patterns if they can jump past it, and
+    // 32-bit skips if they can't
+    fn fit_skips(&mut self, version: bc::Version) {
+        for instruction in &mut self.pattern {
+            instruction.opcode = bc::narrow(instruction.opcode);
+        }
+        if self.size(version) > u16::MAX as usize {
+            for instruction in &mut self.pattern {
+                instruction.opcode = bc::wide(instruction.opcode).unwrap_or(instruction.opcode);
+            }
+        }
+    }
+
+    // a write that puts back the symbol the arm matched
+    fn matched(&self) -> Option<Instruction> {
+        let last = self.pattern.last()?;
+        let opcode = match bc::narrow(last.opcode) {
+            bc::COMPARE_ARG => bc::WRITE_ARG,
+            bc::COMPARE_VAL => bc::WRITE_VAL,
+            bc::OTHER => bc::WRITE_BOUND,
+            _ => return None,
+        };
+        Some(Instruction {
+            opcode,
+            operands: last.operands.iter().copied().take(1).collect(),
+        })
+    }
+}
+
+impl Program {
+    fn decode(compiled: &Compiled) -> Self {
+        let bytes = &compiled.bytes;
+        let version = bc::Version::of(bytes);
+        let entry = u32::from_le_bytes(bytes[2..6].try_into().unwrap());
+        let starts: BTreeSet<u32> = compiled
+            .states
+            .keys()
+            .chain(compiled.signatures.values())
+            .copied()
+            .collect();
+
+        let mut states: Vec<State> = Vec::new();
+        let mut ip = version.code_start() as usize;
+        let mut after_peek = false;
+        while ip < entry as usize {
+            let address = ip as u32;
+            if starts.contains(&address) {
+                states.push(State {
+                    address,
+                    arms: Vec::new(),
+                });
+            }
+            let (instruction, next) = decode_instruction(bytes, ip, version);
+            let arms = &mut states.last_mut().expect("invalid bytecode").arms;
+            if is_pattern(instruction.opcode) && !after_peek {
+                arms.push(Arm {
+                    address,
+                    body_address: address,
+                    pattern: Vec::new(),
+                    body: Vec::new(),
+                });
+            }
+            after_peek = matches!(bc::narrow(instruction.opcode), bc::PEEK_ARG | bc::PEEK_VAL);
+            let arm = arms.last_mut().expect("invalid bytecode");
+            if is_pattern(instruction.opcode) {
+                arm.pattern.push(instruction);
+                arm.body_address = next as u32;
+            } else {
+                arm.body.push(instruction);
+            }
+            ip = next;
+        }
+
+        let mut code = Vec::new();
+        while ip < bytes.len() {
+            let (instruction, next) = decode_instruction(bytes, ip, version);
+            code.push(instruction);
+            ip = next;
+        }
+        Program {
+            version,
+            states,
+            entry: code,
+        }
+    }
+
+    // every right hand side, with the state it's in (`None` for the entry)
+    fn code(&self) -> impl Iterator<Item = (Option<u32>, &Vec<Instruction>)> {
+        let arms = self.states.iter().flat_map(|state| {
+            state
+                .arms
+                .iter()
+                .map(|arm| (Some(state.address), &arm.body))
+        });
+        arms.chain([(None, &self.entry)])
+    }
+
+    // every right hand side, and the entry
+    fn code_mut(&mut self) -> impl Iterator<Item = &mut Vec<Instruction>> {
+        let arms = self.states.iter_mut().flat_map(|state| &mut state.arms);
+        arms.map(|arm| &mut arm.body).chain([&mut self.entry])
+    }
+
+    fn for_each_target(&mut self, mut f: impl FnMut(&mut u32)) {
+        for instruction in self.code_mut().flatten() {
+            if let Some(target) = instruction.target_mut() {
+                f(target);
+            }
+        }
+    }
+
+    // states that are never passed anything, so a jump to one can be replaced by
+    // a jump to somewhere else
+    fn simple_states(&self) -> HashSet<u32> {
+        let mut simple: HashSet<u32> = self.states.iter().map(|state| state.address).collect();
+        for (_, code) in self.code() {
+            for call in calls(code) {
+                if !call.symbols.is_empty() || call.states > 0 {
+                    simple.remove(&code[call.jump].target().unwrap());
+                }
+            }
+        }
+        simple
+    }
+
+    // drops the candidates for `ConstFold` that are called with a symbol that
+    // isn't known before the machine runs: the bound symbol, or an argument of a
+    // state that isn't a candidate itself
+    fn known_arguments(&self, candidates: &mut HashSet<u32>) {
+        loop {
+            let mut unknown = HashSet::new();
+            for (caller, code) in self.code() {
+                let known_args = caller.is_some_and(|caller| candidates.contains(&caller));
+                for call in calls(code) {
+                    let known = call.symbols.iter().all(|&i| match code[i].opcode {
+                        bc::SYMBOL_VAL => true,
+                        bc::SYMBOL_ARG => known_args,
+                        _ => false,
+                    });
+                    if !known {
+                        unknown.insert(code[call.jump].target().unwrap());
+                    }
+                }
+            }
+            let len = candidates.len();
+            candidates.retain(|address| !unknown.contains(address));
+            if candidates.len() == len {
+                return;
+            }
+        }
+    }
+
+    // every candidate and set of symbol arguments it can be called with, or a
+    // candidate that would need too many copies
+    fn instances(&self, candidates: &HashSet<u32>) -> Result<Vec<(u32, Vec<u32>)>, u32> {
+        let states: HashMap<u32, &State> = self
+            .states
+            .iter()
+            .map(|state| (state.address, state))
+            .collect();
+        let mut seen = HashSet::new();
+        let mut instances = Vec::new();
+        let mut counts: HashMap<u32, usize> = HashMap::new();
+        let mut stack = Vec::new();
+        let found = |code: &[Instruction], consts: Option<&[u32]>, stack: &mut Vec<_>| {
+            for call in calls(code) {
+                let address = code[call.jump].target().unwrap();
+                if !candidates.contains(&address) {
+                    continue;
+                }
+                let args: Vec<u32> = call
+                    .symbols
+                    .iter()
+                    .map(|&i| match code[i].opcode {
+                        bc::SYMBOL_ARG => consts.unwrap()[code[i].operands[0] as usize],
+                        _ => code[i].operands[0],
+                    })
+                    .collect();
+                stack.push((address, args));
+            }
+        };
+        for (caller, code) in self.code() {
+            if !caller.is_some_and(|caller| candidates.contains(&caller)) {
+                found(code, None, &mut stack);
+            }
+        }
+        while let Some(instance) = stack.pop() {
+            if !seen.insert(instance.clone()) {
+                continue;
+            }
+            let count = counts.entry(instance.0).or_default();
+            *count += 1;
+            if *count > MAX_COPIES {
+                return Err(instance.0);
+            }
+            for arm in &states[&instance.0].arms {
+                found(&arm.body, Some(&instance.1), &mut stack);
+            }
+            instances.push(instance);
+        }
+        Ok(instances)
+    }
+
+    // inline a state if it has one arm that matches anything, doesn't use the
+    // symbol it matched and can't stop the machine part way through (by moving off
+    // the left edge), and every jump to it can be replaced without the bytecode
+    // growing
+    fn should_inline(&self, state: &State) -> bool {
+        let [arm] = &state.arms[..] else {
+            return false;
+        };
+        let inlinable = arm.is_catchall()
+            && arm.body.iter().all(|instruction| {
+                instruction.target() != Some(state.address)
+                    && !matches!(
+                        instruction.opcode,
+                        bc::WRITE_BOUND
+                            | bc::SYMBOL_BOUND
+                            | bc::LEFT
+                            | bc::LEFT_N
+                            | bc::LEFT_N16
+                            | bc::WRITE_ARG_LEFT
+                            | bc::WRITE_VAL_LEFT
+                            | bc::WRITE_BOUND_LEFT
+                            | bc::WRITE_BOUND_RIGHT
+                    )
+            });
+        if !inlinable {
+            return false;
+        }
+
+        let body_size: usize = arm
+            .body
+            .iter()
+            .map(|instruction| instruction.size(self.version))
+            .sum();
+        let jump_size = 1 + Operand::Address.size(self.version);
+        let mut jumps = 0;
+        for caller in &self.states {
+            for other in &caller.arms {
+                for instruction in &other.body {
+                    if instruction.target() != Some(state.address) {
+                        continue;
+                    }
+                    if instruction.opcode != bc::FINAL_STATE {
+                        return false;
+                    }
+                    jumps += 1;
+                }
+            }
+        }
+        for instruction in &self.entry {
+            if instruction.target() == Some(state.address) {
+                if instruction.opcode != bc::FINAL_STATE {
+                    return false;
+                }
+                jumps += 1;
+            }
+        }
+        jumps > 0 && jumps * body_size <= jumps * jump_size + arm.size(self.version)
+    }
+
+    // replaces every jump to the state with the state's body
+    fn inline(&mut self, address: u32) {
+        let index = self
+            .states
+            .iter()
+            .position(|state| state.address == address)
+            .unwrap();
+        let state = self.states.remove(index);
+        let body = &state.arms[0].body;
+        for code in self.code_mut() {
+            if code
+                .last()
+                .is_some_and(|jump| jump.target() == Some(address))
+            {
+                code.pop();
+                code.extend(body.iter().cloned());
+            }
+        }
+    }
+
+    // maps each state that's the same as an earlier one to that state. states
+    // start out grouped by their parameters, and a group is split by the code of
+    // its states (with each jump replaced by the group it goes to) until no group
+    // splits any further
+    fn duplicates(&self, compiled: &Compiled) -> HashMap<u32, u32> {
+        let params: HashMap<u32, (u16, u16)> = compiled
+            .signatures
+            .iter()
+            .map(|(signature, &address)| (address, (signature.states, signature.symbols)))
+            .collect();
+        let mut groups: HashMap<_, usize> = HashMap::new();
+        let mut group: HashMap<u32, usize> = HashMap::new();
+        for state in &self.states {
+            // a state without a signature is never merged
+            let key = params.get(&state.address).ok_or(state.address);
+            let len = groups.len();
+            group.insert(state.address, *groups.entry(key).or_insert(len));
+        }
+
+        loop {
+            let mut groups: HashMap<(usize, Vec<Instruction>), usize> = HashMap::new();
+            let mut next = HashMap::new();
+            for state in &self.states {
+                let code = state
+                    .arms
+                    .iter()
+                    .flat_map(|arm| arm.pattern.iter().chain(&arm.body))
+                    .map(|instruction| {
+                        let mut instruction = instruction.clone();
+                        if let Some(target) = instruction.target_mut() {
+                            *target = group.get(target).map_or(u32::MAX, |&group| group as u32);
+                        }
+                        instruction
+                    })
+                    .collect();
+                let len = groups.len();
+                let id = *groups.entry((group[&state.address], code)).or_insert(len);
+                next.insert(state.address, id);
+            }
+            let done = groups.len() == group.values().collect::<HashSet<_>>().len();
+            group = next;
+            if done {
+                break;
+            }
+        }
+
+        let mut first = HashMap::new();
+        let mut duplicates = HashMap::new();
+        for state in &self.states {
+            let kept = *first.entry(group[&state.address]).or_insert(state.address);
+            if kept != state.address {
+                duplicates.insert(state.address, kept);
+            }
+        }
+        duplicates
+    }
+
+    fn remove_unreachable(&mut self) {
+        let mut reachable = HashSet::new();
+        let mut stack: Vec<u32> = self.entry.iter().filter_map(Instruction::target).collect();
+        let states: HashMap<u32, &State> = self
+            .states
+            .iter()
+            .map(|state| (state.address, state))
+            .collect();
+        while let Some(address) = stack.pop() {
+            if !reachable.insert(address) {
+                continue;
+            }
+            let Some(state) = states.get(&address) else {
+                continue;
+            };
+            for arm in &state.arms {
+                stack.extend(arm.body.iter().filter_map(Instruction::target));
+            }
+        }
+        self.states
+            .retain(|state| reachable.contains(&state.address));
+    }
+
+    // lays the program out again and moves everything the compiler recorded about
+    // the old addresses over to the new ones
+    fn encode(mut self, compiled: &mut Compiled) {
+        let version = self.version;
+        for arm in self.states.iter_mut().flat_map(|state| &mut state.arms) {
+            arm.fit_skips(version);
+        }
+        let mut addresses = HashMap::from([(bc::HALT_ADDRESS, bc::HALT_ADDRESS)]);
+        let mut address = version.code_start();
+        for state in &self.states {
+            addresses.insert(state.address, address);
+            address += state
+                .arms
+                .iter()
+                .map(|arm| arm.size(version))
+                .sum::<usize>() as u32;
+        }
+        let entry = address;
+
+        let mut bytes = bc::header(version, self.states.len() as u32);
+        bytes[2..6].copy_from_slice(&entry.to_le_bytes());
+        // old address -> new address, for arms and right hand sides
+        let mut moved = HashMap::new();
+        // old start -> new end, for source ranges
+        let mut ends = HashMap::new();
+        for state in &self.states {
+            for arm in &state.arms {
+                let start = bytes.len();
+                let end = start + arm.size(version);
+                moved.insert(arm.address, start as u32);
+                ends.insert(arm.address, end as u32);
+                for instruction in &arm.pattern {
+                    encode_instruction(&mut bytes, instruction, &addresses, end, version);
+                }
+                moved.insert(arm.body_address, bytes.len() as u32);
+                for instruction in &arm.body {
+                    encode_instruction(&mut bytes, instruction, &addresses, end, version);
+                }
+            }
+        }
+        // the entry doesn't have patterns, so nothing skips to its end
+        for instruction in &self.entry {
+            encode_instruction(&mut bytes, instruction, &addresses, 0, version);
+        }
+        compiled.bytes = bytes;
+
+        compiled.states = std::mem::take(&mut compiled.states)
+            .into_iter()
+            .filter_map(|(address, info)| Some((*addresses.get(&address)?, info)))
+            .collect();
+        compiled
+            .signatures
+            .retain(|_, address| match addresses.get(address) {
+                Some(&new) => {
+                    *address = new;
+                    true
+                }
+                None => false,
+            });
+        compiled.arms.retain_mut(
+            |arm| match (moved.get(&arm.offset), addresses.get(&arm.state)) {
+                (Some(&offset), Some(&state)) => {
+                    arm.offset = offset;
+                    arm.state = state;
+                    true
+                }
+                _ => false,
+            },
+        );
+        compiled.source_map.retain_mut(|range| {
+            match (moved.get(&range.start), ends.get(&range.start)) {
+                (Some(&start), Some(&end)) => {
+                    range.start = start;
+                    range.end = end;
+                    true
+                }
+                _ => false,
+            }
+        });
+    }
+}
+
+fn is_pattern(opcode: u8) -> bool {
+    matches!(
+        bc::narrow(opcode),
+        bc::COMPARE_ARG | bc::COMPARE_VAL | bc::OTHER | bc::HALT | bc::PEEK_ARG | bc::PEEK_VAL
+    )
+}
+
+fn decode_instruction(bytes: &[u8], mut ip: usize, version: bc::Version) -> (Instruction, usize) {
+    let opcode = bytes[ip];
+    ip += 1;
+    let mut operands = Vec::new();
+    for operand in bc::operands(opcode).expect("invalid bytecode") {
+        let size = operand.size(version);
+        let value = bytes[ip..ip + size]
+            .iter()
+            .rev()
+            .fold(0, |value, &byte| value << 8 | byte as u32);
+        operands.push(value);
+        ip += size;
+    }
+    (Instruction { opcode, operands }, ip)
+}
+
+// `end` is where the arm ends, which is where a pattern that doesn't match jumps to
+fn encode_instruction(
+    bytes: &mut Vec<u8>,
+    instruction: &Instruction,
+    addresses: &HashMap<u32, u32>,
+    end: usize,
+    version: bc::Version,
+) {
+    bytes.push(instruction.opcode);
+    let operands = bc::operands(instruction.opcode).unwrap();
+    for (&operand, &value) in operands.iter().zip(&instruction.operands) {
+        let size = operand.size(version);
+        let value = match operand {
+            Operand::Skip | Operand::Skip32 => (end - bytes.len() - size) as u32,
+            Operand::Address => addresses[&value],
+            _ => value,
+        };
+        bytes.extend(&value.to_le_bytes()[..size]);
+    }
+}
@@ -0,0 +1,76 @@
+use std::env;
+use std::io::{self, IsTerminal};
+use std::sync::atomic::{AtomicBool, AtomicU8, Ordering};
+
+use clap::ValueEnum;
+
+const UNDECIDED: u8 = 0;
+const ON: u8 = 1;
+const OFF: u8 = 2;
+
+static COLOR: AtomicU8 = AtomicU8::new(UNDECIDED);
+static QUIET: AtomicBool = AtomicBool::new(false);
+
+/// The one value `--output` prints for a run
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum Value {
+    // the final tape's symbols, as a tape file would list them
+    Tape,
+    // the final tape read as a decimal
+    Decimal,
+    // how many moves the machine made
+    Moves,
+}
+
+/// How a run reports its result
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum Format {
+    // labelled lines and a drawing of the tape
+    #[default]
+    Human,
+    // one JSON object
+    Json,
+}
+
+/// Turns color on or off for the rest of the process, whatever the environment
+/// and stdout say
+pub fn set_color(color: bool) {
+    COLOR.store(if color { ON } else { OFF }, Ordering::Relaxed);
+}
+
+/// Whether output should be colored. Unless `set_color` decided it, color is
+/// off when `NO_COLOR` is set, on when `CLICOLOR_FORCE` is set to anything but
+/// `0`, and otherwise on only when stdout is a terminal
+pub fn color() -> bool {
+    match COLOR.load(Ordering::Relaxed) {
+        ON => true,
+        OFF => false,
+        _ => {
+            let color = detect();
+            set_color(color);
+            color
+        }
+    }
+}
+
+// an empty variable counts as unset, as https://no-color.org asks
+fn detect() -> bool {
+    let set = |name| env::var_os(name).is_some_and(|value| !value.is_empty());
+    if set("NO_COLOR") {
+        false
+    } else if set("CLICOLOR_FORCE") {
+        env::var_os("CLICOLOR_FORCE").is_some_and(|value| value != "0")
+    } else {
+        io::stdout().is_terminal()
+    }
+}
+
+/// Stops warnings and other reports from being printed for the rest of the
+/// process, leaving only errors (and whatever the caller prints itself)
+pub fn set_quiet(quiet: bool) {
+    QUIET.store(quiet, Ordering::Relaxed);
+}
+
+pub fn quiet() -> bool {
+    QUIET.load(Ordering::Relaxed)
+}
@@ -1,60 +1,105 @@
 use crate::error::Error;
-use crate::lex::{Span, Token, TokenKind, Tokens};
+use crate::lex::{Edition, Keyword, Span, Token, TokenKind, Tokens};
+use crate::suggest;
 
 #[derive(Clone, Debug)]
-pub struct Name {
-    pub name: &'static str,
-    pub span: Span,
+pub struct Name<'a> {
+    pub name: &'a str,
+    pub span: Span<'a>,
 }
 
 #[derive(Clone, Debug)]
-pub struct Symbol {
+pub struct Symbol<'a> {
     pub symbol: String,
-    pub span: Span,
+    pub span: Span<'a>,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Unit<'a> {
+    pub alphabet: Option<Alphabet<'a>>,
+    pub states: Vec<State<'a>>,
+}
+
+// `alphabet | '0' '1' 'x'` declares every symbol the machine can use (the blank
+// symbol is always included)
+#[derive(Clone, Debug)]
+pub struct Alphabet<'a> {
+    // the `alphabet` name
+    pub span: Span<'a>,
+    pub symbols: Vec<Symbol<'a>>,
 }
 
 #[derive(Clone, Debug)]
-pub struct State {
-    pub name: Name,
-    pub state_params: Vec<Name>,
-    pub symbol_params: Vec<Name>,
-    pub arms: Vec<Arm>,
+pub struct State<'a> {
+    // the first and last tokens of the state, including its doc comment
+    pub first: Span<'a>,
+    pub last: Span<'a>,
+    pub doc: Vec<&'a str>,
+    pub name: Name<'a>,
+    pub state_params: Vec<Name<'a>>,
+    pub symbol_params: Vec<Name<'a>>,
+    pub arms: Vec<Arm<'a>>,
 }
 
 #[derive(Clone, Debug)]
-pub struct Arm {
-    pub pattern: Pattern,
-    pub ops: Vec<Op>,
-    pub to_state: ToState,
+pub struct Arm<'a> {
+    pub pattern: Pattern<'a>,
+    pub lookahead: Option<Pattern<'a>>,
+    pub ops: Vec<Op<'a>>,
+    pub to_state: ToState<'a>,
 }
 
 #[derive(Clone, Debug)]
-pub enum Pattern {
-    Symbol(Symbol),
-    Name(Name),
+pub enum Pattern<'a> {
+    Symbol(Symbol<'a>),
+    Name(Name<'a>),
 }
 
 #[derive(Clone, Debug)]
-pub enum Op {
-    Left(Span),
-    Right(Span),
-    Name(Name),
-    Symbol(Symbol),
+pub enum Op<'a> {
+    Left(Span<'a>),
+    Right(Span<'a>),
+    Name(Name<'a>),
+    Symbol(Symbol<'a>),
 }
 
 #[derive(Clone, Debug)]
-pub enum ToState {
+pub enum ToState<'a> {
     State {
-        name: Name,
-        state_args: Vec<ToState>,
-        symbol_args: Vec<Pattern>,
+        name: Name<'a>,
+        state_args: Vec<ToState<'a>>,
+        symbol_args: Vec<Pattern<'a>>,
     },
     Halt {
-        span: Span,
+        span: Span<'a>,
     },
 }
 
-pub fn parse(mut tokens: Tokens) -> Result<Vec<State>, Error> {
+#[derive(Clone, Debug, Default)]
+pub struct StartArgs<'a> {
+    pub state_args: Vec<ToState<'a>>,
+    pub symbol_args: Vec<Pattern<'a>>,
+}
+
+#[derive(Clone, Debug)]
+pub struct TestCase<'a> {
+    pub name: Name<'a>,
+    pub tape: Vec<Symbol<'a>>,
+    pub expect: Option<Vec<Symbol<'a>>>,
+    pub max_states: Option<usize>,
+    pub max_moves: Option<usize>,
+    pub max_tape_cells: Option<usize>,
+}
+
+const TEST_FIELDS: [&str; 5] = [
+    "tape",
+    "expect",
+    "max_states",
+    "max_moves",
+    "max_tape_cells",
+];
+
+pub fn parse(mut tokens: Tokens) -> Result<Unit, Error> {
     let peek_one = tokens.next()?;
     let peek_two = tokens.next()?;
     let mut parser = Parser {
@@ -66,35 +111,78 @@ pub fn parse(mut tokens: Tokens) -> Result<Vec<State>, Error> {
     parser.unit()
 }
 
+pub fn parse_start_args(mut tokens: Tokens) -> Result<StartArgs, Error> {
+    let peek_one = tokens.next()?;
+    let peek_two = tokens.next()?;
+    let mut parser = Parser {
+        tokens,
+        peek_one,
+        peek_two,
+    };
+
+    parser.start_args()
+}
+
+/// Symbols separated by commas, like `'0', '1'`
+pub fn parse_symbol_list(mut tokens: Tokens) -> Result<Vec<Symbol>, Error> {
+    let peek_one = tokens.next()?;
+    let peek_two = tokens.next()?;
+    let mut parser = Parser {
+        tokens,
+        peek_one,
+        peek_two,
+    };
+
+    parser.symbol_list()
+}
+
+pub fn parse_spec(mut tokens: Tokens) -> Result<Vec<TestCase>, Error> {
+    let peek_one = tokens.next()?;
+    let peek_two = tokens.next()?;
+    let mut parser = Parser {
+        tokens,
+        peek_one,
+        peek_two,
+    };
+
+    parser.spec()
+}
+
 pub fn parse_tape(mut tokens: Tokens) -> Result<Vec<Symbol>, Error> {
     let mut symbols = Vec::new();
+    let mut errors = Vec::new();
     loop {
-        match tokens.next()? {
-            Token {
+        match tokens.next() {
+            Ok(Token {
                 kind: TokenKind::Symbol(symbol),
                 span,
-            } => symbols.push(Symbol { symbol, span }),
-            Token {
+            }) => symbols.push(Symbol { symbol, span }),
+            Ok(Token {
                 kind: TokenKind::Eof,
                 ..
-            } => return Ok(symbols),
-            Token { span, .. } => {
-                return Err(Error::new(
-                    "only symbols are allowed in tape file".to_string(),
-                    Some(span),
-                ))
-            }
+            }) => break,
+            Ok(Token { span, .. }) => errors.push(Error::new(
+                "only symbols are allowed in tape file".to_string(),
+                Some(span),
+            )),
+            Err(error) => errors.push(error),
         }
     }
+
+    if errors.is_empty() {
+        Ok(symbols)
+    } else {
+        Err(Error::combine(errors))
+    }
 }
 
-struct Parser {
-    tokens: Tokens,
-    peek_one: Token,
-    peek_two: Token,
+struct Parser<'a> {
+    tokens: Tokens<'a>,
+    peek_one: Token<'a>,
+    peek_two: Token<'a>,
 }
 
-impl Parser {
+impl<'a> Parser<'a> {
     fn peek(&self) -> &TokenKind {
         &self.peek_one.kind
     }
@@ -103,18 +191,18 @@ impl Parser {
         [&self.peek_one.kind, &self.peek_two.kind]
     }
 
-    fn next(&mut self) -> Result<Token, Error> {
+    fn next(&mut self) -> Result<Token<'a>, Error> {
         let peek_two = self.tokens.next()?;
         let peek_one = std::mem::replace(&mut self.peek_two, peek_two);
         let next = std::mem::replace(&mut self.peek_one, peek_one);
         Ok(next)
     }
 
-    fn peek_span(&mut self) -> Span {
+    fn peek_span(&mut self) -> Span<'a> {
         self.peek_one.span
     }
 
-    fn expect(&mut self, kind: TokenKind) -> Result<Token, Error> {
+    fn expect(&mut self, kind: TokenKind) -> Result<Token<'a>, Error> {
         if self.peek() == &kind {
             self.next()
         } else {
@@ -125,7 +213,19 @@ impl Parser {
         }
     }
 
-    fn name(&mut self) -> Result<Name, Error> {
+    fn name(&mut self) -> Result<Name<'a>, Error> {
+        if let TokenKind::Keyword(keyword) = self.peek() {
+            let msg = if keyword.is_reserved() {
+                format!("`{}` is reserved for future use", keyword.as_str())
+            } else {
+                format!("expected name, found keyword `{}`", keyword.as_str())
+            };
+            let help = format!(
+                "rename it, or use `--edition {}` where it isn't a keyword",
+                Edition::E2023
+            );
+            return Err(Error::new(msg, Some(self.peek_span())).with_help(Some(help)));
+        }
         let token = self.expect(TokenKind::Name)?;
         Ok(Name {
             name: token.span.text,
@@ -133,7 +233,7 @@ impl Parser {
         })
     }
 
-    fn symbol(&mut self) -> Result<Symbol, Error> {
+    fn symbol(&mut self) -> Result<Symbol<'a>, Error> {
         if matches!(self.peek(), TokenKind::Symbol(_)) {
             match self.next()? {
                 Token {
@@ -150,12 +250,122 @@ impl Parser {
         }
     }
 
-    fn unit(&mut self) -> Result<Vec<State>, Error> {
-        let mut unit = Vec::new();
+    fn unit(&mut self) -> Result<Unit<'a>, Error> {
+        let mut unit = Unit::default();
+        let mut errors = Vec::new();
         while self.peek() != &TokenKind::Eof {
-            unit.push(self.state()?);
+            // `alphabet` is only a keyword in newer editions, so older ones
+            // recognize it by the `|` after it
+            let is_alphabet = match self.peek_two() {
+                [TokenKind::Keyword(Keyword::Alphabet), _] => true,
+                [TokenKind::Name, TokenKind::Bar] => self.peek_one.span.text == "alphabet",
+                _ => false,
+            };
+            if is_alphabet {
+                match self.alphabet(&unit) {
+                    Ok(alphabet) => unit.alphabet = Some(alphabet),
+                    Err(error) => errors.push(error),
+                }
+                continue;
+            }
+
+            match self.state() {
+                Ok(state) => unit.states.push(state),
+                Err(error) => {
+                    errors.push(error);
+                    self.synchronize(&mut errors);
+                }
+            }
+        }
+
+        if errors.is_empty() {
+            Ok(unit)
+        } else {
+            Err(Error::combine(errors))
         }
-        Ok(unit)
+    }
+
+    fn alphabet(&mut self, unit: &Unit<'a>) -> Result<Alphabet<'a>, Error> {
+        let span = self.next()?.span;
+        self.expect(TokenKind::Bar)?;
+        let symbols = self.symbols()?;
+
+        if let Some(alphabet) = &unit.alphabet {
+            Err(
+                Error::new("the alphabet is declared twice".to_string(), Some(span))
+                    .with_note(alphabet.span, "first declared here".to_string()),
+            )
+        } else if let Some(state) = unit.states.first() {
+            Err(Error::new(
+                "the alphabet must be declared before any states".to_string(),
+                Some(span),
+            )
+            .with_note(state.name.span, "first state is here".to_string()))
+        } else {
+            Ok(Alphabet { span, symbols })
+        }
+    }
+
+    fn spec(&mut self) -> Result<Vec<TestCase<'a>>, Error> {
+        let mut cases = Vec::new();
+        let mut errors = Vec::new();
+        while self.peek() != &TokenKind::Eof {
+            match self.test_case() {
+                Ok(case) => cases.push(case),
+                Err(error) => {
+                    errors.push(error);
+                    self.synchronize(&mut errors);
+                }
+            }
+        }
+
+        if errors.is_empty() {
+            Ok(cases)
+        } else {
+            Err(Error::combine(errors))
+        }
+    }
+
+    fn synchronize(&mut self, errors: &mut Vec<Error>) {
+        loop {
+            match self.next() {
+                Ok(Token {
+                    kind: TokenKind::RBrace | TokenKind::Eof,
+                    ..
+                }) => return,
+                Ok(_) => {}
+                Err(error) => errors.push(error),
+            }
+        }
+    }
+
+    fn start_args(&mut self) -> Result<StartArgs<'a>, Error> {
+        let mut args = StartArgs::default();
+        while self.peek() != &TokenKind::Eof {
+            if !args.state_args.is_empty() || !args.symbol_args.is_empty() {
+                self.expect(TokenKind::Comma)?;
+                if self.peek() == &TokenKind::Eof {
+                    break;
+                }
+            }
+
+            match self.peek() {
+                TokenKind::Bang | TokenKind::Name | TokenKind::Keyword(_) => {
+                    args.state_args.push(self.next_state()?)
+                }
+                TokenKind::Symbol(_) => args.symbol_args.push(Pattern::Symbol(self.symbol()?)),
+                _ => {
+                    return Err(Error::new(
+                        format!(
+                            "expected state, symbol or `!`, found {}",
+                            self.peek().desc()
+                        ),
+                        Some(self.peek_span()),
+                    ))
+                }
+            }
+        }
+        Ok(args)
     }
 
     fn parens<T, U>(
@@ -210,7 +420,14 @@ impl Parser {
         Ok((before, after))
     }
 
-    fn state(&mut self) -> Result<State, Error> {
+    fn state(&mut self) -> Result<State<'a>, Error> {
+        let first = self.peek_span();
+        let mut doc = Vec::new();
+        while self.peek() == &TokenKind::DocComment {
+            let text = &self.next()?.span.text[3..];
+            doc.push(text.strip_prefix(' ').unwrap_or(text));
+        }
+
         let name = self.name()?;
 
         let (state_params, symbol_params) = self.parens(Parser::name, Parser::name)?;
@@ -228,9 +445,12 @@ impl Parser {
         if self.peek() == &TokenKind::Comma {
             self.expect(TokenKind::Comma)?;
         }
-        self.expect(TokenKind::RBrace)?;
+        let last = self.expect(TokenKind::RBrace)?.span;
 
         Ok(State {
+            first,
+            last,
+            doc,
             name,
             state_params,
             symbol_params,
@@ -238,9 +458,113 @@ impl Parser {
         })
     }
 
-    fn arm(&mut self) -> Result<Arm, Error> {
+    fn test_case(&mut self) -> Result<TestCase<'a>, Error> {
+        let name = self.name()?;
+        let mut case = TestCase {
+            name,
+            tape: Vec::new(),
+            expect: None,
+            max_states: None,
+            max_moves: None,
+            max_tape_cells: None,
+        };
+
+        let mut seen = Vec::new();
+        self.expect(TokenKind::LBrace)?;
+        while self.peek() != &TokenKind::RBrace
+            && self.peek_two() != [&TokenKind::Comma, &TokenKind::RBrace]
+        {
+            if !seen.is_empty() {
+                self.expect(TokenKind::Comma)?;
+            }
+
+            let field = self.name()?;
+            if !TEST_FIELDS.contains(&field.name) {
+                let suggestions =
+                    suggest::closest(field.name, TEST_FIELDS.map(|name| (name, name.to_string())));
+                return Err(Error::new(
+                    format!("unknown test field `{}`", field.name),
+                    Some(field.span),
+                )
+                .with_help(suggest::did_you_mean(&suggestions)));
+            } else if seen.contains(&field.name) {
+                return Err(Error::new(
+                    format!("duplicate test field `{}`", field.name),
+                    Some(field.span),
+                ));
+            }
+            seen.push(field.name);
+
+            self.expect(TokenKind::Bar)?;
+            match field.name {
+                "tape" => case.tape = self.symbols()?,
+                "expect" => case.expect = Some(self.symbols()?),
+                "max_states" => case.max_states = Some(self.number()?),
+                "max_moves" => case.max_moves = Some(self.number()?),
+                "max_tape_cells" => case.max_tape_cells = Some(self.number()?),
+                _ => unreachable!(),
+            }
+        }
+        if self.peek() == &TokenKind::Comma {
+            self.expect(TokenKind::Comma)?;
+        }
+        self.expect(TokenKind::RBrace)?;
+
+        Ok(case)
+    }
+
+    fn symbols(&mut self) -> Result<Vec<Symbol<'a>>, Error> {
+        let mut symbols = Vec::new();
+        while matches!(self.peek(), TokenKind::Symbol(_)) {
+            symbols.push(self.symbol()?);
+        }
+        Ok(symbols)
+    }
+
+    fn symbol_list(&mut self) -> Result<Vec<Symbol<'a>>, Error> {
+        let mut symbols = Vec::new();
+        while self.peek() != &TokenKind::Eof {
+            if !symbols.is_empty() {
+                self.expect(TokenKind::Comma)?;
+                if self.peek() == &TokenKind::Eof {
+                    break;
+                }
+            }
+            symbols.push(self.symbol()?);
+        }
+        Ok(symbols)
+    }
+
+    fn number(&mut self) -> Result<usize, Error> {
+        let span = self.peek_span();
+        match self.peek() {
+            TokenKind::Symbol(symbol)
+                if !symbol.is_empty() && symbol.bytes().all(|b| b.is_ascii_digit()) =>
+            {
+                if let Ok(number) = symbol.parse() {
+                    self.next()?;
+                    Ok(number)
+                } else {
+                    Err(Error::new("number is too large".to_string(), Some(span)))
+                }
+            }
+            _ => Err(Error::new(
+                format!("expected number, found {}", self.peek().desc()),
+                Some(span),
+            )),
+        }
+    }
+
+    fn arm(&mut self) -> Result<Arm<'a>, Error> {
         let pattern = self.pattern()?;
 
+        let lookahead = if self.peek() == &TokenKind::Name && self.peek_one.span.text == "then" {
+            self.next()?;
+            Some(self.pattern()?)
+        } else {
+            None
+        };
+
         self.expect(TokenKind::Bar)?;
         let mut ops = Vec::new();
         while self.peek() != &TokenKind::Bar {
@@ -248,18 +572,19 @@ impl Parser {
         }
 
         self.expect(TokenKind::Bar)?;
-        let to_state = self.to_state()?;
+        let to_state = self.next_state()?;
 
         Ok(Arm {
             pattern,
+            lookahead,
             ops,
             to_state,
         })
     }
 
-    fn pattern(&mut self) -> Result<Pattern, Error> {
+    fn pattern(&mut self) -> Result<Pattern<'a>, Error> {
         match self.peek() {
-            TokenKind::Name => Ok(Pattern::Name(self.name()?)),
+            TokenKind::Name | TokenKind::Keyword(_) => Ok(Pattern::Name(self.name()?)),
             TokenKind::Symbol(_) => Ok(Pattern::Symbol(self.symbol()?)),
             _ => Err(Error::new(
                 format!("expected name or symbol, found {}", self.peek().desc()),
@@ -268,11 +593,11 @@ impl Parser {
         }
     }
 
-    fn op(&mut self) -> Result<Op, Error> {
+    fn op(&mut self) -> Result<Op<'a>, Error> {
         match self.peek() {
             TokenKind::Left => Ok(Op::Left(self.expect(TokenKind::Left)?.span)),
             TokenKind::Right => Ok(Op::Right(self.expect(TokenKind::Right)?.span)),
-            TokenKind::Name => Ok(Op::Name(self.name()?)),
+            TokenKind::Name | TokenKind::Keyword(_) => Ok(Op::Name(self.name()?)),
             TokenKind::Symbol(_) => Ok(Op::Symbol(self.symbol()?)),
             _ => Err(Error::new(
                 format!(
@@ -284,20 +609,20 @@ impl Parser {
         }
     }
 
-    fn to_state(&mut self) -> Result<ToState, Error> {
+    fn next_state(&mut self) -> Result<ToState<'a>, Error> {
         match self.peek() {
-            TokenKind::Name => {
+            TokenKind::Bang | TokenKind::Keyword(Keyword::Halt) => Ok(ToState::Halt {
+                span: self.next()?.span,
+            }),
+            TokenKind::Name | TokenKind::Keyword(_) => {
                 let name = self.name()?;
-                let (state_args, symbol_args) = self.parens(Parser::to_state, Parser::pattern)?;
+                let (state_args, symbol_args) = self.parens(Parser::next_state, Parser::pattern)?;
                 Ok(ToState::State {
                     name,
                     state_args,
                     symbol_args,
                 })
             }
-            TokenKind::Bang => Ok(ToState::Halt {
-                span: self.expect(TokenKind::Bang)?.span,
-            }),
             _ => Err(Error::new(
                 format!("expected name or `!`, found {}", self.peek().desc()),
                 Some(self.peek_span()),
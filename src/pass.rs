@@ -0,0 +1,144 @@
+use crate::bytecode;
+use crate::compile::{self, Compiled};
+use crate::error::Error;
+use crate::parse::{StartArgs, Symbol, Unit};
+
+/// A syntax tree transformation that runs after parsing and before compiling.
+pub trait AstPass {
+    fn name(&self) -> &str;
+    fn run<'a>(&mut self, unit: Unit<'a>) -> Result<Unit<'a>, Error>;
+}
+
+/// A bytecode transformation that runs after the machine is lowered to bytecode and
+/// before its addresses are finalized. Passes must keep the header intact and update
+/// `states`, `signatures`, `arms` and `source_map` if they move any code, but don't
+/// have to keep `source_map` sorted.
+pub trait Pass {
+    fn name(&self) -> &str;
+    fn run(&mut self, compiled: &mut Compiled) -> Result<(), Error>;
+
+    /// What the last run did, if the pass has something to say beyond its size
+    fn summary(&self) -> Option<String> {
+        None
+    }
+}
+
+/// How many bytes of bytecode there were before and after a bytecode pass ran
+pub struct PassReport {
+    pub name: String,
+    pub before: usize,
+    pub after: usize,
+    pub summary: Option<String>,
+}
+
+// the stages around the bytecode passes, which `print_after` can also name
+const LOWER: &str = "lower";
+const FINALIZE: &str = "finalize";
+
+/// Runs a machine through its stages: the syntax tree passes, `lower` (which is
+/// `compile::compile`), the bytecode passes in the order they were added, then
+/// `finalize`, which puts the source map back in address order.
+#[derive(Default)]
+pub struct PassManager {
+    ast_passes: Vec<Box<dyn AstPass>>,
+    bytecode_passes: Vec<Box<dyn Pass>>,
+    symbol_table: Option<Vec<String>>,
+    // the stage to dump the bytecode after, and whether to do it without color
+    print_after: Option<String>,
+    reports: Vec<PassReport>,
+}
+
+impl PassManager {
+    pub fn new() -> Self {
+        PassManager::default()
+    }
+
+    pub fn add_ast_pass(&mut self, pass: impl AstPass + 'static) -> &mut Self {
+        self.ast_passes.push(Box::new(pass));
+        self
+    }
+
+    pub fn add_bytecode_pass(&mut self, pass: impl Pass + 'static) -> &mut Self {
+        self.bytecode_passes.push(Box::new(pass));
+        self
+    }
+
+    /// Compile against a fixed symbol table (see `compile::compile_with_symbols`)
+    pub fn symbol_table(&mut self, symbols: Vec<String>) -> &mut Self {
+        self.symbol_table = Some(symbols);
+        self
+    }
+
+    /// Dump the bytecode (see `bytecode::dump`) after the stage called `name` runs
+    pub fn print_after(&mut self, name: String) -> &mut Self {
+        self.print_after = Some(name);
+        self
+    }
+
+    pub fn pass_names(&self) -> impl Iterator<Item = &str> {
+        let ast = self.ast_passes.iter().map(|pass| pass.name());
+        ast.chain(self.bytecode_passes.iter().map(|pass| pass.name()))
+    }
+
+    // the stages that have bytecode to dump, in the order they run
+    fn stages(&self) -> impl Iterator<Item = &str> {
+        let passes = self.bytecode_passes.iter().map(|pass| pass.name());
+        [LOWER].into_iter().chain(passes).chain([FINALIZE])
+    }
+
+    /// One report for each bytecode pass in the last call to `compile`
+    pub fn reports(&self) -> &[PassReport] {
+        &self.reports
+    }
+
+    pub fn compile(
+        &mut self,
+        mut unit: Unit,
+        symbols: Vec<Symbol>,
+        start_args: StartArgs,
+    ) -> Result<Compiled, Error> {
+        if let Some(name) = &self.print_after {
+            if !self.stages().any(|stage| stage == name) {
+                let names: Vec<_> = self.stages().map(|stage| format!("`{stage}`")).collect();
+                return Err(
+                    Error::new(format!("there is no pass called `{name}`"), None)
+                        .with_help(Some(format!("the passes are {}", names.join(", ")))),
+                );
+            }
+        }
+
+        for pass in &mut self.ast_passes {
+            unit = pass.run(unit)?;
+        }
+
+        let mut compiled =
+            compile::compile_with_symbols(unit, symbols, start_args, self.symbol_table.as_deref())?;
+        self.dump_after(LOWER, &compiled);
+        self.reports.clear();
+        for i in 0..self.bytecode_passes.len() {
+            let pass = &mut self.bytecode_passes[i];
+            let before = compiled.bytes.len();
+            pass.run(&mut compiled)?;
+            self.reports.push(PassReport {
+                name: pass.name().to_string(),
+                before,
+                after: compiled.bytes.len(),
+                summary: pass.summary(),
+            });
+            self.dump_after(self.bytecode_passes[i].name(), &compiled);
+        }
+
+        compiled.source_map.sort_by_key(|range| range.start);
+        self.dump_after(FINALIZE, &compiled);
+        Ok(compiled)
+    }
+
+    fn dump_after(&self, stage: &str, compiled: &Compiled) {
+        if let Some(name) = &self.print_after {
+            if name == stage {
+                bytecode::dump(compiled);
+                println!();
+            }
+        }
+    }
+}
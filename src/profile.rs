@@ -0,0 +1,224 @@
+use std::collections::HashMap;
+use std::io::{self, BufWriter, Stdout, Write};
+
+use crate::backend::{Observer, Simulated};
+use crate::bytecode::{self as bc, Version};
+use crate::compile::Compiled;
+use crate::metrics::metric;
+
+/// Prints the size of each state's bytecode and how often each opcode shows up in it
+pub fn print_static(compiled: &Compiled) {
+    let bytes = &compiled.bytes;
+    let version = Version::of(bytes);
+    let entry = u32::from_le_bytes(bytes[2..6].try_into().unwrap());
+
+    let mut names: HashMap<u32, Vec<String>> = HashMap::new();
+    for (signature, &address) in &compiled.signatures {
+        names
+            .entry(address)
+            .or_default()
+            .push(signature.to_string());
+    }
+    let mut starts: Vec<_> = compiled.states.keys().copied().collect();
+    starts.sort();
+    starts.push(entry);
+    let ends = starts.iter().skip(1).copied().chain([bytes.len() as u32]);
+
+    metric("bytecode size", format!("{} bytes", bytes.len()));
+    for (&start, end) in starts.iter().zip(ends) {
+        let name = match names.get_mut(&start) {
+            Some(names) => {
+                names.sort();
+                names.join(", ")
+            }
+            None if start == entry => "entry".to_string(),
+            None => compiled.states[&start].name.clone(),
+        };
+        let mut counts = [0; 256];
+        let mut ip = start as usize;
+        while ip < end as usize {
+            let opcode = bytes[ip];
+            counts[opcode as usize] += 1;
+            ip += 1;
+            for operand in bc::operands(opcode).expect("invalid bytecode") {
+                ip += operand.size(version);
+            }
+        }
+        println!(
+            "    {name}: {} bytes ({})",
+            end - start,
+            histogram(&counts).join(", ")
+        );
+    }
+    println!();
+}
+
+/// Counts how many times each opcode is executed
+pub struct Profile {
+    counts: [usize; 256],
+}
+
+impl Profile {
+    pub fn new() -> Self {
+        Profile { counts: [0; 256] }
+    }
+
+    pub fn print(&self) {
+        let total: usize = self.counts.iter().sum();
+        metric("instructions executed", total);
+        let mut opcodes: Vec<_> = (0..=u8::MAX)
+            .filter(|&opcode| self.counts[opcode as usize] > 0)
+            .collect();
+        opcodes.sort_by_key(|&opcode| std::cmp::Reverse(self.counts[opcode as usize]));
+        for opcode in opcodes {
+            let count = self.counts[opcode as usize];
+            println!(
+                "    {}: {count} ({:.1}%)",
+                bc::name(opcode).expect("invalid bytecode"),
+                100.0 * count as f64 / total as f64
+            );
+        }
+        println!();
+    }
+}
+
+impl Default for Profile {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Observer for Profile {
+    fn instruction(&mut self, opcode: u8) {
+        self.counts[opcode as usize] += 1;
+    }
+}
+
+/// Counts the states the VM makes to pass to other states, how many of them
+/// were alive at once, and how often its transposition cache had the moves it
+/// looked up
+#[derive(Default)]
+pub struct VmStats {
+    allocated: usize,
+    reused: usize,
+    peak: usize,
+    // transposition cache lookups
+    hits: usize,
+    misses: usize,
+}
+
+impl VmStats {
+    pub fn print(&self) {
+        metric("states allocated", self.allocated);
+        println!(
+            "    {} new, {} reused from the slab",
+            self.allocated - self.reused,
+            self.reused
+        );
+        metric("peak live states", self.peak);
+        let lookups = self.hits + self.misses;
+        if lookups > 0 {
+            metric(
+                "transposition cache hit rate",
+                format!("{:.1}%", 100.0 * self.hits as f64 / lookups as f64),
+            );
+            println!("    {} hits, {} misses", self.hits, self.misses);
+        }
+        println!();
+    }
+}
+
+impl Observer for VmStats {
+    fn allocated(&mut self, live: usize, reused: bool) {
+        self.allocated += 1;
+        self.reused += reused as usize;
+        self.peak = self.peak.max(live);
+    }
+
+    fn cached(&mut self, hit: bool) {
+        self.hits += hit as usize;
+        self.misses += !hit as usize;
+    }
+}
+
+/// Prints a line each time the run changes state, with how many moves it spent
+/// in the state it left
+pub struct StateTrace<'a> {
+    compiled: &'a Compiled,
+    out: BufWriter<Stdout>,
+    // the state it's in and the move it got there on
+    current: Option<(u32, usize)>,
+    // a state it changed to, until `moved` says which move that was
+    entered: Option<u32>,
+}
+
+impl<'a> StateTrace<'a> {
+    pub fn new(compiled: &'a Compiled) -> Self {
+        StateTrace {
+            compiled,
+            out: BufWriter::new(io::stdout()),
+            current: None,
+            entered: None,
+        }
+    }
+
+    /// Prints the state the run stopped in
+    pub fn finish(mut self, simulated: &Simulated) {
+        self.leave(simulated.moves);
+        self.out.flush().ok();
+    }
+
+    // prints the current state, which the run leaves on move `moves`
+    fn leave(&mut self, moves: usize) {
+        let Some((address, since)) = self.current else {
+            return;
+        };
+        // a jump to the halt state isn't a state it spends any moves in
+        let Some(info) = self.compiled.states.get(&address) else {
+            return;
+        };
+        let spent = moves - since;
+        let plural = if spent == 1 { "" } else { "s" };
+        writeln!(
+            self.out,
+            "move {since}: {} for {spent} move{plural}",
+            info.name
+        )
+        .ok();
+    }
+}
+
+impl Observer for StateTrace<'_> {
+    fn state(&mut self, address: u32) {
+        match self.current {
+            // the start state, before the first move
+            None => self.current = Some((address, 0)),
+            Some(_) => self.entered = Some(address),
+        }
+    }
+
+    fn moved(&mut self, moves: usize, _head: i64) {
+        if let Some(address) = self.entered.take() {
+            self.leave(moves);
+            self.current = Some((address, moves));
+        }
+    }
+}
+
+// the opcodes that show up, most common first
+fn histogram(counts: &[usize; 256]) -> Vec<String> {
+    let mut opcodes: Vec<_> = (0..=u8::MAX)
+        .filter(|&opcode| counts[opcode as usize] > 0)
+        .collect();
+    opcodes.sort_by_key(|&opcode| std::cmp::Reverse(counts[opcode as usize]));
+    opcodes
+        .into_iter()
+        .map(|opcode| {
+            format!(
+                "{} {}",
+                bc::name(opcode).expect("invalid bytecode"),
+                counts[opcode as usize]
+            )
+        })
+        .collect()
+}
@@ -0,0 +1,93 @@
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::error::Error;
+use crate::lex::{self, Tokens};
+use crate::parse::{self, Symbol};
+
+/// What `--random-tape` was given
+pub struct Spec {
+    pub len: usize,
+    // the symbols to pick from, written like `'0', '1'`
+    pub alphabet: String,
+}
+
+impl Spec {
+    /// Reads `len=N` and `alphabet=SYMBOLS`, in either order
+    pub fn parse(values: &[String]) -> Result<Spec, Error> {
+        let mut len = None;
+        let mut alphabet = None;
+        for value in values {
+            match value.split_once('=') {
+                Some(("len", n)) => {
+                    len = Some(n.parse().map_err(|_| {
+                        Error::new(format!("`--random-tape`: `{n}` isn't a valid length"), None)
+                    })?)
+                }
+                Some(("alphabet", symbols)) => alphabet = Some(symbols.to_string()),
+                _ => {
+                    let msg = format!(
+                        "`--random-tape` takes `len=N` and `alphabet=SYMBOLS`, not `{value}`"
+                    );
+                    return Err(Error::new(msg, None));
+                }
+            }
+        }
+        match (len, alphabet) {
+            (Some(len), Some(alphabet)) => Ok(Spec { len, alphabet }),
+            (None, _) => Err(Error::new(
+                "`--random-tape` needs a length, like `len=100`".to_string(),
+                None,
+            )),
+            (_, None) => Err(Error::new(
+                "`--random-tape` needs an alphabet, like `alphabet=\"'0','1'\"`".to_string(),
+                None,
+            )),
+        }
+    }
+}
+
+/// A seed for when none was given, which is different every run
+pub fn any_seed() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |time| time.as_nanos() as u64)
+}
+
+/// A tape of `spec.len` symbols, each picked from the alphabet with the same
+/// chance. The same seed always makes the same tape
+pub fn tape(spec: &Spec, seed: u64, options: lex::Options) -> Result<Vec<Symbol<'_>>, Error> {
+    if spec.alphabet.trim().is_empty() {
+        return Err(Error::new(
+            "`--random-tape` needs at least one symbol in its alphabet".to_string(),
+            None,
+        ));
+    }
+    let tokens = Tokens::new(&spec.alphabet, Path::new("<alphabet>"), options)?;
+    let alphabet = parse::parse_symbol_list(tokens)?;
+
+    let mut rng = SplitMix64(seed);
+    Ok((0..spec.len)
+        .map(|_| alphabet[rng.below(alphabet.len())].clone())
+        .collect())
+}
+
+// splitmix64. it's tiny, and unlike a crate's generator, what a seed makes can't
+// change when a dependency is updated
+struct SplitMix64(u64);
+
+impl SplitMix64 {
+    fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    // a number in `0..n`, scaled down from 64 bits, which is too many for the
+    // bias to matter
+    fn below(&mut self, n: usize) -> usize {
+        ((self.next() as u128 * n as u128) >> 64) as usize
+    }
+}
@@ -0,0 +1,294 @@
+use std::path::Path;
+
+use termion::color;
+
+use crate::analysis;
+use crate::compile::Signature;
+use crate::error::Error;
+use crate::lex::{Edition, Keyword, Span};
+use crate::output;
+use crate::parse::{StartArgs, State};
+use crate::suggest;
+
+pub fn rename<'a>(
+    unit: &[State<'a>],
+    old: &str,
+    new: &str,
+    states: Option<u16>,
+    symbols: Option<u16>,
+    edition: Edition,
+) -> Result<(Signature<'a>, Vec<(Span<'a>, String)>), Error> {
+    if old == "start" || new == "start" {
+        return Err(Error::new(
+            "the `start` state can't be renamed".to_string(),
+            None,
+        ));
+    }
+
+    let mut chars = new.chars();
+    let valid = chars.next().is_some_and(|c| c.is_alphabetic() || c == '_')
+        && chars.all(|c| c.is_alphanumeric() || c == '_');
+    if !valid || new == "_" {
+        return Err(Error::new(format!("`{new}` is not a valid name"), None));
+    }
+    if Keyword::from_name(new, edition).is_some() {
+        return Err(Error::new(
+            format!("`{new}` is a keyword in edition {edition}"),
+            None,
+        ));
+    }
+
+    let candidates: Vec<_> = unit
+        .iter()
+        .filter(|state| state.name.name == old)
+        .filter(|state| states.is_none_or(|n| state.state_params.len() == n as usize))
+        .filter(|state| symbols.is_none_or(|n| state.symbol_params.len() == n as usize))
+        .collect();
+
+    let state = match &candidates[..] {
+        [] => {
+            let mut names: Vec<_> = unit.iter().map(|state| state.name.name).collect();
+            names.sort();
+            names.dedup();
+            let suggestions =
+                suggest::closest(old, names.into_iter().map(|name| (name, name.to_string())));
+            let error = if states.is_some() || symbols.is_some() {
+                Error::new(format!("no state `{old}` with that signature"), None)
+            } else {
+                Error::new(format!("no state with name `{old}`"), None)
+            };
+            return Err(error.with_help(suggest::did_you_mean(&suggestions)));
+        }
+        [state] => state,
+        _ => {
+            let mut error = Error::new(format!("`{old}` has more than one signature"), None);
+            for state in candidates {
+                error = error.with_note(
+                    state.name.span,
+                    format!("`{}` is defined here", Signature::of(state)),
+                );
+            }
+            return Err(
+                error.with_help(Some("pick one with `--states` and `--symbols`".to_string()))
+            );
+        }
+    };
+
+    let signature = Signature::of(state);
+    let renamed = Signature {
+        name: new,
+        ..signature
+    };
+    if let Some(existing) = unit.iter().find(|state| Signature::of(state) == renamed) {
+        return Err(Error::new(
+            format!("a function with signature `{renamed}` already exists"),
+            Some(existing.name.span),
+        ));
+    }
+
+    let mut spans = vec![state.name.span];
+    for state in unit {
+        let mut calls = Vec::new();
+        analysis::for_each_call(state, |name, callee| {
+            if callee == signature {
+                calls.push(name.span);
+            }
+        });
+
+        // inside a state with a parameter named `new`, the renamed calls would
+        // refer to the parameter instead
+        if let (Some(call), Some(param)) = (
+            calls.first(),
+            state.state_params.iter().find(|param| param.name == new),
+        ) {
+            return Err(Error::new(
+                format!("`{new}` is a parameter of `{}`", Signature::of(state)),
+                Some(param.span),
+            )
+            .with_label(format!("`{new}` is declared here"))
+            .with_note(
+                *call,
+                format!("this call to `{old}` would refer to the parameter"),
+            ));
+        }
+        spans.extend(calls);
+    }
+
+    let edits = spans
+        .into_iter()
+        .map(|span| (span, new.to_string()))
+        .collect();
+    Ok((signature, edits))
+}
+
+pub fn unreachable<'a, 'b>(unit: &'a [State<'b>], start_args: &StartArgs) -> Vec<&'a State<'b>> {
+    let reachable = analysis::reachable(unit, start_args);
+    unit.iter()
+        .filter(|state| !reachable.contains(&Signature::of(state)))
+        .collect()
+}
+
+pub fn remove(source: &str, states: &[&State]) -> String {
+    let mut lines: Vec<Option<String>> = source
+        .split_inclusive('\n')
+        .map(|line| Some(line.to_string()))
+        .collect();
+    let is_blank = |line: &Option<String>| line.as_ref().is_some_and(|l| l.trim().is_empty());
+
+    let is_comment = |line: &Option<String>| {
+        line.as_ref()
+            .is_some_and(|l| l.trim_start().starts_with("//"))
+    };
+
+    // go from the back so the spans of earlier states stay valid
+    for state in states.iter().rev() {
+        let (first, last) = (state.first, state.last);
+        let last_end = last.prefix.len() + last.text.len();
+
+        if !first.prefix.trim().is_empty() || !last.suffix.trim().is_empty() {
+            // the state shares a line with something else, so only remove its text
+            let tail = lines[last.line].as_ref().unwrap()[last_end..].to_string();
+            for line in &mut lines[first.line + 1..=last.line] {
+                *line = None;
+            }
+            let line = lines[first.line].as_mut().unwrap();
+            let head = &line[..first.prefix.len()];
+            *line = if tail.trim().is_empty() {
+                format!(
+                    "{}{}",
+                    head.trim_end(),
+                    tail.trim_start_matches([' ', '\t'])
+                )
+            } else {
+                format!("{head}{tail}")
+            };
+            continue;
+        }
+
+        // the comments right above the state are about it, so they go too
+        let mut start = first.line;
+        while start > 0 && is_comment(&lines[start - 1]) {
+            start -= 1;
+        }
+        for line in &mut lines[start..=last.line] {
+            *line = None;
+        }
+
+        // remove the blank lines that separated the state from its neighbors
+        let mut next = last.line + 1;
+        while next < lines.len() && (lines[next].is_none() || is_blank(&lines[next])) {
+            lines[next] = None;
+            next += 1;
+        }
+        if lines[last.line + 1..].iter().all(Option::is_none) {
+            let mut prev = start;
+            while prev > 0 && (lines[prev - 1].is_none() || is_blank(&lines[prev - 1])) {
+                lines[prev - 1] = None;
+                prev -= 1;
+            }
+        }
+    }
+
+    lines.into_iter().flatten().collect()
+}
+
+pub fn apply(source: &str, mut edits: Vec<(Span, String)>) -> String {
+    // apply edits from the back so earlier offsets stay valid
+    edits.sort_by_key(|(span, _)| (span.line, span.prefix.len()));
+
+    let mut lines: Vec<String> = source.split_inclusive('\n').map(str::to_string).collect();
+    for (span, text) in edits.into_iter().rev() {
+        let start = span.prefix.len();
+        lines[span.line].replace_range(start..start + span.text.len(), &text);
+    }
+    lines.concat()
+}
+
+pub fn print_rename_diff(path: &Path, old: &str, new: &str) {
+    let old: Vec<_> = old.lines().collect();
+    let new: Vec<_> = new.lines().collect();
+
+    println!("--- {}", path.display());
+    println!("+++ {}", path.display());
+
+    // renaming never adds or removes lines, so the lines can be compared in pairs
+    for (i, (old, new)) in old.iter().zip(&new).enumerate() {
+        if old == new {
+            continue;
+        }
+        println!("@@ line {} @@", i + 1);
+        if !output::color() {
+            println!("-{old}");
+            println!("+{new}");
+        } else {
+            println!("{}-{old}{}", color::Fg(color::Red), color::Fg(color::Reset));
+            println!(
+                "{}+{new}{}",
+                color::Fg(color::Green),
+                color::Fg(color::Reset)
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lex::{self, Tokens};
+    use crate::parse;
+
+    const OPTIONS: lex::Options = lex::Options {
+        allow_tabs: false,
+        bare_digits: true,
+        edition: Edition::E2023,
+    };
+
+    fn rename_in(code: &str, old: &str, new: &str) -> Result<String, Error> {
+        let unit = parse::parse(Tokens::new(code, Path::new("rn.tml"), OPTIONS)?)?;
+        let (_, edits) = rename(&unit.states, old, new, None, None, Edition::E2023)?;
+        Ok(apply(code, edits))
+    }
+
+    fn gc(code: &str) -> String {
+        let unit = parse::parse(Tokens::new(code, Path::new("gc.tml"), OPTIONS).unwrap()).unwrap();
+        remove(code, &unreachable(&unit.states, &StartArgs::default()))
+    }
+
+    #[test]
+    fn removes_leading_comments() {
+        let code = "start { _ | | !, }\n\n// dead one\ndead { _ | | !, }\n";
+        assert_eq!(gc(code), "start { _ | | !, }\n");
+
+        let code = "\
+start { _ | | b, }
+
+// dead one
+/// never called
+dead { _ | | !, }
+
+// b halts
+b { _ | | !, }
+";
+        assert_eq!(
+            gc(code),
+            "start { _ | | b, }\n\n// b halts\nb { _ | | !, }\n"
+        );
+    }
+
+    #[test]
+    fn renames_calls() {
+        let code = "start { _ | | g(f), }\ng(A) { _ | | f, }\nf { _ | | !, }\n";
+        let renamed = rename_in(code, "f", "h").unwrap();
+        assert_eq!(
+            renamed,
+            "start { _ | | g(h), }\ng(A) { _ | | h, }\nh { _ | | !, }\n"
+        );
+    }
+
+    #[test]
+    fn rejects_name_captured_by_parameter() {
+        let code = "start { _ | | g(f), }\ng(A) { _ | | f, }\nf { _ | | !, }\n";
+        let error = rename_in(code, "f", "A").unwrap_err();
+        assert_eq!(error.diagnostics()[0].msg, "`A` is a parameter of `g(_)`");
+    }
+}
@@ -0,0 +1,91 @@
+use std::path::Path;
+
+use rustyline::error::ReadlineError;
+use rustyline::DefaultEditor;
+
+use crate::backend::{self, Simulated};
+use crate::batch::{self, DecimalOptions};
+use crate::compile::Compiled;
+use crate::error::Error;
+use crate::lex::{self, Tokens};
+use crate::metrics;
+use crate::parse;
+use crate::tape;
+use crate::tmb;
+
+pub struct Options {
+    pub rust_vm: bool,
+    /// `None` leaves the decimal out
+    pub decimal: Option<DecimalOptions>,
+    pub backend: backend::Options,
+    pub terminal_width: usize,
+}
+
+/// Reads tapes from a prompt, a line at a time and written like a tape file,
+/// and runs the compiled machine on each one. An empty line is an empty tape.
+/// Ctrl-C clears the line (or stops a run), and Ctrl-D quits
+pub fn run(compiled: &Compiled, lex_options: lex::Options, options: &Options) -> Result<(), Error> {
+    let mut editor = DefaultEditor::new()
+        .map_err(|error| Error::new(format!("couldn't start the prompt: {error}"), None))?;
+    loop {
+        let line = match editor.readline("tape> ") {
+            Ok(line) => line,
+            Err(ReadlineError::Interrupted) => continue,
+            Err(ReadlineError::Eof) => return Ok(()),
+            Err(error) => return Err(Error::new(format!("couldn't read the tape: {error}"), None)),
+        };
+        if !line.trim().is_empty() {
+            editor.add_history_entry(line.as_str()).ok();
+        }
+        // so a Ctrl-C only stops the run after it
+        backend::catch_interrupts()?;
+        match simulate(compiled, line, lex_options, options) {
+            Ok(simulated) => print(compiled, &simulated, options),
+            Err(error) => {
+                error.print();
+                println!();
+            }
+        }
+    }
+}
+
+fn simulate(
+    compiled: &Compiled,
+    line: String,
+    lex_options: lex::Options,
+    options: &Options,
+) -> Result<Simulated, Error> {
+    let tape = if line.trim().is_empty() {
+        Vec::new()
+    } else {
+        let tokens = Tokens::new(&line, Path::new("<tape>"), lex_options)?;
+        // unlike a `tml batch` tape, there's no rebuilding the machine with it
+        tmb::map_tape(compiled, &parse::parse_tape(tokens)?).map_err(|error| {
+            error.with_help(Some(
+                "only symbols the machine uses can be on the tape".to_string(),
+            ))
+        })?
+    };
+    backend::select(options.rust_vm).run(&compiled.bytes, &tape, &options.backend)
+}
+
+fn print(compiled: &Compiled, simulated: &Simulated, options: &Options) {
+    let symbols: Vec<_> = simulated
+        .tape
+        .iter()
+        .map(|&symbol| compiled.symbols[symbol as usize].as_str())
+        .collect();
+    let origin = (simulated.origin > 0).then_some(simulated.origin);
+    tape::dump(&symbols, options.terminal_width, origin);
+    if let Some(decimal) = &options.decimal {
+        metrics::metric("decimal", batch::read_decimal(compiled, simulated, decimal));
+    }
+    metrics::metric("number of moves", simulated.moves);
+    metrics::metric("final head position", simulated.head());
+    metrics::metric(
+        "final state",
+        compiled.describe_state(&simulated.final_state),
+    );
+    metrics::metric("halt reason", simulated.halt_reason);
+    println!();
+}
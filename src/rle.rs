@@ -0,0 +1,379 @@
+use std::cmp::Ordering;
+use std::ops::ControlFlow;
+
+use crate::backend::{self, HaltReason, Options, Simulated};
+use crate::error::Error;
+use crate::table::Table;
+use crate::vm::Tape;
+
+/// Runs machines that fit in a transition table (see `table::Table`) on an
+/// `RleTape`. When a state keeps taking the same arm across a run of one
+/// symbol, writing the same thing on each cell and moving one cell the same
+/// way, it makes all of those moves in a single step. It makes the same moves
+/// as the other backends, so the result is the same
+pub struct AcceleratedVm;
+
+impl AcceleratedVm {
+    /// Runs the machine and returns the result and how many steps it took (a
+    /// step is a single move or a whole run of them). Machines that don't fit
+    /// in a table run on the Rust VM, a move at a time
+    pub fn run(
+        &self,
+        bytes: &[u8],
+        tape: &[u32],
+        options: &Options,
+    ) -> Result<(Simulated, usize), Error> {
+        match Table::build(bytes, tape) {
+            Some(table) => {
+                let (mut simulated, steps) = table.run_chains(tape, options);
+                backend::trim(&mut simulated, tape.len(), options.trim);
+                Ok((simulated, steps))
+            }
+            None => {
+                let simulated = backend::select(true).run(bytes, tape, options)?;
+                let moves = simulated.moves;
+                Ok((simulated, moves))
+            }
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+struct Run {
+    symbol: u32,
+    len: usize,
+}
+
+/// A tape stored as runs of the same symbol, so a stretch of a million blanks
+/// takes as little room as one, and moving across a whole run is one step. It
+/// keeps the same statistics as `vm::DenseTape`, and stops on the same moves
+#[derive(Clone)]
+pub struct RleTape {
+    // the runs left of the head, nearest last, and the same for the right. past
+    // them, the tape is blank
+    left: Vec<Run>,
+    right: Vec<Run>,
+    head: u32,
+    // counting from the initial tape's first cell, like `Simulated::head`
+    position: i64,
+    rightmost: i64,
+    // see `Simulated`
+    leftmost: i64,
+    left_edge_contacts: usize,
+    two_sided: bool,
+    max_cells: usize,
+    initial_len: usize,
+}
+
+impl RleTape {
+    pub fn new(tape: &[u32], two_sided: bool, max_cells: usize) -> Self {
+        let mut right = Vec::new();
+        for &symbol in tape.iter().skip(1).rev() {
+            push(&mut right, symbol, 1);
+        }
+        RleTape {
+            left: Vec::new(),
+            right,
+            head: tape.first().copied().unwrap_or_default(),
+            position: 0,
+            rightmost: 0,
+            leftmost: 0,
+            left_edge_contacts: 0,
+            two_sided,
+            max_cells,
+            initial_len: tape.len(),
+        }
+    }
+
+    /// How many moves the head can make across cells with the symbol it's on,
+    /// going right if `right`, without the run stopping
+    pub(crate) fn sweepable(&self, right: bool) -> usize {
+        match right {
+            true => self.run_right().min(self.room_right()),
+            false => self.run_left().min(self.room_left()),
+        }
+    }
+
+    /// Makes `count` of those moves, writing `symbol` on each cell
+    pub(crate) fn sweep(&mut self, right: bool, symbol: u32, count: usize) {
+        match right {
+            true => self.sweep_right(symbol, count),
+            false => self.sweep_left(symbol, count),
+        }
+    }
+
+    // how many cells from the head on are the same symbol as it, going right
+    // (or left). `usize::MAX` if they're blank and go on forever
+    fn run_right(&self) -> usize {
+        run(self.head, &self.right, 0)
+    }
+
+    fn run_left(&self) -> usize {
+        // a one-sided tape's blanks stop at cell 0
+        let covered: usize = self.left.iter().map(|run| run.len).sum();
+        let edge = match self.two_sided {
+            true => usize::MAX,
+            false => self.position as usize - covered,
+        };
+        run(self.head, &self.left, edge)
+    }
+
+    // writes `symbol` on the head's cell and the `count - 1` after it, which all
+    // have the symbol the head is on, and moves right past them. the moves
+    // mustn't go past the tape cell limit
+    fn sweep_right(&mut self, symbol: u32, count: usize) {
+        push(&mut self.left, symbol, count);
+        drop(&mut self.right, count - 1);
+        self.head = pop(&mut self.right);
+        self.position += count as i64;
+        self.rightmost = self.rightmost.max(self.position);
+    }
+
+    // the same going left. the moves mustn't go past the left edge or the tape
+    // cell limit
+    fn sweep_left(&mut self, symbol: u32, count: usize) {
+        // the moves that end on cell 0 or further left
+        let contacts = (count as i64 - (self.position - 1).max(0)).max(0);
+        self.left_edge_contacts += contacts as usize;
+        push(&mut self.right, symbol, count);
+        drop(&mut self.left, count - 1);
+        self.head = pop(&mut self.left);
+        self.position -= count as i64;
+        self.leftmost = self.leftmost.min(self.position);
+    }
+
+    // how far right (or left) the head can go before the run would stop. it
+    // stays well inside an `i64` either way, even if the tape has no limit
+    fn room_right(&self) -> usize {
+        let limit = self.max_cells as i128 - 1 - (-self.leftmost).max(0) as i128;
+        let limit = limit.min(i64::MAX as i128 / 2);
+        (limit - self.position as i128).max(0) as usize
+    }
+
+    fn room_left(&self) -> usize {
+        if !self.two_sided {
+            return self.position as usize;
+        }
+        let limit = self.rightmost as i128 + 1 - self.max_cells as i128;
+        let limit = limit.max(i64::MIN as i128 / 2);
+        (self.position as i128 - limit).max(0) as usize
+    }
+
+    fn shift_right(&mut self, n: usize) {
+        push(&mut self.left, self.head, 1);
+        transfer(&mut self.right, &mut self.left, n - 1);
+        self.head = pop(&mut self.right);
+        self.position += n as i64;
+    }
+
+    fn shift_left(&mut self, n: usize) {
+        if n == 0 {
+            return;
+        }
+        push(&mut self.right, self.head, 1);
+        transfer(&mut self.left, &mut self.right, n - 1);
+        self.head = pop(&mut self.left);
+        self.position -= n as i64;
+    }
+
+    // like `vm::DenseTape::span`
+    fn span(&self) -> u64 {
+        (self.rightmost + (-self.leftmost).max(0) + 1) as u64
+    }
+}
+
+impl Tape for RleTape {
+    fn read(&self) -> u32 {
+        self.head
+    }
+
+    fn peek(&self) -> u32 {
+        self.right.last().map_or(0, |run| run.symbol)
+    }
+
+    fn write(&mut self, symbol: u32) {
+        self.head = symbol;
+    }
+
+    fn right(&mut self, n: usize) -> ControlFlow<HaltReason> {
+        self.shift_right(n);
+        if self.position > self.rightmost {
+            self.rightmost = self.position;
+            if self.span() > self.max_cells as u64 {
+                return ControlFlow::Break(HaltReason::TapeLimit);
+            }
+        }
+        ControlFlow::Continue(())
+    }
+
+    fn left(&mut self, n: usize) -> ControlFlow<HaltReason> {
+        let target = self.position - n as i64;
+        let extended = target < self.leftmost;
+        if extended {
+            self.leftmost = target;
+        }
+        if target <= 0 {
+            self.left_edge_contacts += 1;
+        }
+        if target < 0 && !self.two_sided {
+            self.shift_left(self.position as usize);
+            return ControlFlow::Break(HaltReason::LeftEdge);
+        }
+        self.shift_left(n);
+        if extended && self.span() > self.max_cells as u64 {
+            return ControlFlow::Break(HaltReason::TapeLimit);
+        }
+        ControlFlow::Continue(())
+    }
+
+    fn head(&self) -> i64 {
+        self.position
+    }
+
+    fn cell(&self, position: i64) -> u32 {
+        let (stack, mut distance) = match position.cmp(&self.position) {
+            Ordering::Equal => return self.head,
+            Ordering::Less => (&self.left, self.position - position),
+            Ordering::Greater => (&self.right, position - self.position),
+        };
+        for run in stack.iter().rev() {
+            if distance <= run.len as i64 {
+                return run.symbol;
+            }
+            distance -= run.len as i64;
+        }
+        0
+    }
+
+    fn finish(self, final_address: u32, moves: usize, halt_reason: HaltReason) -> Simulated {
+        // the cells from the first non-blank one (or cell 0, or the head, if
+        // they're further left) to the last non-blank one or the end of the
+        // initial tape. the blanks past them don't need to be spelled out
+        let mut cells = self.left.clone();
+        let below: usize = self.left.iter().map(|run| run.len).sum();
+        cells.push(Run {
+            symbol: self.head,
+            len: 1,
+        });
+        cells.extend(self.right.iter().rev());
+        let start = self.position - below as i64;
+
+        let mut first = start;
+        for run in &cells {
+            if run.symbol != 0 {
+                break;
+            }
+            first += run.len as i64;
+        }
+        let last = cells
+            .iter()
+            .scan(start, |position, run| {
+                *position += run.len as i64;
+                Some((*position, run.symbol))
+            })
+            .filter(|&(_, symbol)| symbol != 0)
+            .map(|(end, _)| end)
+            .last()
+            .unwrap_or(0);
+        let low = first.min(0).min(self.position);
+        let high = last.max(self.initial_len as i64);
+
+        let mut tape = Vec::with_capacity((high - low) as usize);
+        let mut position = start;
+        tape.resize((start.max(low) - low) as usize, 0);
+        for run in &cells {
+            let from = position.max(low);
+            let to = (position + run.len as i64).min(high);
+            if to > from {
+                tape.resize(tape.len() + (to - from) as usize, run.symbol);
+            }
+            position += run.len as i64;
+        }
+        tape.resize((high - low) as usize, 0);
+
+        let origin = -low;
+        Simulated {
+            peak_tape_len: tape.len(),
+            cells_allocated: tape.len(),
+            tape,
+            head_position: (self.position + origin) as usize,
+            rightmost_head_position: (self.rightmost + origin) as usize,
+            origin: origin as usize,
+            leftmost_index: self.leftmost,
+            left_edge_contacts: self.left_edge_contacts,
+            final_address,
+            final_state: vec![final_address, 0, 0],
+            moves,
+            halt_reason,
+        }
+    }
+}
+
+// the length of the run of `symbol` made of the head's cell and the runs on
+// `stack`, with `beyond` more blanks past them
+fn run(symbol: u32, stack: &[Run], beyond: usize) -> usize {
+    let mut len = 1usize;
+    for run in stack.iter().rev() {
+        if run.symbol != symbol {
+            return len;
+        }
+        len = len.saturating_add(run.len);
+    }
+    match symbol {
+        0 => len.saturating_add(beyond),
+        _ => len,
+    }
+}
+
+fn push(stack: &mut Vec<Run>, symbol: u32, len: usize) {
+    match stack.last_mut() {
+        Some(run) if run.symbol == symbol => run.len += len,
+        _ => stack.push(Run { symbol, len }),
+    }
+}
+
+// takes one cell off the top of `stack`
+fn pop(stack: &mut Vec<Run>) -> u32 {
+    let Some(run) = stack.last_mut() else {
+        return 0;
+    };
+    let symbol = run.symbol;
+    run.len -= 1;
+    if run.len == 0 {
+        stack.pop();
+    }
+    symbol
+}
+
+// takes `n` cells off the top of `stack`
+fn drop(stack: &mut Vec<Run>, mut n: usize) {
+    while n > 0 {
+        let Some(run) = stack.last_mut() else {
+            return;
+        };
+        if run.len > n {
+            run.len -= n;
+            return;
+        }
+        n -= run.len;
+        stack.pop();
+    }
+}
+
+// moves `n` cells from the top of `from` to the top of `to`, nearest first
+fn transfer(from: &mut Vec<Run>, to: &mut Vec<Run>, mut n: usize) {
+    while n > 0 {
+        let Some(run) = from.last_mut() else {
+            push(to, 0, n);
+            return;
+        };
+        let len = run.len.min(n);
+        let symbol = run.symbol;
+        run.len -= len;
+        if run.len == 0 {
+            from.pop();
+        }
+        push(to, symbol, len);
+        n -= len;
+    }
+}
@@ -0,0 +1,111 @@
+use std::io::{self, BufWriter, Stdout, Write};
+
+use termion::{color, style};
+
+use crate::backend::{HaltReason, Observer, Simulated};
+use crate::compile::Compiled;
+use crate::cycle::Analyzer;
+use crate::output;
+use crate::vm::{Tape, Vm};
+
+// background colors for the non-blank symbols, in the order they're numbered
+const PALETTE: [u8; 12] = [4, 2, 1, 3, 5, 6, 12, 10, 9, 11, 13, 14];
+
+/// Prints the run as a space-time diagram while it goes: a row for every
+/// `every`th configuration and one for the last, with a column for each cell,
+/// so time runs down the page. Each cell shows its symbol's first character
+/// (on a color for the symbol, if output is colored), and the head's cell is
+/// inverted, or a `*` without color
+pub struct SpaceTime<'a> {
+    compiled: &'a Compiled,
+    every: usize,
+    // the cell in the first column, and how many columns there are
+    first: i64,
+    width: usize,
+    out: BufWriter<Stdout>,
+    // the move the last row was for
+    drawn: Option<usize>,
+}
+
+impl<'a> SpaceTime<'a> {
+    pub fn new(compiled: &'a Compiled, every: usize, two_sided: bool, width: usize) -> Self {
+        // a two-sided tape can grow either way, so cell 0 goes in the middle
+        let first = match two_sided {
+            true => -(width as i64 / 2),
+            false => 0,
+        };
+        SpaceTime {
+            compiled,
+            every,
+            first,
+            width,
+            out: BufWriter::new(io::stdout()),
+            drawn: None,
+        }
+    }
+
+    /// Draws the configuration the run stopped in, if it isn't the last row
+    pub fn finish(mut self, simulated: &Simulated) {
+        if self.drawn != Some(simulated.moves) {
+            let origin = simulated.origin as i64;
+            let head = simulated.head_position as i64 - origin;
+            self.row(head, |position| {
+                usize::try_from(position + origin)
+                    .ok()
+                    .and_then(|index| simulated.tape.get(index))
+                    .copied()
+                    .unwrap_or_default()
+            });
+        }
+        self.out.flush().ok();
+    }
+
+    // `cell` is the symbol on a cell, counting from the initial tape's first one
+    fn row(&mut self, head: i64, cell: impl Fn(i64) -> u32) {
+        let mut line = String::new();
+        for position in self.first..self.first + self.width as i64 {
+            let symbol = cell(position);
+            let character = self.compiled.symbols[symbol as usize]
+                .chars()
+                .next()
+                .unwrap_or(' ');
+            if !output::color() {
+                line.push(if position == head { '*' } else { character });
+                continue;
+            }
+            if symbol != 0 {
+                let background = PALETTE[(symbol as usize - 1) % PALETTE.len()];
+                line += &format!(
+                    "{}{}",
+                    color::Bg(color::AnsiValue(background)),
+                    color::Fg(color::Black)
+                );
+            }
+            if position == head {
+                line += style::Invert.as_ref();
+            }
+            line.push(character);
+            if symbol != 0 || position == head {
+                line += &format!(
+                    "{}{}{}",
+                    style::Reset,
+                    color::Bg(color::Reset),
+                    color::Fg(color::Reset)
+                );
+            }
+        }
+        writeln!(self.out, "{}", line.trim_end()).ok();
+    }
+}
+
+impl Analyzer for SpaceTime<'_> {
+    fn check<O: Observer>(&mut self, vm: &Vm<O>) -> Option<HaltReason> {
+        let configuration = vm.configuration();
+        if configuration.moves.is_multiple_of(self.every) {
+            let tape = vm.tape();
+            self.row(configuration.head, |position| tape.cell(position));
+            self.drawn = Some(configuration.moves);
+        }
+        None
+    }
+}
@@ -0,0 +1,176 @@
+use std::cmp;
+
+use termion::{color, style};
+
+use crate::backend;
+use crate::compile;
+use crate::error::Error;
+use crate::output;
+use crate::parse::{StartArgs, TestCase, Unit};
+use crate::tape;
+
+pub struct Options {
+    pub max_moves: usize,
+    pub rust_vm: bool,
+}
+
+pub fn run(
+    unit: &Unit,
+    cases: Vec<TestCase>,
+    start_args: &StartArgs,
+    options: &Options,
+) -> Result<(), Error> {
+    println!("running {} test{}", cases.len(), plural(cases.len()));
+
+    let mut passed = 0;
+    let mut failed = 0;
+    for case in cases {
+        let name = case.name.name;
+        let failures = check(unit, case, start_args, options)?;
+        if failures.is_empty() {
+            passed += 1;
+            print_result(name, "ok", color::Green);
+        } else {
+            failed += 1;
+            print_result(name, "FAILED", color::Red);
+            for failure in failures {
+                println!("    {failure}");
+            }
+        }
+    }
+
+    println!();
+    if failed == 0 {
+        println!("test result: ok. {passed} passed; {failed} failed");
+        Ok(())
+    } else {
+        println!("test result: FAILED. {passed} passed; {failed} failed");
+        Err(Error::new(
+            format!("{failed} test{} failed", plural(failed)),
+            None,
+        ))
+    }
+}
+
+fn check(
+    unit: &Unit,
+    case: TestCase,
+    start_args: &StartArgs,
+    options: &Options,
+) -> Result<Vec<String>, Error> {
+    let mut failures = Vec::new();
+
+    if let Some(max) = case.max_states {
+        if unit.states.len() > max {
+            failures.push(format!(
+                "machine has {} states (max_states is {max})",
+                unit.states.len()
+            ));
+        }
+    }
+
+    let compiled = compile::compile(unit.clone(), case.tape, start_args.clone())?;
+
+    // run one extra move so we can tell a machine that halts on its last
+    // allowed move from one that would keep going
+    let limit = case
+        .max_moves
+        .map_or(options.max_moves, |max| max.saturating_add(1));
+    let simulated = backend::select(options.rust_vm).run(
+        &compiled.bytes,
+        &compiled.tape,
+        &backend::Options {
+            max_moves: limit,
+            trim: backend::Trim::TrailingBlanks,
+            table: true,
+            two_sided: false,
+            max_tape_cells: case.max_tape_cells.unwrap_or(usize::MAX),
+            block_size: None,
+            tape_repr: backend::TapeRepr::Dense,
+            tape_backing: backend::TapeBacking::Memory,
+            transpositions: None,
+            dispatch: None,
+        },
+    )?;
+
+    let halted = !matches!(
+        simulated.halt_reason,
+        backend::HaltReason::MaxMoves | backend::HaltReason::TapeLimit
+    );
+    if simulated.halt_reason == backend::HaltReason::MaxMoves {
+        failures.push(match case.max_moves {
+            Some(max) => format!("took more than {max} moves (max_moves is {max})"),
+            None => format!("did not halt within {limit} moves"),
+        });
+    }
+
+    if let Some(max) = case.max_tape_cells {
+        // the run stops as soon as the head goes past `max`, so cells the machine
+        // used and then erased (or moved back from) still count
+        if simulated.halt_reason == backend::HaltReason::TapeLimit {
+            failures.push(format!(
+                "used more than {max} tape cells (max_tape_cells is {max})"
+            ));
+        } else {
+            let cells = cmp::max(simulated.tape.len(), simulated.rightmost_head_position + 1);
+            if cells > max {
+                failures.push(format!("used {cells} tape cells (max_tape_cells is {max})"));
+            }
+        }
+    }
+
+    // the final tape of a machine that was cut off isn't meaningful
+    if let Some(expect) = case.expect.filter(|_| halted) {
+        let mut expected: Vec<_> = expect.iter().map(|symbol| symbol.symbol.as_str()).collect();
+        while let Some(&"") = expected.last() {
+            expected.pop();
+        }
+
+        let found: Vec<_> = simulated
+            .tape
+            .iter()
+            .map(|&i| compiled.symbols[i as usize].as_str())
+            .collect();
+
+        if expected != found {
+            failures.push(format!(
+                "expected tape {}, found {}",
+                tape_literal(&expected),
+                tape_literal(&found)
+            ));
+        }
+    }
+
+    Ok(failures)
+}
+
+fn tape_literal(symbols: &[&str]) -> String {
+    if symbols.is_empty() {
+        "(empty)".to_string()
+    } else {
+        let literals: Vec<_> = symbols.iter().map(|symbol| tape::literal(symbol)).collect();
+        literals.join(" ")
+    }
+}
+
+fn print_result(name: &str, result: &str, result_color: impl color::Color) {
+    if !output::color() {
+        println!("test {name} ... {result}");
+    } else {
+        println!(
+            "test {name} ... {}{}{result}{}{}",
+            style::Bold,
+            color::Fg(result_color),
+            style::Reset,
+            color::Fg(color::Reset)
+        );
+    }
+}
+
+fn plural(n: usize) -> &'static str {
+    if n == 1 {
+        ""
+    } else {
+        "s"
+    }
+}
@@ -0,0 +1,75 @@
+use std::ops::Range;
+
+use crate::backend::Simulated;
+use crate::metrics::metric;
+use crate::tape;
+
+pub fn print(simulated: &Simulated, symbols: &[String]) {
+    // positions count from the initial tape's first cell, even on a two-sided tape
+    let origin = simulated.origin as i64;
+    metric(
+        "rightmost head position",
+        simulated.rightmost_head_position as i64 - origin,
+    );
+    metric("leftmost index", simulated.leftmost_index);
+    metric("left edge contacts", simulated.left_edge_contacts);
+    metric("peak tape length", simulated.peak_tape_len);
+    metric("cells allocated", simulated.cells_allocated);
+    metric("final tape length", simulated.tape.len());
+
+    // how many of each symbol the final tape has, most common first
+    let mut counts = vec![0usize; symbols.len()];
+    for &symbol in &simulated.tape {
+        counts[symbol as usize] += 1;
+    }
+    metric("non-blank cells", simulated.tape.len() - counts[0]);
+    let mut histogram: Vec<_> = (1..symbols.len()).filter(|&id| counts[id] > 0).collect();
+    histogram.sort_by_key(|&id| std::cmp::Reverse(counts[id]));
+    let histogram: Vec<_> = histogram
+        .into_iter()
+        .map(|id| format!("{} {}", tape::literal(&symbols[id]), counts[id]))
+        .collect();
+    if !histogram.is_empty() {
+        metric("symbols on the final tape", histogram.join(", "));
+    }
+
+    let segments: Vec<_> = segments(&simulated.tape)
+        .into_iter()
+        .map(|segment| {
+            format!(
+                "[{}..{}]",
+                segment.start as i64 - origin,
+                segment.end as i64 - origin
+            )
+        })
+        .collect();
+    if segments.is_empty() {
+        metric("non-blank segments", 0);
+    } else {
+        metric(
+            "non-blank segments",
+            format!("{} ({})", segments.len(), segments.join(", ")),
+        );
+    }
+    println!();
+}
+
+/// The maximal runs of non-blank cells on the tape, as half-open ranges of cell indices
+pub fn segments(tape: &[u32]) -> Vec<Range<usize>> {
+    let mut segments = Vec::new();
+    let mut start = None;
+    for (i, &symbol) in tape.iter().enumerate() {
+        match (start, symbol) {
+            (None, 0) | (Some(_), 1..) => {}
+            (None, _) => start = Some(i),
+            (Some(first), 0) => {
+                segments.push(first..i);
+                start = None;
+            }
+        }
+    }
+    if let Some(first) = start {
+        segments.push(first..tape.len());
+    }
+    segments
+}
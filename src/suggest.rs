@@ -0,0 +1,49 @@
+use std::cmp;
+
+const MAX_SUGGESTIONS: usize = 3;
+
+pub fn closest<'a, T>(target: &str, candidates: impl IntoIterator<Item = (&'a str, T)>) -> Vec<T> {
+    let max_distance = cmp::max(1, target.chars().count() / 3);
+
+    let mut best = Vec::new();
+    let mut best_distance = max_distance + 1;
+    for (name, candidate) in candidates {
+        let distance = distance(target, name);
+        if distance < best_distance {
+            best_distance = distance;
+            best.clear();
+        }
+        if distance == best_distance {
+            best.push(candidate);
+        }
+    }
+
+    best.truncate(MAX_SUGGESTIONS);
+    best
+}
+
+pub fn did_you_mean(suggestions: &[String]) -> Option<String> {
+    match suggestions {
+        [] => None,
+        [one] => Some(format!("did you mean `{one}`?")),
+        [init @ .., last] => {
+            let init: Vec<_> = init.iter().map(|s| format!("`{s}`")).collect();
+            Some(format!("did you mean {} or `{last}`?", init.join(", ")))
+        }
+    }
+}
+
+fn distance(a: &str, b: &str) -> usize {
+    let b: Vec<_> = b.chars().collect();
+    let mut row: Vec<_> = (0..=b.len()).collect();
+    for (i, a) in a.chars().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+        for (j, &b) in b.iter().enumerate() {
+            let substitution = diagonal + usize::from(a != b);
+            diagonal = row[j + 1];
+            row[j + 1] = cmp::min(substitution, cmp::min(row[j], row[j + 1]) + 1);
+        }
+    }
+    row[b.len()]
+}
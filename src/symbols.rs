@@ -0,0 +1,32 @@
+use std::fs;
+use std::path::Path;
+
+use crate::error::Error;
+
+/// Reads a symbol table written by `to_json`
+pub fn read(path: &Path) -> Result<Vec<String>, Error> {
+    let Ok(json) = fs::read_to_string(path) else {
+        return Err(Error::new(
+            format!("couldn't read file {}", path.display()),
+            None,
+        ));
+    };
+    parse(&json, path)
+}
+
+pub fn parse(json: &str, path: &Path) -> Result<Vec<String>, Error> {
+    serde_json::from_str(json).map_err(|error| {
+        Error::new(
+            format!("{} is not a valid symbols file ({error})", path.display()),
+            None,
+        )
+        .with_help(Some(
+            "a symbols file is a JSON array of symbols, starting with the blank `\"\"`".to_string(),
+        ))
+    })
+}
+
+/// The symbol table as a JSON array, where a symbol's id is its index
+pub fn to_json(symbols: &[String]) -> String {
+    serde_json::to_string_pretty(symbols).unwrap() + "\n"
+}
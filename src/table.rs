@@ -0,0 +1,601 @@
+use std::collections::HashMap;
+use std::hash::{BuildHasherDefault, Hasher};
+use std::mem;
+use std::ops::ControlFlow;
+
+use crate::backend::{self, HaltReason, Options, Simulated};
+use crate::bytecode::{self as bc, Operand, Version};
+use crate::rle::RleTape;
+use crate::vm::{DenseTape, Tape};
+
+// bigger tables take longer to fill in than most machines take to run
+const MAX_CELLS: usize = 1 << 22;
+// a cell for a state and symbol that halts, or a jump to the halt state
+pub(crate) const HALT: u32 = u32::MAX;
+// `run_blocks` stops remembering jumps past this many
+const MAX_JUMPS: usize = 1 << 20;
+
+/// A machine without state or symbol parameters as a flat `state x symbol`
+/// transition table, so each move is one lookup instead of walking the state's
+/// arms. It runs the same moves as the bytecode VMs
+pub struct Table {
+    // the column count: one more than the largest symbol in the code or on the tape
+    pub(crate) width: usize,
+    // `width` cells per state, each an index into `arms` or `HALT`
+    pub(crate) cells: Vec<u32>,
+    pub(crate) arms: Vec<Arm>,
+    // where each state starts in the bytecode, for `Simulated::final_address`
+    addresses: Vec<u32>,
+    pub(crate) entry: Arm,
+    // the address `entry` runs at
+    pub(crate) entry_address: u32,
+}
+
+pub(crate) struct Arm {
+    pub(crate) ops: Vec<Op>,
+    // a state index or `HALT`
+    pub(crate) next: u32,
+}
+
+// what the machine does from entering a block of cells in some state, on some
+// cell, until the head leaves it. offsets count from the block's first cell
+struct Jump {
+    // the block's cells afterwards
+    cells: Vec<u32>,
+    next: u32,
+    moves: usize,
+    // the furthest the head went each way, and the cell it left the block for
+    low: i64,
+    high: i64,
+    exit: i64,
+}
+
+impl Jump {
+    // makes the jump from `position` in the block starting at `start`. the head
+    // goes as far each way as it did the first time, so the tape grows the same
+    // way and `Simulated` gets the same head positions
+    fn apply(&self, tape: &mut DenseTape, position: i64, start: i64) -> ControlFlow<HaltReason> {
+        let (low, high) = (start + self.low, start + self.high);
+        tape.left((position - low) as usize)?;
+        tape.right((high - low) as usize)?;
+        let first = (start + tape.origin as i64) as usize;
+        match tape.tape.get_mut(first..first + self.cells.len()) {
+            Some(cells) => cells.copy_from_slice(&self.cells),
+            None => {
+                for (position, &symbol) in (start..).zip(&self.cells) {
+                    tape.write_at(position, symbol);
+                }
+            }
+        }
+        tape.left((high - start - self.exit) as usize)
+    }
+
+    // after a jump from `offset` in the block starting at `start` that ends on
+    // the same cell of the next block over, in the state it started in, it makes
+    // the jump again as many times as the blocks it sweeps across are the same as
+    // `before` was, and returns how many. the head only has to move at the end,
+    // as long as it never goes further than the cell it leaves each block for
+    fn sweep(
+        &self,
+        tape: &mut DenseTape,
+        start: i64,
+        offset: i64,
+        before: &[u32],
+        moves: usize,
+        options: &Options,
+    ) -> ControlFlow<HaltReason, usize> {
+        let block = before.len() as i64;
+        let step = self.exit - offset;
+        let furthest = if step > 0 { self.high } else { self.low };
+        if step.abs() != block || furthest != self.exit {
+            return ControlFlow::Continue(0);
+        }
+        let origin = tape.origin as i64;
+        let mut count = 0;
+        let mut next = start + step;
+        while moves + (count + 1) * self.moves <= options.max_moves {
+            // the same limits as a single jump
+            let span = (next + self.high + (-tape.leftmost).max(0) + 1) as u64;
+            if (step > 0 && span > options.max_tape_cells as u64) || next + self.low <= 0 {
+                break;
+            }
+            let first = (next + origin) as usize;
+            let Some(cells) = tape.tape.get_mut(first..first + block as usize) else {
+                break;
+            };
+            if cells != before {
+                break;
+            }
+            cells.copy_from_slice(&self.cells);
+            count += 1;
+            next += step;
+        }
+        if count > 0 {
+            let distance = count * block as usize;
+            match step > 0 {
+                true => tape.right(distance)?,
+                false => tape.left(distance)?,
+            }
+        }
+        ControlFlow::Continue(count)
+    }
+}
+
+// the hash rustc uses for its own tables, which is much quicker than the default
+// one for short keys like `run_blocks`'s (and they don't come from anyone who'd
+// want to make it slow)
+#[derive(Default)]
+struct FxHasher(u64);
+
+impl FxHasher {
+    fn add(&mut self, word: u64) {
+        self.0 = (self.0.rotate_left(5) ^ word).wrapping_mul(0x517c_c1b7_2722_0a95);
+    }
+}
+
+impl Hasher for FxHasher {
+    fn write(&mut self, bytes: &[u8]) {
+        for chunk in bytes.chunks(8) {
+            let mut word = [0; 8];
+            word[..chunk.len()].copy_from_slice(chunk);
+            self.add(u64::from_le_bytes(word));
+        }
+    }
+
+    fn write_usize(&mut self, n: usize) {
+        self.add(n as u64);
+    }
+
+    fn finish(&self) -> u64 {
+        self.0
+    }
+}
+
+#[derive(Clone, Copy)]
+pub(crate) enum Op {
+    Write(u32),
+    // writes the symbol the move started on
+    WriteRead,
+    Left(usize),
+    Right(usize),
+}
+
+impl Table {
+    /// Builds the table, or returns `None` if the machine passes arguments, looks
+    /// ahead or would need too big a table
+    pub fn build(bytes: &[u8], tape: &[u32]) -> Option<Table> {
+        let version = Version::of(bytes);
+        let entry_address = u32::from_le_bytes(bytes[2..6].try_into().unwrap());
+        let mut reader = Reader {
+            bytes,
+            ip: version.code_start() as usize,
+            version,
+            max_symbol: tape.iter().copied().max().unwrap_or(0),
+        };
+
+        // each state's arms, as (the symbol they match or `None` for `_`, the arm)
+        let mut states: Vec<Vec<(Option<u32>, Option<Arm>)>> = Vec::new();
+        let mut addresses = Vec::new();
+        while reader.ip < entry_address as usize {
+            addresses.push(reader.ip as u32);
+            let mut arms = Vec::new();
+            loop {
+                match reader.opcode()? {
+                    opcode @ (bc::COMPARE_VAL | bc::COMPARE_VAL32) => {
+                        let symbol = reader.symbol();
+                        // the skip always goes to the next arm
+                        reader.operand(match opcode {
+                            bc::COMPARE_VAL => Operand::Skip,
+                            _ => Operand::Skip32,
+                        });
+                        arms.push((Some(symbol), Some(reader.body(false)?)));
+                    }
+                    bc::OTHER => {
+                        arms.push((None, Some(reader.body(true)?)));
+                        break;
+                    }
+                    bc::HALT => {
+                        arms.push((None, None));
+                        break;
+                    }
+                    _ => return None,
+                }
+            }
+            states.push(arms);
+        }
+        let entry = reader.body(false)?;
+        if reader.ip != bytes.len() {
+            return None;
+        }
+
+        let width = reader.max_symbol as usize + 1;
+        if states.len().checked_mul(width)? > MAX_CELLS {
+            return None;
+        }
+        // jumps go to addresses, but the table is indexed by state
+        let index = |address: u32| match address {
+            bc::HALT_ADDRESS => Some(HALT),
+            _ => addresses
+                .binary_search(&address)
+                .ok()
+                .map(|index| index as u32),
+        };
+        let mut cells = vec![HALT; states.len() * width];
+        let mut arms = Vec::new();
+        for (state, state_arms) in states.into_iter().enumerate() {
+            let row = &mut cells[state * width..(state + 1) * width];
+            // later arms only fill the cells earlier ones didn't match
+            let mut matched = vec![false; width];
+            for (symbol, arm) in state_arms {
+                let arm = match arm {
+                    Some(mut arm) => {
+                        arm.next = index(arm.next)?;
+                        arms.push(arm);
+                        (arms.len() - 1) as u32
+                    }
+                    None => HALT,
+                };
+                match symbol {
+                    Some(symbol) => {
+                        let symbol = symbol as usize;
+                        if !matched[symbol] {
+                            matched[symbol] = true;
+                            row[symbol] = arm;
+                        }
+                    }
+                    None => {
+                        for (cell, matched) in row.iter_mut().zip(&matched) {
+                            if !matched {
+                                *cell = arm;
+                            }
+                        }
+                    }
+                }
+            }
+        }
+        let mut entry = entry;
+        entry.next = index(entry.next)?;
+
+        Some(Table {
+            width,
+            cells,
+            arms,
+            addresses,
+            entry,
+            entry_address,
+        })
+    }
+
+    pub fn run(&self, mut tape: impl Tape, options: &Options) -> Simulated {
+        let mut moves = 0;
+        if let ControlFlow::Break(reason) = run_arm(&self.entry, &mut tape, 0) {
+            return tape.finish(self.entry_address, moves, reason);
+        }
+        let mut state = self.entry.next;
+        // checked in the same order as the VMs, which only find out they halted
+        // when they try to make the next move
+        let reason = loop {
+            if moves == options.max_moves {
+                break HaltReason::MaxMoves;
+            }
+            if moves.is_multiple_of(backend::INTERRUPT_CHECK_INTERVAL) && backend::interrupted() {
+                break HaltReason::Interrupted;
+            }
+            if state == HALT {
+                break HaltReason::Halted;
+            }
+            let symbol = tape.read();
+            let cell = self.cells[state as usize * self.width + symbol as usize];
+            if cell == HALT {
+                break HaltReason::Halted;
+            }
+            let arm = &self.arms[cell as usize];
+            if let ControlFlow::Break(reason) = run_arm(arm, &mut tape, symbol) {
+                break reason;
+            }
+            state = arm.next;
+            moves += 1;
+        };
+        tape.finish(self.address(state), moves, reason)
+    }
+
+    /// Where a state (or `HALT`) starts in the bytecode
+    pub(crate) fn address(&self, state: u32) -> u32 {
+        match state {
+            HALT => bc::HALT_ADDRESS,
+            _ => self.addresses[state as usize],
+        }
+    }
+
+    /// Runs like `run`, but `block` cells at a time. The first time the machine
+    /// enters a block with some contents, in some state and on some cell, it
+    /// runs one move at a time until the head leaves the block, and remembers
+    /// what happened. The next time, it jumps straight to the end. It makes the
+    /// same moves as `run`, but only has to work them out once for each block,
+    /// which speeds up machines that keep doing the same thing to the same
+    /// patterns
+    pub fn run_blocks(&self, tape: &[u32], options: &Options, block: usize) -> Simulated {
+        let mut tape = DenseTape::new(tape.to_vec(), options.two_sided);
+        tape.max_cells = options.max_tape_cells;
+        let mut moves = 0;
+        if let ControlFlow::Break(reason) = run_arm(&self.entry, &mut tape, 0) {
+            return tape.finish(self.entry_address, moves, reason);
+        }
+        let mut state = self.entry.next;
+        let block = block as i64;
+        let mut jumps = Vec::new();
+        let mut indices: HashMap<Vec<u32>, usize, BuildHasherDefault<FxHasher>> =
+            HashMap::default();
+        // the state, the head's offset in the block and the block's cells
+        let mut key = Vec::new();
+        // the last jump and its key. sweeping across the same pattern makes the
+        // same jump over and over, which is cheaper to check for than to look up
+        let mut last = None;
+        let mut last_key = Vec::new();
+        let reason = 'run: loop {
+            let position = tape.head as i64 - tape.origin as i64;
+            let start = position - position.rem_euclid(block);
+            // nothing that happens in the blocks from cell 0 left is cached (see
+            // the left edge contacts below)
+            let cacheable = start > 0 && state != HALT;
+            if cacheable {
+                key.clear();
+                key.push(state);
+                key.push((position - start) as u32);
+                let first = start + tape.origin as i64;
+                match tape.tape.get(first as usize..(first + block) as usize) {
+                    Some(cells) => key.extend_from_slice(cells),
+                    None => key.extend((start..start + block).map(|position| tape.cell(position))),
+                }
+                let found = match last {
+                    Some(index) if key == last_key => Some(index),
+                    _ => indices.get(key.as_slice()).copied(),
+                };
+                if let Some(index) = found {
+                    let jump: &Jump = &jumps[index];
+                    let right = (tape.rightmost as i64 - tape.origin as i64).max(start + jump.high);
+                    let left = tape.leftmost.min(start + jump.low);
+                    // a jump that passes a limit goes one move at a time, to stop
+                    // on the same move as `run`. so does one that gets to cell 0,
+                    // since it can't tell how many left edge contacts there were
+                    let span = (right + (-left).max(0) + 1) as u64;
+                    if moves + jump.moves <= options.max_moves
+                        && span <= options.max_tape_cells as u64
+                        && start + jump.low > 0
+                        && !backend::interrupted()
+                    {
+                        if let ControlFlow::Break(reason) = jump.apply(&mut tape, position, start) {
+                            break reason;
+                        }
+                        moves += jump.moves;
+                        if jump.next == state {
+                            let offset = position - start;
+                            match jump.sweep(&mut tape, start, offset, &key[2..], moves, options) {
+                                ControlFlow::Break(reason) => break reason,
+                                ControlFlow::Continue(count) => moves += count * jump.moves,
+                            }
+                        }
+                        state = jump.next;
+                        last = Some(index);
+                        mem::swap(&mut key, &mut last_key);
+                        continue;
+                    }
+                }
+            }
+
+            // one move at a time until the head leaves the block
+            let (first_moves, contacts) = (moves, tape.left_edge_contacts);
+            let (mut low, mut high) = (position, position);
+            let mut outside = false;
+            loop {
+                if moves == options.max_moves {
+                    break 'run HaltReason::MaxMoves;
+                }
+                if moves.is_multiple_of(backend::INTERRUPT_CHECK_INTERVAL) && backend::interrupted()
+                {
+                    break 'run HaltReason::Interrupted;
+                }
+                if state == HALT {
+                    break 'run HaltReason::Halted;
+                }
+                let symbol = tape.read();
+                let cell = self.cells[state as usize * self.width + symbol as usize];
+                if cell == HALT {
+                    break 'run HaltReason::Halted;
+                }
+                let arm = &self.arms[cell as usize];
+                for &op in &arm.ops {
+                    let moved = match op {
+                        Op::Write(symbol) => {
+                            tape.write(symbol);
+                            ControlFlow::Continue(())
+                        }
+                        Op::WriteRead => {
+                            tape.write(symbol);
+                            ControlFlow::Continue(())
+                        }
+                        Op::Left(n) => tape.left(n),
+                        Op::Right(n) => tape.right(n),
+                    };
+                    if let ControlFlow::Break(reason) = moved {
+                        break 'run reason;
+                    }
+                    let head = tape.head as i64 - tape.origin as i64;
+                    low = low.min(head);
+                    high = high.max(head);
+                    // a write outside the block makes the jump depend on more
+                    // than the block
+                    outside |= matches!(op, Op::Write(_) | Op::WriteRead)
+                        && !(start..start + block).contains(&head);
+                }
+                state = arm.next;
+                moves += 1;
+                let head = tape.head as i64 - tape.origin as i64;
+                if !(start..start + block).contains(&head) {
+                    if cacheable
+                        && !outside
+                        && tape.left_edge_contacts == contacts
+                        && jumps.len() < MAX_JUMPS
+                    {
+                        let cells = (start..start + block)
+                            .map(|position| tape.cell(position))
+                            .collect();
+                        indices.insert(key.clone(), jumps.len());
+                        jumps.push(Jump {
+                            cells,
+                            next: state,
+                            moves: moves - first_moves,
+                            low: low - start,
+                            high: high - start,
+                            exit: head - start,
+                        });
+                    }
+                    break;
+                }
+            }
+        };
+        tape.finish(self.address(state), moves, reason)
+    }
+
+    /// Runs like `run`, but on an `RleTape`, and makes all the moves of an arm
+    /// that loops back to its own state, writes and moves one cell, across a
+    /// run of the symbol it matches in one step. Returns the result and how
+    /// many steps it took
+    pub fn run_chains(&self, tape: &[u32], options: &Options) -> (Simulated, usize) {
+        let mut tape = RleTape::new(tape, options.two_sided, options.max_tape_cells);
+        let mut moves = 0;
+        let mut steps = 0;
+        if let ControlFlow::Break(reason) = run_arm(&self.entry, &mut tape, 0) {
+            return (tape.finish(self.entry_address, moves, reason), steps);
+        }
+        let mut state = self.entry.next;
+        let reason = loop {
+            if moves == options.max_moves {
+                break HaltReason::MaxMoves;
+            }
+            if steps.is_multiple_of(backend::INTERRUPT_CHECK_INTERVAL) && backend::interrupted() {
+                break HaltReason::Interrupted;
+            }
+            if state == HALT {
+                break HaltReason::Halted;
+            }
+            let symbol = tape.read();
+            let cell = self.cells[state as usize * self.width + symbol as usize];
+            if cell == HALT {
+                break HaltReason::Halted;
+            }
+            let arm = &self.arms[cell as usize];
+            steps += 1;
+            if arm.next == state {
+                if let Some((write, right)) = arm.chain() {
+                    // the run stops short of a move that would halt it, which
+                    // is left to `run_arm`
+                    let count = tape.sweepable(right).min(options.max_moves - moves);
+                    if count > 0 {
+                        tape.sweep(right, write.unwrap_or(symbol), count);
+                        moves += count;
+                        continue;
+                    }
+                }
+            }
+            if let ControlFlow::Break(reason) = run_arm(arm, &mut tape, symbol) {
+                break reason;
+            }
+            state = arm.next;
+            moves += 1;
+        };
+        (tape.finish(self.address(state), moves, reason), steps)
+    }
+}
+
+impl Arm {
+    // the symbol it writes (if it doesn't write back the one it read) and
+    // whether it goes right, if all it does is that and move one cell
+    fn chain(&self) -> Option<(Option<u32>, bool)> {
+        let (write, step) = match self.ops[..] {
+            [step] => (None, step),
+            [Op::Write(symbol), step] => (Some(symbol), step),
+            [Op::WriteRead, step] => (None, step),
+            _ => return None,
+        };
+        match step {
+            Op::Left(1) => Some((write, false)),
+            Op::Right(1) => Some((write, true)),
+            _ => None,
+        }
+    }
+}
+
+// stops if the head runs off the left edge or past the tape cell limit
+pub(crate) fn run_arm(arm: &Arm, tape: &mut impl Tape, read: u32) -> ControlFlow<HaltReason> {
+    for &op in &arm.ops {
+        match op {
+            Op::Write(symbol) => tape.write(symbol),
+            Op::WriteRead => tape.write(read),
+            Op::Left(n) => tape.left(n)?,
+            Op::Right(n) => tape.right(n)?,
+        }
+    }
+    ControlFlow::Continue(())
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    ip: usize,
+    version: Version,
+    max_symbol: u32,
+}
+
+impl Reader<'_> {
+    fn opcode(&mut self) -> Option<u8> {
+        let opcode = *self.bytes.get(self.ip)?;
+        self.ip += 1;
+        Some(opcode)
+    }
+
+    fn operand(&mut self, operand: Operand) -> u32 {
+        let size = operand.size(self.version);
+        let value = self.bytes[self.ip..self.ip + size]
+            .iter()
+            .rev()
+            .fold(0, |value, &byte| value << 8 | byte as u32);
+        self.ip += size;
+        value
+    }
+
+    fn symbol(&mut self) -> u32 {
+        let symbol = self.operand(Operand::Symbol);
+        self.max_symbol = self.max_symbol.max(symbol);
+        symbol
+    }
+
+    // a right hand side, with the address it jumps to in `next`. `bound` is
+    // whether the arm bound the symbol it matched
+    fn body(&mut self, bound: bool) -> Option<Arm> {
+        let mut ops = Vec::new();
+        loop {
+            let opcode = self.opcode()?;
+            let (write, step) = match opcode {
+                bc::LEFT => (None, Some(Op::Left(1))),
+                bc::RIGHT => (None, Some(Op::Right(1))),
+                bc::LEFT_N => (None, Some(Op::Left(self.operand(Operand::U8) as usize))),
+                bc::RIGHT_N => (None, Some(Op::Right(self.operand(Operand::U8) as usize))),
+                bc::LEFT_N16 => (None, Some(Op::Left(self.operand(Operand::U16) as usize))),
+                bc::RIGHT_N16 => (None, Some(Op::Right(self.operand(Operand::U16) as usize))),
+                bc::WRITE_VAL => (Some(Op::Write(self.symbol())), None),
+                bc::WRITE_VAL_LEFT => (Some(Op::Write(self.symbol())), Some(Op::Left(1))),
+                bc::WRITE_VAL_RIGHT => (Some(Op::Write(self.symbol())), Some(Op::Right(1))),
+                bc::WRITE_BOUND if bound => (Some(Op::WriteRead), None),
+                bc::WRITE_BOUND_LEFT if bound => (Some(Op::WriteRead), Some(Op::Left(1))),
+                bc::WRITE_BOUND_RIGHT if bound => (Some(Op::WriteRead), Some(Op::Right(1))),
+                bc::FINAL_STATE => {
+                    let next = self.operand(Operand::Address);
+                    return Some(Arm { ops, next });
+                }
+                _ => return None,
+            };
+            ops.extend(write);
+            ops.extend(step);
+        }
+    }
+}
@@ -1,5 +1,7 @@
 use std::cmp;
+use std::fmt::Write;
 use std::iter::Peekable;
+use std::str::FromStr;
 
 use unicode_segmentation::UnicodeSegmentation;
 
@@ -8,19 +10,75 @@ use crate::int::Int;
 
 const EXTRA_DIGITS: usize = 10;
 
-pub fn dump(tape: &[&str], terminal_width: usize) {
+/// Where the head starts: a cell, counting from the initial tape's first one,
+/// or the initial tape's last cell
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HeadStart {
+    Cell(i64),
+    End,
+}
+
+impl HeadStart {
+    pub fn cell(self, tape_len: usize) -> i64 {
+        match self {
+            HeadStart::Cell(cell) => cell,
+            HeadStart::End => tape_len.saturating_sub(1) as i64,
+        }
+    }
+}
+
+impl FromStr for HeadStart {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "end" => Ok(HeadStart::End),
+            _ => s
+                .parse()
+                .map(HeadStart::Cell)
+                .map_err(|_| "expected a cell number or `end`".to_string()),
+        }
+    }
+}
+
+/// Prints the tape as rows of boxed cells. The cell at index `origin` (the first
+/// cell of the initial tape, when a two-sided tape grew left of it) gets a double
+/// border
+pub fn dump(tape: &[&str], terminal_width: usize, origin: Option<usize>) {
+    print!("{}", render(tape, terminal_width, origin));
+}
+
+/// What `dump` prints
+pub fn render(tape: &[&str], terminal_width: usize, origin: Option<usize>) -> String {
+    let mut out = String::new();
     if tape.is_empty() {
-        println!("┬──┬──┬");
-        println!("│  │  │");
-        println!("┴──┴──┴");
+        out += "┬──┬──┬\n│  │  │\n┴──┴──┴\n";
     }
 
     let mut symbols = tape.iter().copied().peekable();
+    let mut start = 0;
     while symbols.peek().is_some() {
         let line = next_line(&mut symbols, terminal_width);
-        print_line(&line);
+        let marked = origin
+            .and_then(|origin| origin.checked_sub(start))
+            .filter(|&i| i < line.len());
+        render_line(&mut out, &line, marked);
+        start += line.len();
+    }
+    out.push('\n');
+    out
+}
+
+pub fn literal(symbol: &str) -> String {
+    let mut literal = String::from('\'');
+    for c in symbol.chars() {
+        if c == '\'' || c == '\\' {
+            literal.push('\\');
+        }
+        literal.push(c);
     }
-    println!();
+    literal.push('\'');
+    literal
 }
 
 fn next_line<'a>(
@@ -32,7 +90,7 @@ fn next_line<'a>(
     while line.is_empty()
         || symbols
             .peek()
-            .map_or(false, |s| len + s.graphemes(true).count() + 3 <= width)
+            .is_some_and(|s| len + s.graphemes(true).count() + 3 <= width)
     {
         let symbol = symbols.next().unwrap();
         line.push(symbol);
@@ -41,24 +99,36 @@ fn next_line<'a>(
     line
 }
 
-fn print_line(symbols: &[&str]) {
-    print!("┬");
-    for symbol in symbols {
-        print!("{}┬", "─".repeat(2 + symbol.graphemes(true).count()));
+fn render_line(out: &mut String, symbols: &[&str], marked: Option<usize>) {
+    let border = |i: usize| if Some(i) == marked { "═" } else { "─" };
+
+    out.push('┬');
+    for (i, symbol) in symbols.iter().enumerate() {
+        write!(
+            out,
+            "{}┬",
+            border(i).repeat(2 + symbol.graphemes(true).count())
+        )
+        .ok();
     }
-    println!();
+    out.push('\n');
 
-    print!("│");
+    out.push('│');
     for symbol in symbols {
-        print!(" {symbol} │");
+        write!(out, " {symbol} │").ok();
     }
-    println!();
+    out.push('\n');
 
-    print!("┴");
-    for symbol in symbols {
-        print!("{}┴", "─".repeat(2 + symbol.graphemes(true).count()));
+    out.push('┴');
+    for (i, symbol) in symbols.iter().enumerate() {
+        write!(
+            out,
+            "{}┴",
+            border(i).repeat(2 + symbol.graphemes(true).count())
+        )
+        .ok();
     }
-    println!();
+    out.push('\n');
 }
 
 pub fn parse_decimal(
@@ -0,0 +1,326 @@
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+use crate::compile::{ArmInfo, Compiled, SignatureBuf, SourceRange, StateInfo};
+use crate::error::Error;
+use crate::lex::SpanBuf;
+use crate::parse::Symbol;
+use crate::tape;
+
+// a compiled machine, so it can be run again without the front end. every number
+// is little endian and every string is a u32 length followed by UTF-8:
+//   magic, version (u32)
+//   bytecode: length (u32), bytes
+//   symbols: count (u32), strings
+//   states: count (u32), then address (u32), name, doc line count (u32), doc lines
+//   signatures: count (u32), then name, state count (u16), symbol count (u16), address (u32)
+//   tape: length (u32), symbol ids (u32)
+//   debug info (optional): arm count (u32), then offset (u32), state address (u32),
+//     span; source range count (u32), then start (u32), end (u32), state index (u32),
+//     arm index (u32, u32::MAX for a halt), span
+// a span is its path, line (u32), column (u32), and the text before, in and after it
+// on its line. version 1 files (with u8 parameter counts and u16 symbol ids) and
+// version 2 files (without debug info) can still be read
+const MAGIC: &[u8; 4] = b"TMB\0";
+const VERSION: u32 = 3;
+
+pub fn is_tmb(path: &Path) -> bool {
+    let mut magic = [0; 4];
+    File::open(path).is_ok_and(|mut file| file.read_exact(&mut magic).is_ok() && &magic == MAGIC)
+}
+
+/// Writes `compiled` to `path`, with the arms and source map if `debug_info` is set
+pub fn write(compiled: &Compiled, path: &Path, debug_info: bool) -> Result<(), Error> {
+    let mut writer = Writer(Vec::new());
+    writer.0.extend(MAGIC);
+    writer.u32(VERSION);
+
+    writer.u32(compiled.bytes.len() as u32);
+    writer.0.extend(&compiled.bytes);
+
+    writer.u32(compiled.symbols.len() as u32);
+    for symbol in &compiled.symbols {
+        writer.string(symbol);
+    }
+
+    let mut states: Vec<_> = compiled.states.iter().collect();
+    states.sort_by_key(|&(&address, _)| address);
+    writer.u32(states.len() as u32);
+    for (&address, info) in states {
+        writer.u32(address);
+        writer.string(&info.name);
+        writer.u32(info.doc.len() as u32);
+        for line in &info.doc {
+            writer.string(line);
+        }
+    }
+
+    let mut signatures: Vec<_> = compiled.signatures.iter().collect();
+    // several signatures can share a state, so the name keeps the order stable
+    signatures.sort_by_key(|&(signature, &address)| {
+        (
+            address,
+            signature.name.as_str(),
+            signature.states,
+            signature.symbols,
+        )
+    });
+    writer.u32(signatures.len() as u32);
+    for (signature, &address) in signatures {
+        writer.string(&signature.name);
+        writer.0.extend(signature.states.to_le_bytes());
+        writer.0.extend(signature.symbols.to_le_bytes());
+        writer.u32(address);
+    }
+
+    writer.u32(compiled.tape.len() as u32);
+    for &symbol in &compiled.tape {
+        writer.0.extend(symbol.to_le_bytes());
+    }
+
+    // machines from `tml asm` don't have any source to point at
+    if debug_info && !compiled.source_map.is_empty() {
+        writer.u32(compiled.arms.len() as u32);
+        for arm in &compiled.arms {
+            writer.u32(arm.offset);
+            writer.u32(arm.state);
+            writer.span(&arm.span);
+        }
+        writer.u32(compiled.source_map.len() as u32);
+        for range in &compiled.source_map {
+            writer.u32(range.start);
+            writer.u32(range.end);
+            writer.u32(range.state as u32);
+            writer.u32(range.arm.map_or(u32::MAX, |arm| arm as u32));
+            writer.span(&range.span);
+        }
+    }
+
+    fs::write(path, writer.0)
+        .map_err(|_| Error::new(format!("couldn't write file {}", path.display()), None))
+}
+
+/// Loads a machine written by `write`. `arms` and `source_map` are empty unless it
+/// was written with debug info
+pub fn read(path: &Path) -> Result<Compiled, Error> {
+    let Ok(bytes) = fs::read(path) else {
+        return Err(Error::new(
+            format!("couldn't read file {}", path.display()),
+            None,
+        ));
+    };
+    let mut reader = Reader {
+        bytes: &bytes,
+        path,
+    };
+
+    if reader.take(4)? != MAGIC {
+        return Err(Error::new(
+            format!("{} is not a .tmb file", path.display()),
+            None,
+        ));
+    }
+    let version = reader.u32()?;
+    if !(1..=VERSION).contains(&version) {
+        return Err(Error::new(
+            format!(
+                "{} is a version {version} .tmb file (expected version 1 to {VERSION})",
+                path.display()
+            ),
+            None,
+        )
+        .with_help(Some("rebuild it with `tml build`".to_string())));
+    }
+
+    let len = reader.u32()? as usize;
+    let code = reader.take(len)?.to_vec();
+
+    let mut symbols = Vec::new();
+    for _ in 0..reader.u32()? {
+        symbols.push(reader.string()?);
+    }
+
+    let mut states = HashMap::new();
+    for _ in 0..reader.u32()? {
+        let address = reader.u32()?;
+        let name = reader.string()?;
+        let mut doc = Vec::new();
+        for _ in 0..reader.u32()? {
+            doc.push(reader.string()?);
+        }
+        states.insert(address, StateInfo { name, doc });
+    }
+
+    let mut signatures = HashMap::new();
+    for _ in 0..reader.u32()? {
+        let signature = SignatureBuf {
+            name: reader.string()?,
+            states: reader.count(version)?,
+            symbols: reader.count(version)?,
+        };
+        signatures.insert(signature, reader.u32()?);
+    }
+
+    let mut tape = Vec::new();
+    for _ in 0..reader.u32()? {
+        tape.push(reader.symbol(version)?);
+    }
+    if tape.iter().any(|&symbol| symbol as usize >= symbols.len()) {
+        return Err(reader.corrupt());
+    }
+
+    let mut arms = Vec::new();
+    let mut source_map = Vec::new();
+    if version >= 3 && !reader.bytes.is_empty() {
+        for _ in 0..reader.u32()? {
+            arms.push(ArmInfo {
+                offset: reader.u32()?,
+                state: reader.u32()?,
+                span: reader.span()?,
+            });
+        }
+        for _ in 0..reader.u32()? {
+            source_map.push(SourceRange {
+                start: reader.u32()?,
+                end: reader.u32()?,
+                state: reader.u32()? as usize,
+                arm: match reader.u32()? {
+                    u32::MAX => None,
+                    arm => Some(arm as usize),
+                },
+                span: reader.span()?,
+            });
+        }
+    }
+    if !reader.bytes.is_empty() {
+        return Err(reader.corrupt());
+    }
+    let len = code.len() as u32;
+    if arms.iter().any(|arm| arm.offset >= len) || source_map.iter().any(|range| range.end > len) {
+        return Err(reader.corrupt());
+    }
+
+    Ok(Compiled {
+        bytes: code,
+        symbols,
+        states,
+        signatures,
+        arms,
+        source_map,
+        tape,
+    })
+}
+
+/// Replaces the machine's tape, using the symbol ids it was compiled with
+pub fn set_tape(compiled: &mut Compiled, tape: Vec<Symbol>) -> Result<(), Error> {
+    compiled.tape = map_tape(compiled, &tape)?;
+    Ok(())
+}
+
+/// The ids a compiled machine uses for the symbols on a tape
+pub fn map_tape(compiled: &Compiled, tape: &[Symbol]) -> Result<Vec<u32>, Error> {
+    let ids: HashMap<_, _> = compiled
+        .symbols
+        .iter()
+        .enumerate()
+        .map(|(id, symbol)| (symbol.as_str(), id as u32))
+        .collect();
+    tape.iter()
+        .map(|symbol| match ids.get(symbol.symbol.as_str()) {
+            Some(&id) => Ok(id),
+            None => Err(Error::new(
+                format!(
+                    "symbol `{}` is not in the machine's symbol table",
+                    tape::literal(&symbol.symbol)
+                ),
+                Some(symbol.span),
+            )
+            .with_help(Some(
+                "the machine never uses it, so it needs to be rebuilt with this tape".to_string(),
+            ))),
+        })
+        .collect()
+}
+
+struct Writer(Vec<u8>);
+
+impl Writer {
+    fn u32(&mut self, value: u32) {
+        self.0.extend(value.to_le_bytes());
+    }
+
+    fn string(&mut self, string: &str) {
+        self.u32(string.len() as u32);
+        self.0.extend(string.as_bytes());
+    }
+
+    fn span(&mut self, span: &SpanBuf) {
+        self.string(&span.path.to_string_lossy());
+        self.u32(span.line as u32);
+        self.u32(span.column as u32);
+        self.string(&span.prefix);
+        self.string(&span.text);
+        self.string(&span.suffix);
+    }
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    path: &'a Path,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], Error> {
+        if self.bytes.len() < len {
+            return Err(self.corrupt());
+        }
+        let (taken, rest) = self.bytes.split_at(len);
+        self.bytes = rest;
+        Ok(taken)
+    }
+
+    fn u32(&mut self) -> Result<u32, Error> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    // a signature's parameter count
+    fn count(&mut self, version: u32) -> Result<u16, Error> {
+        match version {
+            1 => Ok(self.take(1)?[0] as u16),
+            _ => Ok(u16::from_le_bytes(self.take(2)?.try_into().unwrap())),
+        }
+    }
+
+    fn symbol(&mut self, version: u32) -> Result<u32, Error> {
+        match version {
+            1 => Ok(u16::from_le_bytes(self.take(2)?.try_into().unwrap()) as u32),
+            _ => self.u32(),
+        }
+    }
+
+    fn string(&mut self) -> Result<String, Error> {
+        let len = self.u32()? as usize;
+        let bytes = self.take(len)?;
+        String::from_utf8(bytes.to_vec()).map_err(|_| self.corrupt())
+    }
+
+    fn span(&mut self) -> Result<SpanBuf, Error> {
+        Ok(SpanBuf {
+            path: PathBuf::from(self.string()?),
+            line: self.u32()? as usize,
+            column: self.u32()? as usize,
+            prefix: self.string()?,
+            text: self.string()?,
+            suffix: self.string()?,
+        })
+    }
+
+    fn corrupt(&self) -> Error {
+        Error::new(
+            format!("{} is truncated or corrupt", self.path.display()),
+            None,
+        )
+    }
+}
@@ -0,0 +1,199 @@
+use std::cmp::Ordering;
+use std::collections::HashMap;
+
+use crate::vm::Tape;
+
+/// How much a `Transpositions` cache remembers
+#[derive(Clone, Copy, Debug)]
+pub struct CacheSize {
+    /// Cells on each side of the head in a window
+    pub window: usize,
+    /// The most windows it remembers. Past that, it stops adding more
+    pub capacity: usize,
+}
+
+/// A transposition cache for the Rust VM. The first time the machine is in some
+/// state with some window of cells around the head, the VM runs it a move at a
+/// time until the head leaves the window, and remembers what it left behind.
+/// The next time, it skips to the end. Only states without arguments are
+/// remembered, and only away from cell 0, so it makes the same moves with the
+/// same statistics
+#[derive(Clone)]
+pub struct Transpositions {
+    size: CacheSize,
+    // keyed by the state's address followed by the window's cells
+    entries: HashMap<Vec<u32>, Entry>,
+    // the window being run a move at a time, to remember once the head leaves it
+    recording: Option<Recording>,
+    // reused for each lookup
+    key: Vec<u32>,
+}
+
+#[derive(Clone)]
+pub struct Entry {
+    /// The cells it wrote, from where the head started, and what's on them
+    /// afterwards, left to right
+    pub writes: Vec<(i64, u32)>,
+    /// The state it ended up in
+    pub next: u32,
+    pub moves: usize,
+    /// The furthest the head went each way (in the middle of a move too), and
+    /// where it ended up, from where it started
+    pub low: i64,
+    pub high: i64,
+    pub exit: i64,
+}
+
+impl Entry {
+    /// Makes the moves from the head's cell, writing what they left behind.
+    /// The head goes as far each way as it did the first time, so `Simulated`
+    /// gets the same head positions
+    pub fn apply(&self, tape: &mut impl Tape) {
+        let start = tape.head();
+        let mut position = start + self.low;
+        shift(tape, start, position);
+        for &(offset, symbol) in &self.writes {
+            shift(tape, position, start + offset);
+            position = start + offset;
+            tape.write(symbol);
+        }
+        shift(tape, position, start + self.high);
+        shift(tape, start + self.high, start + self.exit);
+    }
+}
+
+/// What `Transpositions::lookup` found
+pub enum Lookup<'c> {
+    /// It didn't look, because it's remembering a window already or the head
+    /// is too close to cell 0
+    Skipped,
+    Miss,
+    Hit(&'c Entry),
+}
+
+#[derive(Clone)]
+struct Recording {
+    key: Vec<u32>,
+    start: i64,
+    moves: usize,
+    low: i64,
+    high: i64,
+    written: Vec<i64>,
+}
+
+impl Transpositions {
+    pub fn new(size: CacheSize) -> Self {
+        Transpositions {
+            size,
+            entries: HashMap::new(),
+            recording: None,
+            key: Vec::new(),
+        }
+    }
+
+    /// What the machine did the last time it was in the state at `address`
+    /// (which has no arguments) with the same cells around the head, if it can
+    /// be replayed from here. On a miss, it starts remembering the window
+    pub fn lookup(&mut self, address: u32, tape: &impl Tape, moves: usize) -> Lookup<'_> {
+        let head = tape.head();
+        let window = self.size.window as i64;
+        // a window that reaches cell 0 could count left edge contacts
+        if self.recording.is_some() || head - window <= 0 {
+            return Lookup::Skipped;
+        }
+        self.key.clear();
+        self.key.push(address);
+        self.key.extend(cells(tape, head, self.size.window));
+        match self.entries.get(self.key.as_slice()) {
+            // where the head went from here has to be clear of cell 0 too
+            Some(entry) if head + entry.low > 0 => Lookup::Hit(entry),
+            Some(_) => Lookup::Miss,
+            None => {
+                if self.entries.len() < self.size.capacity {
+                    self.recording = Some(Recording {
+                        key: self.key.clone(),
+                        start: head,
+                        moves,
+                        low: head,
+                        high: head,
+                        written: Vec::new(),
+                    });
+                }
+                Lookup::Miss
+            }
+        }
+    }
+
+    /// Called whenever the head moves, since a move can go past the window and
+    /// write there before it ends
+    pub fn visited(&mut self, head: i64) {
+        if let Some(recording) = &mut self.recording {
+            recording.low = recording.low.min(head);
+            recording.high = recording.high.max(head);
+        }
+    }
+
+    /// Called whenever the VM writes a cell. Only those get written back, since
+    /// the head can also land on cells outside the window without writing them
+    pub fn wrote(&mut self, head: i64) {
+        if let Some(recording) = &mut self.recording {
+            recording.written.push(head);
+        }
+    }
+
+    /// Called after each move the VM runs itself. Once the head leaves the
+    /// window being remembered, remembers where the machine got to. A move into
+    /// a state with arguments drops the window, since skipping it would skip
+    /// making the states it was passed
+    pub fn moved(&mut self, tape: &impl Tape, moves: usize, address: u32, arguments: bool) {
+        let Some(recording) = &self.recording else {
+            return;
+        };
+        if arguments {
+            self.recording = None;
+            return;
+        }
+        let head = tape.head();
+        if (head - recording.start).abs() <= self.size.window as i64 {
+            return;
+        }
+        let mut recording = self.recording.take().unwrap();
+        recording.written.sort();
+        recording.written.dedup();
+        let entry = Entry {
+            writes: recording
+                .written
+                .iter()
+                .map(|&position| (position - recording.start, tape.cell(position)))
+                .collect(),
+            next: address,
+            moves: moves - recording.moves,
+            low: recording.low - recording.start,
+            high: recording.high - recording.start,
+            exit: head - recording.start,
+        };
+        self.entries.insert(recording.key, entry);
+    }
+
+    /// Stops remembering the current window, because the machine stopped in it
+    pub fn stopped(&mut self) {
+        self.recording = None;
+    }
+}
+
+// the `window` cells each side of `head`, and the one after them, which
+// lookahead can read
+fn cells(tape: &impl Tape, head: i64, window: usize) -> impl Iterator<Item = u32> + '_ {
+    let window = window as i64;
+    (head - window..=head + window + 1).map(|position| tape.cell(position))
+}
+
+// moves the head between two cells clear of cell 0. it can't stop the run,
+// since the cache is only used without a tape cell limit
+fn shift(tape: &mut impl Tape, from: i64, to: i64) {
+    let _ = match to.cmp(&from) {
+        Ordering::Less => tape.left((from - to) as usize),
+        Ordering::Greater => tape.right((to - from) as usize),
+        Ordering::Equal => return,
+    };
+}
@@ -1,4 +1,7 @@
 #include <assert.h>
+#include <setjmp.h>
+#include <stdarg.h>
+#include <stdatomic.h>
 #include <stdbool.h>
 #include <stdint.h>
 #include <stdlib.h>
@@ -6,6 +9,12 @@
 
 #include <stdio.h>
 
+// computed gotos (for `DISPATCH_GOTO` and `DISPATCH_THREADED`) are a GNU C
+// extension, so other compilers only get `DISPATCH_SWITCH`
+#if defined(__GNUC__) || defined(__clang__)
+#define USE_COMPUTED_GOTO 1
+#endif
+
 #define LEFT 0
 #define RIGHT 1
 #define LEFT_N 2
@@ -28,110 +37,262 @@
 #define COMPARE_VAL 17
 #define OTHER 18
 #define HALT 19
+#define PEEK_ARG 20
+#define PEEK_VAL 21
+
+#define LEFT_N16 22
+#define RIGHT_N16 23
+#define COMPARE_ARG32 24
+#define COMPARE_VAL32 25
+#define PEEK_ARG32 26
+#define PEEK_VAL32 27
+
+#define WRITE_ARG_LEFT 28
+#define WRITE_ARG_RIGHT 29
+#define WRITE_VAL_LEFT 30
+#define WRITE_VAL_RIGHT 31
+#define WRITE_BOUND_LEFT 32
+#define WRITE_BOUND_RIGHT 33
+
+#define V2_FLAG 0xffff
+#define MAX_ARGS 65536
 
 #define INTIAL_TAPE_CAPACITY 256
 #define TAPE_GROWTH_FACTOR 2
-#define STATE_STACK_CAPACITY 1024
+#define INITIAL_STATE_STACK_CAPACITY 1024
+// moves between checks for an interrupt (a power of 2)
+#define INTERRUPT_CHECK_INTERVAL 65536
 
 #define ControlFlow bool
 #define STOP true
 #define CONTINUE false
 
-#ifdef DEBUG
-void debug_free(void *p) {
-  printf("free %p\n", p);
+// why the run stopped (see `backend::HaltReason`)
+#define HALTED 0
+#define MAX_MOVES 1
+#define LEFT_EDGE 2
+#define TAPE_LIMIT 3
+#define INTERRUPTED 4
+// why the run couldn't go on (`run` returns these instead of a halt reason)
+#define INVALID_BYTECODE 5
+#define OUT_OF_MEMORY 6
+
+// bytes of an invalid opcode after the copy of the bytecode, so an instruction
+// cut off at the end reads them instead of whatever comes after it
+#define BYTECODE_PADDING 8
+#define INVALID_OPCODE 0xff
+
+// how a right hand side gets from one instruction to the next (see
+// `set_dispatch`)
+#define DISPATCH_GOTO 0
+#define DISPATCH_SWITCH 1
+#define DISPATCH_THREADED 2
+
+typedef struct State {
+  uint32_t address;
+  struct State *states;
+  size_t state_count;
+  uint32_t *symbols;
+  size_t symbol_count;
+} State;
+
+// where a VM gets its memory from. every function gets `data` back, and `free`
+// and `realloc` get the size and alignment that were asked for when `p` was
+// allocated. `alloc` and `realloc` return NULL when there isn't enough memory
+typedef struct Allocator {
+  void *(*alloc)(void *data, size_t size, size_t align);
+  void *(*realloc)(void *data, void *p, size_t old_size, size_t new_size,
+                   size_t align);
+  void (*free)(void *data, void *p, size_t size, size_t align);
+  void *data;
+} Allocator;
+
+// called every few moves (see `set_hook`) with the number of moves so far, the
+// head's position counting from the initial tape's first cell and the current
+// state's address. returning true stops the run with `INTERRUPTED`
+typedef bool (*Hook)(void *data, size_t moves, int64_t head, uint32_t address);
+
+// everything one run needs, so runs on different threads don't share anything
+// but `interrupted`. a VM is for one run: `new_vm`, `init_tape`, `run`, the
+// getters, then `free_vm`
+typedef struct Vm {
+  // tape
+  uint32_t *tape;
+  uint32_t *tape_end;
+  uint32_t *tape_head;
+  size_t rightmost_head_position;
+  // the index of the initial tape's first cell, more than 0 once a two-sided
+  // tape has grown to the left
+  size_t origin;
+  int64_t leftmost_index;
+  size_t left_edge_contacts;
+  bool two_sided;
+  // the most cells the head may span before the run stops (see `tape_span`)
+  size_t max_tape_cells;
+  // the length of every buffer the tape has had, added up
+  size_t cells_allocated;
+  // whether `take_tape` gave the tape away, so `free_vm` leaves it alone
+  bool tape_taken;
+  uint8_t halt_reason;
+
+  // current state
+  uint32_t address;
+  State states[MAX_ARGS];
+  size_t state_count;
+  uint32_t symbols[MAX_ARGS];
+  size_t symbol_count;
+
+  // stacks. the state stack grows (see `push_state`), since states can nest
+  // their arguments as deep as a machine likes
+  State *state_stack;
+  State *state_stack_top;
+  State *state_stack_end;
+  uint32_t symbol_stack[MAX_ARGS];
+  uint32_t *symbol_stack_top;
+
+  // bytes (a padded copy of the bytecode `run` was given)
+  uint8_t *bytes_start;
+  size_t bytes_len;
+  uint8_t *ip;
+  // where a jump past the end of the bytecode went (see `go_to`), which the run
+  // faults on right after
+  size_t past_end;
+  // version 2 bytecode has 16-bit argument indices and 32-bit symbols
+  bool v2;
+  uint8_t dispatch;
+  // the label of every byte's opcode (see `run_rhs_threaded`), or NULL until
+  // the first right hand side with `DISPATCH_THREADED`
+  void **threaded_code;
+
+  // misc
+  size_t max_moves;
+  size_t moves;
+  uint32_t bound;
+  Allocator allocator;
+  // where the tape comes from (see `set_tape_allocator`)
+  Allocator tape_allocator;
+  Hook hook;
+  void *hook_data;
+  size_t hook_every;
+  // the move count the hook is called at next, or SIZE_MAX if there's no hook
+  size_t next_hook;
+
+  // where a fault or a failed allocation jumps back to in `init_tape` or
+  // `run`, with the status to return
+  jmp_buf fault_jump;
+  // what was wrong with the bytecode, after `run` returns `INVALID_BYTECODE`
+  char error_message[128];
+  uint32_t error_address;
+} Vm;
+
+// set from another thread when the user presses Ctrl-C, which stops every run
+atomic_bool interrupted;
+
+// malloc's blocks are aligned for anything the VM allocates
+void *default_alloc(void *data, size_t size, size_t align) {
+  (void)data;
+  (void)align;
+  return malloc(size);
+}
+
+void *default_realloc(void *data, void *p, size_t old_size, size_t new_size,
+                      size_t align) {
+  (void)data;
+  (void)old_size;
+  (void)align;
+  return realloc(p, new_size);
+}
+
+void default_free(void *data, void *p, size_t size, size_t align) {
+  (void)data;
+  (void)size;
+  (void)align;
   free(p);
 }
-void *debug_malloc(size_t s) {
-  void *p = malloc(s);
+
+const Allocator DEFAULT_ALLOCATOR = {default_alloc, default_realloc,
+                                     default_free, NULL};
+
+void *checked(Vm *vm, void *p) {
+  if (!p) {
+    longjmp(vm->fault_jump, OUT_OF_MEMORY);
+  }
+#ifdef DEBUG
   printf("alloc %p\n", p);
+#endif
   return p;
 }
-void *debug_calloc(size_t n, size_t s) {
-  void *p = calloc(n, s);
-  printf("alloc %p\n", p);
+
+void *vm_malloc(Vm *vm, const Allocator *allocator, size_t size,
+                size_t align) {
+  return checked(vm, allocator->alloc(allocator->data, size, align));
+}
+
+void *vm_calloc(Vm *vm, const Allocator *allocator, size_t size,
+                size_t align) {
+  void *p = vm_malloc(vm, allocator, size, align);
+  memset(p, 0, size);
   return p;
 }
-void *debug_realloc(void *p, size_t s) {
+
+void *vm_realloc(Vm *vm, const Allocator *allocator, void *p, size_t old_size,
+                 size_t new_size, size_t align) {
+#ifdef DEBUG
   printf("free %p\n", p);
-  p = realloc(p, s);
-  printf("alloc %p\n", p);
-  return p;
+#endif
+  return checked(vm, allocator->realloc(allocator->data, p, old_size, new_size,
+                                        align));
 }
 
-#define FREE(p) debug_free((p))
-#define MALLOC(s) debug_malloc((s))
-#define CALLOC(n, s) debug_calloc((n), (s))
-#define REALLOC(p, s) debug_realloc((p), (s))
-#else
-#define FREE(p) free((p))
-#define MALLOC(s) malloc((s))
-#define CALLOC(n, s) calloc((n), (s))
-#define REALLOC(p, s) realloc((p), (s))
+void vm_free(const Allocator *allocator, void *p, size_t size, size_t align) {
+#ifdef DEBUG
+  printf("free %p\n", p);
 #endif
+  allocator->free(allocator->data, p, size, align);
+}
 
-typedef struct State {
-  uint32_t address;
-  struct State *states;
-  size_t state_count;
-  uint16_t *symbols;
-  size_t symbol_count;
-} State;
+// arrays of `n` of a type. these need a `vm` in scope
+#define MALLOC(type, n)                                                        \
+  vm_malloc(vm, &vm->allocator, (n) * sizeof(type), _Alignof(type))
+#define FREE(p, type, n)                                                       \
+  vm_free(&vm->allocator, (p), (n) * sizeof(type), _Alignof(type))
 
-// tape
-uint16_t *tape;
-uint16_t *tape_end;
-uint16_t *tape_head;
-
-// current state
-uint32_t address;
-State states[256];
-size_t state_count;
-uint16_t symbols[256];
-size_t symbol_count;
-
-// stacks
-State state_stack[STATE_STACK_CAPACITY];
-State *state_stack_top = &state_stack[0];
-uint16_t symbol_stack[256];
-uint16_t *symbol_stack_top = &symbol_stack[0];
-
-// bytes
-uint8_t *bytes_start;
-uint8_t *ip;
-
-// misc
-size_t max_moves;
-size_t moves;
-uint16_t bound;
-
-void free_state(State *state) {
+// the tape's cells, which come from the tape allocator
+#define TAPE_CALLOC(n)                                                         \
+  vm_calloc(vm, &vm->tape_allocator, (n) * sizeof(uint32_t), _Alignof(uint32_t))
+#define TAPE_REALLOC(p, old, new)                                              \
+  vm_realloc(vm, &vm->tape_allocator, (p), (old) * sizeof(uint32_t),           \
+             (new) * sizeof(uint32_t), _Alignof(uint32_t))
+#define TAPE_FREE(p, n)                                                        \
+  vm_free(&vm->tape_allocator, (p), (n) * sizeof(uint32_t), _Alignof(uint32_t))
+
+void free_state(Vm *vm, State *state) {
   if (state->state_count) {
     for (size_t i = 0; i < state->state_count; i++) {
-      free_state(&state->states[i]);
+      free_state(vm, &state->states[i]);
     }
-    FREE(state->states);
+    FREE(state->states, State, state->state_count);
   }
   if (state->symbol_count) {
-    FREE(state->symbols);
+    FREE(state->symbols, uint32_t, state->symbol_count);
   }
 }
 
-State clone_state(State *state) {
+State clone_state(Vm *vm, State *state) {
   State cloned;
   cloned.address = state->address;
   cloned.state_count = state->state_count;
   cloned.symbol_count = state->symbol_count;
   if (cloned.state_count) {
-    cloned.states = MALLOC(cloned.state_count * sizeof(State));
+    cloned.states = MALLOC(State, cloned.state_count);
     for (size_t i = 0; i < cloned.state_count; i++) {
-      cloned.states[i] = clone_state(&state->states[i]);
+      cloned.states[i] = clone_state(vm, &state->states[i]);
     }
   }
   if (cloned.symbol_count) {
-    cloned.symbols = MALLOC(cloned.symbol_count * sizeof(uint16_t));
+    cloned.symbols = MALLOC(uint32_t, cloned.symbol_count);
     memcpy(cloned.symbols, state->symbols,
-           cloned.symbol_count * sizeof(uint16_t));
+           cloned.symbol_count * sizeof(uint32_t));
   }
   return cloned;
 }
@@ -153,404 +314,975 @@ void print_state(State *state) {
       } else {
         printf("; ");
       }
-      printf("%hu", state->symbols[i]);
+      printf("%u", state->symbols[i]);
     }
   }
   printf(")");
 }
 
-void init_tape(uint16_t *symbols, size_t len) {
+// returns false if there wasn't enough memory for the tape
+bool init_tape(Vm *vm, uint32_t *initial, size_t len, bool grow_left,
+               size_t max_cells) {
+  vm->tape = NULL;
+  if (setjmp(vm->fault_jump)) {
+    return false;
+  }
   if (len < INTIAL_TAPE_CAPACITY) {
-    tape = CALLOC(INTIAL_TAPE_CAPACITY, sizeof(uint16_t));
-    tape_end = &tape[INTIAL_TAPE_CAPACITY];
+    vm->tape = TAPE_CALLOC(INTIAL_TAPE_CAPACITY);
+    vm->tape_end = &vm->tape[INTIAL_TAPE_CAPACITY];
   } else {
-    tape = CALLOC(len, sizeof(uint16_t));
-    tape_end = &tape[len];
+    vm->tape = TAPE_CALLOC(len);
+    vm->tape_end = &vm->tape[len];
   }
-  tape_head = tape;
-  memcpy(tape, symbols, len * sizeof(uint16_t));
+  vm->tape_head = vm->tape;
+  vm->rightmost_head_position = 0;
+  vm->origin = 0;
+  vm->leftmost_index = 0;
+  vm->left_edge_contacts = 0;
+  vm->two_sided = grow_left;
+  vm->max_tape_cells = max_cells;
+  vm->cells_allocated = vm->tape_end - vm->tape;
+  memcpy(vm->tape, initial, len * sizeof(uint32_t));
+  return true;
+}
+
+// adds at least n blanks to the left end of the tape, and as many as there are
+// cells already so that a head that keeps going left doesn't keep copying
+void extend_left(Vm *vm, size_t n) {
+  size_t head_offset = vm->tape_head - vm->tape;
+  size_t old_len = vm->tape_end - vm->tape;
+  size_t extra = (n > old_len ? n : old_len) + INTIAL_TAPE_CAPACITY;
+
+  uint32_t *new_tape = TAPE_CALLOC(old_len + extra);
+  vm->cells_allocated += old_len + extra;
+  memcpy(&new_tape[extra], vm->tape, old_len * sizeof(uint32_t));
+  TAPE_FREE(vm->tape, old_len);
+  vm->tape = new_tape;
+  vm->tape_head = &vm->tape[head_offset + extra];
+  vm->tape_end = &vm->tape[old_len + extra];
+
+  vm->rightmost_head_position += extra;
+  vm->origin += extra;
+}
+
+// the number of cells from the leftmost to the rightmost one the head has been on
+size_t tape_span(Vm *vm) {
+  int64_t leftmost = vm->leftmost_index < 0 ? vm->leftmost_index : 0;
+  return vm->rightmost_head_position - vm->origin + (size_t)(-leftmost) + 1;
 }
 
-ControlFlow tape_left(size_t n) {
-  if (tape_head - tape < (long)n) {
-    tape_head = tape;
+ControlFlow tape_left(Vm *vm, size_t n) {
+  // track where the head would have gone on a two-way tape
+  int64_t target = (int64_t)(vm->tape_head - vm->tape) - (int64_t)vm->origin -
+                   (int64_t)n;
+  bool extended = target < vm->leftmost_index;
+  if (extended) {
+    vm->leftmost_index = target;
+  }
+  if (target <= 0) {
+    vm->left_edge_contacts++;
+  }
+
+  if ((size_t)(vm->tape_head - vm->tape) < n) {
+    if (!vm->two_sided) {
+      vm->tape_head = vm->tape;
+      vm->halt_reason = LEFT_EDGE;
+      return STOP;
+    }
+    extend_left(vm, n);
+  }
+  vm->tape_head -= n;
+  if (extended && tape_span(vm) > vm->max_tape_cells) {
+    vm->halt_reason = TAPE_LIMIT;
     return STOP;
-  } else {
-    tape_head -= n;
-    return CONTINUE;
   }
+  return CONTINUE;
+}
+
+ControlFlow tape_right(Vm *vm, size_t n) {
+  vm->tape_head += n;
+  size_t position = vm->tape_head - vm->tape;
+  if (position > vm->rightmost_head_position) {
+    vm->rightmost_head_position = position;
+    if (tape_span(vm) > vm->max_tape_cells) {
+      vm->halt_reason = TAPE_LIMIT;
+      return STOP;
+    }
+  }
+  return CONTINUE;
 }
 
-void tape_right(size_t n) { tape_head += n; }
+uint32_t read_tape(Vm *vm) {
+  if (vm->tape_head >= vm->tape_end) {
+    return 0;
+  } else {
+    return *vm->tape_head;
+  }
+}
 
-uint16_t read_tape() {
-  if (tape_head >= tape_end) {
+uint32_t peek_tape(Vm *vm) {
+  if (vm->tape_head + 1 >= vm->tape_end) {
     return 0;
   } else {
-    return *tape_head;
+    return vm->tape_head[1];
   }
 }
 
-void write_tape(uint16_t value) {
-  if (tape_head < tape_end) {
-    *tape_head = value;
+void write_tape(Vm *vm, uint32_t value) {
+  if (vm->tape_head < vm->tape_end) {
+    *vm->tape_head = value;
   } else {
     if (value) {
-      size_t head_offset = tape_head - tape;
-      size_t old_len = tape_end - tape;
+      size_t head_offset = vm->tape_head - vm->tape;
+      size_t old_len = vm->tape_end - vm->tape;
       size_t new_len = TAPE_GROWTH_FACTOR * head_offset;
 
-      tape = REALLOC(tape, new_len * sizeof(uint16_t));
-      memset(&tape[old_len], 0, (new_len - old_len) * sizeof(uint16_t));
-      tape_head = &tape[head_offset];
-      tape_end = &tape[new_len];
+      vm->tape = TAPE_REALLOC(vm->tape, old_len, new_len);
+      vm->cells_allocated += new_len;
+      memset(&vm->tape[old_len], 0, (new_len - old_len) * sizeof(uint32_t));
+      vm->tape_head = &vm->tape[head_offset];
+      vm->tape_end = &vm->tape[new_len];
 
-      *tape_head = value;
+      *vm->tape_head = value;
     }
   }
 }
 
-uint8_t next() { return *ip++; }
+// stops the run with `INVALID_BYTECODE`. `instruction` is where the instruction
+// that was wrong starts
+_Noreturn void fault(Vm *vm, size_t instruction, const char *format, ...) {
+  va_list args;
+  va_start(args, format);
+  vsnprintf(vm->error_message, sizeof(vm->error_message), format, args);
+  va_end(args);
+  vm->error_address = instruction;
+  longjmp(vm->fault_jump, INVALID_BYTECODE);
+}
 
-uint16_t next_u16() {
-  uint16_t low = next();
-  uint16_t high = next();
+// the opcode that was just read isn't one the VM runs here
+_Noreturn void invalid_opcode(Vm *vm) {
+  size_t instruction = vm->ip - 1 - vm->bytes_start;
+  if (instruction >= vm->bytes_len) {
+    fault(vm, vm->past_end ? vm->past_end : instruction,
+          "ran past the end of the bytecode");
+  }
+  fault(vm, instruction, "unknown opcode 0x%02x", vm->ip[-1]);
+}
+
+uint8_t next(Vm *vm) { return *vm->ip++; }
+
+uint16_t next_u16(Vm *vm) {
+  uint16_t low = next(vm);
+  uint16_t high = next(vm);
   return low | (high << 8);
 }
 
-uint32_t next_u32() {
-  uint32_t a = next();
-  uint32_t b = next();
-  uint32_t c = next();
-  uint32_t d = next();
+uint32_t next_u32(Vm *vm) {
+  uint32_t a = next(vm);
+  uint32_t b = next(vm);
+  uint32_t c = next(vm);
+  uint32_t d = next(vm);
   return a | (b << 8) | (c << 16) | (d << 24);
 }
 
-void go_to(uint32_t address) { ip = bytes_start + address; }
+uint16_t next_arg(Vm *vm) { return vm->v2 ? next_u16(vm) : next(vm); }
+
+uint32_t next_symbol(Vm *vm) { return vm->v2 ? next_u32(vm) : next_u16(vm); }
+
+// a jump past the end of the bytecode goes to the padding, so it faults on the
+// next opcode like it would have there (and the move it finished still counts)
+void go_to(Vm *vm, size_t target) {
+  if (target >= vm->bytes_len) {
+    vm->past_end = target;
+    vm->ip = &vm->bytes_start[vm->bytes_len];
+  } else {
+    vm->ip = &vm->bytes_start[target];
+  }
+}
+
+void skip(Vm *vm, uint32_t skip) {
+  size_t target = vm->ip - vm->bytes_start + skip;
+  if (target >= vm->bytes_len) {
+    go_to(vm, target);
+  } else {
+    vm->ip += skip;
+  }
+}
 
-void skip(uint16_t skip) { ip += skip; }
+// the index of a symbol argument of the current state. these are read right
+// after the opcode, so it's the byte before them
+uint16_t next_symbol_arg(Vm *vm) {
+  size_t instruction = vm->ip - 1 - vm->bytes_start;
+  uint16_t index = next_arg(vm);
+  if (index >= vm->symbol_count) {
+    fault(vm, instruction, "the state has no argument %u", index);
+  }
+  return index;
+}
 
-void push_symbol(uint16_t value) {
-  *symbol_stack_top = value;
-  symbol_stack_top++;
+uint16_t next_state_arg(Vm *vm) {
+  size_t instruction = vm->ip - 1 - vm->bytes_start;
+  uint16_t index = next_arg(vm);
+  if (index >= vm->state_count) {
+    fault(vm, instruction, "the state has no argument %u", index);
+  }
+  return index;
 }
 
-void push_state(State state) {
-  *state_stack_top = state;
-  state_stack_top++;
+// how many states `MAKE_STATE` takes off the stack
+uint16_t next_pushed_states(Vm *vm) {
+  size_t instruction = vm->ip - 1 - vm->bytes_start;
+  uint16_t count = next_arg(vm);
+  if (count > vm->state_stack_top - vm->state_stack) {
+    fault(vm, instruction,
+          "a state takes %u states, but not that many were pushed", count);
+  }
+  return count;
 }
 
-ControlFlow run_rhs() {
-#ifdef USE_COMPUTED_GOTO
-  static void *dispatch_table[] = {
-      &&do_left,       &&do_right,        &&do_left_n,      &&do_right_n,
-      &&do_write_arg,  &&do_write_val,    &&do_write_bound, &&do_symbol_arg,
-      &&do_symbol_val, &&do_symbol_bound, &&do_take_arg,    &&do_clone_arg,
-      &&do_free_arg,   &&do_make_state,   &&do_final_state, &&do_final_arg,
-  };
-#define DISPATCH() goto *dispatch_table[next()]
+// `instruction` is where the instruction pushing the symbol starts. a state
+// takes fewer than `MAX_ARGS` symbols, so only bad bytecode fills the stack
+void push_symbol(Vm *vm, size_t instruction, uint32_t value) {
+  if (vm->symbol_stack_top == &vm->symbol_stack[MAX_ARGS]) {
+    fault(vm, instruction,
+          "a state takes at most %d symbols, but more were pushed", MAX_ARGS);
+  }
+  *vm->symbol_stack_top = value;
+  vm->symbol_stack_top++;
+}
 
-  DISPATCH();
-  while (true) {
-  do_left:
-    if (tape_left(1) == STOP) {
-      return STOP;
+// doubles the state stack when it's full
+void push_state(Vm *vm, State state) {
+  if (vm->state_stack_top == vm->state_stack_end) {
+    size_t len = vm->state_stack_end - vm->state_stack;
+    size_t new_len = len ? len * 2 : INITIAL_STATE_STACK_CAPACITY;
+    if (len) {
+      vm->state_stack =
+          vm_realloc(vm, &vm->allocator, vm->state_stack, len * sizeof(State),
+                     new_len * sizeof(State), _Alignof(State));
+    } else {
+      vm->state_stack = MALLOC(State, new_len);
     }
-    DISPATCH();
-  do_right:
-    tape_right(1);
-    DISPATCH();
-  do_left_n:
-    if (tape_left(next()) == STOP) {
-      return STOP;
+    vm->state_stack_top = &vm->state_stack[len];
+    vm->state_stack_end = &vm->state_stack[new_len];
+  }
+  *vm->state_stack_top = state;
+  vm->state_stack_top++;
+}
+
+// the address of `FINAL_STATE`'s state, whose states are copied off the stack
+// into `states`. that only has room for as many as a state takes
+uint32_t next_final_address(Vm *vm) {
+  size_t instruction = vm->ip - 1 - vm->bytes_start;
+  uint32_t address = next_u32(vm);
+  if (vm->state_stack_top - vm->state_stack > MAX_ARGS) {
+    fault(vm, instruction,
+          "a state takes at most %d states, but more were pushed", MAX_ARGS);
+  }
+  return address;
+}
+
+#ifdef USE_COMPUTED_GOTO
+// the labels of `RHS_HANDLERS` by opcode, for a `static void *` array
+#define RHS_DISPATCH_TABLE                                                     \
+  {                                                                            \
+    &&do_left,       &&do_right,        &&do_left_n,      &&do_right_n,        \
+    &&do_write_arg,  &&do_write_val,    &&do_write_bound, &&do_symbol_arg,     \
+    &&do_symbol_val, &&do_symbol_bound, &&do_take_arg,    &&do_clone_arg,      \
+    &&do_free_arg,   &&do_make_state,   &&do_final_state, &&do_final_arg,      \
+    &&do_invalid,    &&do_invalid,      &&do_invalid,     &&do_invalid,        \
+    &&do_invalid,    &&do_invalid,      &&do_left_n16,    &&do_right_n16,      \
+    &&do_invalid,    &&do_invalid,      &&do_invalid,     &&do_invalid,        \
+    &&do_write_arg_left,   &&do_write_arg_right,                               \
+    &&do_write_val_left,   &&do_write_val_right,                               \
+    &&do_write_bound_left, &&do_write_bound_right,                             \
+    [34 ... 255] = &&do_invalid,                                               \
+  }
+
+// the right hand side instructions for the computed-goto loops, which each
+// define their own `DISPATCH` to go on to the next one
+#define RHS_HANDLERS                                                           \
+  do_left:                                                                     \
+    if (tape_left(vm, 1) == STOP) {                                            \
+      return STOP;                                                             \
+    }                                                                          \
+    DISPATCH();                                                                \
+  do_right:                                                                    \
+    if (tape_right(vm, 1) == STOP) {                                           \
+      return STOP;                                                             \
+    }                                                                          \
+    DISPATCH();                                                                \
+  do_left_n:                                                                   \
+    if (tape_left(vm, next(vm)) == STOP) {                                     \
+      return STOP;                                                             \
+    }                                                                          \
+    DISPATCH();                                                                \
+  do_right_n:                                                                  \
+    if (tape_right(vm, next(vm)) == STOP) {                                    \
+      return STOP;                                                             \
+    }                                                                          \
+    DISPATCH();                                                                \
+  do_left_n16:                                                                 \
+    if (tape_left(vm, next_u16(vm)) == STOP) {                                 \
+      return STOP;                                                             \
+    }                                                                          \
+    DISPATCH();                                                                \
+  do_right_n16:                                                                \
+    if (tape_right(vm, next_u16(vm)) == STOP) {                                \
+      return STOP;                                                             \
+    }                                                                          \
+    DISPATCH();                                                                \
+  do_write_arg:                                                                \
+    write_tape(vm, vm->symbols[next_symbol_arg(vm)]);                          \
+    DISPATCH();                                                                \
+  do_write_val:                                                                \
+    write_tape(vm, next_symbol(vm));                                           \
+    DISPATCH();                                                                \
+  do_write_bound:                                                              \
+    write_tape(vm, vm->bound);                                                 \
+    DISPATCH();                                                                \
+  do_write_arg_left:                                                           \
+    write_tape(vm, vm->symbols[next_symbol_arg(vm)]);                          \
+    if (tape_left(vm, 1) == STOP) {                                            \
+      return STOP;                                                             \
+    }                                                                          \
+    DISPATCH();                                                                \
+  do_write_arg_right:                                                          \
+    write_tape(vm, vm->symbols[next_symbol_arg(vm)]);                          \
+    if (tape_right(vm, 1) == STOP) {                                           \
+      return STOP;                                                             \
+    }                                                                          \
+    DISPATCH();                                                                \
+  do_write_val_left:                                                           \
+    write_tape(vm, next_symbol(vm));                                           \
+    if (tape_left(vm, 1) == STOP) {                                            \
+      return STOP;                                                             \
+    }                                                                          \
+    DISPATCH();                                                                \
+  do_write_val_right:                                                          \
+    write_tape(vm, next_symbol(vm));                                           \
+    if (tape_right(vm, 1) == STOP) {                                           \
+      return STOP;                                                             \
+    }                                                                          \
+    DISPATCH();                                                                \
+  do_write_bound_left:                                                         \
+    write_tape(vm, vm->bound);                                                 \
+    if (tape_left(vm, 1) == STOP) {                                            \
+      return STOP;                                                             \
+    }                                                                          \
+    DISPATCH();                                                                \
+  do_write_bound_right:                                                        \
+    write_tape(vm, vm->bound);                                                 \
+    if (tape_right(vm, 1) == STOP) {                                           \
+      return STOP;                                                             \
+    }                                                                          \
+    DISPATCH();                                                                \
+  do_symbol_arg : {                                                            \
+    size_t instruction = vm->ip - 1 - vm->bytes_start;                         \
+    push_symbol(vm, instruction, vm->symbols[next_symbol_arg(vm)]);            \
+    DISPATCH();                                                                \
+  }                                                                            \
+  do_symbol_val : {                                                            \
+    size_t instruction = vm->ip - 1 - vm->bytes_start;                         \
+    push_symbol(vm, instruction, next_symbol(vm));                             \
+    DISPATCH();                                                                \
+  }                                                                            \
+  do_symbol_bound:                                                             \
+    push_symbol(vm, vm->ip - 1 - vm->bytes_start, vm->bound);                  \
+    DISPATCH();                                                                \
+  do_take_arg:                                                                 \
+    push_state(vm, vm->states[next_state_arg(vm)]);                            \
+    DISPATCH();                                                                \
+  do_clone_arg:                                                                \
+    push_state(vm, clone_state(vm, &vm->states[next_state_arg(vm)]));          \
+    DISPATCH();                                                                \
+  do_free_arg:                                                                 \
+    free_state(vm, &vm->states[next_state_arg(vm)]);                           \
+    DISPATCH();                                                                \
+  do_make_state : {                                                            \
+    uint16_t args = next_pushed_states(vm);                                    \
+    uint32_t state_address = next_u32(vm);                                     \
+                                                                               \
+    State state;                                                               \
+    state.address = state_address;                                             \
+    state.state_count = args;                                                  \
+    state.symbol_count = vm->symbol_stack_top - vm->symbol_stack;              \
+                                                                               \
+    if (state.state_count) {                                                   \
+      vm->state_stack_top -= args;                                             \
+      state.states = MALLOC(State, args);                                      \
+      memcpy(state.states, vm->state_stack_top, args * sizeof(State));         \
+    }                                                                          \
+    if (state.symbol_count) {                                                  \
+      state.symbols = MALLOC(uint32_t, state.symbol_count);                    \
+      memcpy(state.symbols, vm->symbol_stack,                                  \
+             state.symbol_count * sizeof(uint32_t));                           \
+      vm->symbol_stack_top = vm->symbol_stack;                                 \
+    }                                                                          \
+                                                                               \
+    push_state(vm, state);                                                     \
+    DISPATCH();                                                                \
+  }                                                                            \
+  do_final_state : {                                                           \
+    vm->address = next_final_address(vm);                                      \
+    vm->state_count = vm->state_stack_top - vm->state_stack;                   \
+    vm->symbol_count = vm->symbol_stack_top - vm->symbol_stack;                \
+                                                                               \
+    if (vm->state_count) {                                                     \
+      memcpy(vm->states, vm->state_stack, vm->state_count * sizeof(State));    \
+      vm->state_stack_top = vm->state_stack;                                   \
+    }                                                                          \
+    if (vm->symbol_count) {                                                    \
+      memcpy(vm->symbols, vm->symbol_stack,                                    \
+             vm->symbol_count * sizeof(uint32_t));                             \
+      vm->symbol_stack_top = vm->symbol_stack;                                 \
+    }                                                                          \
+                                                                               \
+    go_to(vm, vm->address);                                                    \
+    return CONTINUE;                                                           \
+  }                                                                            \
+  do_final_arg : {                                                             \
+    uint16_t arg_index = next_state_arg(vm);                                   \
+    State state = vm->states[arg_index];                                       \
+    vm->address = state.address;                                               \
+    vm->state_count = state.state_count;                                       \
+    if (vm->state_count) {                                                     \
+      memcpy(vm->states, &state.states[0], state.state_count * sizeof(State)); \
+      FREE(state.states, State, state.state_count);                            \
+    }                                                                          \
+    vm->symbol_count = state.symbol_count;                                     \
+    if (vm->symbol_count) {                                                    \
+      memcpy(vm->symbols, &state.symbols[0],                                   \
+             state.symbol_count * sizeof(uint32_t));                           \
+      FREE(state.symbols, uint32_t, state.symbol_count);                       \
+    }                                                                          \
+                                                                               \
+    go_to(vm, vm->address);                                                    \
+    return CONTINUE;                                                           \
+  }                                                                            \
+  do_invalid:                                                                  \
+    invalid_opcode(vm);
+
+// every byte of the bytecode gets its label the first time this runs, so
+// dispatching reads the next label directly instead of the opcode and then the
+// table. the bytes that aren't opcodes never get jumped through
+ControlFlow run_rhs_threaded(Vm *vm) {
+  static void *dispatch_table[] = RHS_DISPATCH_TABLE;
+  if (!vm->threaded_code) {
+    size_t len = vm->bytes_len + BYTECODE_PADDING;
+    vm->threaded_code = MALLOC(void *, len);
+    for (size_t i = 0; i < len; i++) {
+      vm->threaded_code[i] = dispatch_table[vm->bytes_start[i]];
     }
-    DISPATCH();
-  do_right_n:
-    tape_right(next());
-    DISPATCH();
-  do_write_arg:
-    write_tape(symbols[next()]);
-    DISPATCH();
-  do_write_val:
-    write_tape(next_u16());
-    DISPATCH();
-  do_write_bound:
-    write_tape(bound);
-    DISPATCH();
-  do_symbol_arg:
-    push_symbol(symbols[next()]);
-    DISPATCH();
-  do_symbol_val:
-    push_symbol(next_u16());
-    DISPATCH();
-  do_symbol_bound:
-    push_symbol(bound);
-    DISPATCH();
-  do_take_arg:
-    push_state(states[next()]);
-    DISPATCH();
-  do_clone_arg:
-    push_state(clone_state(&states[next()]));
-    DISPATCH();
-  do_free_arg:
-    free_state(&states[next()]);
-    DISPATCH();
-  do_make_state : {
-    uint8_t args = next();
-    uint32_t address = next_u32();
-
-    State state;
-    state.address = address;
-    state.state_count = args;
-    state.symbol_count = symbol_stack_top - symbol_stack;
-
-    if (state.state_count) {
-      state_stack_top -= args;
-      state.states = MALLOC(args * sizeof(State));
-      memcpy(state.states, state_stack_top, args * sizeof(State));
-    }
-    if (state.symbol_count) {
-      state.symbols = MALLOC(state.symbol_count * sizeof(uint16_t));
-      memcpy(state.symbols, symbol_stack,
-             state.symbol_count * sizeof(uint16_t));
-      symbol_stack_top = symbol_stack;
-    }
-
-    push_state(state);
-    DISPATCH();
-  }
-  do_final_state : {
-    address = next_u32();
-    state_count = state_stack_top - state_stack;
-    symbol_count = symbol_stack_top - symbol_stack;
-
-    if (state_count) {
-      memcpy(states, state_stack, state_count * sizeof(State));
-      state_stack_top = state_stack;
-    }
-    if (symbol_count) {
-      memcpy(symbols, symbol_stack, symbol_count * sizeof(uint16_t));
-      symbol_stack_top = symbol_stack;
-    }
-
-    go_to(address);
-    return CONTINUE;
-  }
-  do_final_arg : {
-    uint8_t arg_index = next();
-    State state = states[arg_index];
-    address = state.address;
-    state_count = state.state_count;
-    if (state_count) {
-      memcpy(states, &state.states[0], state.state_count * sizeof(State));
-      FREE(state.states);
-    }
-    symbol_count = state.symbol_count;
-    if (symbol_count) {
-      memcpy(symbols, &state.symbols[0], state.symbol_count * sizeof(uint8_t));
-      FREE(state.symbols);
-    }
-
-    go_to(address);
-    return CONTINUE;
   }
+  void **code = vm->threaded_code;
+#define DISPATCH() goto *code[vm->ip++ - vm->bytes_start]
+
+  DISPATCH();
+  while (true) {
+    RHS_HANDLERS
   }
-#else
+#undef DISPATCH
+}
+#endif
+
+ControlFlow run_rhs_switch(Vm *vm) {
   while (true) {
-    switch (next()) {
+    switch (next(vm)) {
     case LEFT: {
-      if (tape_left(1) == STOP) {
+      if (tape_left(vm, 1) == STOP) {
         return STOP;
       }
       break;
     }
     case RIGHT: {
-      tape_right(1);
+      if (tape_right(vm, 1) == STOP) {
+        return STOP;
+      }
       break;
     }
     case LEFT_N: {
-      if (tape_left(next()) == STOP) {
+      if (tape_left(vm, next(vm)) == STOP) {
         return STOP;
       }
       break;
     }
     case RIGHT_N: {
-      tape_right(next());
+      if (tape_right(vm, next(vm)) == STOP) {
+        return STOP;
+      }
+      break;
+    }
+    case LEFT_N16: {
+      if (tape_left(vm, next_u16(vm)) == STOP) {
+        return STOP;
+      }
+      break;
+    }
+    case RIGHT_N16: {
+      if (tape_right(vm, next_u16(vm)) == STOP) {
+        return STOP;
+      }
       break;
     }
     case WRITE_ARG: {
-      uint8_t arg_index = next();
-      write_tape(symbols[arg_index]);
+      uint16_t arg_index = next_symbol_arg(vm);
+      write_tape(vm, vm->symbols[arg_index]);
       break;
     }
     case WRITE_VAL: {
-      uint16_t value = next_u16();
-      write_tape(value);
+      uint32_t value = next_symbol(vm);
+      write_tape(vm, value);
       break;
     }
     case WRITE_BOUND: {
-      write_tape(bound);
+      write_tape(vm, vm->bound);
+      break;
+    }
+    case WRITE_ARG_LEFT: {
+      uint16_t arg_index = next_symbol_arg(vm);
+      write_tape(vm, vm->symbols[arg_index]);
+      if (tape_left(vm, 1) == STOP) {
+        return STOP;
+      }
+      break;
+    }
+    case WRITE_ARG_RIGHT: {
+      uint16_t arg_index = next_symbol_arg(vm);
+      write_tape(vm, vm->symbols[arg_index]);
+      if (tape_right(vm, 1) == STOP) {
+        return STOP;
+      }
+      break;
+    }
+    case WRITE_VAL_LEFT: {
+      uint32_t value = next_symbol(vm);
+      write_tape(vm, value);
+      if (tape_left(vm, 1) == STOP) {
+        return STOP;
+      }
+      break;
+    }
+    case WRITE_VAL_RIGHT: {
+      uint32_t value = next_symbol(vm);
+      write_tape(vm, value);
+      if (tape_right(vm, 1) == STOP) {
+        return STOP;
+      }
+      break;
+    }
+    case WRITE_BOUND_LEFT: {
+      write_tape(vm, vm->bound);
+      if (tape_left(vm, 1) == STOP) {
+        return STOP;
+      }
+      break;
+    }
+    case WRITE_BOUND_RIGHT: {
+      write_tape(vm, vm->bound);
+      if (tape_right(vm, 1) == STOP) {
+        return STOP;
+      }
       break;
     }
     case SYMBOL_ARG: {
-      uint8_t arg_index = next();
-      push_symbol(symbols[arg_index]);
+      size_t instruction = vm->ip - 1 - vm->bytes_start;
+      uint16_t arg_index = next_symbol_arg(vm);
+      push_symbol(vm, instruction, vm->symbols[arg_index]);
       break;
     }
     case SYMBOL_VAL: {
-      uint16_t value = next_u16();
-      push_symbol(value);
+      size_t instruction = vm->ip - 1 - vm->bytes_start;
+      uint32_t value = next_symbol(vm);
+      push_symbol(vm, instruction, value);
       break;
     }
     case SYMBOL_BOUND: {
-      push_symbol(bound);
+      push_symbol(vm, vm->ip - 1 - vm->bytes_start, vm->bound);
       break;
     }
     case TAKE_ARG: {
-      uint8_t arg_index = next();
-      push_state(states[arg_index]);
+      uint16_t arg_index = next_state_arg(vm);
+      push_state(vm, vm->states[arg_index]);
       break;
     }
     case CLONE_ARG: {
-      uint8_t arg_index = next();
-      push_state(clone_state(&states[arg_index]));
+      uint16_t arg_index = next_state_arg(vm);
+      push_state(vm, clone_state(vm, &vm->states[arg_index]));
       break;
     }
     case FREE_ARG: {
-      uint8_t arg_index = next();
-      free_state(&states[arg_index]);
+      uint16_t arg_index = next_state_arg(vm);
+      free_state(vm, &vm->states[arg_index]);
       break;
     }
     case MAKE_STATE: {
-      uint8_t args = next();
-      uint32_t address = next_u32();
+      uint16_t args = next_pushed_states(vm);
+      uint32_t state_address = next_u32(vm);
 
       State state;
-      state.address = address;
+      state.address = state_address;
       state.state_count = args;
-      state.symbol_count = symbol_stack_top - symbol_stack;
+      state.symbol_count = vm->symbol_stack_top - vm->symbol_stack;
 
       if (state.state_count) {
-        state_stack_top -= args;
-        state.states = MALLOC(args * sizeof(State));
-        memcpy(state.states, state_stack_top, args * sizeof(State));
+        vm->state_stack_top -= args;
+        state.states = MALLOC(State, args);
+        memcpy(state.states, vm->state_stack_top, args * sizeof(State));
       }
       if (state.symbol_count) {
-        state.symbols = MALLOC(state.symbol_count * sizeof(uint16_t));
-        memcpy(state.symbols, symbol_stack,
-               state.symbol_count * sizeof(uint16_t));
-        symbol_stack_top = symbol_stack;
+        state.symbols = MALLOC(uint32_t, state.symbol_count);
+        memcpy(state.symbols, vm->symbol_stack,
+               state.symbol_count * sizeof(uint32_t));
+        vm->symbol_stack_top = vm->symbol_stack;
       }
 
-      push_state(state);
+      push_state(vm, state);
       break;
     }
     case FINAL_STATE: {
-      address = next_u32();
-      state_count = state_stack_top - state_stack;
-      symbol_count = symbol_stack_top - symbol_stack;
+      vm->address = next_final_address(vm);
+      vm->state_count = vm->state_stack_top - vm->state_stack;
+      vm->symbol_count = vm->symbol_stack_top - vm->symbol_stack;
 
-      if (state_count) {
-        memcpy(states, state_stack, state_count * sizeof(State));
-        state_stack_top = state_stack;
+      if (vm->state_count) {
+        memcpy(vm->states, vm->state_stack, vm->state_count * sizeof(State));
+        vm->state_stack_top = vm->state_stack;
       }
-      if (symbol_count) {
-        memcpy(symbols, symbol_stack, symbol_count * sizeof(uint16_t));
-        symbol_stack_top = symbol_stack;
+      if (vm->symbol_count) {
+        memcpy(vm->symbols, vm->symbol_stack,
+               vm->symbol_count * sizeof(uint32_t));
+        vm->symbol_stack_top = vm->symbol_stack;
       }
 
-      go_to(address);
+      go_to(vm, vm->address);
       return CONTINUE;
     }
     case FINAL_ARG: {
-      uint8_t arg_index = next();
-      State state = states[arg_index];
-      address = state.address;
-      state_count = state.state_count;
-      if (state_count) {
-        memcpy(states, &state.states[0], state.state_count * sizeof(State));
-        FREE(state.states);
+      uint16_t arg_index = next_state_arg(vm);
+      State state = vm->states[arg_index];
+      vm->address = state.address;
+      vm->state_count = state.state_count;
+      if (vm->state_count) {
+        memcpy(vm->states, &state.states[0], state.state_count * sizeof(State));
+        FREE(state.states, State, state.state_count);
       }
-      symbol_count = state.symbol_count;
-      if (symbol_count) {
-        memcpy(symbols, &state.symbols[0],
-               state.symbol_count * sizeof(uint8_t));
-        FREE(state.symbols);
+      vm->symbol_count = state.symbol_count;
+      if (vm->symbol_count) {
+        memcpy(vm->symbols, &state.symbols[0],
+               state.symbol_count * sizeof(uint32_t));
+        FREE(state.symbols, uint32_t, state.symbol_count);
       }
-      go_to(address);
+      go_to(vm, vm->address);
       return CONTINUE;
     }
+    default:
+      invalid_opcode(vm);
     }
   }
+}
+
+// runs a right hand side the way `set_dispatch` picked. the computed-goto loop
+// is here instead of in a function of its own, since one more call on every
+// move made the default slower
+ControlFlow run_rhs(Vm *vm) {
+#ifdef USE_COMPUTED_GOTO
+  static void *dispatch_table[] = RHS_DISPATCH_TABLE;
+  if (vm->dispatch == DISPATCH_SWITCH) {
+    return run_rhs_switch(vm);
+  }
+  if (vm->dispatch == DISPATCH_THREADED) {
+    return run_rhs_threaded(vm);
+  }
+#define DISPATCH() goto *dispatch_table[next(vm)]
+
+  DISPATCH();
+  while (true) {
+    RHS_HANDLERS
+  }
+#undef DISPATCH
+#else
+  return run_rhs_switch(vm);
 #endif
 }
 
-ControlFlow run_move() {
+ControlFlow run_move(Vm *vm) {
   while (true) {
-    switch (next()) {
+    switch (next(vm)) {
     case COMPARE_ARG: {
-      uint8_t arg_index = next();
-      if (read_tape() == symbols[arg_index]) {
-        next_u16();
-        return run_rhs();
+      uint16_t arg_index = next_symbol_arg(vm);
+      if (read_tape(vm) == vm->symbols[arg_index]) {
+        next_u16(vm);
+        return run_rhs(vm);
       } else {
-        skip(next_u16());
+        skip(vm, next_u16(vm));
       }
       break;
     }
     case COMPARE_VAL: {
-      if (next_u16() == read_tape()) {
-        next_u16();
-        return run_rhs();
+      if (next_symbol(vm) == read_tape(vm)) {
+        next_u16(vm);
+        return run_rhs(vm);
+      } else {
+        skip(vm, next_u16(vm));
+      }
+      break;
+    }
+    case PEEK_ARG: {
+      uint16_t arg_index = next_symbol_arg(vm);
+      if (peek_tape(vm) == vm->symbols[arg_index]) {
+        next_u16(vm);
+      } else {
+        skip(vm, next_u16(vm));
+      }
+      break;
+    }
+    case PEEK_VAL: {
+      if (next_symbol(vm) == peek_tape(vm)) {
+        next_u16(vm);
+      } else {
+        skip(vm, next_u16(vm));
+      }
+      break;
+    }
+    case COMPARE_ARG32: {
+      uint16_t arg_index = next_symbol_arg(vm);
+      if (read_tape(vm) == vm->symbols[arg_index]) {
+        next_u32(vm);
+        return run_rhs(vm);
+      } else {
+        skip(vm, next_u32(vm));
+      }
+      break;
+    }
+    case COMPARE_VAL32: {
+      if (next_symbol(vm) == read_tape(vm)) {
+        next_u32(vm);
+        return run_rhs(vm);
       } else {
-        skip(next_u16());
+        skip(vm, next_u32(vm));
+      }
+      break;
+    }
+    case PEEK_ARG32: {
+      uint16_t arg_index = next_symbol_arg(vm);
+      if (peek_tape(vm) == vm->symbols[arg_index]) {
+        next_u32(vm);
+      } else {
+        skip(vm, next_u32(vm));
+      }
+      break;
+    }
+    case PEEK_VAL32: {
+      if (next_symbol(vm) == peek_tape(vm)) {
+        next_u32(vm);
+      } else {
+        skip(vm, next_u32(vm));
       }
       break;
     }
     case OTHER: {
-      bound = read_tape();
-      return run_rhs();
+      vm->bound = read_tape(vm);
+      return run_rhs(vm);
     }
     case HALT: {
+      vm->halt_reason = HALTED;
       return STOP;
     }
+    default:
+      invalid_opcode(vm);
     }
   }
 }
 
-void run(uint8_t *bytes, size_t max_moves_) {
-  bytes_start = bytes;
-  ip = bytes;
-  max_moves = max_moves_;
-  moves = 0;
+// runs the `len` bytes of bytecode at `bytes` on the tape from `init_tape`, and
+// returns why it stopped, or `INVALID_BYTECODE` or `OUT_OF_MEMORY` if it
+// couldn't go on (the tape and move count are where it got to)
+uint8_t run(Vm *vm, uint8_t *bytes, size_t len, size_t max_moves_) {
+  vm->max_moves = max_moves_;
+  vm->moves = 0;
+  vm->halt_reason = MAX_MOVES;
+  vm->state_count = 0;
+  vm->symbol_count = 0;
+  // a run that stopped in the middle of a move can leave things on the stacks
+  vm->state_stack_top = vm->state_stack;
+  vm->symbol_stack_top = vm->symbol_stack;
+  vm->bytes_start = NULL;
+  vm->threaded_code = NULL;
+
+  int status = setjmp(vm->fault_jump);
+  if (status) {
+    return status;
+  }
+
+  vm->bytes_start = MALLOC(uint8_t, len + BYTECODE_PADDING);
+  memcpy(vm->bytes_start, bytes, len);
+  memset(&vm->bytes_start[len], INVALID_OPCODE, BYTECODE_PADDING);
+  vm->bytes_len = len;
+  vm->ip = vm->bytes_start;
+  vm->past_end = 0;
+
+  vm->next_hook = vm->hook ? vm->hook_every : SIZE_MAX;
+
+  vm->v2 = next_u16(vm) == V2_FLAG;
+  vm->address = next_u32(vm);
+  go_to(vm, vm->address);
 
-  next_u16();
-  state_count = 0;
-  symbol_count = 0;
-  address = next_u32();
-  go_to(address);
+  if (run_rhs(vm) == STOP) {
+    return vm->halt_reason;
+  }
 
-  while (moves < max_moves) {
-    if (run_move() == STOP) {
+  while (vm->moves < vm->max_moves) {
+    if (vm->moves % INTERRUPT_CHECK_INTERVAL == 0 &&
+        atomic_load_explicit(&interrupted, memory_order_relaxed)) {
+      vm->halt_reason = INTERRUPTED;
       break;
     }
-    moves++;
+    if (vm->moves == vm->next_hook) {
+      vm->next_hook = vm->hook_every <= SIZE_MAX - vm->moves
+                          ? vm->moves + vm->hook_every
+                          : SIZE_MAX;
+      int64_t head = (int64_t)(vm->tape_head - vm->tape) - (int64_t)vm->origin;
+      if (vm->hook(vm->hook_data, vm->moves, head, vm->address)) {
+        vm->halt_reason = INTERRUPTED;
+        break;
+      }
+    }
+    if (run_move(vm) == STOP) {
+      break;
+    }
+    vm->moves++;
+  }
+  return vm->halt_reason;
+}
+
+// makes `run` call `hook` with `data` every `every` moves (which isn't 0),
+// before the next move. a NULL `hook` turns it off
+void set_hook(Vm *vm, Hook hook, void *data, size_t every) {
+  vm->hook = hook;
+  vm->hook_data = data;
+  vm->hook_every = every;
+}
+
+// picks how `run` dispatches the instructions of right hand sides: with a
+// computed goto on the opcode (`DISPATCH_GOTO`, the default), a switch on it
+// (`DISPATCH_SWITCH`) or a computed goto on a label looked up for every byte
+// beforehand (`DISPATCH_THREADED`). returns false, leaving it as it was, if this
+// build doesn't have computed gotos
+bool set_dispatch(Vm *vm, uint8_t dispatch) {
+#ifndef USE_COMPUTED_GOTO
+  if (dispatch != DISPATCH_SWITCH) {
+    return false;
+  }
+#endif
+  if (dispatch > DISPATCH_THREADED) {
+    return false;
   }
+  vm->dispatch = dispatch;
+  return true;
+}
+
+void interrupt() { atomic_store(&interrupted, true); }
+
+void clear_interrupt() { atomic_store(&interrupted, false); }
+
+uint32_t get_final_address(const Vm *vm) { return vm->address; }
+
+// the final state with the states and symbols it was passed. they still belong
+// to the VM, so they can only be read until `free_vm`
+State get_final_state(Vm *vm) {
+  State state = {vm->address, vm->states, vm->state_count, vm->symbols,
+                 vm->symbol_count};
+  return state;
 }
 
-uint32_t get_final_address() { return address; }
+uint32_t *get_tape(const Vm *vm) { return vm->tape; }
+
+size_t get_tape_len(const Vm *vm) { return vm->tape_end - vm->tape; }
+
+// gives the tape (`get_tape_len` cells, allocated as an array of them) to the
+// caller, who frees it with the tape allocator instead of copying it out. the
+// getters still read it, so `free_vm` is the only thing to call after this
+uint32_t *take_tape(Vm *vm) {
+  vm->tape_taken = true;
+  return vm->tape;
+}
+
+size_t get_tape_head_position(const Vm *vm) { return vm->tape_head - vm->tape; }
+
+size_t get_rightmost_head_position(const Vm *vm) {
+  return vm->rightmost_head_position;
+}
+
+size_t get_origin(const Vm *vm) { return vm->origin; }
+
+int64_t get_leftmost_index(const Vm *vm) { return vm->leftmost_index; }
+
+size_t get_left_edge_contacts(const Vm *vm) { return vm->left_edge_contacts; }
 
-uint16_t *get_tape() { return tape; }
+size_t get_move_count(const Vm *vm) { return vm->moves; }
 
-size_t get_tape_len() { return tape_end - tape; }
+size_t get_cells_allocated(const Vm *vm) { return vm->cells_allocated; }
 
-size_t get_tape_head_position() { return tape_head - tape; }
+const char *get_error_message(const Vm *vm) { return vm->error_message; }
 
-size_t get_move_count() { return moves; }
+uint32_t get_error_address(const Vm *vm) { return vm->error_address; }
 
-void cleanup() {
-  FREE(tape);
-  for (size_t i = 0; i < state_count; i++) {
-    free_state(&states[i]);
+// returns NULL if there isn't enough memory. with a NULL `allocator`, the VM
+// uses malloc, realloc and free
+Vm *new_vm(const Allocator *allocator) {
+  if (!allocator) {
+    allocator = &DEFAULT_ALLOCATOR;
+  }
+  Vm *vm = allocator->alloc(allocator->data, sizeof(Vm), _Alignof(Vm));
+  if (!vm) {
+    return NULL;
+  }
+#ifdef DEBUG
+  printf("alloc %p\n", (void *)vm);
+#endif
+  vm->allocator = *allocator;
+  vm->tape_allocator = *allocator;
+  vm->hook = NULL;
+  vm->tape = NULL;
+  vm->tape_taken = false;
+  vm->bytes_start = NULL;
+  vm->threaded_code = NULL;
+#ifdef USE_COMPUTED_GOTO
+  vm->dispatch = DISPATCH_GOTO;
+#else
+  vm->dispatch = DISPATCH_SWITCH;
+#endif
+  vm->state_count = 0;
+  vm->state_stack = NULL;
+  vm->state_stack_top = NULL;
+  vm->state_stack_end = NULL;
+  vm->symbol_stack_top = vm->symbol_stack;
+  return vm;
+}
+
+// makes the tape come from `allocator` instead of the VM's allocator, so a
+// caller that takes it (see `take_tape`) can own it without every state the
+// run makes going through the same allocator. call it before `init_tape`
+void set_tape_allocator(Vm *vm, const Allocator *allocator) {
+  vm->tape_allocator = *allocator;
+}
+
+void free_vm(Vm *vm) {
+  if (vm->tape && !vm->tape_taken) {
+    TAPE_FREE(vm->tape, vm->tape_end - vm->tape);
+  }
+  for (size_t i = 0; i < vm->state_count; i++) {
+    free_state(vm, &vm->states[i]);
+  }
+  if (vm->bytes_start) {
+    FREE(vm->bytes_start, uint8_t, vm->bytes_len + BYTECODE_PADDING);
+  }
+  if (vm->threaded_code) {
+    FREE(vm->threaded_code, void *, vm->bytes_len + BYTECODE_PADDING);
+  }
+  if (vm->state_stack) {
+    FREE(vm->state_stack, State, vm->state_stack_end - vm->state_stack);
   }
+  FREE(vm, Vm, 1);
 }
@@ -1,242 +1,1068 @@
+use std::cmp;
+use std::fmt;
+use std::iter;
 use std::ops::ControlFlow;
 
+use crate::backend::{
+    self, Backend, Checkpoint, HaltReason, Hook, Observer, Options, Simulated, TapeBacking,
+    TapeRepr,
+};
 use crate::bytecode as bc;
+use crate::error::Error;
+use crate::mmap::MmapTape;
+use crate::rle::RleTape;
+use crate::transposition::{CacheSize, Lookup, Transpositions};
 
 const EXTRA_RESIZE_ROOM: usize = 256;
 
-pub struct Simulated {
-    pub tape: Vec<u16>,
-    pub head_position: usize,
-    pub final_address: u32,
-    pub moves: usize,
+pub struct RustVm;
+
+impl Backend for RustVm {
+    fn execute(&self, bytes: &[u8], tape: &[u32], options: &Options) -> Result<Simulated, Error> {
+        simulate(bytes, tape.to_vec(), options, (), None)
+    }
+
+    fn execute_hooked(
+        &self,
+        bytes: &[u8],
+        tape: &[u32],
+        options: &Options,
+        hook: Hook,
+    ) -> Result<Simulated, Error> {
+        simulate(bytes, tape.to_vec(), options, (), Some(hook))
+    }
 }
 
-#[derive(Debug, Clone)]
+impl RustVm {
+    pub fn observe(
+        &self,
+        bytes: &[u8],
+        tape: &[u32],
+        options: &Options,
+        observer: impl Observer,
+    ) -> Result<Simulated, Error> {
+        simulate(bytes, tape.to_vec(), options, observer, None)
+    }
+}
+
+// the current state. the states it was passed are nodes in the `Slab`
+#[derive(Clone)]
 struct State {
     address: u32,
-    states: Vec<State>,
-    symbols: Vec<u16>,
-}
-
-pub fn simulate(bytes: &[u8], tape: Vec<u16>, max_moves: usize) -> Simulated {
-    let mut bytes = Bytes { bytes, ip: 2 };
-    bytes.goto();
-    let address = bytes.ip as u32;
-
-    let mut vm = Vm {
-        bytes,
-        tape: Tape { tape, head: 0 },
-        state: State {
-            address,
-            states: Vec::new(),
-            symbols: Vec::new(),
-        },
-        state_stack: Vec::new(),
-        symbol_stack: Vec::new(),
-        bound: 0,
-        moves: 0,
-        max_moves,
+    states: Vec<u32>,
+    symbols: Vec<u32>,
+}
+
+// a state that's been passed to another one. nodes are shared rather than copied,
+// so passing one along (`TAKE_ARG`, `CLONE_ARG` or `FINAL_ARG`) costs the same
+// however deeply it's nested
+#[derive(Clone)]
+struct Node {
+    address: u32,
+    states: Vec<u32>,
+    symbols: Vec<u32>,
+    refs: u32,
+}
+
+// every node the machine has made. a node goes back on the free list (keeping its
+// vectors) when the last state holding it is replaced, which is also what makes
+// `FREE_ARG` a no-op, so a machine that keeps making states reuses the same few
+// allocations instead of going through the allocator on every move
+#[derive(Clone, Default)]
+struct Slab {
+    nodes: Vec<Node>,
+    free: Vec<u32>,
+    live: usize,
+    // the nodes `release` still has to let go of
+    pending: Vec<u32>,
+}
+
+impl Slab {
+    // makes a node out of the top `count` states on `states` and all of `symbols`,
+    // and returns it and whether it reused a freed node
+    fn alloc(
+        &mut self,
+        address: u32,
+        states: &mut Vec<u32>,
+        count: usize,
+        symbols: &mut Vec<u32>,
+    ) -> (u32, bool) {
+        let (index, reused) = match self.free.pop() {
+            Some(index) => (index, true),
+            None => {
+                self.nodes.push(Node {
+                    address,
+                    states: Vec::new(),
+                    symbols: Vec::new(),
+                    refs: 0,
+                });
+                (self.nodes.len() as u32 - 1, false)
+            }
+        };
+        let node = &mut self.nodes[index as usize];
+        node.address = address;
+        node.refs = 1;
+        node.states.extend(states.drain(states.len() - count..));
+        node.symbols.append(symbols);
+        self.live += 1;
+        (index, reused)
+    }
+
+    fn retain(&mut self, index: u32) {
+        self.nodes[index as usize].refs += 1;
+    }
+
+    // frees the node if nothing else holds it, and so on down the states it holds
+    fn release(&mut self, index: u32) {
+        self.pending.push(index);
+        while let Some(index) = self.pending.pop() {
+            let node = &mut self.nodes[index as usize];
+            node.refs -= 1;
+            if node.refs == 0 {
+                self.pending.append(&mut node.states);
+                node.symbols.clear();
+                self.free.push(index);
+                self.live -= 1;
+            }
+        }
+    }
+
+    // turns the node into the current state, moving its vectors over if nothing
+    // else holds it and copying them if something does
+    fn enter(&mut self, index: u32, state: &mut State) {
+        for &other in &state.states {
+            self.release(other);
+        }
+        state.states.clear();
+        state.symbols.clear();
+        let node = &mut self.nodes[index as usize];
+        state.address = node.address;
+        if node.refs == 1 {
+            std::mem::swap(&mut state.states, &mut node.states);
+            std::mem::swap(&mut state.symbols, &mut node.symbols);
+            node.refs = 0;
+            self.free.push(index);
+            self.live -= 1;
+        } else {
+            state.states.extend(&node.states);
+            state.symbols.extend(&node.symbols);
+            for &other in &state.states {
+                self.retain(other);
+            }
+            self.release(index);
+        }
+    }
+}
+
+fn simulate(
+    bytes: &[u8],
+    tape: Vec<u32>,
+    options: &Options,
+    observer: impl Observer,
+    hook: Option<Hook>,
+) -> Result<Simulated, Error> {
+    let simulated = match (options.tape_repr, &options.tape_backing) {
+        (TapeRepr::Dense, TapeBacking::Memory) => {
+            let mut tape = DenseTape::new(tape, options.two_sided);
+            tape.max_cells = options.max_tape_cells;
+            run(Vm::observed(bytes, tape, observer), options, hook)
+        }
+        (TapeRepr::Dense, TapeBacking::Mmap(path)) => {
+            let tape = MmapTape::new(path, &tape, options.two_sided, options.max_tape_cells)?;
+            run(Vm::observed(bytes, tape, observer), options, hook)
+        }
+        (TapeRepr::Rle, _) => {
+            let tape = RleTape::new(&tape, options.two_sided, options.max_tape_cells);
+            run(Vm::observed(bytes, tape, observer), options, hook)
+        }
+    };
+    Ok(simulated?)
+}
+
+fn run<O: Observer, T: Tape>(
+    mut vm: Vm<O, T>,
+    options: &Options,
+    hook: Option<Hook>,
+) -> Result<Simulated, VmError> {
+    if let Some(size) = options.transpositions {
+        if options.max_tape_cells == usize::MAX {
+            vm = vm.with_transpositions(size);
+        }
+    }
+    let Some(hook) = hook else {
+        vm.run_for(options.max_moves)?;
+        return vm.finish();
     };
+    // pause at every multiple of `every` moves, which is where the C VM calls
+    // its hook too
+    while vm.moves < options.max_moves {
+        let moves = cmp::min(
+            hook.every - vm.moves % hook.every,
+            options.max_moves - vm.moves,
+        );
+        if let StepOutcome::Stopped(_) = vm.run_for(moves)? {
+            break;
+        }
+        if vm.moves == options.max_moves {
+            break;
+        }
+        let checkpoint = Checkpoint {
+            moves: vm.moves,
+            head: vm.tape.head(),
+            address: vm.state.address,
+        };
+        if (hook.callback)(checkpoint).is_break() {
+            vm.stopped = Some(HaltReason::Interrupted);
+            break;
+        }
+    }
+    vm.finish()
+}
 
-    vm.run();
+/// Bytecode a VM couldn't run, because it came from a corrupt .tmb file
+/// or the compiler has a bug
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct VmError {
+    pub message: String,
+    /// The address of the instruction it stopped on
+    pub address: u32,
+    /// How many moves the machine had made
+    pub moves: usize,
+}
 
-    let mut tape = vm.tape.tape;
-    while let Some(0) = tape.last() {
-        tape.pop();
+impl fmt::Display for VmError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "invalid bytecode at address {} after {} moves: {}",
+            self.address, self.moves, self.message
+        )
     }
+}
+
+impl From<VmError> for Error {
+    fn from(error: VmError) -> Self {
+        Error::new(error.to_string(), None).with_help(Some(
+            "rebuild the .tmb file; if it still happens, it's a bug in the compiler".to_string(),
+        ))
+    }
+}
+
+// what was wrong with the bytecode
+#[derive(Clone, Copy)]
+enum Fault {
+    // an operand or jump ran past the end of the bytecode
+    Truncated,
+    Opcode(u8),
+    // an argument index past the current state's arguments
+    Argument(usize),
+    // `MAKE_STATE` took more states than had been pushed
+    Stack(usize),
+}
 
-    Simulated {
-        tape,
-        head_position: vm.tape.head,
-        final_address: vm.state.address,
-        moves: vm.moves,
+impl fmt::Display for Fault {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Fault::Truncated => write!(f, "ran past the end of the bytecode"),
+            Fault::Opcode(opcode) => write!(f, "unknown opcode {opcode:#04x}"),
+            Fault::Argument(index) => write!(f, "the state has no argument {index}"),
+            Fault::Stack(count) => write!(
+                f,
+                "a state takes {count} states, but not that many were pushed"
+            ),
+        }
     }
 }
 
-struct Vm<'a> {
+// why a move didn't finish
+enum Stop {
+    Halt(HaltReason),
+    Fault(Fault),
+}
+
+/// What `Vm::run_for` stopped on
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StepOutcome {
+    /// It made every move it was given, and can be run for more
+    Paused,
+    /// The machine halted, or the run was cut off by something other than the
+    /// move count, so it won't move again
+    Stopped(HaltReason),
+}
+
+/// Where the machine is before a move
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Configuration {
+    /// The address of the current state
+    pub address: u32,
+    /// Counting from the initial tape's first cell, like `Simulated::head`
+    pub head: i64,
+    /// The symbol under the head
+    pub symbol: u32,
+    /// How many moves the machine has made
+    pub moves: usize,
+}
+
+/// A machine running on the Rust VM that can be stopped after any number of
+/// moves and picked up again where it left off. A clone carries on from the
+/// same point independently, which is how the debugger goes back in time
+#[derive(Clone)]
+pub struct Vm<'a, O = (), T = DenseTape> {
     bytes: Bytes<'a>,
-    tape: Tape,
+    tape: T,
     state: State,
-    state_stack: Vec<State>,
-    symbol_stack: Vec<u16>,
-    bound: u16,
+    state_stack: Vec<u32>,
+    symbol_stack: Vec<u32>,
+    slab: Slab,
+    bound: u32,
     moves: usize,
-    max_moves: usize,
+    // where the instruction being run starts, for `VmError::address`
+    instruction: usize,
+    // whether the entry code before the first move has run
+    started: bool,
+    stopped: Option<HaltReason>,
+    error: Option<VmError>,
+    observer: O,
+    transpositions: Option<Transpositions>,
 }
 
-impl Vm<'_> {
-    fn run(&mut self) -> ControlFlow<()> {
-        loop {
-            if self.moves == self.max_moves {
-                return ControlFlow::Break(());
+impl<'a, T: Tape> Vm<'a, (), T> {
+    pub fn new(bytes: &'a [u8], tape: T) -> Self {
+        Vm::observed(bytes, tape, ())
+    }
+}
+
+impl<'a, O: Observer, T: Tape> Vm<'a, O, T> {
+    /// Like `new`, but calls `observer` as the machine runs
+    pub fn observed(bytes: &'a [u8], tape: T, observer: O) -> Self {
+        let version = match bytes.len() {
+            0 | 1 => bc::Version::V1,
+            _ => bc::Version::of(bytes),
+        };
+        let mut bytes = Bytes {
+            bytes,
+            ip: 2,
+            version,
+        };
+        // a header too short to have an entry address fails on the first `run_for`
+        let address = match bytes.goto() {
+            ControlFlow::Continue(address) => address,
+            ControlFlow::Break(_) => u32::MAX,
+        };
+        let mut vm = Vm {
+            bytes,
+            tape,
+            state: State {
+                address,
+                states: Vec::new(),
+                symbols: Vec::new(),
+            },
+            state_stack: Vec::new(),
+            symbol_stack: Vec::new(),
+            slab: Slab::default(),
+            bound: 0,
+            moves: 0,
+            instruction: 2,
+            started: false,
+            stopped: None,
+            error: None,
+            observer,
+            transpositions: None,
+        };
+        if address == u32::MAX {
+            vm.fail(Fault::Truncated);
+        }
+        vm
+    }
+
+    /// Remembers what the machine does to small windows of the tape, and skips
+    /// the moves the next time it's in the same place (see `Transpositions`).
+    /// The moves it skips aren't observed, and it only works on a tape without
+    /// a cell limit
+    pub fn with_transpositions(mut self, size: CacheSize) -> Self {
+        self.transpositions = Some(Transpositions::new(size));
+        self
+    }
+
+    /// Makes up to `moves` more moves. Once it returns `Stopped` or an error, it
+    /// always will
+    pub fn run_for(&mut self, moves: usize) -> Result<StepOutcome, VmError> {
+        if let Some(error) = &self.error {
+            return Err(error.clone());
+        }
+        if let Some(reason) = self.stopped {
+            return Ok(StepOutcome::Stopped(reason));
+        }
+        if !self.started {
+            self.started = true;
+            if let ControlFlow::Break(stop) = self.rhs() {
+                return self.stop(stop);
+            }
+            self.observer.state(self.state.address);
+        }
+        let limit = self.moves.saturating_add(moves);
+        while self.moves < limit {
+            if self.moves.is_multiple_of(backend::INTERRUPT_CHECK_INTERVAL)
+                && backend::interrupted()
+            {
+                return self.stop(Stop::Halt(HaltReason::Interrupted));
+            }
+            if self.transpose(limit) {
+                // a run of hits can skip past every multiple of the check interval
+                if backend::interrupted() {
+                    return self.stop(Stop::Halt(HaltReason::Interrupted));
+                }
+                continue;
+            }
+            let address = self.state.address;
+            if let ControlFlow::Break(stop) = self.run_move() {
+                if let Some(transpositions) = &mut self.transpositions {
+                    transpositions.stopped();
+                }
+                return self.stop(stop);
             }
-            self.run_move()?;
             self.moves += 1;
+            if self.state.address != address {
+                self.observer.state(self.state.address);
+            }
+            self.observer.moved(self.moves, self.tape.head());
+            if let Some(transpositions) = &mut self.transpositions {
+                let arguments = !self.state.states.is_empty() || !self.state.symbols.is_empty();
+                transpositions.moved(&self.tape, self.moves, self.state.address, arguments);
+            }
+        }
+        Ok(StepOutcome::Paused)
+    }
+
+    // makes the next moves from the transposition cache if they're there, and
+    // don't go past `limit`. returns whether it did
+    fn transpose(&mut self, limit: usize) -> bool {
+        let Some(transpositions) = &mut self.transpositions else {
+            return false;
+        };
+        if !self.state.states.is_empty() || !self.state.symbols.is_empty() {
+            return false;
+        }
+        match transpositions.lookup(self.state.address, &self.tape, self.moves) {
+            Lookup::Hit(entry) if self.moves + entry.moves <= limit => {
+                entry.apply(&mut self.tape);
+                self.moves += entry.moves;
+                self.state.address = entry.next;
+                self.bytes.ip = entry.next as usize;
+                self.observer.cached(true);
+                true
+            }
+            Lookup::Hit(_) | Lookup::Skipped => false,
+            Lookup::Miss => {
+                self.observer.cached(false);
+                false
+            }
+        }
+    }
+
+    fn stop(&mut self, stop: Stop) -> Result<StepOutcome, VmError> {
+        match stop {
+            Stop::Halt(reason) => {
+                self.stopped = Some(reason);
+                Ok(StepOutcome::Stopped(reason))
+            }
+            Stop::Fault(fault) => Err(self.fail(fault)),
+        }
+    }
+
+    fn fail(&mut self, fault: Fault) -> VmError {
+        let error = VmError {
+            message: fault.to_string(),
+            address: self.instruction as u32,
+            moves: self.moves,
+        };
+        self.error = Some(error.clone());
+        error
+    }
+
+    /// The configuration before each move from here on, ending with the one the
+    /// machine halts in. It doesn't end if the machine never halts, so take as
+    /// many as you need. If the bytecode is invalid it ends early, and `run_for`
+    /// and `finish` return the error
+    pub fn configurations(&mut self) -> Configurations<'_, 'a, O, T> {
+        Configurations {
+            vm: self,
+            first: true,
+        }
+    }
+
+    pub fn configuration(&self) -> Configuration {
+        Configuration {
+            address: self.state.address,
+            head: self.tape.head(),
+            symbol: self.tape.read(),
+            moves: self.moves,
+        }
+    }
+
+    pub fn tape(&self) -> &T {
+        &self.tape
+    }
+
+    /// Appends the current state to `out`: its address, its symbols and the
+    /// states it was passed (each written the same way), with counts in front of
+    /// the symbols and states. Two states are the same if they write the same
+    /// numbers
+    pub fn write_state(&self, out: &mut Vec<u32>) {
+        out.push(self.state.address);
+        write_arguments(out, &self.state.symbols, &self.state.states);
+        // nested states are written depth first without recursing, since they can
+        // be nested deeper than the stack allows
+        let mut pending: Vec<u32> = self.state.states.iter().rev().copied().collect();
+        while let Some(index) = pending.pop() {
+            let node = &self.slab.nodes[index as usize];
+            out.push(node.address);
+            write_arguments(out, &node.symbols, &node.states);
+            pending.extend(node.states.iter().rev());
+        }
+    }
+
+    pub fn moves(&self) -> usize {
+        self.moves
+    }
+
+    pub fn observer_mut(&mut self) -> &mut O {
+        &mut self.observer
+    }
+
+    /// Why the machine stopped, or `None` if it can still move (or the bytecode
+    /// was invalid)
+    pub fn stopped(&self) -> Option<HaltReason> {
+        self.stopped
+    }
+
+    /// The address of the current state, or of the entry code before the first
+    /// `run_for`
+    pub fn address(&self) -> u32 {
+        self.state.address
+    }
+
+    /// The configuration so far, untrimmed. A machine that hasn't stopped
+    /// reached the move limit
+    pub fn finish(self) -> Result<Simulated, VmError> {
+        if let Some(error) = self.error {
+            return Err(error);
+        }
+        let reason = self.stopped.unwrap_or(HaltReason::MaxMoves);
+        let mut final_state = Vec::new();
+        self.write_state(&mut final_state);
+        let mut simulated = self.tape.finish(self.state.address, self.moves, reason);
+        simulated.final_state = final_state;
+        Ok(simulated)
+    }
+
+    fn opcode(&mut self) -> ControlFlow<Stop, u8> {
+        self.instruction = self.bytes.ip;
+        let opcode = self.bytes.next()?;
+        self.observer.instruction(opcode);
+        ControlFlow::Continue(opcode)
+    }
+
+    fn symbol_arg(&mut self) -> ControlFlow<Stop, u32> {
+        let index = self.bytes.next_arg()?;
+        match self.state.symbols.get(index) {
+            Some(&symbol) => ControlFlow::Continue(symbol),
+            None => ControlFlow::Break(Stop::Fault(Fault::Argument(index))),
+        }
+    }
+
+    fn state_arg(&mut self) -> ControlFlow<Stop, usize> {
+        let index = self.bytes.next_arg()?;
+        if index < self.state.states.len() {
+            ControlFlow::Continue(index)
+        } else {
+            ControlFlow::Break(Stop::Fault(Fault::Argument(index)))
+        }
+    }
+
+    fn left(&mut self, n: usize) -> ControlFlow<Stop> {
+        self.tape.left(n).map_break(Stop::Halt)?;
+        self.visited();
+        ControlFlow::Continue(())
+    }
+
+    fn right(&mut self, n: usize) -> ControlFlow<Stop> {
+        self.tape.right(n).map_break(Stop::Halt)?;
+        self.visited();
+        ControlFlow::Continue(())
+    }
+
+    fn visited(&mut self) {
+        if let Some(transpositions) = &mut self.transpositions {
+            transpositions.visited(self.tape.head());
+        }
+    }
+
+    fn write(&mut self, value: u32) {
+        self.observer.write(self.tape.head(), value);
+        if let Some(transpositions) = &mut self.transpositions {
+            transpositions.wrote(self.tape.head());
         }
+        self.tape.write(value);
     }
 
-    fn run_move(&mut self) -> ControlFlow<()> {
+    // the move half of a fused write and move
+    fn fused_move(&mut self, opcode: u8) -> ControlFlow<Stop> {
+        match opcode {
+            bc::WRITE_ARG_LEFT | bc::WRITE_VAL_LEFT | bc::WRITE_BOUND_LEFT => self.left(1),
+            _ => self.right(1),
+        }
+    }
+
+    fn run_move(&mut self) -> ControlFlow<Stop> {
         loop {
-            match self.bytes.next() {
-                bc::COMPARE_ARG => {
-                    let arg_index = self.bytes.next();
-                    if self.tape.read() == self.state.symbols[arg_index as usize] {
-                        self.bytes.next_u16();
-                        self.rhs()?;
+            match self.opcode()? {
+                op @ (bc::COMPARE_ARG | bc::COMPARE_ARG32) => {
+                    let symbol = self.symbol_arg()?;
+                    let wide = op == bc::COMPARE_ARG32;
+                    if self.tape.read() == symbol {
+                        self.bytes.skip_operand(wide);
+                        self.take_arm()?;
                         return ControlFlow::Continue(());
                     }
-                    self.bytes.skip();
+                    self.bytes.skip(wide)?;
                 }
-                bc::COMPARE_VAL => {
-                    let value = self.bytes.next_u16();
+                op @ (bc::COMPARE_VAL | bc::COMPARE_VAL32) => {
+                    let value = self.bytes.next_symbol()?;
+                    let wide = op == bc::COMPARE_VAL32;
                     if self.tape.read() == value {
-                        self.bytes.next_u16();
-                        self.rhs()?;
+                        self.bytes.skip_operand(wide);
+                        self.take_arm()?;
                         return ControlFlow::Continue(());
                     }
-                    self.bytes.skip();
+                    self.bytes.skip(wide)?;
+                }
+                op @ (bc::PEEK_ARG | bc::PEEK_ARG32) => {
+                    let symbol = self.symbol_arg()?;
+                    let wide = op == bc::PEEK_ARG32;
+                    if self.tape.peek() == symbol {
+                        self.bytes.skip_operand(wide);
+                    } else {
+                        self.bytes.skip(wide)?;
+                    }
+                }
+                op @ (bc::PEEK_VAL | bc::PEEK_VAL32) => {
+                    let value = self.bytes.next_symbol()?;
+                    let wide = op == bc::PEEK_VAL32;
+                    if self.tape.peek() == value {
+                        self.bytes.skip_operand(wide);
+                    } else {
+                        self.bytes.skip(wide)?;
+                    }
                 }
                 bc::OTHER => {
                     self.bound = self.tape.read();
-                    self.rhs()?;
+                    self.take_arm()?;
                     return ControlFlow::Continue(());
                 }
-                bc::HALT => return ControlFlow::Break(()),
-                _ => panic!("invalid bytecode"),
+                bc::HALT => return ControlFlow::Break(Stop::Halt(HaltReason::Halted)),
+                opcode => return ControlFlow::Break(Stop::Fault(Fault::Opcode(opcode))),
             }
         }
     }
 
-    fn rhs(&mut self) -> ControlFlow<()> {
+    fn take_arm(&mut self) -> ControlFlow<Stop> {
+        self.observer.arm(self.bytes.ip as u32);
+        self.rhs()
+    }
+
+    fn rhs(&mut self) -> ControlFlow<Stop> {
         loop {
-            match self.bytes.next() {
-                bc::LEFT => self.tape.left(1)?,
-                bc::RIGHT => self.tape.right(1),
-                bc::LEFT_N => self.tape.left(self.bytes.next())?,
-                bc::RIGHT_N => self.tape.right(self.bytes.next()),
+            match self.opcode()? {
+                bc::LEFT => self.left(1)?,
+                bc::RIGHT => self.right(1)?,
+                bc::LEFT_N => {
+                    let n = self.bytes.next()?;
+                    self.left(n as usize)?;
+                }
+                bc::RIGHT_N => {
+                    let n = self.bytes.next()?;
+                    self.right(n as usize)?;
+                }
+                bc::LEFT_N16 => {
+                    let n = self.bytes.next_u16()?;
+                    self.left(n as usize)?;
+                }
+                bc::RIGHT_N16 => {
+                    let n = self.bytes.next_u16()?;
+                    self.right(n as usize)?;
+                }
                 bc::WRITE_ARG => {
-                    let arg_index = self.bytes.next() as usize;
-                    self.tape.write(self.state.symbols[arg_index]);
+                    let symbol = self.symbol_arg()?;
+                    self.write(symbol);
                 }
                 bc::WRITE_VAL => {
-                    let value = self.bytes.next_u16();
-                    self.tape.write(value);
+                    let value = self.bytes.next_symbol()?;
+                    self.write(value);
+                }
+                bc::WRITE_BOUND => self.write(self.bound),
+                op @ (bc::WRITE_ARG_LEFT | bc::WRITE_ARG_RIGHT) => {
+                    let symbol = self.symbol_arg()?;
+                    self.write(symbol);
+                    self.fused_move(op)?;
+                }
+                op @ (bc::WRITE_VAL_LEFT | bc::WRITE_VAL_RIGHT) => {
+                    let value = self.bytes.next_symbol()?;
+                    self.write(value);
+                    self.fused_move(op)?;
+                }
+                op @ (bc::WRITE_BOUND_LEFT | bc::WRITE_BOUND_RIGHT) => {
+                    self.write(self.bound);
+                    self.fused_move(op)?;
                 }
-                bc::WRITE_BOUND => self.tape.write(self.bound),
                 bc::SYMBOL_ARG => {
-                    let arg_index = self.bytes.next() as usize;
-                    self.symbol_stack.push(self.state.symbols[arg_index]);
+                    let symbol = self.symbol_arg()?;
+                    self.symbol_stack.push(symbol);
                 }
                 bc::SYMBOL_VAL => {
-                    let value = self.bytes.next_u16();
+                    let value = self.bytes.next_symbol()?;
                     self.symbol_stack.push(value);
                 }
                 bc::SYMBOL_BOUND => self.symbol_stack.push(self.bound),
                 bc::TAKE_ARG | bc::CLONE_ARG => {
-                    let arg_index = self.bytes.next() as usize;
-                    self.state_stack.push(self.state.states[arg_index].clone());
+                    let arg_index = self.state_arg()?;
+                    let node = self.state.states[arg_index];
+                    self.slab.retain(node);
+                    self.state_stack.push(node);
                 }
                 bc::FREE_ARG => {
-                    self.bytes.next();
+                    self.state_arg()?;
                 }
                 bc::MAKE_STATE => {
-                    let end = self.state_stack.len() - self.bytes.next() as usize;
-                    let states = self.state_stack.drain(end..).collect();
-                    let symbols = std::mem::take(&mut self.symbol_stack);
-                    let address = self.bytes.next_u32();
-                    self.state_stack.push(State {
+                    let count = self.bytes.next_arg()?;
+                    let address = self.bytes.next_u32()?;
+                    if count > self.state_stack.len() {
+                        return ControlFlow::Break(Stop::Fault(Fault::Stack(count)));
+                    }
+                    let (node, reused) = self.slab.alloc(
                         address,
-                        states,
-                        symbols,
-                    });
+                        &mut self.state_stack,
+                        count,
+                        &mut self.symbol_stack,
+                    );
+                    self.observer.allocated(self.slab.live, reused);
+                    self.state_stack.push(node);
                 }
                 bc::FINAL_STATE => {
-                    let states = std::mem::take(&mut self.state_stack);
-                    let symbols = std::mem::take(&mut self.symbol_stack);
-                    let address = self.bytes.goto();
-                    self.state = State {
-                        address,
-                        states,
-                        symbols,
-                    };
+                    for &node in &self.state.states {
+                        self.slab.release(node);
+                    }
+                    // the old state's vectors are emptied and reused for the next call
+                    self.state.states.clear();
+                    self.state.symbols.clear();
+                    std::mem::swap(&mut self.state.states, &mut self.state_stack);
+                    std::mem::swap(&mut self.state.symbols, &mut self.symbol_stack);
+                    self.state.address = self.bytes.goto()?;
                     return ControlFlow::Continue(());
                 }
                 bc::FINAL_ARG => {
-                    let arg_index = self.bytes.next() as usize;
-                    self.state = self.state.states[arg_index].clone();
+                    let arg_index = self.state_arg()?;
+                    // the current state is replaced, so its argument can be moved out
+                    let node = self.state.states.swap_remove(arg_index);
+                    self.slab.enter(node, &mut self.state);
                     self.bytes.ip = self.state.address as usize;
                     return ControlFlow::Continue(());
                 }
-                _ => panic!("invalid bytecode"),
+                opcode => return ControlFlow::Break(Stop::Fault(Fault::Opcode(opcode))),
             }
         }
     }
 }
 
-struct Tape {
-    tape: Vec<u16>,
-    head: usize,
+fn write_arguments(out: &mut Vec<u32>, symbols: &[u32], states: &[u32]) {
+    out.push(symbols.len() as u32);
+    out.extend(symbols);
+    out.push(states.len() as u32);
 }
 
-impl Tape {
-    fn left(&mut self, n: u8) -> ControlFlow<()> {
-        if let Some(head) = self.head.checked_sub(n as usize) {
-            self.head = head;
-            ControlFlow::Continue(())
-        } else {
-            self.head = 0;
-            ControlFlow::Break(())
+/// The iterator `Vm::configurations` returns
+pub struct Configurations<'v, 'a, O, T> {
+    vm: &'v mut Vm<'a, O, T>,
+    first: bool,
+}
+
+impl<O: Observer, T: Tape> Iterator for Configurations<'_, '_, O, T> {
+    type Item = Configuration;
+
+    fn next(&mut self) -> Option<Configuration> {
+        // the first configuration is the current one (after the entry code, if
+        // the machine hasn't started yet)
+        let moves = if self.first { 0 } else { 1 };
+        self.first = false;
+        match self.vm.run_for(moves) {
+            Ok(StepOutcome::Paused) => Some(self.vm.configuration()),
+            Ok(StepOutcome::Stopped(_)) | Err(_) => None,
+        }
+    }
+}
+
+/// The tape a `Vm` runs on (the transition table in `table` uses it too).
+/// `DenseTape` keeps every cell in a `Vec`, `mmap::MmapTape` keeps them in a
+/// file for tapes bigger than RAM, and `rle::RleTape` keeps runs of the same
+/// symbol, for machines that leave huge stretches of tape the same.
+/// Either way, it tracks the head's movement for `Simulated`, and stops the
+/// run when the head goes off the left end of a one-sided tape or spans more
+/// than its cell limit
+pub trait Tape: Clone {
+    /// The symbol under the head
+    fn read(&self) -> u32;
+    /// The symbol right of the head, for lookahead
+    fn peek(&self) -> u32;
+    fn write(&mut self, value: u32);
+    fn left(&mut self, n: usize) -> ControlFlow<HaltReason>;
+    fn right(&mut self, n: usize) -> ControlFlow<HaltReason>;
+    /// Where the head is, counting from the initial tape's first cell
+    fn head(&self) -> i64;
+    /// The symbol on a cell, counting from the initial tape's first cell
+    fn cell(&self, position: i64) -> u32;
+    /// The run's result, with a final state that wasn't passed anything (the
+    /// table backends only run machines whose states don't take any, and
+    /// `Vm::finish` fills them in)
+    fn finish(self, final_address: u32, moves: usize, halt_reason: HaltReason) -> Simulated;
+}
+
+/// A tape that keeps every cell from the leftmost one the head has been on (or
+/// the first one on the initial tape) to the last one written in a `Vec`
+#[derive(Clone)]
+pub struct DenseTape {
+    pub tape: Vec<u32>,
+    pub head: usize,
+    pub rightmost: usize,
+    // see `Simulated`
+    pub origin: usize,
+    pub leftmost: i64,
+    pub left_edge_contacts: usize,
+    pub two_sided: bool,
+    /// The most cells the head may span (see `span`) before the run stops.
+    /// There's no limit by default
+    pub max_cells: usize,
+    // see `Simulated::cells_allocated`
+    allocated: usize,
+}
+
+impl DenseTape {
+    pub fn new(tape: Vec<u32>, two_sided: bool) -> Self {
+        DenseTape {
+            allocated: tape.capacity(),
+            tape,
+            head: 0,
+            rightmost: 0,
+            origin: 0,
+            leftmost: 0,
+            left_edge_contacts: 0,
+            two_sided,
+            max_cells: usize::MAX,
         }
     }
 
-    fn right(&mut self, n: u8) {
-        self.head += n as usize;
+    /// The number of cells from the leftmost to the rightmost one the head has
+    /// been on
+    pub fn span(&self) -> usize {
+        self.rightmost - self.origin + self.leftmost.min(0).unsigned_abs() as usize + 1
     }
 
-    fn read(&self) -> u16 {
-        self.tape.get(self.head).copied().unwrap_or_default()
+    /// The cells from the first non-blank one to the last, and where the first
+    /// one is counting from the initial tape's first cell
+    pub fn non_blank(&self) -> (i64, &[u32]) {
+        let Some(start) = self.tape.iter().position(|&symbol| symbol != 0) else {
+            return (0, &[]);
+        };
+        let end = self.tape.iter().rposition(|&symbol| symbol != 0).unwrap() + 1;
+        (start as i64 - self.origin as i64, &self.tape[start..end])
+    }
+
+    // adds at least `n` blanks to the left end of the tape, and as many as there
+    // are cells already so that a head that keeps going left doesn't keep copying
+    fn grow_left(&mut self, n: usize) {
+        let extra = cmp::max(n, self.tape.len()) + EXTRA_RESIZE_ROOM;
+        let capacity = self.tape.capacity();
+        self.tape.splice(0..0, iter::repeat_n(0, extra));
+        self.count_allocation(capacity);
+        self.head += extra;
+        self.rightmost += extra;
+        self.origin += extra;
     }
 
-    fn write(&mut self, value: u16) {
-        if self.head < self.tape.len() {
-            self.tape[self.head] = value;
+    /// Writes a cell the head has been on, counting from the initial tape's
+    /// first cell
+    pub fn write_at(&mut self, position: i64, value: u32) {
+        let index = (position + self.origin as i64) as usize;
+        if index < self.tape.len() {
+            self.tape[index] = value;
         } else if value != 0 {
-            self.tape.resize(self.head + EXTRA_RESIZE_ROOM, 0);
-            self.tape[self.head] = value;
+            let capacity = self.tape.capacity();
+            self.tape.resize(index + EXTRA_RESIZE_ROOM, 0);
+            self.count_allocation(capacity);
+            self.tape[index] = value;
+        }
+    }
+
+    /// Grows the buffer so it covers a cell right of the head, counting from
+    /// the initial tape's first cell, at least doubling it so a head that keeps
+    /// going right doesn't keep copying
+    pub fn reserve(&mut self, position: i64) {
+        let index = (position + self.origin as i64) as usize;
+        if index >= self.tape.len() {
+            let capacity = self.tape.capacity();
+            let len = cmp::max(index + 1, self.tape.len() * 2) + EXTRA_RESIZE_ROOM;
+            self.tape.resize(len, 0);
+            self.count_allocation(capacity);
+        }
+    }
+
+    // adds the tape's buffer to `allocated` if it had to be reallocated
+    fn count_allocation(&mut self, old_capacity: usize) {
+        if self.tape.capacity() != old_capacity {
+            self.allocated += self.tape.capacity();
+        }
+    }
+}
+
+impl Tape for DenseTape {
+    fn read(&self) -> u32 {
+        self.tape.get(self.head).copied().unwrap_or_default()
+    }
+
+    fn peek(&self) -> u32 {
+        self.tape.get(self.head + 1).copied().unwrap_or_default()
+    }
+
+    fn write(&mut self, value: u32) {
+        self.write_at(self.head(), value);
+    }
+
+    fn left(&mut self, n: usize) -> ControlFlow<HaltReason> {
+        // track where the head would have gone on a two-way tape
+        let target = self.head as i64 - self.origin as i64 - n as i64;
+        let extended = target < self.leftmost;
+        if extended {
+            self.leftmost = target;
+        }
+        if target <= 0 {
+            self.left_edge_contacts += 1;
+        }
+
+        if n > self.head {
+            if !self.two_sided {
+                self.head = 0;
+                return ControlFlow::Break(HaltReason::LeftEdge);
+            }
+            self.grow_left(n - self.head);
+        }
+        self.head -= n;
+        if extended && self.span() > self.max_cells {
+            return ControlFlow::Break(HaltReason::TapeLimit);
+        }
+        ControlFlow::Continue(())
+    }
+
+    fn right(&mut self, n: usize) -> ControlFlow<HaltReason> {
+        self.head += n;
+        if self.head > self.rightmost {
+            self.rightmost = self.head;
+            if self.span() > self.max_cells {
+                return ControlFlow::Break(HaltReason::TapeLimit);
+            }
+        }
+        ControlFlow::Continue(())
+    }
+
+    fn head(&self) -> i64 {
+        self.head as i64 - self.origin as i64
+    }
+
+    fn cell(&self, position: i64) -> u32 {
+        usize::try_from(position + self.origin as i64)
+            .ok()
+            .and_then(|index| self.tape.get(index))
+            .copied()
+            .unwrap_or_default()
+    }
+
+    fn finish(self, final_address: u32, moves: usize, halt_reason: HaltReason) -> Simulated {
+        Simulated {
+            peak_tape_len: self.tape.len(),
+            tape: self.tape,
+            head_position: self.head,
+            rightmost_head_position: self.rightmost,
+            origin: self.origin,
+            leftmost_index: self.leftmost,
+            left_edge_contacts: self.left_edge_contacts,
+            final_address,
+            final_state: vec![final_address, 0, 0],
+            moves,
+            halt_reason,
+            cells_allocated: self.allocated,
         }
     }
 }
 
+#[derive(Clone)]
 struct Bytes<'a> {
     bytes: &'a [u8],
     ip: usize,
+    version: bc::Version,
 }
 
 impl Bytes<'_> {
-    fn skip(&mut self) {
-        let offset = self.next_u16();
-        self.ip += offset as usize;
+    // jumps to the next arm, reading a 32 bit offset if `wide`
+    fn skip(&mut self, wide: bool) -> ControlFlow<Stop> {
+        let offset = if wide {
+            self.next_u32()? as usize
+        } else {
+            self.next_u16()? as usize
+        };
+        self.ip += offset;
+        ControlFlow::Continue(())
+    }
+
+    // steps over the offset `skip` would have read
+    fn skip_operand(&mut self, wide: bool) {
+        self.ip += if wide { 4 } else { 2 };
     }
 
-    fn goto(&mut self) -> u32 {
-        let address = self.next_u32();
+    fn goto(&mut self) -> ControlFlow<Stop, u32> {
+        let address = self.next_u32()?;
         self.ip = address as usize;
-        address
+        ControlFlow::Continue(address)
+    }
+
+    fn next(&mut self) -> ControlFlow<Stop, u8> {
+        match self.bytes.get(self.ip) {
+            Some(&byte) => {
+                self.ip += 1;
+                ControlFlow::Continue(byte)
+            }
+            None => ControlFlow::Break(Stop::Fault(Fault::Truncated)),
+        }
+    }
+
+    fn next_u16(&mut self) -> ControlFlow<Stop, u16> {
+        let bytes = [self.next()?, self.next()?];
+        ControlFlow::Continue(u16::from_le_bytes(bytes))
     }
 
-    fn next(&mut self) -> u8 {
-        let byte = *self.bytes.get(self.ip).expect("invalid bytecode");
-        self.ip += 1;
-        byte
+    fn next_u32(&mut self) -> ControlFlow<Stop, u32> {
+        let bytes = [self.next()?, self.next()?, self.next()?, self.next()?];
+        ControlFlow::Continue(u32::from_le_bytes(bytes))
     }
 
-    fn next_u16(&mut self) -> u16 {
-        let bytes = [self.next(), self.next()];
-        u16::from_le_bytes(bytes)
+    fn next_arg(&mut self) -> ControlFlow<Stop, usize> {
+        ControlFlow::Continue(match self.version {
+            bc::Version::V1 => self.next()? as usize,
+            bc::Version::V2 => self.next_u16()? as usize,
+        })
     }
 
-    fn next_u32(&mut self) -> u32 {
-        let bytes = [self.next(), self.next(), self.next(), self.next()];
-        u32::from_le_bytes(bytes)
+    fn next_symbol(&mut self) -> ControlFlow<Stop, u32> {
+        ControlFlow::Continue(match self.version {
+            bc::Version::V1 => self.next_u16()? as u32,
+            bc::Version::V2 => self.next_u32()?,
+        })
     }
 }
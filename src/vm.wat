@@ -0,0 +1,437 @@
+(module
+  (memory (export "memory") {{PAGES}})
+
+  ;; tape
+  (global $tape (mut i32) (i32.const 0))
+  (global $tape_cap (mut i32) (i32.const 0))
+  (global $head (mut i32) (i32.const 0))
+
+  ;; current state
+  (global $cur (mut i32) (i32.const 0))
+
+  ;; stacks
+  (global $states_top (mut i32) (i32.const {{STATE_STACK}}))
+  (global $symbols_top (mut i32) (i32.const {{SYMBOL_STACK}}))
+
+  ;; heap
+  (global $heap_top (mut i32) (i32.const {{HEAP}}))
+
+  ;; misc
+  (global $ip (mut i32) (i32.const {{BYTES}}))
+  (global $moves (mut i64) (i64.const 0))
+  (global $bound (mut i32) (i32.const 0))
+
+  (func $next (result i32)
+    (local $byte i32)
+    (local.set $byte (i32.load8_u (global.get $ip)))
+    (global.set $ip (i32.add (global.get $ip) (i32.const 1)))
+    (local.get $byte))
+
+  (func $next_u16 (result i32)
+    (local $value i32)
+    (local.set $value (i32.load16_u (global.get $ip)))
+    (global.set $ip (i32.add (global.get $ip) (i32.const 2)))
+    (local.get $value))
+
+  (func $next_u32 (result i32)
+    (local $value i32)
+    (local.set $value (i32.load (global.get $ip)))
+    (global.set $ip (i32.add (global.get $ip) (i32.const 4)))
+    (local.get $value))
+
+  (func $go_to (param $address i32)
+    (global.set $ip (i32.add (i32.const {{BYTES}}) (local.get $address))))
+
+  (func $skip
+    (local $offset i32)
+    (local.set $offset (call $next_u16))
+    (global.set $ip (i32.add (global.get $ip) (local.get $offset))))
+
+  (func $skip32
+    (local $offset i32)
+    (local.set $offset (call $next_u32))
+    (global.set $ip (i32.add (global.get $ip) (local.get $offset))))
+
+  ;; bump allocates `size` bytes, growing the memory if needed
+  (func $reserve (param $size i32) (result i32)
+    (local $ptr i32)
+    (local $end i32)
+    (local $pages i32)
+    (local.set $ptr (global.get $heap_top))
+    (local.set $end (i32.add (local.get $ptr) (local.get $size)))
+    (local.set $pages (i32.shr_u (i32.add (local.get $end) (i32.const 65535)) (i32.const 16)))
+    (if (i32.gt_u (local.get $pages) (memory.size))
+      (then
+        (if (i32.eq (memory.grow (i32.sub (local.get $pages) (memory.size))) (i32.const -1))
+          (then unreachable))))
+    (global.set $heap_top (i32.and (i32.add (local.get $end) (i32.const 3)) (i32.const -4)))
+    (local.get $ptr))
+
+  ;; states are laid out as [address, state count, symbol count, states..., symbols...]
+  (func $words (param $state i32) (result i32)
+    (i32.add
+      (i32.const 3)
+      (i32.add (i32.load offset=4 (local.get $state)) (i32.load offset=8 (local.get $state)))))
+
+  (func $state_slot (param $state i32) (param $index i32) (result i32)
+    (i32.add (i32.add (local.get $state) (i32.const 12)) (i32.shl (local.get $index) (i32.const 2))))
+
+  (func $symbol_arg (param $state i32) (param $index i32) (result i32)
+    (i32.load
+      (call $state_slot
+        (local.get $state)
+        (i32.add (i32.load offset=4 (local.get $state)) (local.get $index)))))
+
+  ;; states too big for the free lists are never reused
+  (func $alloc (param $words i32) (result i32)
+    (local $list i32)
+    (local $ptr i32)
+    (if (i32.ge_u (local.get $words) (i32.const {{FREE_LISTS_LEN}}))
+      (then (return (call $reserve (i32.shl (local.get $words) (i32.const 2))))))
+    (local.set $list (i32.add (i32.const {{FREE_LISTS}}) (i32.shl (local.get $words) (i32.const 2))))
+    (local.set $ptr (i32.load (local.get $list)))
+    (if (result i32) (local.get $ptr)
+      (then
+        (i32.store (local.get $list) (i32.load (local.get $ptr)))
+        (local.get $ptr))
+      (else
+        (call $reserve (i32.shl (local.get $words) (i32.const 2))))))
+
+  (func $free_shell (param $state i32)
+    (local $list i32)
+    (if (i32.ge_u (call $words (local.get $state)) (i32.const {{FREE_LISTS_LEN}}))
+      (then return))
+    (local.set $list
+      (i32.add (i32.const {{FREE_LISTS}}) (i32.shl (call $words (local.get $state)) (i32.const 2))))
+    (i32.store (local.get $state) (i32.load (local.get $list)))
+    (i32.store (local.get $list) (local.get $state)))
+
+  (func $free_state (param $state i32)
+    (local $i i32)
+    (block $done
+      (loop $each
+        (br_if $done (i32.ge_u (local.get $i) (i32.load offset=4 (local.get $state))))
+        (call $free_state (i32.load (call $state_slot (local.get $state) (local.get $i))))
+        (local.set $i (i32.add (local.get $i) (i32.const 1)))
+        (br $each)))
+    (call $free_shell (local.get $state)))
+
+  (func $clone_state (param $state i32) (result i32)
+    (local $words i32)
+    (local $cloned i32)
+    (local $slot i32)
+    (local $i i32)
+    (local.set $words (call $words (local.get $state)))
+    (local.set $cloned (call $alloc (local.get $words)))
+    (memory.copy (local.get $cloned) (local.get $state) (i32.shl (local.get $words) (i32.const 2)))
+    (block $done
+      (loop $each
+        (br_if $done (i32.ge_u (local.get $i) (i32.load offset=4 (local.get $state))))
+        (local.set $slot (call $state_slot (local.get $cloned) (local.get $i)))
+        (i32.store (local.get $slot) (call $clone_state (i32.load (local.get $slot))))
+        (local.set $i (i32.add (local.get $i) (i32.const 1)))
+        (br $each)))
+    (local.get $cloned))
+
+  (func $make_state (param $args i32) (param $address i32) (result i32)
+    (local $symbols i32)
+    (local $state i32)
+    (local.set $symbols
+      (i32.shr_u (i32.sub (global.get $symbols_top) (i32.const {{SYMBOL_STACK}})) (i32.const 2)))
+    (global.set $states_top (i32.sub (global.get $states_top) (i32.shl (local.get $args) (i32.const 2))))
+    (local.set $state
+      (call $alloc (i32.add (i32.const 3) (i32.add (local.get $args) (local.get $symbols)))))
+    (i32.store (local.get $state) (local.get $address))
+    (i32.store offset=4 (local.get $state) (local.get $args))
+    (i32.store offset=8 (local.get $state) (local.get $symbols))
+    (memory.copy
+      (call $state_slot (local.get $state) (i32.const 0))
+      (global.get $states_top)
+      (i32.shl (local.get $args) (i32.const 2)))
+    (memory.copy
+      (call $state_slot (local.get $state) (local.get $args))
+      (i32.const {{SYMBOL_STACK}})
+      (i32.shl (local.get $symbols) (i32.const 2)))
+    (global.set $symbols_top (i32.const {{SYMBOL_STACK}}))
+    (local.get $state))
+
+  ;; the stacks have a fixed size, so a machine that nests too many arguments
+  ;; traps instead of writing over the next region
+  (func $push_state (param $state i32)
+    (if (i32.ge_u (global.get $states_top) (i32.const {{SYMBOL_STACK}}))
+      (then unreachable))
+    (i32.store (global.get $states_top) (local.get $state))
+    (global.set $states_top (i32.add (global.get $states_top) (i32.const 4))))
+
+  (func $push_symbol (param $symbol i32)
+    (if (i32.ge_u (global.get $symbols_top) (i32.const {{BYTES}}))
+      (then unreachable))
+    (i32.store (global.get $symbols_top) (local.get $symbol))
+    (global.set $symbols_top (i32.add (global.get $symbols_top) (i32.const 4))))
+
+  (func $enter (param $state i32)
+    (if (global.get $cur)
+      (then (call $free_shell (global.get $cur))))
+    (global.set $cur (local.get $state))
+    (call $go_to (i32.load (local.get $state))))
+
+  ;; returns 1 if the machine fell off the left edge of the tape
+  (func $left (param $n i32) (result i32)
+    (if (i32.lt_u (global.get $head) (local.get $n))
+      (then
+        (global.set $head (i32.const 0))
+        (return (i32.const 1))))
+    (global.set $head (i32.sub (global.get $head) (local.get $n)))
+    (i32.const 0))
+
+  (func $read (result i32)
+    (if (result i32) (i32.lt_u (global.get $head) (global.get $tape_cap))
+      (then
+        (i32.load16_u (i32.add (global.get $tape) (i32.shl (global.get $head) (i32.const 1)))))
+      (else (i32.const 0))))
+
+  (func $peek (result i32)
+    (if (result i32) (i32.lt_u (i32.add (global.get $head) (i32.const 1)) (global.get $tape_cap))
+      (then
+        (i32.load16_u
+          (i32.add (global.get $tape) (i32.shl (i32.add (global.get $head) (i32.const 1)) (i32.const 1)))))
+      (else (i32.const 0))))
+
+  (func $write (param $value i32)
+    (local $cap i32)
+    (local $tape i32)
+    (if (i32.ge_u (global.get $head) (global.get $tape_cap))
+      (then
+        (if (i32.eqz (local.get $value))
+          (then (return)))
+        (local.set $cap (i32.shl (i32.add (global.get $head) (i32.const 1)) (i32.const 1)))
+        (local.set $tape (call $reserve (i32.shl (local.get $cap) (i32.const 1))))
+        (memory.fill (local.get $tape) (i32.const 0) (i32.shl (local.get $cap) (i32.const 1)))
+        (memory.copy
+          (local.get $tape)
+          (global.get $tape)
+          (i32.shl (global.get $tape_cap) (i32.const 1)))
+        (global.set $tape (local.get $tape))
+        (global.set $tape_cap (local.get $cap))))
+    (i32.store16
+      (i32.add (global.get $tape) (i32.shl (global.get $head) (i32.const 1)))
+      (local.get $value)))
+
+  ;; returns 1 if the machine should stop
+  (func $run_rhs (result i32)
+    (local $state i32)
+    (loop $dispatch
+      (block $write_bound_right
+      (block $write_bound_left
+      (block $write_val_right
+      (block $write_val_left
+      (block $write_arg_right
+      (block $write_arg_left
+      (block $right_n16
+      (block $left_n16
+      (block $final_arg
+      (block $final_state
+      (block $make_state
+      (block $free_arg
+      (block $clone_arg
+      (block $take_arg
+      (block $symbol_bound
+      (block $symbol_val
+      (block $symbol_arg
+      (block $write_bound
+      (block $write_val
+      (block $write_arg
+      (block $right_n
+      (block $left_n
+      (block $right
+      (block $left
+      (block $invalid
+        (br_table
+          $left $right $left_n $right_n
+          $write_arg $write_val $write_bound
+          $symbol_arg $symbol_val $symbol_bound
+          $take_arg $clone_arg $free_arg $make_state $final_state $final_arg
+          $invalid $invalid $invalid $invalid $invalid $invalid
+          $left_n16 $right_n16
+          $invalid $invalid $invalid $invalid
+          $write_arg_left $write_arg_right $write_val_left $write_val_right
+          $write_bound_left $write_bound_right
+          $invalid
+          (call $next)))
+        unreachable)
+        (if (call $left (i32.const 1))
+          (then (return (i32.const 1))))
+        (br $dispatch))
+        (global.set $head (i32.add (global.get $head) (i32.const 1)))
+        (br $dispatch))
+        (if (call $left (call $next))
+          (then (return (i32.const 1))))
+        (br $dispatch))
+        (global.set $head (i32.add (global.get $head) (call $next)))
+        (br $dispatch))
+        (call $write (call $symbol_arg (global.get $cur) (call $next)))
+        (br $dispatch))
+        (call $write (call $next_u16))
+        (br $dispatch))
+        (call $write (global.get $bound))
+        (br $dispatch))
+        (call $push_symbol (call $symbol_arg (global.get $cur) (call $next)))
+        (br $dispatch))
+        (call $push_symbol (call $next_u16))
+        (br $dispatch))
+        (call $push_symbol (global.get $bound))
+        (br $dispatch))
+        (call $push_state (i32.load (call $state_slot (global.get $cur) (call $next))))
+        (br $dispatch))
+        (call $push_state
+          (call $clone_state (i32.load (call $state_slot (global.get $cur) (call $next)))))
+        (br $dispatch))
+        (call $free_state (i32.load (call $state_slot (global.get $cur) (call $next))))
+        (br $dispatch))
+        (call $push_state (call $make_state (call $next) (call $next_u32)))
+        (br $dispatch))
+        (call $enter
+          (call $make_state
+            (i32.shr_u (i32.sub (global.get $states_top) (i32.const {{STATE_STACK}})) (i32.const 2))
+            (call $next_u32)))
+        (return (i32.const 0)))
+      (call $enter (i32.load (call $state_slot (global.get $cur) (call $next))))
+      (return (i32.const 0)))
+      (if (call $left (call $next_u16))
+        (then (return (i32.const 1))))
+      (br $dispatch))
+      (global.set $head (i32.add (global.get $head) (call $next_u16)))
+      (br $dispatch))
+      (call $write (call $symbol_arg (global.get $cur) (call $next)))
+      (if (call $left (i32.const 1))
+        (then (return (i32.const 1))))
+      (br $dispatch))
+      (call $write (call $symbol_arg (global.get $cur) (call $next)))
+      (global.set $head (i32.add (global.get $head) (i32.const 1)))
+      (br $dispatch))
+      (call $write (call $next_u16))
+      (if (call $left (i32.const 1))
+        (then (return (i32.const 1))))
+      (br $dispatch))
+      (call $write (call $next_u16))
+      (global.set $head (i32.add (global.get $head) (i32.const 1)))
+      (br $dispatch))
+      (call $write (global.get $bound))
+      (if (call $left (i32.const 1))
+        (then (return (i32.const 1))))
+      (br $dispatch))
+      (call $write (global.get $bound))
+      (global.set $head (i32.add (global.get $head) (i32.const 1)))
+      (br $dispatch))
+    unreachable)
+
+  ;; returns 1 if the machine should stop
+  (func $run_move (result i32)
+    (local $op i32)
+    (loop $arms
+      (local.set $op (call $next))
+      (if (i32.eq (local.get $op) (i32.const 16))
+        (then
+          (if (i32.eq (call $read) (call $symbol_arg (global.get $cur) (call $next)))
+            (then
+              (drop (call $next_u16))
+              (return (call $run_rhs))))
+          (call $skip)
+          (br $arms)))
+      (if (i32.eq (local.get $op) (i32.const 17))
+        (then
+          (if (i32.eq (call $next_u16) (call $read))
+            (then
+              (drop (call $next_u16))
+              (return (call $run_rhs))))
+          (call $skip)
+          (br $arms)))
+      (if (i32.eq (local.get $op) (i32.const 20))
+        (then
+          (if (i32.eq (call $peek) (call $symbol_arg (global.get $cur) (call $next)))
+            (then (drop (call $next_u16)))
+            (else (call $skip)))
+          (br $arms)))
+      (if (i32.eq (local.get $op) (i32.const 21))
+        (then
+          (if (i32.eq (call $next_u16) (call $peek))
+            (then (drop (call $next_u16)))
+            (else (call $skip)))
+          (br $arms)))
+      (if (i32.eq (local.get $op) (i32.const 24))
+        (then
+          (if (i32.eq (call $read) (call $symbol_arg (global.get $cur) (call $next)))
+            (then
+              (drop (call $next_u32))
+              (return (call $run_rhs))))
+          (call $skip32)
+          (br $arms)))
+      (if (i32.eq (local.get $op) (i32.const 25))
+        (then
+          (if (i32.eq (call $next_u16) (call $read))
+            (then
+              (drop (call $next_u32))
+              (return (call $run_rhs))))
+          (call $skip32)
+          (br $arms)))
+      (if (i32.eq (local.get $op) (i32.const 26))
+        (then
+          (if (i32.eq (call $peek) (call $symbol_arg (global.get $cur) (call $next)))
+            (then (drop (call $next_u32)))
+            (else (call $skip32)))
+          (br $arms)))
+      (if (i32.eq (local.get $op) (i32.const 27))
+        (then
+          (if (i32.eq (call $next_u16) (call $peek))
+            (then (drop (call $next_u32)))
+            (else (call $skip32)))
+          (br $arms)))
+      (if (i32.eq (local.get $op) (i32.const 18))
+        (then
+          (global.set $bound (call $read))
+          (return (call $run_rhs))))
+      (if (i32.eq (local.get $op) (i32.const 19))
+        (then (return (i32.const 1))))
+      unreachable)
+    unreachable)
+
+  (func (export "run") (param $max_moves i64) (result i64)
+    (memory.fill (i32.const {{FREE_LISTS}}) (i32.const 0) (i32.const {{FREE_LISTS_SIZE}}))
+    (global.set $heap_top (i32.const {{HEAP}}))
+    (global.set $states_top (i32.const {{STATE_STACK}}))
+    (global.set $symbols_top (i32.const {{SYMBOL_STACK}}))
+    (global.set $cur (i32.const 0))
+    (global.set $head (i32.const 0))
+    (global.set $moves (i64.const 0))
+
+    (global.set $tape_cap (i32.const {{TAPE_CAPACITY}}))
+    (global.set $tape (call $reserve (i32.const {{TAPE_CAPACITY_BYTES}})))
+    (memory.fill (global.get $tape) (i32.const 0) (i32.const {{TAPE_CAPACITY_BYTES}}))
+    (memory.copy (global.get $tape) (i32.const {{INITIAL_TAPE}}) (i32.const {{INITIAL_TAPE_BYTES}}))
+
+    (global.set $ip (i32.const {{BYTES}}))
+    (drop (call $next_u16))
+    (call $go_to (call $next_u32))
+
+    (if (i32.eqz (call $run_rhs))
+      (then
+        (block $done
+          (loop $moves
+            (br_if $done (i64.ge_u (global.get $moves) (local.get $max_moves)))
+            (br_if $done (call $run_move))
+            (global.set $moves (i64.add (global.get $moves) (i64.const 1)))
+            (br $moves)))))
+
+    (global.get $moves))
+
+  (func (export "tape") (result i32) (global.get $tape))
+  (func (export "tape_len") (result i32) (global.get $tape_cap))
+  (func (export "head") (result i32) (global.get $head))
+  (func (export "moves") (result i64) (global.get $moves))
+  (func (export "symbols") (result i32) (i32.const {{SYMBOLS}}))
+  (func (export "symbols_len") (result i32) (i32.const {{SYMBOLS_LEN}}))
+
+  (data (i32.const {{BYTES}}) "{{BYTES_DATA}}")
+  (data (i32.const {{INITIAL_TAPE}}) "{{INITIAL_TAPE_DATA}}")
+  (data (i32.const {{SYMBOLS}}) "{{SYMBOLS_DATA}}")
+)
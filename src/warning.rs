@@ -0,0 +1,656 @@
+use std::collections::hash_map::Entry;
+use std::collections::{HashMap, HashSet};
+
+use clap::ValueEnum;
+use unicode_normalization::{is_nfc, UnicodeNormalization};
+use unicode_script::{Script, UnicodeScript};
+use unicode_security::{skeleton, MixedScript};
+
+use crate::analysis;
+use crate::compile::Signature;
+use crate::error::{Error, Level};
+use crate::lex::Span;
+use crate::output;
+use crate::parse::{Arm, Name, Op, Pattern, StartArgs, State, Symbol, ToState, Unit};
+use crate::tape;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum Lint {
+    // states that can't be reached from `start`
+    UnreachableState,
+    // state and symbol parameters that are never used
+    UnusedParameter,
+    // tape symbols that the machine never matches
+    UnusedSymbol,
+    // symbols the machine writes but never matches (only with a declared alphabet)
+    UnreadSymbol,
+    // arms that go back to their own state without moving the head or changing the tape
+    InfiniteLoop,
+    // arms that match the same thing as an earlier arm
+    ShadowedArm,
+    // machines that can't halt (off by default)
+    NeverHalts,
+    // state names that mix scripts (off by default)
+    MixedScriptIdent,
+    // state names that look like other state names or aren't in NFC (off by default)
+    ConfusableIdent,
+    // states that share a name but take different arguments (off by default)
+    OverloadedState,
+}
+
+impl Lint {
+    pub const STRICT_IDENTS: [Lint; 2] = [Lint::MixedScriptIdent, Lint::ConfusableIdent];
+
+    pub fn enabled_by_default(self) -> bool {
+        !Lint::STRICT_IDENTS.contains(&self)
+            && self != Lint::OverloadedState
+            && self != Lint::NeverHalts
+    }
+}
+
+pub struct Warning {
+    pub lint: Lint,
+    pub diagnostic: Error,
+}
+
+pub fn check(unit: &Unit, start_args: &StartArgs, tape: &[Symbol]) -> Vec<Warning> {
+    let mut warnings = Vec::new();
+    unreachable_states(&unit.states, start_args, &mut warnings);
+    for state in &unit.states {
+        unused_parameters(state, &mut warnings);
+        infinite_loops(state, &mut warnings);
+        shadowed_arms(state, &mut warnings);
+    }
+    never_halts(unit, start_args, &mut warnings);
+    unused_symbols(&unit.states, start_args, tape, &mut warnings);
+    if unit.alphabet.is_some() {
+        unread_symbols(&unit.states, start_args, &mut warnings);
+    }
+    strict_idents(&unit.states, &mut warnings);
+    overloaded_states(&unit.states, &mut warnings);
+    warnings
+}
+
+fn unreachable_states(unit: &[State], start_args: &StartArgs, warnings: &mut Vec<Warning>) {
+    let reachable = analysis::reachable(unit, start_args);
+    for state in unit {
+        let signature = Signature::of(state);
+        if !reachable.contains(&signature) {
+            warnings.push(Warning {
+                lint: Lint::UnreachableState,
+                diagnostic: Error::warning(
+                    format!("state `{signature}` is never reached from `start`"),
+                    Some(state.name.span),
+                ),
+            });
+        }
+    }
+}
+
+fn unused_parameters(state: &State, warnings: &mut Vec<Warning>) {
+    let mut used = HashSet::new();
+    for arm in &state.arms {
+        for pattern in [Some(&arm.pattern), arm.lookahead.as_ref()]
+            .into_iter()
+            .flatten()
+        {
+            if let Pattern::Name(name) = pattern {
+                used.insert(name.name);
+            }
+        }
+        for op in &arm.ops {
+            if let Op::Name(name) = op {
+                used.insert(name.name);
+            }
+        }
+        names_in(&arm.to_state, &mut used);
+    }
+
+    let params = state
+        .state_params
+        .iter()
+        .map(|param| ("state", param))
+        .chain(state.symbol_params.iter().map(|param| ("symbol", param)));
+    for (kind, param) in params {
+        if !used.contains(param.name) && !param.name.starts_with('_') {
+            warnings.push(Warning {
+                lint: Lint::UnusedParameter,
+                diagnostic: Error::warning(
+                    format!("unused {kind} parameter `{}`", param.name),
+                    Some(param.span),
+                )
+                .with_help(Some(format!(
+                    "if this is intentional, prefix it with an underscore: `_{}`",
+                    param.name
+                ))),
+            });
+        }
+    }
+}
+
+fn names_in<'a>(to_state: &ToState<'a>, used: &mut HashSet<&'a str>) {
+    if let ToState::State {
+        name,
+        state_args,
+        symbol_args,
+    } = to_state
+    {
+        if state_args.is_empty() && symbol_args.is_empty() {
+            used.insert(name.name);
+        }
+        for state_arg in state_args {
+            names_in(state_arg, used);
+        }
+        for symbol_arg in symbol_args {
+            if let Pattern::Name(Name { name, .. }) = symbol_arg {
+                used.insert(name);
+            }
+        }
+    }
+}
+
+// a symbol counts as matched if it shows up anywhere it could be compared
+// against the tape: a pattern, a lookahead or a symbol argument
+fn matched_symbols(unit: &[State], start_args: &StartArgs) -> HashSet<String> {
+    let mut matched = HashSet::new();
+    let mut add = |pattern: &Pattern| {
+        if let Pattern::Symbol(symbol) = pattern {
+            matched.insert(symbol.symbol.clone());
+        }
+    };
+    for pattern in &start_args.symbol_args {
+        add(pattern);
+    }
+    for state_arg in &start_args.state_args {
+        symbol_args_in(state_arg, &mut add);
+    }
+    for state in unit {
+        for arm in &state.arms {
+            add(&arm.pattern);
+            if let Some(lookahead) = &arm.lookahead {
+                add(lookahead);
+            }
+            symbol_args_in(&arm.to_state, &mut add);
+        }
+    }
+    matched
+}
+
+fn unused_symbols(
+    unit: &[State],
+    start_args: &StartArgs,
+    tape: &[Symbol],
+    warnings: &mut Vec<Warning>,
+) {
+    let matched = matched_symbols(unit, start_args);
+    let mut reported = HashSet::new();
+    for symbol in tape {
+        if !symbol.symbol.is_empty()
+            && !matched.contains(&symbol.symbol)
+            && reported.insert(&symbol.symbol)
+        {
+            warnings.push(Warning {
+                lint: Lint::UnusedSymbol,
+                diagnostic: Error::warning(
+                    format!(
+                        "tape symbol `{}` is never matched by the machine",
+                        tape::literal(&symbol.symbol)
+                    ),
+                    Some(symbol.span),
+                ),
+            });
+        }
+    }
+}
+
+fn unread_symbols(unit: &[State], start_args: &StartArgs, warnings: &mut Vec<Warning>) {
+    let matched = matched_symbols(unit, start_args);
+    let mut reported = HashSet::new();
+    for state in unit {
+        for arm in &state.arms {
+            for op in &arm.ops {
+                if let Op::Symbol(symbol) = op {
+                    if !symbol.symbol.is_empty()
+                        && !matched.contains(&symbol.symbol)
+                        && reported.insert(&symbol.symbol)
+                    {
+                        warnings.push(Warning {
+                            lint: Lint::UnreadSymbol,
+                            diagnostic: Error::warning(
+                                format!(
+                                    "symbol `{}` is written but never read",
+                                    tape::literal(&symbol.symbol)
+                                ),
+                                Some(symbol.span),
+                            ),
+                        });
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn symbol_args_in(to_state: &ToState, add: &mut impl FnMut(&Pattern)) {
+    if let ToState::State {
+        state_args,
+        symbol_args,
+        ..
+    } = to_state
+    {
+        for state_arg in state_args {
+            symbol_args_in(state_arg, add);
+        }
+        for symbol_arg in symbol_args {
+            add(symbol_arg);
+        }
+    }
+}
+
+// an arm that leaves the head, the tape and the state the way it found them
+// matches again on the next move, and the one after that...
+fn infinite_loops(state: &State, warnings: &mut Vec<Warning>) {
+    for arm in &state.arms {
+        if !is_same_state(state, &arm.to_state) || !leaves_tape_alone(arm) {
+            continue;
+        }
+        let span = match &arm.pattern {
+            Pattern::Symbol(symbol) => symbol.span,
+            Pattern::Name(name) => name.span,
+        };
+        warnings.push(Warning {
+            lint: Lint::InfiniteLoop,
+            diagnostic: Error::warning(
+                format!(
+                    "state `{}` loops forever once this arm matches",
+                    Signature::of(state)
+                ),
+                Some(span),
+            )
+            .with_label("it doesn't move the head or change the tape".to_string())
+            .with_help(Some(
+                "move the head, write a different symbol or go to another state".to_string(),
+            )),
+        });
+    }
+}
+
+fn shadowed_arms(state: &State, warnings: &mut Vec<Warning>) {
+    let mut seen: HashMap<_, Span> = HashMap::new();
+    for arm in &state.arms {
+        let Some((kind, pattern, span)) = arm_key(state, &arm.pattern) else {
+            continue;
+        };
+        let lookahead = match &arm.lookahead {
+            Some(lookahead) => match arm_key(state, lookahead) {
+                Some((kind, lookahead, _)) => Some((kind, lookahead)),
+                None => continue,
+            },
+            None => None,
+        };
+
+        let earlier = seen
+            .get(&(kind, pattern, lookahead))
+            .or_else(|| seen.get(&(kind, pattern, None)));
+        if let Some(&earlier) = earlier {
+            let literal = |(kind, pattern): (&str, &str)| match kind {
+                "symbol" => tape::literal(pattern),
+                _ => pattern.to_string(),
+            };
+            let what = match lookahead.filter(|_| seen.contains_key(&(kind, pattern, lookahead))) {
+                Some(lookahead) => {
+                    format!("`{} then {}`", literal((kind, pattern)), literal(lookahead))
+                }
+                None => format!("`{}`", literal((kind, pattern))),
+            };
+            warnings.push(Warning {
+                lint: Lint::ShadowedArm,
+                diagnostic: Error::warning("this arm is never taken".to_string(), Some(span))
+                    .with_note(earlier, format!("{what} is already matched here")),
+            });
+        } else {
+            seen.insert((kind, pattern, lookahead), span);
+        }
+    }
+}
+
+// what an arm compares against the tape, so `'a'` and `'a' then 'b'` are different
+fn arm_key<'a>(
+    state: &'a State,
+    pattern: &'a Pattern,
+) -> Option<(&'static str, &'a str, Span<'a>)> {
+    match pattern {
+        Pattern::Symbol(symbol) => Some(("symbol", symbol.symbol.as_str(), symbol.span)),
+        Pattern::Name(name)
+            if state
+                .symbol_params
+                .iter()
+                .any(|param| param.name == name.name) =>
+        {
+            Some(("parameter", name.name, name.span))
+        }
+        // a catchall (only the last arm can be one)
+        Pattern::Name(_) => None,
+    }
+}
+
+// a machine halts by going to `!`, reaching a state that has no arm for the
+// symbol under the head or moving off the left edge. if none of those can
+// happen from `start`, it runs forever
+fn never_halts(unit: &Unit, start_args: &StartArgs, warnings: &mut Vec<Warning>) {
+    let start = Signature::start(start_args);
+    let reachable = analysis::reachable(&unit.states, start_args);
+    let states: Vec<_> = unit
+        .states
+        .iter()
+        .filter(|state| reachable.contains(&Signature::of(state)))
+        .collect();
+    let Some(first) = states.iter().find(|state| Signature::of(state) == start) else {
+        return;
+    };
+
+    let handles_everything = |state: &State| {
+        let arms: Vec<_> = state
+            .arms
+            .iter()
+            .filter(|arm| arm.lookahead.is_none())
+            .collect();
+        let catchall = arms.iter().any(|arm| {
+            matches!(&arm.pattern, Pattern::Name(name)
+                if !state.symbol_params.iter().any(|param| param.name == name.name))
+        });
+        let exhaustive = unit.alphabet.as_ref().is_some_and(|alphabet| {
+            alphabet
+                .symbols
+                .iter()
+                .map(|symbol| symbol.symbol.as_str())
+                .chain([""])
+                .all(|symbol| {
+                    arms.iter()
+                        .any(|arm| matches!(&arm.pattern, Pattern::Symbol(s) if s.symbol == symbol))
+                })
+        });
+        catchall || exhaustive
+    };
+    let mut halts = start_args.state_args.iter().any(contains_halt);
+    for state in &states {
+        halts |= !handles_everything(state)
+            || state.arms.iter().any(|arm| {
+                contains_halt(&arm.to_state) || arm.ops.iter().any(|op| matches!(op, Op::Left(_)))
+            });
+    }
+
+    if !halts {
+        warnings.push(Warning {
+            lint: Lint::NeverHalts,
+            diagnostic: Error::warning(
+                "the machine never halts".to_string(),
+                Some(first.name.span),
+            )
+            .with_help(Some(
+                "every state reachable from here handles every symbol, and none of them go to `!` \
+                 or move left"
+                    .to_string(),
+            )),
+        });
+    }
+}
+
+fn contains_halt(to_state: &ToState) -> bool {
+    match to_state {
+        ToState::State { state_args, .. } => state_args.iter().any(contains_halt),
+        ToState::Halt { .. } => true,
+    }
+}
+
+// `f(A, B; x)` only stays in the same state by passing its own parameters
+// along in the same order
+fn is_same_state(state: &State, to_state: &ToState) -> bool {
+    let ToState::State {
+        name,
+        state_args,
+        symbol_args,
+    } = to_state
+    else {
+        return false;
+    };
+    let is_param = |param: &Name, name: &str| param.name == name;
+    name.name == state.name.name
+        && !state.state_params.iter().any(|param| is_param(param, name.name))
+        && state_args.len() == state.state_params.len()
+        && symbol_args.len() == state.symbol_params.len()
+        && state_args.iter().zip(&state.state_params).all(|(arg, param)| {
+            matches!(
+                arg,
+                ToState::State { name, state_args, symbol_args }
+                    if is_param(param, name.name) && state_args.is_empty() && symbol_args.is_empty()
+            )
+        })
+        && symbol_args
+            .iter()
+            .zip(&state.symbol_params)
+            .all(|(arg, param)| matches!(arg, Pattern::Name(name) if is_param(param, name.name)))
+}
+
+// runs the ops on a tape where only the matched cell and the lookahead are
+// known, and checks that the head ends where it started with every cell it
+// wrote holding what it held before
+fn leaves_tape_alone(arm: &Arm) -> bool {
+    let mut head = 0isize;
+    let mut written = HashMap::new();
+    for op in &arm.ops {
+        match op {
+            Op::Left(_) => head -= 1,
+            Op::Right(_) => head += 1,
+            Op::Name(name) => {
+                written.insert(head, Pattern::Name(name.clone()));
+            }
+            Op::Symbol(symbol) => {
+                written.insert(head, Pattern::Symbol(symbol.clone()));
+            }
+        }
+    }
+
+    let same = |a: &Pattern, b: &Pattern| match (a, b) {
+        (Pattern::Symbol(a), Pattern::Symbol(b)) => a.symbol == b.symbol,
+        (Pattern::Name(a), Pattern::Name(b)) => a.name == b.name,
+        _ => false,
+    };
+    head == 0
+        && written.iter().all(|(&cell, value)| {
+            let before = match cell {
+                0 => Some(&arm.pattern),
+                1 => arm.lookahead.as_ref(),
+                _ => None,
+            };
+            before.is_some_and(|before| same(before, value))
+        })
+}
+
+fn strict_idents(unit: &[State], warnings: &mut Vec<Warning>) {
+    let mut names: Vec<&Name> = Vec::new();
+    for state in unit {
+        if !names.iter().any(|name| name.name == state.name.name) {
+            names.push(&state.name);
+        }
+    }
+
+    let mut skeletons: HashMap<String, &Name> = HashMap::new();
+    for name in names {
+        let normalized: String = name.name.nfc().collect();
+        if !is_nfc(name.name) {
+            warnings.push(Warning {
+                lint: Lint::ConfusableIdent,
+                diagnostic: Error::warning(
+                    format!("state name `{}` is not in NFC form", name.name),
+                    Some(name.span),
+                )
+                .with_help(Some(format!(
+                    "write `{}` instead of `{}`",
+                    escape(&normalized),
+                    escape(name.name)
+                ))),
+            });
+        }
+
+        if !name.name.is_single_script() {
+            let mut scripts = Vec::new();
+            for c in name.name.chars() {
+                let script = c.script();
+                if ![Script::Common, Script::Inherited].contains(&script)
+                    && !scripts.contains(&script)
+                {
+                    scripts.push(script);
+                }
+            }
+            let scripts: Vec<_> = scripts.into_iter().map(Script::full_name).collect();
+            let scripts = match scripts.split_last() {
+                Some((last, rest)) if !rest.is_empty() => format!("{} and {last}", rest.join(", ")),
+                _ => "scripts".to_string(),
+            };
+            warnings.push(Warning {
+                lint: Lint::MixedScriptIdent,
+                diagnostic: Error::warning(
+                    format!("state name `{}` mixes {scripts}", name.name),
+                    Some(name.span),
+                ),
+            });
+        }
+
+        match skeletons.entry(skeleton(&normalized).collect()) {
+            Entry::Occupied(entry) => {
+                let other = entry.get();
+                warnings.push(Warning {
+                    lint: Lint::ConfusableIdent,
+                    diagnostic: Error::warning(
+                        format!(
+                            "state name `{}` is confusable with `{}`",
+                            name.name, other.name
+                        ),
+                        Some(name.span),
+                    )
+                    .with_note(other.span, format!("`{}` is defined here", other.name))
+                    .with_help(Some(format!(
+                        "the names are `{}` and `{}`",
+                        escape(name.name),
+                        escape(other.name)
+                    ))),
+                });
+            }
+            Entry::Vacant(entry) => {
+                entry.insert(name);
+            }
+        }
+    }
+}
+
+fn overloaded_states(unit: &[State], warnings: &mut Vec<Warning>) {
+    let mut first: HashMap<&str, &State> = HashMap::new();
+    for state in unit {
+        match first.entry(state.name.name) {
+            Entry::Occupied(entry) => {
+                let other = entry.get();
+                if Signature::of(other) == Signature::of(state) {
+                    // redefinitions are a compile error
+                    continue;
+                }
+                warnings.push(Warning {
+                    lint: Lint::OverloadedState,
+                    diagnostic: Error::warning(
+                        format!(
+                            "state `{}` has the same name as `{}`",
+                            Signature::of(state),
+                            Signature::of(other)
+                        ),
+                        Some(state.name.span),
+                    )
+                    .with_note(
+                        other.name.span,
+                        format!("`{}` is defined here", Signature::of(other)),
+                    )
+                    .with_help(Some(
+                        "states with different arguments are different states, so one of them may \
+                         be misnamed"
+                            .to_string(),
+                    )),
+                });
+            }
+            Entry::Vacant(entry) => {
+                entry.insert(state);
+            }
+        }
+    }
+}
+
+// spells out non-ascii characters so names that look the same can be told apart
+fn escape(name: &str) -> String {
+    name.chars()
+        .map(|c| {
+            if c.is_ascii() {
+                c.to_string()
+            } else {
+                c.escape_unicode().to_string()
+            }
+        })
+        .collect()
+}
+
+pub fn report(warnings: Vec<Warning>, lints: &Lints, deny_warnings: bool) -> Result<usize, Error> {
+    let diagnostics: Vec<_> = warnings
+        .into_iter()
+        .filter_map(|warning| lints.apply(warning))
+        .collect();
+    let count = diagnostics.len();
+    if count == 0 {
+        return Ok(0);
+    }
+
+    let diagnostics = Error::combine(diagnostics);
+    if diagnostics
+        .diagnostics()
+        .iter()
+        .any(|diagnostic| diagnostic.level == Level::Error)
+    {
+        // `main` prints these along with the usual "aborting due to" line
+        return Err(diagnostics);
+    }
+    // denied warnings are errors, which are printed even when output is quiet
+    if !output::quiet() || deny_warnings {
+        diagnostics.print();
+    }
+
+    if deny_warnings {
+        println!();
+        let plural = if count == 1 { "" } else { "s" };
+        Err(Error::new(
+            format!("{count} warning{plural} denied by `--deny-warnings`"),
+            None,
+        ))
+    } else {
+        Ok(count)
+    }
+}
+
+/// Which lints to report and which to report as errors
+#[derive(Default)]
+pub struct Lints {
+    pub warn: Vec<Lint>,
+    pub allow: Vec<Lint>,
+    pub deny: Vec<Lint>,
+}
+
+impl Lints {
+    /// The warning's diagnostic as it should be reported, or `None` if its lint
+    /// is allowed. Denying a lint wins over allowing it
+    pub fn apply(&self, warning: Warning) -> Option<Error> {
+        if self.deny.contains(&warning.lint) {
+            Some(warning.diagnostic.deny())
+        } else if self.warn.contains(&warning.lint)
+            || (warning.lint.enabled_by_default() && !self.allow.contains(&warning.lint))
+        {
+            Some(warning.diagnostic)
+        } else {
+            None
+        }
+    }
+}
@@ -0,0 +1,92 @@
+use std::fs;
+use std::io::{self, IsTerminal, Write};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::time::Duration;
+
+use notify::event::ModifyKind;
+use notify::{Event, EventKind, RecursiveMode, Watcher};
+use termion::{clear, cursor};
+
+use crate::backend;
+use crate::error::Error;
+
+// how often to look for a Ctrl-C while waiting for a change
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+// editors often save in a few steps, which should only rerun once
+const SETTLE_TIME: Duration = Duration::from_millis(50);
+
+/// Calls `run` once, and again whenever one of `paths` is saved, clearing the
+/// screen first if stdout is a terminal. Ctrl-C stops the run in progress, and
+/// quits between runs
+pub fn watch(paths: &[PathBuf], mut run: impl FnMut()) -> Result<(), Error> {
+    // editors often save by replacing the file, which ends a watch on the file
+    // itself, so this watches the directories and picks out the files
+    let files: Vec<_> = paths
+        .iter()
+        .map(|path| absolute(path))
+        .collect::<Result<_, _>>()?;
+    let (sender, receiver) = mpsc::channel();
+    let mut watcher = notify::recommended_watcher(sender).map_err(watch_error)?;
+    let mut dirs: Vec<_> = files.iter().filter_map(|file| file.parent()).collect();
+    dirs.dedup();
+    for dir in dirs {
+        watcher
+            .watch(dir, RecursiveMode::NonRecursive)
+            .map_err(watch_error)?;
+    }
+
+    let names: Vec<_> = paths
+        .iter()
+        .map(|path| path.display().to_string())
+        .collect();
+    loop {
+        if io::stdout().is_terminal() {
+            print!("{}{}", clear::All, cursor::Goto(1, 1));
+        }
+        run();
+        println!("watching {} (Ctrl-C to quit)", names.join(" and "));
+        io::stdout().flush().ok();
+
+        // a Ctrl-C that stopped the run shouldn't also quit
+        backend::catch_interrupts()?;
+        loop {
+            match receiver.recv_timeout(POLL_INTERVAL) {
+                Ok(Ok(event)) if changes(&event, &files) => break,
+                Ok(_) => {}
+                Err(RecvTimeoutError::Timeout) if backend::interrupted() => return Ok(()),
+                Err(RecvTimeoutError::Timeout) => {}
+                Err(RecvTimeoutError::Disconnected) => {
+                    return Err(Error::new("stopped watching for changes".to_string(), None))
+                }
+            }
+        }
+        std::thread::sleep(SETTLE_TIME);
+        while receiver.try_recv().is_ok() {}
+    }
+}
+
+// whether `event` wrote, made or removed one of `files`. reading a file (which
+// every run does) isn't a change
+fn changes(event: &Event, files: &[PathBuf]) -> bool {
+    let kind = matches!(
+        event.kind,
+        EventKind::Create(_) | EventKind::Modify(_) | EventKind::Remove(_)
+    ) && !matches!(event.kind, EventKind::Modify(ModifyKind::Metadata(_)));
+    kind && event.paths.iter().any(|path| files.contains(path))
+}
+
+// `path` with its directory resolved the way the watcher reports it
+fn absolute(path: &Path) -> Result<PathBuf, Error> {
+    let error = || Error::new(format!("couldn't read file {}", path.display()), None);
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    let name = path.file_name().ok_or_else(error)?;
+    Ok(fs::canonicalize(dir).map_err(|_| error())?.join(name))
+}
+
+fn watch_error(error: notify::Error) -> Error {
+    Error::new(format!("couldn't watch for changes: {error}"), None)
+}